@@ -0,0 +1,97 @@
+//! Criterion benchmarks of the concurrency strategies (`src/strategies.rs`)
+//!
+//! Run with `make bench` or `cargo bench`; criterion writes HTML reports to
+//! `target/criterion/`. `cargo bench -- channels` runs one group only.
+
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rust_async_await_course_example::strategies::{
+    await_boxed, await_unboxed, channel_throughput, Channel, Strategy,
+};
+use tokio::runtime::Runtime;
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build the benchmark runtime")
+}
+
+/// Pure overhead: operations that only yield.
+fn strategies_overhead(c: &mut Criterion) {
+    let runtime = runtime();
+    let mut group = c.benchmark_group("strategies/overhead");
+    for operations in [10, 100, 1000] {
+        group.throughput(Throughput::Elements(operations));
+        for strategy in Strategy::ALL {
+            group.bench_with_input(
+                BenchmarkId::new(strategy.to_string(), operations),
+                &operations,
+                |b, &operations| {
+                    b.to_async(&runtime)
+                        .iter(|| strategy.run(operations, Duration::ZERO))
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+/// Operations waiting 1ms each (tokio's timer resolution).
+fn strategies_latency(c: &mut Criterion) {
+    let runtime = runtime();
+    let mut group = c.benchmark_group("strategies/1ms");
+    // Sequential runs take `operations` milliseconds: keep the sample small.
+    group.sample_size(10);
+    let operations = 50;
+    for strategy in Strategy::ALL {
+        group.bench_function(strategy.to_string(), |b| {
+            b.to_async(&runtime)
+                .iter(|| strategy.run(operations, Duration::from_millis(1)))
+        });
+    }
+    group.finish();
+}
+
+fn boxing(c: &mut Criterion) {
+    let runtime = runtime();
+    let mut group = c.benchmark_group("boxing");
+    let count = 1000;
+    group.throughput(Throughput::Elements(count));
+    group.bench_function("unboxed", |b| {
+        b.to_async(&runtime).iter(|| await_unboxed(count))
+    });
+    group.bench_function("boxed", |b| {
+        b.to_async(&runtime).iter(|| await_boxed(count))
+    });
+    group.finish();
+}
+
+fn channels(c: &mut Criterion) {
+    let runtime = runtime();
+    let mut group = c.benchmark_group("channels");
+    let messages = 10_000;
+    group.throughput(Throughput::Elements(messages));
+    for channel in [
+        Channel::Bounded(1),
+        Channel::Bounded(16),
+        Channel::Bounded(1024),
+        Channel::Unbounded,
+    ] {
+        group.bench_function(channel.to_string(), |b| {
+            b.to_async(&runtime)
+                .iter(|| channel_throughput(channel, messages))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    strategies_overhead,
+    strategies_latency,
+    boxing,
+    channels
+);
+criterion_main!(benches);
@@ -0,0 +1,481 @@
+//! Adaptive timeouts from observed latencies
+//!
+//! A fixed timeout is either too long (a stuck call wastes the caller's
+//! time) or too short (a slightly slow dependency fails every call). An
+//! [`AdaptiveTimeout`] derives the timeout from the latencies it observes:
+//! - it keeps the last `window` latencies, and the timeout is a high
+//!   quantile of them (e.g. p99) times some headroom, clamped to bounds
+//! - until `min_samples` latencies were seen, the initial timeout is used
+//! - an attempt that times out is recorded with the timeout as its latency
+//!   (the true latency is at least that): if the dependency slows down, the
+//!   timeouts fill the window and push the quantile, hence the timeout, up
+//!   until calls succeed again
+//!
+//! With a lower quantile, the timeout becomes speculative: a call in the
+//! slow tail is abandoned early and retried, and the retry is likely to be
+//! fast. [`fetch_with_adaptive_timeout`] retries this way, and
+//! [`AdaptiveFetcher`] puts it in front of `fetch_data_from_api`: the HTTP
+//! server example fetches through one.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::{timeout, Instant};
+use tracing::{debug, info, instrument, warn};
+
+use crate::example_context::ExampleContext;
+use crate::flaky::{FlakyError, FlakyService, Step};
+
+/// Configuration of an [`AdaptiveTimeout`].
+#[derive(Debug, Clone, Copy)]
+pub struct AdaptiveTimeoutConfig {
+    /// Timeout used until enough latencies were observed.
+    pub initial: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    /// Quantile of the observed latencies the timeout is based on, in `0..=1`.
+    pub quantile: f64,
+    /// Multiplier applied to the quantile.
+    pub headroom: f64,
+    /// Number of recent latencies kept, at least one.
+    pub window: usize,
+    /// Latencies needed before adapting.
+    pub min_samples: usize,
+}
+
+impl Default for AdaptiveTimeoutConfig {
+    fn default() -> Self {
+        AdaptiveTimeoutConfig {
+            initial: Duration::from_secs(1),
+            min: Duration::from_millis(1),
+            max: Duration::from_secs(10),
+            quantile: 0.99,
+            headroom: 1.5,
+            window: 100,
+            min_samples: 10,
+        }
+    }
+}
+
+/// A timeout following the latencies of the calls it bounds.
+///
+/// Shared by reference between concurrent calls.
+#[derive(Debug)]
+pub struct AdaptiveTimeout {
+    config: AdaptiveTimeoutConfig,
+    latencies: Mutex<VecDeque<Duration>>,
+}
+
+impl AdaptiveTimeout {
+    /// Panics if `config.window` is zero.
+    pub fn new(config: AdaptiveTimeoutConfig) -> Self {
+        assert!(config.window > 0, "the latency window cannot be empty");
+        AdaptiveTimeout {
+            config,
+            latencies: Mutex::new(VecDeque::with_capacity(config.window)),
+        }
+    }
+
+    /// Records an observed latency, evicting the oldest one if the window is
+    /// full.
+    pub fn record(&self, latency: Duration) {
+        let mut latencies = self.latencies.lock().unwrap();
+        if latencies.len() == self.config.window {
+            latencies.pop_front();
+        }
+        latencies.push_back(latency);
+    }
+
+    /// The configured quantile of the latencies in the window, if enough were
+    /// observed.
+    pub fn observed_quantile(&self) -> Option<Duration> {
+        let latencies = self.latencies.lock().unwrap();
+        if latencies.len() < self.config.min_samples.max(1) {
+            return None;
+        }
+        let mut sorted: Vec<Duration> = latencies.iter().copied().collect();
+        sorted.sort_unstable();
+        let rank = (self.config.quantile * sorted.len() as f64).ceil() as usize;
+        Some(sorted[rank.clamp(1, sorted.len()) - 1])
+    }
+
+    /// The timeout for the next call.
+    pub fn current(&self) -> Duration {
+        match self.observed_quantile() {
+            Some(quantile) => quantile
+                .mul_f64(self.config.headroom)
+                .clamp(self.config.min, self.config.max),
+            None => self.config.initial,
+        }
+    }
+
+    /// Runs `future` with the current timeout, and records its latency.
+    ///
+    /// Returns the timeout that elapsed on failure.
+    pub async fn run<F: Future>(&self, future: F) -> Result<F::Output, Duration> {
+        let limit = self.current();
+        let start = Instant::now();
+        match timeout(limit, future).await {
+            Ok(output) => {
+                self.record(start.elapsed());
+                Ok(output)
+            }
+            Err(_) => {
+                self.record(limit);
+                Err(limit)
+            }
+        }
+    }
+}
+
+/// Why an attempt failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttemptError<E> {
+    /// No answer within the given timeout.
+    TimedOut(Duration),
+    Failed(E),
+}
+
+impl<E: fmt::Display> fmt::Display for AttemptError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttemptError::TimedOut(limit) => write!(f, "timed out after {:?}", limit),
+            AttemptError::Failed(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for AttemptError<E> {}
+
+/// Calls `operation` up to `max_attempts` times, each attempt bounded by the
+/// adaptive timeout. Returns the error of the last attempt if all fail.
+pub async fn fetch_with_adaptive_timeout<F, Fut, T, E>(
+    timeouts: &AdaptiveTimeout,
+    max_attempts: usize,
+    mut operation: F,
+) -> Result<T, AttemptError<E>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: fmt::Display,
+{
+    let mut attempt = 1;
+    loop {
+        let error = match timeouts.run(operation()).await {
+            Ok(Ok(value)) => return Ok(value),
+            Ok(Err(error)) => AttemptError::Failed(error),
+            Err(limit) => AttemptError::TimedOut(limit),
+        };
+        if attempt >= max_attempts {
+            warn!(attempt, %error, "giving up");
+            return Err(error);
+        }
+        debug!(attempt, %error, "attempt failed, retrying");
+        attempt += 1;
+    }
+}
+
+/// `fetch_data_from_api` with a retried, adaptive per-attempt timeout.
+///
+/// Its timeout follows the latencies of every URL it fetches: use one
+/// fetcher per dependency.
+#[derive(Debug)]
+pub struct AdaptiveFetcher {
+    timeouts: AdaptiveTimeout,
+    max_attempts: usize,
+}
+
+impl AdaptiveFetcher {
+    pub fn new(config: AdaptiveTimeoutConfig, max_attempts: usize) -> Self {
+        AdaptiveFetcher {
+            timeouts: AdaptiveTimeout::new(config),
+            max_attempts,
+        }
+    }
+
+    /// The timeout the next attempt gets.
+    pub fn current_timeout(&self) -> Duration {
+        self.timeouts.current()
+    }
+
+    /// Fetches `url`, keeping the error message only: the error of
+    /// `fetch_data_from_api` is not `Send`.
+    pub async fn fetch(&self, url: &str) -> Result<String, AttemptError<String>> {
+        fetch_with_adaptive_timeout(&self.timeouts, self.max_attempts, || async {
+            crate::fetch_data_from_api(url)
+                .await
+                .map_err(|error| error.to_string())
+        })
+        .await
+    }
+}
+
+/// Example: the timeout following a dependency that slows down
+///
+/// The calls take the latencies of `ctx`; a cancelled run stops calling and
+/// returns the timeouts so far.
+#[instrument(skip_all)]
+pub async fn adaptive_timeout_example(ctx: &ExampleContext) -> Vec<Duration> {
+    let timeouts = AdaptiveTimeout::new(AdaptiveTimeoutConfig {
+        initial: Duration::from_millis(100),
+        window: 20,
+        min_samples: 5,
+        ..AdaptiveTimeoutConfig::default()
+    });
+    // 20 calls taking 10ms, then 30ms from then on.
+    let delay = |ms| Step::Delay(ctx.latency().delay(Duration::from_millis(ms)));
+    let service =
+        FlakyService::scripted_otherwise("catalog", (0..20).map(|_| delay(10)), delay(30));
+
+    let mut limits = Vec::new();
+    for _ in 0..40 {
+        if ctx.cancel_token().is_cancelled() {
+            warn!(calls = service.calls(), "cancelled");
+            return limits;
+        }
+        limits.push(timeouts.current());
+        let result: Result<String, AttemptError<FlakyError>> =
+            fetch_with_adaptive_timeout(&timeouts, 3, || service.call()).await;
+        if let Err(error) = result {
+            warn!(%error, "fetch failed");
+        }
+    }
+    info!(
+        calls = service.calls(),
+        first = ?limits[0],
+        warmed_up = ?limits[19],
+        after_slowdown = ?limits[39],
+        "timeout adapted"
+    );
+    limits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delays(ms: impl IntoIterator<Item = u64>) -> impl Iterator<Item = Step> {
+        ms.into_iter()
+            .map(|ms| Step::Delay(Duration::from_millis(ms)))
+    }
+
+    /// 20ms, except every `period`-th call which takes `slow_ms`.
+    fn with_tail(calls: usize, period: usize, slow_ms: u64) -> Vec<u64> {
+        (1..=calls)
+            .map(|call| if call % period == 0 { slow_ms } else { 20 })
+            .collect()
+    }
+
+    #[test]
+    fn test_initial_timeout_until_enough_samples() {
+        let timeouts = AdaptiveTimeout::new(AdaptiveTimeoutConfig::default());
+        for _ in 0..9 {
+            timeouts.record(Duration::from_millis(20));
+        }
+        assert_eq!(timeouts.current(), Duration::from_secs(1));
+        timeouts.record(Duration::from_millis(20));
+        assert_eq!(timeouts.current(), Duration::from_millis(30));
+    }
+
+    #[test]
+    #[should_panic(expected = "the latency window cannot be empty")]
+    fn test_empty_window_is_rejected() {
+        AdaptiveTimeout::new(AdaptiveTimeoutConfig {
+            window: 0,
+            ..AdaptiveTimeoutConfig::default()
+        });
+    }
+
+    #[test]
+    fn test_window_stays_bounded() {
+        let timeouts = AdaptiveTimeout::new(AdaptiveTimeoutConfig {
+            window: 3,
+            ..AdaptiveTimeoutConfig::default()
+        });
+        for ms in 0..10 {
+            timeouts.record(Duration::from_millis(ms));
+        }
+        assert_eq!(timeouts.latencies.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_quantile_of_window() {
+        let timeouts = AdaptiveTimeout::new(AdaptiveTimeoutConfig {
+            quantile: 0.9,
+            headroom: 1.0,
+            window: 10,
+            min_samples: 1,
+            ..AdaptiveTimeoutConfig::default()
+        });
+        for ms in 1..=10 {
+            timeouts.record(Duration::from_millis(ms));
+        }
+        assert_eq!(timeouts.current(), Duration::from_millis(9));
+        // The window slides: 1..=10 replaced by 101..=110.
+        for ms in 101..=110 {
+            timeouts.record(Duration::from_millis(ms));
+        }
+        assert_eq!(timeouts.current(), Duration::from_millis(109));
+    }
+
+    #[test]
+    fn test_timeout_is_clamped() {
+        let timeouts = AdaptiveTimeout::new(AdaptiveTimeoutConfig {
+            min: Duration::from_millis(5),
+            max: Duration::from_millis(50),
+            min_samples: 1,
+            ..AdaptiveTimeoutConfig::default()
+        });
+        timeouts.record(Duration::from_micros(10));
+        assert_eq!(timeouts.current(), Duration::from_millis(5));
+        timeouts.record(Duration::from_secs(3));
+        assert_eq!(timeouts.current(), Duration::from_millis(50));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_converges_on_stable_latency() {
+        let timeouts = AdaptiveTimeout::new(AdaptiveTimeoutConfig::default());
+        let service = FlakyService::scripted("stable", delays(with_tail(200, 50, 28)));
+
+        for _ in 0..200 {
+            fetch_with_adaptive_timeout(&timeouts, 1, || service.call())
+                .await
+                .unwrap();
+        }
+        // p99 of 100 calls with 2 at 28ms is 28ms, times 1.5.
+        assert_eq!(timeouts.current(), Duration::from_millis(42));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grows_after_slowdown() {
+        let timeouts = AdaptiveTimeout::new(AdaptiveTimeoutConfig {
+            window: 20,
+            ..AdaptiveTimeoutConfig::default()
+        });
+        let service = FlakyService::scripted_otherwise(
+            "slowing",
+            delays(vec![20; 20]),
+            Step::Delay(Duration::from_millis(100)),
+        );
+        for _ in 0..20 {
+            fetch_with_adaptive_timeout(&timeouts, 1, || service.call())
+                .await
+                .unwrap();
+        }
+        assert_eq!(timeouts.current(), Duration::from_millis(30));
+
+        // Every timeout is recorded at its limit, raising the next limit
+        // 1.5x: 30, 45, 67.5, 101.25ms, which lets the call through.
+        let mut timed_out = Vec::new();
+        loop {
+            match fetch_with_adaptive_timeout(&timeouts, 1, || service.call()).await {
+                Ok(_) => break,
+                Err(AttemptError::TimedOut(limit)) => timed_out.push(limit),
+                Err(AttemptError::Failed(error)) => panic!("unexpected {}", error),
+            }
+        }
+        assert_eq!(
+            timed_out,
+            vec![
+                Duration::from_millis(30),
+                Duration::from_millis(45),
+                Duration::from_micros(67_500),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_speculative_retry_cuts_the_tail() {
+        // Every 10th call takes 500ms. With a p80 timeout, a slow call is
+        // abandoned after 30ms and retried, and the retry is fast.
+        let calls = with_tail(1000, 10, 500);
+        let config = AdaptiveTimeoutConfig {
+            quantile: 0.8,
+            window: 50,
+            min_samples: 5,
+            ..AdaptiveTimeoutConfig::default()
+        };
+
+        let timeouts = AdaptiveTimeout::new(config);
+        let service = FlakyService::scripted("tail", delays(calls.clone()));
+        let mut worst = Duration::ZERO;
+        for _ in 0..100 {
+            let start = Instant::now();
+            fetch_with_adaptive_timeout(&timeouts, 3, || service.call())
+                .await
+                .unwrap();
+            worst = worst.max(start.elapsed());
+        }
+
+        // Without retries, the worst call is the 500ms one.
+        assert!(worst < Duration::from_millis(100), "worst {:?}", worst);
+        assert!(service.calls() > 100, "slow calls were retried");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_returns_last_error() {
+        let timeouts = AdaptiveTimeout::new(AdaptiveTimeoutConfig::default());
+        let service = FlakyService::always("down", Step::Fail);
+        let result = fetch_with_adaptive_timeout(&timeouts, 3, || service.call()).await;
+
+        assert_eq!(result, Err(AttemptError::Failed(FlakyError { call: 3 })));
+        assert_eq!(
+            AttemptError::<FlakyError>::TimedOut(Duration::from_millis(5)).to_string(),
+            "timed out after 5ms"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetcher_times_out_a_slow_request() {
+        let server = crate::http_server::HttpServer::start_local().unwrap();
+        let fetcher = AdaptiveFetcher::new(
+            AdaptiveTimeoutConfig {
+                min_samples: 5,
+                ..AdaptiveTimeoutConfig::default()
+            },
+            2,
+        );
+        for _ in 0..5 {
+            let body = fetcher.fetch(&server.url("/delay/20")).await.unwrap();
+            assert_eq!(body, "Waited 20ms");
+        }
+        // Adapted from the initial second to about 1.5 times 20ms.
+        let adapted = fetcher.current_timeout();
+        assert!(adapted >= Duration::from_millis(30) && adapted < Duration::from_millis(500));
+
+        let result = fetcher.fetch(&server.url("/delay/2000")).await;
+        assert!(
+            matches!(result, Err(AttemptError::TimedOut(_))),
+            "{:?}",
+            result
+        );
+        assert!(fetcher.fetch(&server.url("/missing")).await.is_err());
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_adaptive_timeout_example() {
+        let limits = adaptive_timeout_example(&ExampleContext::captured()).await;
+        assert_eq!(limits[0], Duration::from_millis(100));
+        assert_eq!(limits[19], Duration::from_millis(15));
+        assert!(limits[39] >= Duration::from_millis(30));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_adaptive_timeout_example_stops_when_cancelled() {
+        let ctx = ExampleContext::captured();
+        let cancel = ctx.cancel_token().clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            cancel.cancel();
+        });
+
+        // Ten calls of 10ms end before the cancellation; the one starting at
+        // the same instant may or may not be made.
+        let limits = adaptive_timeout_example(&ctx).await;
+        assert!((10..=11).contains(&limits.len()), "{}", limits.len());
+    }
+}
@@ -0,0 +1,430 @@
+//! `Drop` across awaits, and async cleanup
+//!
+//! A future dropped while suspended at an await is never resumed, but its
+//! state machine is dropped: every local alive at that await is dropped,
+//! in reverse order, exactly once. RAII guards therefore clean up after
+//! cancellation (a `timeout`, a lost `select!` branch, `abort`) as well as
+//! after completion.
+//!
+//! `Drop` is synchronous, though: it cannot await a flush or a goodbye
+//! message. The workarounds:
+//! - an explicit `async fn close(self)` the owner awaits, with `Drop` only
+//!   reporting a forgotten close ([`Connection`])
+//! - a scopeguard-style [`defer`]: a closure run on drop, which can spawn
+//!   the async cleanup as a task if a runtime is still there
+//! - cleanup in the shutdown branch of a `select!`: the branch losing the
+//!   race is dropped before the winner's handler runs, then the cleanup is
+//!   awaited ([`serve_until_shutdown`])
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+use tokio::time::{sleep, timeout};
+use tracing::{info, instrument, warn};
+
+use crate::example_context::ExampleContext;
+
+/// Shared record of cleanup events, in order.
+#[derive(Clone, Default)]
+pub struct DropLog(Arc<Mutex<Vec<String>>>);
+
+impl DropLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&self, event: impl Into<String>) {
+        let event = event.into();
+        info!(event = %event, "cleanup");
+        self.0.lock().unwrap().push(event);
+    }
+
+    pub fn events(&self) -> Vec<String> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// Times `event` was recorded.
+    pub fn count(&self, event: &str) -> usize {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| *e == event)
+            .count()
+    }
+}
+
+impl fmt::Debug for DropLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.events()).finish()
+    }
+}
+
+/// RAII guard recording `drop <name>` when dropped.
+#[derive(Debug)]
+pub struct Guard {
+    name: &'static str,
+    log: DropLog,
+}
+
+impl Guard {
+    pub fn new(name: &'static str, log: &DropLog) -> Self {
+        log.push(format!("create {}", name));
+        Guard {
+            name,
+            log: log.clone(),
+        }
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.log.push(format!("drop {}", self.name));
+    }
+}
+
+/// Holds guards across two awaits of 10ms each.
+pub async fn guarded_work(log: DropLog) -> u32 {
+    let _outer = Guard::new("outer", &log);
+    sleep(Duration::from_millis(10)).await;
+    let _inner = Guard::new("inner", &log);
+    sleep(Duration::from_millis(10)).await;
+    log.push("work done");
+    42
+}
+
+/// A connection needing async cleanup: pending writes must be flushed.
+#[derive(Debug)]
+pub struct Connection {
+    log: DropLog,
+    pending: Vec<String>,
+    closed: bool,
+}
+
+impl Connection {
+    pub fn open(log: &DropLog) -> Self {
+        Connection {
+            log: log.clone(),
+            pending: Vec::new(),
+            closed: false,
+        }
+    }
+
+    /// Buffers a write, sent on [`close`](Self::close).
+    pub fn write(&mut self, message: impl Into<String>) {
+        self.pending.push(message.into());
+    }
+
+    /// Flushes the pending writes, then closes.
+    pub async fn close(mut self) {
+        sleep(Duration::from_millis(5)).await;
+        self.log
+            .push(format!("flushed {} write(s)", self.pending.len()));
+        self.pending.clear();
+        self.closed = true;
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if !self.closed {
+            // No way to await the flush here: report it instead.
+            warn!(
+                lost = self.pending.len(),
+                "connection dropped without close"
+            );
+            self.log.push(format!(
+                "dropped without close, lost {}",
+                self.pending.len()
+            ));
+        }
+    }
+}
+
+/// Runs a closure when dropped, unless dismissed.
+pub struct Defer<F: FnOnce()> {
+    cleanup: Option<F>,
+}
+
+/// Defers `cleanup` to the end of the scope, however the scope ends.
+pub fn defer<F: FnOnce()>(cleanup: F) -> Defer<F> {
+    Defer {
+        cleanup: Some(cleanup),
+    }
+}
+
+impl<F: FnOnce()> Defer<F> {
+    /// Cancels the cleanup, e.g. once the work it undoes is committed.
+    pub fn dismiss(mut self) {
+        self.cleanup = None;
+    }
+}
+
+impl<F: FnOnce()> Drop for Defer<F> {
+    fn drop(&mut self) {
+        if let Some(cleanup) = self.cleanup.take() {
+            cleanup();
+        }
+    }
+}
+
+/// Writes to a connection, deferring its async close to a spawned task so it
+/// is flushed even if this future is cancelled.
+pub async fn write_with_deferred_close(log: DropLog, messages: usize) {
+    let connection = Arc::new(Mutex::new(Some(Connection::open(&log))));
+    let to_close = Arc::clone(&connection);
+    let _close = defer(move || {
+        let Some(connection) = to_close.lock().unwrap().take() else {
+            return;
+        };
+        // The runtime may be shutting down: the connection is then dropped.
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(connection.close());
+        }
+    });
+
+    for index in 0..messages {
+        sleep(Duration::from_millis(10)).await;
+        if let Some(connection) = connection.lock().unwrap().as_mut() {
+            connection.write(format!("message {}", index));
+        }
+    }
+}
+
+/// Serves on a connection until shutdown, then closes it: the serving
+/// future is dropped when the shutdown branch wins, and the close is awaited.
+pub async fn serve_until_shutdown(log: DropLog, shutdown: oneshot::Receiver<()>) -> usize {
+    let mut connection = Connection::open(&log);
+    let mut served = 0;
+    let serve = async {
+        let _guard = Guard::new("serve loop", &log);
+        loop {
+            sleep(Duration::from_millis(10)).await;
+            served += 1;
+            connection.write(format!("reply {}", served));
+        }
+    };
+    tokio::select! {
+        _ = serve => unreachable!("serves forever"),
+        _ = shutdown => log.push("shutdown requested"),
+    }
+    connection.close().await;
+    served
+}
+
+/// Example: guards on completion and cancellation, and async cleanup
+#[instrument(skip_all)]
+pub async fn async_drop_example(ctx: &ExampleContext) -> Vec<String> {
+    let log = DropLog::new();
+
+    log.push("-- completed");
+    guarded_work(log.clone()).await;
+
+    log.push("-- cancelled at the second await");
+    let _ = timeout(Duration::from_millis(15), guarded_work(log.clone())).await;
+
+    log.push("-- explicit close vs drop");
+    let mut connection = Connection::open(&log);
+    connection.write("hello");
+    connection.close().await;
+    let mut forgotten = Connection::open(&log);
+    forgotten.write("lost");
+    drop(forgotten);
+
+    log.push("-- deferred close, cancelled");
+    let _ = timeout(
+        Duration::from_millis(25),
+        write_with_deferred_close(log.clone(), 5),
+    )
+    .await;
+    // Lets the spawned close run.
+    ctx.latency().sleep(Duration::from_millis(10)).await;
+
+    log.push("-- cleanup in the shutdown branch");
+    let (stop, stopped) = oneshot::channel();
+    let server = tokio::spawn(serve_until_shutdown(log.clone(), stopped));
+    ctx.latency().sleep(Duration::from_millis(35)).await;
+    let _ = stop.send(());
+    let _ = server.await;
+
+    log.events()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_guards_dropped_once_on_completion() {
+        let log = DropLog::new();
+        assert_eq!(guarded_work(log.clone()).await, 42);
+        assert_eq!(
+            log.events(),
+            vec![
+                "create outer",
+                "create inner",
+                "work done",
+                "drop inner",
+                "drop outer"
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_guards_dropped_once_on_cancellation() {
+        let log = DropLog::new();
+        let result = timeout(Duration::from_millis(15), guarded_work(log.clone())).await;
+
+        assert!(result.is_err());
+        assert_eq!(
+            log.events(),
+            vec!["create outer", "create inner", "drop inner", "drop outer"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_only_live_guards_dropped_before_first_await() {
+        let log = DropLog::new();
+        let _ = timeout(Duration::from_millis(5), guarded_work(log.clone())).await;
+        assert_eq!(log.events(), vec!["create outer", "drop outer"]);
+    }
+
+    #[tokio::test]
+    async fn test_never_polled_future_creates_nothing() {
+        let log = DropLog::new();
+        drop(guarded_work(log.clone()));
+        assert!(log.events().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_guards_dropped_once_on_abort() {
+        let log = DropLog::new();
+        let task = tokio::spawn(guarded_work(log.clone()));
+        sleep(Duration::from_millis(5)).await;
+        task.abort();
+        assert!(task.await.unwrap_err().is_cancelled());
+
+        assert_eq!(log.count("drop outer"), 1);
+        assert_eq!(log.count("create inner"), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_close_flushes_and_drop_reports() {
+        let log = DropLog::new();
+        let mut connection = Connection::open(&log);
+        connection.write("a");
+        connection.write("b");
+        connection.close().await;
+
+        let mut forgotten = Connection::open(&log);
+        forgotten.write("c");
+        drop(forgotten);
+
+        assert_eq!(
+            log.events(),
+            vec!["flushed 2 write(s)", "dropped without close, lost 1"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_defer_runs_once_on_both_paths() {
+        let log = DropLog::new();
+        {
+            let log = log.clone();
+            let _cleanup = defer(move || log.push("deferred"));
+        }
+        let cancelled = {
+            let log = log.clone();
+            async move {
+                let _cleanup = defer(move || log.push("deferred"));
+                std::future::pending::<()>().await;
+            }
+        };
+        let _ = timeout(Duration::from_millis(1), cancelled).await;
+        assert_eq!(log.count("deferred"), 2);
+
+        let dismissed = {
+            let log = log.clone();
+            defer(move || log.push("deferred"))
+        };
+        dismissed.dismiss();
+        assert_eq!(log.count("deferred"), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_deferred_close_flushes_after_cancellation() {
+        let log = DropLog::new();
+        let _ = timeout(
+            Duration::from_millis(25),
+            write_with_deferred_close(log.clone(), 5),
+        )
+        .await;
+        sleep(Duration::from_millis(10)).await;
+
+        assert_eq!(log.events(), vec!["flushed 2 write(s)"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_deferred_close_flushes_after_completion() {
+        let log = DropLog::new();
+        write_with_deferred_close(log.clone(), 3).await;
+        sleep(Duration::from_millis(10)).await;
+
+        assert_eq!(log.events(), vec!["flushed 3 write(s)"]);
+    }
+
+    #[test]
+    fn test_deferred_close_without_runtime_drops() {
+        let log = DropLog::new();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let task = runtime.spawn(write_with_deferred_close(log.clone(), 5));
+        runtime.block_on(async { sleep(Duration::from_millis(15)).await });
+        // Shutting the runtime down drops the task; no runtime to close on.
+        drop(runtime);
+        drop(task);
+
+        assert_eq!(log.events(), vec!["dropped without close, lost 1"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cleanup_in_shutdown_branch() {
+        let log = DropLog::new();
+        let (stop, stopped) = oneshot::channel();
+        let server = tokio::spawn(serve_until_shutdown(log.clone(), stopped));
+        sleep(Duration::from_millis(35)).await;
+        stop.send(()).unwrap();
+
+        assert_eq!(server.await.unwrap(), 3);
+        assert_eq!(
+            log.events(),
+            vec![
+                "create serve loop",
+                "drop serve loop",
+                "shutdown requested",
+                "flushed 3 write(s)"
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_async_drop_example() {
+        let events = async_drop_example(&ExampleContext::captured()).await;
+        for guard in ["outer", "inner", "serve loop"] {
+            let created = events
+                .iter()
+                .filter(|e| **e == format!("create {}", guard))
+                .count();
+            let dropped = events
+                .iter()
+                .filter(|e| **e == format!("drop {}", guard))
+                .count();
+            assert_eq!(created, dropped, "{} dropped once per creation", guard);
+        }
+        assert!(events.contains(&"dropped without close, lost 1".to_string()));
+    }
+}
@@ -0,0 +1,336 @@
+//! Async functions in traits
+//!
+//! A plugin-style `DataSource` trait (`async fn fetch(&self, key)`) written
+//! three ways, each implemented by the same two sources:
+//! 1. Native `async fn` in traits (Rust 1.75+): zero-cost static dispatch,
+//!    but the trait is not `dyn`-compatible.
+//! 2. `#[async_trait]`: the macro rewrites each method to return a boxed
+//!    future, which makes the trait usable as `Box<dyn Trait>`.
+//! 3. The same boxed-future desugaring written by hand, to show there is no
+//!    magic in the macro.
+//!
+//! # The `dyn`-compatibility problem
+//!
+//! `async fn fetch(&self)` returns an anonymous future type, different for
+//! every implementation (like `-> impl Future`). A trait object needs a single
+//! vtable signature, so it has to erase that type, which native async traits
+//! do not do:
+//!
+//! ```compile_fail
+//! use rust_async_await_course_example::async_traits::{DataSource, InMemorySource};
+//!
+//! let sources: Vec<Box<dyn DataSource>> = vec![Box::new(InMemorySource::default())];
+//! ```
+//!
+//! Boxing the future (`Pin<Box<dyn Future + Send + '_>>`) erases the type at
+//! the cost of one allocation per call.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tracing::{info, instrument};
+
+use crate::example_context::ExampleContext;
+use crate::runtime::sleep;
+
+/// Error returned by a data source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SourceError {
+    NotFound(String),
+    Unavailable(String),
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceError::NotFound(key) => write!(f, "key not found: {}", key),
+            SourceError::Unavailable(source) => write!(f, "source unavailable: {}", source),
+        }
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+/// Pattern 1: native `async fn` in a trait (static dispatch only).
+// `async fn` in a public trait cannot promise its future is `Send`, which
+// callers spawning it on a multi-thread runtime would need. Acceptable for a
+// teaching trait; production code would use `-> impl Future + Send` instead.
+#[allow(async_fn_in_trait)]
+pub trait DataSource {
+    fn name(&self) -> &str;
+
+    async fn fetch(&self, key: &str) -> Result<String, SourceError>;
+}
+
+/// Pattern 2: `#[async_trait]`, usable as `Box<dyn DynDataSource>`.
+#[async_trait]
+pub trait DynDataSource: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn fetch(&self, key: &str) -> Result<String, SourceError>;
+}
+
+/// Future type returned by [`BoxedDataSource::fetch`].
+pub type BoxFetch<'a> = Pin<Box<dyn Future<Output = Result<String, SourceError>> + Send + 'a>>;
+
+/// Pattern 3: the boxed-future desugaring `#[async_trait]` generates, by hand.
+pub trait BoxedDataSource: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn fetch<'a>(&'a self, key: &'a str) -> BoxFetch<'a>;
+}
+
+/// A source answering from a map held in memory.
+#[derive(Debug, Default, Clone)]
+pub struct InMemorySource {
+    entries: HashMap<String, String>,
+}
+
+impl InMemorySource {
+    pub fn with_entry(mut self, key: &str, value: &str) -> Self {
+        self.entries.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    fn lookup(&self, key: &str) -> Result<String, SourceError> {
+        self.entries
+            .get(key)
+            .cloned()
+            .ok_or_else(|| SourceError::NotFound(key.to_string()))
+    }
+}
+
+/// A simulated remote source: answers every key after some latency, unless
+/// it is offline.
+#[derive(Debug, Clone)]
+pub struct RemoteSource {
+    pub latency: Duration,
+    pub online: bool,
+}
+
+impl RemoteSource {
+    async fn lookup(&self, key: &str) -> Result<String, SourceError> {
+        sleep(self.latency).await;
+        if self.online {
+            Ok(format!("remote:{}", key))
+        } else {
+            Err(SourceError::Unavailable("remote".to_string()))
+        }
+    }
+}
+
+impl DataSource for InMemorySource {
+    fn name(&self) -> &str {
+        "in-memory"
+    }
+
+    async fn fetch(&self, key: &str) -> Result<String, SourceError> {
+        self.lookup(key)
+    }
+}
+
+impl DataSource for RemoteSource {
+    fn name(&self) -> &str {
+        "remote"
+    }
+
+    async fn fetch(&self, key: &str) -> Result<String, SourceError> {
+        self.lookup(key).await
+    }
+}
+
+#[async_trait]
+impl DynDataSource for InMemorySource {
+    fn name(&self) -> &str {
+        "in-memory"
+    }
+
+    async fn fetch(&self, key: &str) -> Result<String, SourceError> {
+        self.lookup(key)
+    }
+}
+
+#[async_trait]
+impl DynDataSource for RemoteSource {
+    fn name(&self) -> &str {
+        "remote"
+    }
+
+    async fn fetch(&self, key: &str) -> Result<String, SourceError> {
+        self.lookup(key).await
+    }
+}
+
+impl BoxedDataSource for InMemorySource {
+    fn name(&self) -> &str {
+        "in-memory"
+    }
+
+    fn fetch<'a>(&'a self, key: &'a str) -> BoxFetch<'a> {
+        Box::pin(async move { self.lookup(key) })
+    }
+}
+
+impl BoxedDataSource for RemoteSource {
+    fn name(&self) -> &str {
+        "remote"
+    }
+
+    fn fetch<'a>(&'a self, key: &'a str) -> BoxFetch<'a> {
+        Box::pin(self.lookup(key))
+    }
+}
+
+/// Static dispatch: one copy of this function is compiled per source type.
+pub async fn fetch_all<S: DataSource>(
+    source: &S,
+    keys: &[&str],
+) -> Vec<Result<String, SourceError>> {
+    let mut results = Vec::with_capacity(keys.len());
+    for key in keys {
+        results.push(source.fetch(key).await);
+    }
+    results
+}
+
+/// Dynamic dispatch: tries each plugin in turn until one has the key.
+pub async fn fetch_from_first(
+    sources: &[Box<dyn DynDataSource>],
+    key: &str,
+) -> Result<(String, String), SourceError> {
+    let mut last_error = SourceError::NotFound(key.to_string());
+    for source in sources {
+        match source.fetch(key).await {
+            Ok(value) => return Ok((source.name().to_string(), value)),
+            Err(error) => last_error = error,
+        }
+    }
+    Err(last_error)
+}
+
+/// Same as [`fetch_from_first`], over hand-desugared trait objects.
+pub async fn fetch_from_first_boxed(
+    sources: &[Box<dyn BoxedDataSource>],
+    key: &str,
+) -> Result<(String, String), SourceError> {
+    let mut last_error = SourceError::NotFound(key.to_string());
+    for source in sources {
+        match source.fetch(key).await {
+            Ok(value) => return Ok((source.name().to_string(), value)),
+            Err(error) => last_error = error,
+        }
+    }
+    Err(last_error)
+}
+
+/// Example: the three async trait patterns side by side
+#[instrument(skip_all)]
+pub async fn async_traits_example(ctx: &ExampleContext) {
+    let memory = InMemorySource::default().with_entry("course", "async-await");
+    let remote = RemoteSource {
+        latency: ctx.latency().delay(Duration::from_millis(20)),
+        online: true,
+    };
+
+    // Await before logging: a future awaited inside the macro arguments would
+    // keep the (non-Send) formatting arguments alive across the await.
+    let results = fetch_all(&memory, &["course", "missing"]).await;
+    info!(?results, "static dispatch (in-memory)");
+    let results = fetch_all(&remote, &["course"]).await;
+    info!(?results, "static dispatch (remote)");
+
+    let plugins: Vec<Box<dyn DynDataSource>> =
+        vec![Box::new(memory.clone()), Box::new(remote.clone())];
+    let result = fetch_from_first(&plugins, "missing").await;
+    info!(?result, "#[async_trait] dyn dispatch");
+
+    let plugins: Vec<Box<dyn BoxedDataSource>> = vec![Box::new(memory), Box::new(remote)];
+    let result = fetch_from_first_boxed(&plugins, "course").await;
+    info!(?result, "manual boxed dyn dispatch");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Instant;
+
+    fn memory() -> InMemorySource {
+        InMemorySource::default().with_entry("a", "1")
+    }
+
+    fn remote(online: bool) -> RemoteSource {
+        RemoteSource {
+            latency: Duration::from_millis(10),
+            online,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_native_in_memory_source() {
+        let results = fetch_all(&memory(), &["a", "b"]).await;
+        assert_eq!(
+            results,
+            vec![
+                Ok("1".to_string()),
+                Err(SourceError::NotFound("b".to_string()))
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_native_remote_source() {
+        let start = Instant::now();
+        let results = fetch_all(&remote(true), &["a", "b"]).await;
+        assert_eq!(
+            results,
+            vec![Ok("remote:a".to_string()), Ok("remote:b".to_string())]
+        );
+        assert_eq!(start.elapsed(), Duration::from_millis(20));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_async_trait_dyn_dispatch() {
+        let plugins: Vec<Box<dyn DynDataSource>> = vec![Box::new(memory()), Box::new(remote(true))];
+
+        assert_eq!(
+            fetch_from_first(&plugins, "a").await,
+            Ok(("in-memory".to_string(), "1".to_string()))
+        );
+        assert_eq!(
+            fetch_from_first(&plugins, "b").await,
+            Ok(("remote".to_string(), "remote:b".to_string()))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_async_trait_reports_last_error() {
+        let plugins: Vec<Box<dyn DynDataSource>> =
+            vec![Box::new(memory()), Box::new(remote(false))];
+        assert_eq!(
+            fetch_from_first(&plugins, "b").await,
+            Err(SourceError::Unavailable("remote".to_string()))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_manual_boxed_dyn_dispatch() {
+        let plugins: Vec<Box<dyn BoxedDataSource>> =
+            vec![Box::new(remote(false)), Box::new(memory())];
+        assert_eq!(
+            fetch_from_first_boxed(&plugins, "a").await,
+            Ok(("in-memory".to_string(), "1".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_boxed_futures_can_be_spawned() {
+        // The boxed futures are `Send`, so trait objects work across tasks.
+        let source: Box<dyn DynDataSource> = Box::new(memory());
+        let handle = tokio::spawn(async move { source.fetch("a").await });
+        assert_eq!(handle.await.unwrap(), Ok("1".to_string()));
+    }
+}
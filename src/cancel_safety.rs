@@ -0,0 +1,291 @@
+//! Testing cancellation safety systematically
+//!
+//! A future can be dropped at any of its await points: a `timeout` expiring,
+//! a `select!` branch losing, a task aborted. Code is cancellation-safe when
+//! every such drop leaves its invariants intact: no acknowledged write lost,
+//! no half-applied write visible, no record consumed and then thrown away.
+//!
+//! Reviewing await points one by one does not scale; enumerating them does:
+//! - [`cancel_at`] polls a future and drops it when it suspends for the
+//!   `point`-th time (0-based), a cancellation at exactly that await point
+//! - [`sweep`] runs a scenario once per await point, until the operation
+//!   completes without being cancelled, so every point is exercised
+//! - [`random_points`] draws seeded cancellation points, to cancel a long
+//!   sequence of operations at random places while checking the invariants
+//!   after each one; a failing seed replays exactly
+//!
+//! The tests apply them to the transactional key-value store of
+//! [`crate::transaction`] (an acknowledged transfer is applied, a cancelled
+//! one leaves no trace, money is conserved) and to the batching consumer of
+//! [`crate::coalescing`] (every update is received exactly once, in order).
+//! They also show the harness catching a consumer that is not
+//! cancellation-safe.
+
+use std::future::{poll_fn, Future};
+use std::pin::pin;
+use std::sync::Mutex;
+use std::task::Poll;
+
+use tracing::{debug, info, instrument};
+
+use crate::transaction::{transfer, Database, TxStats};
+
+/// A scenario still not completing after that many points is considered
+/// stuck.
+const MAX_POINTS: usize = 10_000;
+
+/// Polls `future` to completion, unless it suspends `point + 1` times: it is
+/// then dropped at its `point`-th await point and `None` is returned.
+pub async fn cancel_at<F: Future>(future: F, point: usize) -> Option<F::Output> {
+    let mut future = pin!(future);
+    let mut suspended = 0;
+    poll_fn(|cx| match future.as_mut().poll(cx) {
+        Poll::Ready(output) => Poll::Ready(Some(output)),
+        Poll::Pending if suspended == point => Poll::Ready(None),
+        Poll::Pending => {
+            suspended += 1;
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+/// Runs `scenario(point)` for `point` = 0, 1, 2... until it reports that the
+/// operation completed, and returns the number of points it was cancelled
+/// at. The scenario builds fresh state, runs the operation through
+/// [`cancel_at`] and checks its invariants.
+///
+/// # Panics
+///
+/// If the operation still has not completed after `MAX_POINTS` points.
+pub async fn sweep<S, Fut>(mut scenario: S) -> usize
+where
+    S: FnMut(usize) -> Fut,
+    Fut: Future<Output = bool>,
+{
+    for point in 0..MAX_POINTS {
+        if scenario(point).await {
+            debug!(points = point, "operation completed");
+            return point;
+        }
+    }
+    panic!("operation never completed in {} points", MAX_POINTS);
+}
+
+/// Seeded cancellation points below `bound`; the same seed gives the same
+/// points.
+pub fn random_points(seed: u64, bound: usize) -> impl Iterator<Item = usize> {
+    assert!(bound > 0, "no point to draw from");
+    // Xorshift never leaves zero.
+    let mut state = (seed ^ 0x9E37_79B9_7F4A_7C15).max(1);
+    std::iter::from_fn(move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        Some((state % bound as u64) as usize)
+    })
+}
+
+/// A transfer cancelled at one await point, and what it left behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferProbe {
+    pub point: usize,
+    /// The transfer completed and was acknowledged.
+    pub acked: bool,
+    /// Balance of the sender afterwards.
+    pub from_balance: i64,
+    /// Sum of the balances, conserved by every transfer.
+    pub total: i64,
+    pub stats: TxStats,
+}
+
+/// Cancels a transfer of 30 from a balance of 100 at each of its await
+/// points, on a fresh database every time.
+pub async fn probe_transfer() -> Vec<TransferProbe> {
+    let probes = Mutex::new(Vec::new());
+    sweep(|point| {
+        let db = Database::with_entries([("alice", 100), ("bob", 50)]);
+        let probes = &probes;
+        async move {
+            let acked = cancel_at(transfer(&db, "alice", "bob", 30), point).await;
+            let acked = matches!(acked, Some(Ok(())));
+            probes.lock().unwrap().push(TransferProbe {
+                point,
+                acked,
+                from_balance: db.get("alice").unwrap_or(0),
+                total: db.snapshot().values().sum(),
+                stats: db.stats(),
+            });
+            acked
+        }
+    })
+    .await;
+    probes.into_inner().unwrap()
+}
+
+/// Example: a transfer cancelled at every await point keeps the store intact
+#[instrument]
+pub async fn cancel_safety_example() -> Vec<TransferProbe> {
+    let probes = probe_transfer().await;
+    for probe in &probes {
+        info!(
+            point = probe.point,
+            acked = probe.acked,
+            alice = probe.from_balance,
+            total = probe.total,
+            "transfer cancelled"
+        );
+    }
+    probes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    use tokio::time::sleep;
+
+    use super::*;
+    use crate::coalescing::{self, Notifier, Updates, WakeStrategy};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancel_at_counts_await_points() {
+        let three_points = || async {
+            for _ in 0..3 {
+                sleep(Duration::from_millis(1)).await;
+            }
+            "done"
+        };
+        assert_eq!(cancel_at(three_points(), 0).await, None);
+        assert_eq!(cancel_at(three_points(), 2).await, None);
+        assert_eq!(cancel_at(three_points(), 3).await, Some("done"));
+        assert_eq!(cancel_at(async { 1 }, 0).await, Some(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sweep_visits_every_point() {
+        let mut visited = Vec::new();
+        let points = sweep(|point| {
+            visited.push(point);
+            async move {
+                cancel_at(sleep(Duration::from_millis(1)), point)
+                    .await
+                    .is_some()
+            }
+        })
+        .await;
+        assert_eq!(points, 1);
+        assert_eq!(visited, [0, 1]);
+    }
+
+    #[test]
+    fn test_random_points_are_seeded_and_bounded() {
+        let points: Vec<usize> = random_points(9, 5).take(100).collect();
+        assert!(points.iter().all(|&point| point < 5));
+        assert!((0..5).all(|point| points.contains(&point)));
+        assert_eq!(points, random_points(9, 5).take(100).collect::<Vec<_>>());
+        assert_ne!(points, random_points(10, 5).take(100).collect::<Vec<_>>());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_transfer_cancelled_at_every_point() {
+        let probes = probe_transfer().await;
+        // get, set, get, set: one await point each.
+        assert_eq!(probes.len(), 5);
+        for probe in &probes {
+            assert_eq!(probe.total, 150, "money conserved at {}", probe.point);
+            assert_eq!(
+                probe.from_balance,
+                if probe.acked { 70 } else { 100 },
+                "applied if and only if acknowledged, at {}",
+                probe.point
+            );
+            assert_eq!(probe.stats.committed + probe.stats.rolled_back, 1);
+        }
+        assert!(probes.last().unwrap().acked);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_random_cancellations_lose_no_acknowledged_transfer() {
+        let accounts = ["alice", "bob", "carol"];
+        let db = Database::with_entries(accounts.iter().map(|&account| (account, 100)));
+        let mut model: BTreeMap<String, i64> = db.snapshot();
+        let mut draws = random_points(42, 1000);
+
+        for round in 0..300 {
+            let from = accounts[draws.next().unwrap() % 3];
+            let to = accounts[(accounts.iter().position(|&a| a == from).unwrap() + 1) % 3];
+            let amount = (draws.next().unwrap() % 80) as i64;
+            let point = draws.next().unwrap() % 6;
+
+            // Refused or cancelled, the model stays as is.
+            if let Some(Ok(())) = cancel_at(transfer(&db, from, to, amount), point).await {
+                *model.get_mut(from).unwrap() -= amount;
+                *model.get_mut(to).unwrap() += amount;
+            }
+            assert_eq!(db.snapshot(), model, "round {}", round);
+            let stats = db.stats();
+            assert_eq!(stats.committed + stats.rolled_back, round + 1);
+        }
+        assert_eq!(model.values().sum::<i64>(), 300);
+    }
+
+    /// Publishes `count` updates, one per millisecond, then closes.
+    fn produce(notifier: Notifier, count: u64) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            for update in 0..count {
+                notifier.push(update);
+                sleep(Duration::from_millis(1)).await;
+            }
+        })
+    }
+
+    /// Next batch; with `lossy`, the batch is taken, then held across an
+    /// await point and lost if cancelled there: not cancellation-safe.
+    async fn next_batch(updates: &mut Updates, lossy: bool) -> Option<Vec<u64>> {
+        let batch = updates.next_batch().await;
+        if lossy {
+            sleep(Duration::from_micros(10)).await;
+        }
+        batch
+    }
+
+    /// Consumes every update with `next_batch` cancelled at random points.
+    async fn consume_with_cancellations(seed: u64, lossy: bool) -> Vec<u64> {
+        let (notifier, mut updates) = coalescing::channel(WakeStrategy::Coalesced);
+        let producer = produce(notifier, 200);
+        let mut received = Vec::new();
+        for point in random_points(seed, 3) {
+            match cancel_at(next_batch(&mut updates, lossy), point).await {
+                Some(Some(batch)) => received.extend(batch),
+                Some(None) => break,
+                None => {}
+            }
+        }
+        producer.await.unwrap();
+        received
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_batches_survive_cancellation() {
+        for seed in 0..5 {
+            // `Updates::next_batch` takes the updates only when it returns
+            // them.
+            let received = consume_with_cancellations(seed, false).await;
+            assert_eq!(received, (0..200).collect::<Vec<_>>(), "seed {}", seed);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_harness_catches_a_consumer_losing_batches() {
+        let received = consume_with_cancellations(1, true).await;
+        assert!(received.len() < 200, "some batches were lost");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancel_safety_example() {
+        let probes = cancel_safety_example().await;
+        assert!(probes.iter().all(|probe| probe.total == 150));
+    }
+}
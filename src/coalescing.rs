@@ -0,0 +1,275 @@
+//! Coalesced wakeups
+//!
+//! A producer publishing many small updates to a consumer task can wake that
+//! task once per update. Every `wake` costs atomic operations and, if the
+//! task is already scheduled, buys nothing: the task drains all the pending
+//! updates in a single poll anyway. These are spurious wakeups.
+//!
+//! Coalescing fixes it with a dirty flag:
+//! - the producer sets the flag with every update, and wakes the consumer
+//!   only when the flag goes from clean to dirty
+//! - the consumer clears the flag *before* draining the updates, so an update
+//!   pushed during the drain sets it again and wakes the consumer for the
+//!   next round (no lost wakeup)
+//!
+//! The wakes are counted in both strategies to show the difference.
+
+use std::future::poll_fn;
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use tracing::{info, instrument};
+
+/// When the producer wakes the consumer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WakeStrategy {
+    /// On every update.
+    EveryUpdate,
+    /// Only when the first update since the consumer's last drain arrives.
+    Coalesced,
+}
+
+struct Shared {
+    strategy: WakeStrategy,
+    updates: Mutex<Vec<u64>>,
+    waker: Mutex<Option<Waker>>,
+    dirty: AtomicBool,
+    closed: AtomicBool,
+    wakes: AtomicUsize,
+}
+
+impl Shared {
+    fn wake(&self) {
+        if let Some(waker) = self.waker.lock().unwrap().as_ref() {
+            self.wakes.fetch_add(1, Ordering::Relaxed);
+            waker.wake_by_ref();
+        }
+    }
+}
+
+/// Producing half: publishes updates.
+pub struct Notifier {
+    shared: Arc<Shared>,
+}
+
+/// Consuming half: receives the updates in batches.
+pub struct Updates {
+    shared: Arc<Shared>,
+    polls: usize,
+    batches: usize,
+    received: usize,
+}
+
+/// Creates a notifier and its consumer.
+pub fn channel(strategy: WakeStrategy) -> (Notifier, Updates) {
+    let shared = Arc::new(Shared {
+        strategy,
+        updates: Mutex::new(Vec::new()),
+        waker: Mutex::new(None),
+        dirty: AtomicBool::new(false),
+        closed: AtomicBool::new(false),
+        wakes: AtomicUsize::new(0),
+    });
+    (
+        Notifier {
+            shared: Arc::clone(&shared),
+        },
+        Updates {
+            shared,
+            polls: 0,
+            batches: 0,
+            received: 0,
+        },
+    )
+}
+
+impl Notifier {
+    /// Publishes an update and wakes the consumer according to the strategy.
+    pub fn push(&self, update: u64) {
+        self.shared.updates.lock().unwrap().push(update);
+        match self.shared.strategy {
+            WakeStrategy::EveryUpdate => self.shared.wake(),
+            WakeStrategy::Coalesced => {
+                if !self.shared.dirty.swap(true, Ordering::AcqRel) {
+                    self.shared.wake();
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Notifier {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.wake();
+    }
+}
+
+/// Wake and batch counts of a consumer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WakeStats {
+    pub strategy: WakeStrategy,
+    /// Updates received.
+    pub updates: usize,
+    /// Times the consumer was polled.
+    pub polls: usize,
+    /// Non-empty batches received, i.e. polls that found work.
+    pub batches: usize,
+    /// Times the producer woke the consumer.
+    pub wakes: usize,
+}
+
+impl Updates {
+    fn poll_batch(&mut self, cx: &mut Context<'_>) -> Poll<Option<Vec<u64>>> {
+        self.polls += 1;
+        // Register before checking, to not miss an update pushed in between.
+        *self.shared.waker.lock().unwrap() = Some(cx.waker().clone());
+        // Clear before draining: a later update marks us dirty again.
+        self.shared.dirty.store(false, Ordering::Release);
+        // Read before draining: an update pushed after the drain is seen next
+        // time, since `closed` is only set after the last update.
+        let closed = self.shared.closed.load(Ordering::Acquire);
+
+        let batch = mem::take(&mut *self.shared.updates.lock().unwrap());
+        if !batch.is_empty() {
+            self.batches += 1;
+            self.received += batch.len();
+            Poll::Ready(Some(batch))
+        } else if closed {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Waits for the updates published since the last batch, or `None` once
+    /// the notifier is dropped and everything was received.
+    pub async fn next_batch(&mut self) -> Option<Vec<u64>> {
+        poll_fn(|cx| self.poll_batch(cx)).await
+    }
+
+    /// Counts so far.
+    pub fn stats(&self) -> WakeStats {
+        WakeStats {
+            strategy: self.shared.strategy,
+            updates: self.received,
+            polls: self.polls,
+            batches: self.batches,
+            wakes: self.shared.wakes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Publishes `count` updates from an OS thread as fast as possible, and
+/// consumes them on the current task.
+pub async fn run_noisy_producer(strategy: WakeStrategy, count: u64) -> WakeStats {
+    let (notifier, mut updates) = channel(strategy);
+    // Register the consumer's waker before starting the producer, so that
+    // every update can wake it.
+    poll_fn(|cx| {
+        let _ = updates.poll_batch(cx);
+        Poll::Ready(())
+    })
+    .await;
+    let producer = thread::spawn(move || {
+        for update in 0..count {
+            notifier.push(update);
+        }
+    });
+
+    while updates.next_batch().await.is_some() {}
+    producer.join().expect("producer panicked");
+    updates.stats()
+}
+
+/// Example: the same noisy producer with and without coalescing
+#[instrument]
+pub async fn coalescing_example() -> [WakeStats; 2] {
+    let naive = run_noisy_producer(WakeStrategy::EveryUpdate, 10_000).await;
+    let coalesced = run_noisy_producer(WakeStrategy::Coalesced, 10_000).await;
+    for stats in [naive, coalesced] {
+        info!(
+            strategy = ?stats.strategy,
+            updates = stats.updates,
+            batches = stats.batches,
+            wakes = stats.wakes,
+            "consumer finished"
+        );
+    }
+    [naive, coalesced]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::task::Wake;
+
+    #[derive(Default)]
+    struct CountingWaker {
+        wakes: AtomicUsize,
+    }
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.wakes.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Polls once, pushes 100 updates, polls again; returns the wakes seen.
+    fn wakes_for_burst(strategy: WakeStrategy) -> usize {
+        let (notifier, mut updates) = channel(strategy);
+        let counter = Arc::new(CountingWaker::default());
+        let waker = Waker::from(Arc::clone(&counter));
+        let mut cx = Context::from_waker(&waker);
+
+        assert_eq!(updates.poll_batch(&mut cx), Poll::Pending);
+        for update in 0..100 {
+            notifier.push(update);
+        }
+        let batch = updates.poll_batch(&mut cx);
+        assert_eq!(batch, Poll::Ready(Some((0..100).collect())));
+        counter.wakes.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn test_every_update_wakes_every_time() {
+        assert_eq!(wakes_for_burst(WakeStrategy::EveryUpdate), 100);
+    }
+
+    #[test]
+    fn test_coalesced_wakes_once_per_burst() {
+        assert_eq!(wakes_for_burst(WakeStrategy::Coalesced), 1);
+    }
+
+    #[test]
+    fn test_coalesced_wakes_again_after_drain() {
+        let (notifier, mut updates) = channel(WakeStrategy::Coalesced);
+        let counter = Arc::new(CountingWaker::default());
+        let waker = Waker::from(Arc::clone(&counter));
+        let mut cx = Context::from_waker(&waker);
+
+        assert_eq!(updates.poll_batch(&mut cx), Poll::Pending);
+        notifier.push(1);
+        assert_eq!(updates.poll_batch(&mut cx), Poll::Ready(Some(vec![1])));
+        notifier.push(2);
+        assert_eq!(counter.wakes.load(Ordering::SeqCst), 2);
+        assert_eq!(updates.poll_batch(&mut cx), Poll::Ready(Some(vec![2])));
+    }
+
+    #[tokio::test]
+    async fn test_noisy_producer() {
+        let naive = run_noisy_producer(WakeStrategy::EveryUpdate, 10_000).await;
+        let coalesced = run_noisy_producer(WakeStrategy::Coalesced, 10_000).await;
+
+        assert_eq!(naive.updates, 10_000);
+        assert_eq!(coalesced.updates, 10_000);
+        // One wake per update, plus the final close.
+        assert_eq!(naive.wakes, 10_001);
+        // A coalesced wake needs the dirty flag cleared first, which only a
+        // poll does: at most one wake per poll.
+        assert!(coalesced.wakes <= coalesced.polls);
+        assert!(coalesced.wakes < naive.wakes);
+    }
+}
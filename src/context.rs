@@ -0,0 +1,411 @@
+//! Deadline-aware request context
+//!
+//! A [`RequestContext`] travels with a request through every nested async
+//! call, as Go's `context.Context` does: a request ID for the logs, a
+//! deadline and a cancellation token. Each stage of
+//! [`handle_request`] (validate → process → finalize, the stages of
+//! [`crate::complex_async_function`]):
+//! - checks the remaining budget before starting, and fails fast with
+//!   [`ContextError::DeadlineExceeded`] when its estimated cost no longer
+//!   fits, instead of doing work whose result would arrive too late
+//! - runs under [`RequestContext::run`], which aborts the stage the moment
+//!   the deadline passes or the request is cancelled, whichever comes first
+//!
+//! The deadline is an instant, not a duration: nested calls share the same
+//! end time instead of each starting a fresh timeout. A call can narrow it
+//! with [`RequestContext::with_budget`], never extend it.
+
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tokio::time::{sleep, sleep_until, Instant};
+use tracing::{debug, info, info_span, instrument, Instrument};
+
+use crate::example_context::ExampleContext;
+use crate::request_id::RequestId;
+
+/// Cost of each stage of [`handle_request`].
+pub const STAGE_COST: Duration = Duration::from_millis(30);
+
+/// Why a request stopped early.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContextError {
+    /// The deadline passed, or would pass before `stage` completes.
+    DeadlineExceeded { stage: &'static str },
+    /// The request was cancelled before or during `stage`.
+    Cancelled { stage: &'static str },
+}
+
+impl fmt::Display for ContextError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContextError::DeadlineExceeded { stage } => write!(f, "deadline exceeded in {}", stage),
+            ContextError::Cancelled { stage } => write!(f, "cancelled in {}", stage),
+        }
+    }
+}
+
+impl std::error::Error for ContextError {}
+
+/// Cancels every [`RequestContext`] sharing it. Clones share the token.
+#[derive(Debug, Clone)]
+pub struct CancelToken {
+    cancelled: Arc<watch::Sender<bool>>,
+}
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        CancelToken {
+            cancelled: Arc::new(watch::channel(false).0),
+        }
+    }
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.cancelled.borrow()
+    }
+
+    /// Completes once the token is cancelled.
+    pub async fn cancelled(&self) {
+        let mut cancelled = self.cancelled.subscribe();
+        // The sender lives in `self`: `wait_for` cannot fail.
+        let _ = cancelled.wait_for(|cancelled| *cancelled).await;
+    }
+}
+
+/// Request ID, deadline and cancellation, passed to every nested call.
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    id: RequestId,
+    deadline: Instant,
+    cancel: CancelToken,
+}
+
+impl RequestContext {
+    /// A context expiring `budget` from now.
+    pub fn new(id: RequestId, budget: Duration) -> Self {
+        RequestContext {
+            id,
+            deadline: Instant::now() + budget,
+            cancel: CancelToken::new(),
+        }
+    }
+
+    /// The same context, cancelled through `cancel`.
+    pub fn with_cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// A context for a nested call expiring `budget` from now, or at this
+    /// context's deadline if sooner. Shares the ID and the cancellation.
+    pub fn with_budget(&self, budget: Duration) -> Self {
+        RequestContext {
+            deadline: self.deadline.min(Instant::now() + budget),
+            ..self.clone()
+        }
+    }
+
+    pub fn id(&self) -> &RequestId {
+        &self.id
+    }
+
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Time left before the deadline, zero once it passed.
+    pub fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    pub fn cancel_token(&self) -> &CancelToken {
+        &self.cancel
+    }
+
+    /// Fails if the request was cancelled, or if less than `estimate` is
+    /// left before the deadline.
+    pub fn check(&self, stage: &'static str, estimate: Duration) -> Result<(), ContextError> {
+        if self.cancel.is_cancelled() {
+            return Err(ContextError::Cancelled { stage });
+        }
+        let remaining = self.remaining();
+        if remaining < estimate {
+            debug!(stage, ?remaining, ?estimate, "not enough budget left");
+            return Err(ContextError::DeadlineExceeded { stage });
+        }
+        Ok(())
+    }
+
+    /// Runs `future`, dropping it if the deadline passes or the request is
+    /// cancelled first.
+    pub async fn run<F: Future>(
+        &self,
+        stage: &'static str,
+        future: F,
+    ) -> Result<F::Output, ContextError> {
+        tokio::select! {
+            // Cancellation wins ties; work completing right at the deadline
+            // is kept.
+            biased;
+            _ = self.cancel.cancelled() => Err(ContextError::Cancelled { stage }),
+            output = future => Ok(output),
+            _ = sleep_until(self.deadline) => Err(ContextError::DeadlineExceeded { stage }),
+        }
+    }
+}
+
+/// Error of [`handle_request`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandleError {
+    InvalidId(u32),
+    Context(ContextError),
+}
+
+impl fmt::Display for HandleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandleError::InvalidId(id) => write!(f, "invalid ID {}: cannot be zero", id),
+            HandleError::Context(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for HandleError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HandleError::InvalidId(_) => None,
+            HandleError::Context(error) => Some(error),
+        }
+    }
+}
+
+impl From<ContextError> for HandleError {
+    fn from(error: ContextError) -> Self {
+        HandleError::Context(error)
+    }
+}
+
+/// Checks the budget of `stage`, then runs its simulated work under `ctx`.
+async fn stage(ctx: &RequestContext, name: &'static str) -> Result<(), ContextError> {
+    ctx.check(name, STAGE_COST)?;
+    ctx.run(name, sleep(STAGE_COST)).await
+}
+
+async fn validate(ctx: &RequestContext, id: u32) -> Result<(), HandleError> {
+    stage(ctx, "validate").await?;
+    if id == 0 {
+        return Err(HandleError::InvalidId(id));
+    }
+    Ok(())
+}
+
+async fn process(ctx: &RequestContext, id: u32, data: &str) -> Result<String, HandleError> {
+    stage(ctx, "process").await?;
+    Ok(format!("Processed(id={}, data={})", id, data))
+}
+
+async fn finalize(ctx: &RequestContext, processed: String) -> Result<String, HandleError> {
+    stage(ctx, "finalize").await?;
+    Ok(processed)
+}
+
+/// `complex_async_function` with a context: every stage gets the context
+/// and gives up once the deadline or a cancellation makes its work useless.
+pub async fn handle_request(
+    ctx: &RequestContext,
+    id: u32,
+    data: &str,
+) -> Result<String, HandleError> {
+    let span = info_span!("request", request_id = %ctx.id(), budget = ?ctx.remaining());
+    async {
+        validate(ctx, id).await?;
+        let processed = process(ctx, id, data).await?;
+        finalize(ctx, processed).await
+    }
+    .instrument(span)
+    .await
+}
+
+/// Example: a request within its budget, one over it, and one cancelled
+/// after 40ms of the latency of `ctx`
+#[instrument(skip_all)]
+pub async fn context_example(ctx: &ExampleContext) -> Vec<Result<String, HandleError>> {
+    let mut results = Vec::new();
+
+    let request = RequestContext::new(RequestId::new("ample"), Duration::from_millis(100));
+    results.push(handle_request(&request, 1, "data").await);
+
+    // 50ms: validate runs, then process no longer fits and is skipped.
+    let request = RequestContext::new(RequestId::new("tight"), Duration::from_millis(50));
+    results.push(handle_request(&request, 2, "data").await);
+
+    let request = RequestContext::new(RequestId::new("cancelled"), Duration::from_secs(1));
+    let cancel = request.cancel_token().clone();
+    let (result, ()) = tokio::join!(handle_request(&request, 3, "data"), async {
+        ctx.latency().sleep(Duration::from_millis(40)).await;
+        cancel.cancel();
+    });
+    results.push(result);
+
+    for result in &results {
+        info!(?result, "request finished");
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn context(budget_ms: u64) -> RequestContext {
+        RequestContext::new(RequestId::new("test"), millis(budget_ms))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_success_within_budget() {
+        let ctx = context(100);
+        let start = Instant::now();
+        let result = handle_request(&ctx, 7, "payload").await;
+
+        assert_eq!(result, Ok("Processed(id=7, data=payload)".to_string()));
+        assert_eq!(start.elapsed(), millis(90));
+        assert_eq!(ctx.remaining(), millis(10));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_exact_budget_is_enough() {
+        assert!(handle_request(&context(90), 1, "x").await.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stage_that_no_longer_fits_is_skipped() {
+        let ctx = context(50);
+        let start = Instant::now();
+        let result = handle_request(&ctx, 1, "x").await;
+
+        assert_eq!(
+            result,
+            Err(HandleError::Context(ContextError::DeadlineExceeded {
+                stage: "process"
+            }))
+        );
+        // Failed fast, 20ms before the deadline, without starting process.
+        assert_eq!(start.elapsed(), millis(30));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_expired_deadline_fails_the_first_stage() {
+        let ctx = context(10);
+        sleep(millis(20)).await;
+        assert_eq!(ctx.remaining(), Duration::ZERO);
+        assert_eq!(
+            handle_request(&ctx, 1, "x").await,
+            Err(HandleError::Context(ContextError::DeadlineExceeded {
+                stage: "validate"
+            }))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_run_aborts_at_the_deadline() {
+        let ctx = context(50);
+        let start = Instant::now();
+        let result = ctx.run("slow", sleep(millis(200))).await;
+
+        assert_eq!(
+            result,
+            Err(ContextError::DeadlineExceeded { stage: "slow" })
+        );
+        assert_eq!(start.elapsed(), millis(50));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancellation_aborts_the_running_stage() {
+        let ctx = context(1_000);
+        let cancel = ctx.cancel_token().clone();
+        let start = Instant::now();
+        let (result, ()) = tokio::join!(handle_request(&ctx, 1, "x"), async {
+            sleep(millis(45)).await;
+            cancel.cancel();
+        });
+
+        assert_eq!(
+            result,
+            Err(HandleError::Context(ContextError::Cancelled {
+                stage: "process"
+            }))
+        );
+        assert_eq!(start.elapsed(), millis(45));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancelled_before_start() {
+        let cancel = CancelToken::new();
+        let ctx = context(1_000).with_cancel(cancel.clone());
+        cancel.cancel();
+        assert_eq!(
+            ctx.check("validate", Duration::ZERO),
+            Err(ContextError::Cancelled { stage: "validate" })
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_with_budget_narrows_but_never_extends() {
+        let ctx = context(100);
+        assert_eq!(ctx.with_budget(millis(40)).remaining(), millis(40));
+        assert_eq!(ctx.with_budget(millis(500)).remaining(), millis(100));
+
+        let nested = ctx.with_budget(millis(40));
+        assert_eq!(nested.id(), ctx.id());
+        ctx.cancel_token().cancel();
+        assert!(
+            nested.cancel_token().is_cancelled(),
+            "cancellation is shared"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_invalid_id() {
+        let result = handle_request(&context(100), 0, "x").await;
+        assert_eq!(result, Err(HandleError::InvalidId(0)));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "invalid ID 0: cannot be zero"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_context_example() {
+        let results = context_example(&ExampleContext::captured()).await;
+        assert!(results[0].is_ok());
+        assert_eq!(
+            results[1],
+            Err(HandleError::Context(ContextError::DeadlineExceeded {
+                stage: "process"
+            }))
+        );
+        assert_eq!(
+            results[2],
+            Err(HandleError::Context(ContextError::Cancelled {
+                stage: "process"
+            }))
+        );
+    }
+}
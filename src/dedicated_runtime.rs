@@ -0,0 +1,299 @@
+//! Dedicated runtime for latency-sensitive work
+//!
+//! A task that blocks its worker thread (CPU-bound work without await points,
+//! a synchronous call) delays every other task scheduled on that thread,
+//! including timers. Latency-sensitive work can be isolated from such a noisy
+//! bulk workload by giving it its own single-threaded runtime, running on its
+//! own OS thread:
+//! - the dedicated runtime only runs the work spawned on it through its
+//!   `Handle`
+//! - the two runtimes communicate through channels, which work across
+//!   runtimes
+//! - dropping the [`DedicatedRuntime`] stops its thread
+//!
+//! Pinning that thread to a CPU core would further isolate it from the OS
+//! scheduler, but needs a platform-specific crate (e.g. `core_affinity`), so
+//! this example stops at the thread.
+//!
+//! Like `runtimes`, everything here is synchronous: call it from
+//! `spawn_blocking` when inside an async context.
+
+use std::future::Future;
+use std::io;
+use std::thread;
+use std::time::Duration;
+use tokio::runtime::{Builder, Handle, Runtime};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep_until, Instant};
+use tracing::{debug, info, instrument};
+
+use crate::example_context::ExampleContext;
+
+/// Name of the OS thread running a [`DedicatedRuntime`].
+pub const DEDICATED_THREAD_NAME: &str = "dedicated-runtime";
+
+/// A current-thread runtime running on its own OS thread.
+pub struct DedicatedRuntime {
+    handle: Handle,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl DedicatedRuntime {
+    /// Starts the runtime thread and waits until the runtime is ready.
+    pub fn start() -> io::Result<Self> {
+        let (handle_tx, handle_rx) = std::sync::mpsc::channel();
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+
+        let thread = thread::Builder::new()
+            .name(DEDICATED_THREAD_NAME.to_string())
+            .spawn(move || {
+                let runtime = match Builder::new_current_thread().enable_all().build() {
+                    Ok(runtime) => runtime,
+                    Err(error) => {
+                        let _ = handle_tx.send(Err(error));
+                        return;
+                    }
+                };
+                let _ = handle_tx.send(Ok(runtime.handle().clone()));
+                // Drive the spawned tasks until asked to stop. An error means
+                // the `DedicatedRuntime` was dropped: stop as well.
+                let _ = runtime.block_on(shutdown_rx);
+                debug!("dedicated runtime stopped");
+            })?;
+
+        let handle = handle_rx
+            .recv()
+            .map_err(|_| io::Error::other("dedicated runtime thread exited"))??;
+        Ok(DedicatedRuntime {
+            handle,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        })
+    }
+
+    /// Spawns a task on the dedicated runtime.
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.handle.spawn(future)
+    }
+}
+
+impl Drop for DedicatedRuntime {
+    /// Stops the runtime and joins its thread. Tasks still running on it are
+    /// cancelled.
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// How late the ticks of a periodic timer fired.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyReport {
+    pub ticks: usize,
+    pub max_lateness: Duration,
+    pub mean_lateness: Duration,
+}
+
+impl LatencyReport {
+    fn from_samples(samples: &[Duration]) -> Self {
+        let total: Duration = samples.iter().sum();
+        LatencyReport {
+            ticks: samples.len(),
+            max_lateness: samples.iter().max().copied().unwrap_or_default(),
+            mean_lateness: total / samples.len().max(1) as u32,
+        }
+    }
+}
+
+/// Latency of the same timer next to the noisy workload, and isolated from it.
+#[derive(Debug, Clone)]
+pub struct IsolationReport {
+    pub shared: LatencyReport,
+    pub dedicated: LatencyReport,
+}
+
+/// Configuration of the isolation measurement.
+#[derive(Debug, Clone, Copy)]
+pub struct IsolationConfig {
+    pub ticks: u32,
+    pub tick_interval: Duration,
+    /// Number of noisy tasks.
+    pub noisy_tasks: usize,
+    /// How long each noisy chunk blocks its thread.
+    pub noisy_chunk: Duration,
+}
+
+/// Latency-sensitive work: a periodic timer sending how late each tick fired.
+async fn ticker(ticks: u32, interval: Duration, lateness: mpsc::UnboundedSender<Duration>) {
+    let start = Instant::now();
+    for tick in 1..=ticks {
+        let deadline = start + interval * tick;
+        sleep_until(deadline).await;
+        if lateness.send(Instant::now() - deadline).is_err() {
+            return;
+        }
+    }
+}
+
+/// Bulk work: blocks its thread for `chunk` at a time until `done` is set.
+async fn noisy_work(chunk: Duration, mut done: oneshot::Receiver<()>) {
+    while done.try_recv().is_err() {
+        thread::sleep(chunk);
+        tokio::task::yield_now().await;
+    }
+}
+
+/// Runs the noisy workload on `main`, and the ticker either on `main` too or
+/// on `dedicated`, collecting the ticks' lateness on `main`.
+fn measure(
+    main: &Runtime,
+    dedicated: Option<&DedicatedRuntime>,
+    config: IsolationConfig,
+) -> LatencyReport {
+    main.block_on(async {
+        let mut stop_noise = Vec::new();
+        for _ in 0..config.noisy_tasks {
+            let (stop, done) = oneshot::channel();
+            stop_noise.push(stop);
+            tokio::spawn(noisy_work(config.noisy_chunk, done));
+        }
+
+        let (lateness_tx, mut lateness_rx) = mpsc::unbounded_channel();
+        let ticker = ticker(config.ticks, config.tick_interval, lateness_tx);
+        match dedicated {
+            Some(dedicated) => drop(dedicated.spawn(ticker)),
+            None => drop(tokio::spawn(ticker)),
+        }
+
+        let mut samples = Vec::new();
+        while let Some(lateness) = lateness_rx.recv().await {
+            samples.push(lateness);
+        }
+        for stop in stop_noise {
+            let _ = stop.send(());
+        }
+        LatencyReport::from_samples(&samples)
+    })
+}
+
+/// Measures the ticker's lateness on a busy current-thread runtime, then on
+/// a dedicated runtime while the same runtime stays busy.
+pub fn compare_isolation(config: IsolationConfig) -> io::Result<IsolationReport> {
+    let shared = measure(
+        &Builder::new_current_thread().enable_all().build()?,
+        None,
+        config,
+    );
+
+    let dedicated_runtime = DedicatedRuntime::start()?;
+    let dedicated = measure(
+        &Builder::new_current_thread().enable_all().build()?,
+        Some(&dedicated_runtime),
+        config,
+    );
+
+    Ok(IsolationReport { shared, dedicated })
+}
+
+/// Example: isolating a periodic timer from a noisy workload
+///
+/// Synchronous; call it from `spawn_blocking` when inside an async context.
+#[instrument(skip_all)]
+pub fn dedicated_runtime_example(ctx: &ExampleContext) -> io::Result<IsolationReport> {
+    let report = compare_isolation(IsolationConfig {
+        ticks: 10,
+        tick_interval: ctx.latency().delay(Duration::from_millis(5)),
+        noisy_tasks: 2,
+        noisy_chunk: ctx.latency().delay(Duration::from_millis(20)),
+    })?;
+    info!(
+        max = ?report.shared.max_lateness,
+        mean = ?report.shared.mean_lateness,
+        "timer sharing the runtime with noisy work"
+    );
+    info!(
+        max = ?report.dedicated.max_lateness,
+        mean = ?report.dedicated.mean_lateness,
+        "timer on a dedicated runtime"
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tasks_run_on_dedicated_thread() {
+        let dedicated = DedicatedRuntime::start().unwrap();
+        let main = Builder::new_current_thread().enable_all().build().unwrap();
+
+        let name = main.block_on(async {
+            dedicated
+                .spawn(async { thread::current().name().map(str::to_string) })
+                .await
+                .unwrap()
+        });
+        assert_eq!(name.as_deref(), Some(DEDICATED_THREAD_NAME));
+    }
+
+    #[test]
+    fn test_channels_connect_the_runtimes() {
+        let dedicated = DedicatedRuntime::start().unwrap();
+        let main = Builder::new_current_thread().enable_all().build().unwrap();
+        let (request_tx, mut request_rx) = mpsc::channel::<(u32, oneshot::Sender<u32>)>(4);
+
+        dedicated.spawn(async move {
+            while let Some((value, reply)) = request_rx.recv().await {
+                let _ = reply.send(value * 2);
+            }
+        });
+
+        let answers = main.block_on(async {
+            let mut answers = Vec::new();
+            for value in 1..=3 {
+                let (reply_tx, reply_rx) = oneshot::channel();
+                request_tx.send((value, reply_tx)).await.unwrap();
+                answers.push(reply_rx.await.unwrap());
+            }
+            answers
+        });
+        assert_eq!(answers, vec![2, 4, 6]);
+    }
+
+    #[test]
+    fn test_drop_stops_the_runtime_thread() {
+        let dedicated = DedicatedRuntime::start().unwrap();
+        // A task that never finishes must not keep the thread alive.
+        dedicated.spawn(std::future::pending::<()>());
+        drop(dedicated);
+    }
+
+    #[test]
+    fn test_dedicated_runtime_isolates_timer() {
+        let report = compare_isolation(IsolationConfig {
+            ticks: 8,
+            tick_interval: Duration::from_millis(5),
+            noisy_tasks: 2,
+            noisy_chunk: Duration::from_millis(30),
+        })
+        .unwrap();
+
+        assert_eq!(report.shared.ticks, 8);
+        assert_eq!(report.dedicated.ticks, 8);
+        // Next to the noise, a tick waits for at least one blocking chunk.
+        assert!(report.shared.max_lateness >= Duration::from_millis(20));
+        assert!(report.dedicated.max_lateness < report.shared.max_lateness);
+        assert!(report.dedicated.mean_lateness < Duration::from_millis(15));
+    }
+}
@@ -0,0 +1,510 @@
+//! Surfacing non-fatal errors from background tasks
+//!
+//! A spawned task has nobody to return a recoverable error to: it logs it
+//! and carries on, and nobody notices the dependency failing every call.
+//! Instead, background tasks hold an [`ErrorReporter`] (a cloneable `mpsc`
+//! sender) and report errors to one [`ErrorHandler`] task, which:
+//! - logs every error and counts them per task
+//! - runs an action when a [`Threshold`] is crossed (N errors within a
+//!   window), e.g. triggering the ordered shutdown of a
+//!   [`ShutdownCoordinator`] through [`ErrorHandler::shutdown_on`]
+//! - stops once every reporter is dropped, returning the [`ErrorStats`]
+//!
+//! Reporting never blocks: when the channel is full the error is dropped and
+//! counted, so a burst of errors cannot stall the tasks reporting them.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{mpsc, watch};
+use tokio::time::Instant;
+use tracing::{info, instrument, warn};
+
+use crate::example_context::{ExampleContext, LatencySimulator};
+use crate::shutdown::{ShutdownCoordinator, ShutdownError, ShutdownTrigger};
+
+/// Boxed error, as reported by background tasks.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// An error reported by a background task.
+#[derive(Debug)]
+pub struct ReportedError {
+    /// Name of the reporting task.
+    pub task: &'static str,
+    pub error: BoxError,
+}
+
+/// Handle background tasks report errors through. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct ErrorReporter {
+    errors: mpsc::Sender<ReportedError>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl ErrorReporter {
+    /// Reports an error without waiting. Returns `false` if the error was
+    /// dropped because the channel is full or the handler stopped.
+    pub fn report(&self, task: &'static str, error: impl Into<BoxError>) -> bool {
+        let reported = ReportedError {
+            task,
+            error: error.into(),
+        };
+        if self.errors.try_send(reported).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+}
+
+/// Runs an action when `errors` errors are reported within `window`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Threshold {
+    pub errors: usize,
+    pub window: Duration,
+    /// Counts only the errors of this task, if set.
+    pub task: Option<&'static str>,
+}
+
+impl Threshold {
+    pub fn new(errors: usize, window: Duration) -> Self {
+        Threshold {
+            errors,
+            window,
+            task: None,
+        }
+    }
+
+    /// Counts only the errors reported by `task`.
+    pub fn for_task(self, task: &'static str) -> Self {
+        Threshold {
+            task: Some(task),
+            ..self
+        }
+    }
+}
+
+/// A crossed threshold, passed to its action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trip {
+    pub threshold: Threshold,
+    /// Task whose error crossed the threshold.
+    pub task: &'static str,
+}
+
+type Action = Box<dyn FnMut(&Trip) + Send>;
+
+/// A threshold with its action and the errors counting towards it.
+struct ArmedThreshold {
+    threshold: Threshold,
+    /// Times of the matching errors within the window.
+    recent: VecDeque<Instant>,
+    action: Action,
+}
+
+impl ArmedThreshold {
+    /// Records an error, returning whether the threshold is crossed. The
+    /// window starts over after a trip, so a burst trips it once.
+    fn record(&mut self, task: &'static str, now: Instant) -> bool {
+        if self.threshold.task.is_some_and(|only| only != task) {
+            return false;
+        }
+        while self
+            .recent
+            .front()
+            .is_some_and(|&at| now.duration_since(at) >= self.threshold.window)
+        {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(now);
+        if self.recent.len() < self.threshold.errors {
+            return false;
+        }
+        self.recent.clear();
+        true
+    }
+}
+
+/// What the handler saw, returned when it stops.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorStats {
+    pub total: usize,
+    pub by_task: BTreeMap<&'static str, usize>,
+    /// Errors dropped because the channel was full.
+    pub dropped: usize,
+    pub trips: usize,
+}
+
+/// Central task receiving every reported error.
+pub struct ErrorHandler {
+    errors: mpsc::Receiver<ReportedError>,
+    dropped: Arc<AtomicUsize>,
+    thresholds: Vec<ArmedThreshold>,
+}
+
+/// Creates a reporter and the handler receiving its errors, buffering up to
+/// `capacity` errors.
+pub fn error_channel(capacity: usize) -> (ErrorReporter, ErrorHandler) {
+    let (errors, receiver) = mpsc::channel(capacity);
+    let dropped = Arc::new(AtomicUsize::new(0));
+    let reporter = ErrorReporter {
+        errors,
+        dropped: Arc::clone(&dropped),
+    };
+    let handler = ErrorHandler {
+        errors: receiver,
+        dropped,
+        thresholds: Vec::new(),
+    };
+    (reporter, handler)
+}
+
+impl ErrorHandler {
+    /// Runs `action` every time `threshold` is crossed.
+    pub fn on_threshold(
+        mut self,
+        threshold: Threshold,
+        action: impl FnMut(&Trip) + Send + 'static,
+    ) -> Self {
+        self.thresholds.push(ArmedThreshold {
+            threshold,
+            recent: VecDeque::new(),
+            action: Box::new(action),
+        });
+        self
+    }
+
+    /// Requests a shutdown through `trigger` when `threshold` is crossed.
+    pub fn shutdown_on(self, threshold: Threshold, trigger: ShutdownTrigger) -> Self {
+        self.on_threshold(threshold, move |trip| {
+            warn!(task = trip.task, "error threshold crossed, shutting down");
+            trigger.request();
+        })
+    }
+
+    /// Handles errors until every reporter is dropped.
+    pub async fn run(mut self) -> ErrorStats {
+        let mut stats = ErrorStats::default();
+        while let Some(ReportedError { task, error }) = self.errors.recv().await {
+            stats.total += 1;
+            let count = stats.by_task.entry(task).or_default();
+            *count += 1;
+            warn!(task, count = *count, %error, "background task error");
+
+            let now = Instant::now();
+            for armed in &mut self.thresholds {
+                if armed.record(task, now) {
+                    stats.trips += 1;
+                    (armed.action)(&Trip {
+                        threshold: armed.threshold,
+                        task,
+                    });
+                }
+            }
+        }
+        stats.dropped = self.dropped.load(Ordering::Relaxed);
+        stats
+    }
+}
+
+impl fmt::Debug for ErrorHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ErrorHandler")
+            .field("thresholds", &self.thresholds.len())
+            .finish()
+    }
+}
+
+/// A background task ticking every 10ms of `latency` and failing every
+/// `fail_every`-th tick, until shutdown.
+async fn worker(
+    task: &'static str,
+    fail_every: u32,
+    reporter: ErrorReporter,
+    mut shutdown: watch::Receiver<bool>,
+    latency: LatencySimulator,
+) -> u32 {
+    let mut tick = 0;
+    loop {
+        tokio::select! {
+            _ = shutdown.wait_for(|&stop| stop) => return tick,
+            _ = latency.sleep(Duration::from_millis(10)) => {}
+        }
+        tick += 1;
+        if tick % fail_every == 0 {
+            reporter.report(task, format!("tick {} failed", tick));
+        }
+    }
+}
+
+/// Example: background workers reporting errors until one fails too often,
+/// which shuts them down in order
+///
+/// Cancelling `ctx` shuts them down the same way.
+#[instrument(skip_all)]
+pub async fn error_reporter_example(ctx: &ExampleContext) -> Result<ErrorStats, ShutdownError> {
+    let mut coordinator = ShutdownCoordinator::new();
+    let (cancel, trigger) = (ctx.cancel_token().clone(), coordinator.trigger());
+    let cancelled = tokio::spawn(async move {
+        cancel.cancelled().await;
+        trigger.request();
+    });
+    let (reporter, handler) = error_channel(16);
+    // "ingest" fails every other tick and trips the threshold after 60ms;
+    // "index" fails every 5th tick, not often enough to trip it alone.
+    let handler = handler.shutdown_on(
+        Threshold::new(3, Duration::from_millis(50)).for_task("ingest"),
+        coordinator.trigger(),
+    );
+    let handler = tokio::spawn(handler.run());
+
+    let ingest = tokio::spawn(worker(
+        "ingest",
+        2,
+        reporter.clone(),
+        coordinator.subscribe(),
+        ctx.latency().clone(),
+    ));
+    let index = tokio::spawn(worker(
+        "index",
+        5,
+        reporter,
+        coordinator.subscribe(),
+        ctx.latency().clone(),
+    ));
+    coordinator
+        .phase(
+            "stop ingest",
+            &[],
+            Duration::from_millis(100),
+            || async move {
+                let ticks = ingest.await.unwrap_or_default();
+                info!(ticks, "ingest stopped");
+            },
+        )
+        .phase(
+            "stop index",
+            &["stop ingest"],
+            Duration::from_millis(100),
+            || async move {
+                let ticks = index.await.unwrap_or_default();
+                info!(ticks, "index stopped");
+            },
+        );
+    let report = coordinator.shutdown_when_triggered().await;
+    cancelled.abort();
+    let report = report?;
+
+    let stats = handler.await.unwrap();
+    info!(order = ?report.order(), ?stats, "workers shut down");
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio::time::sleep;
+
+    /// Runs `handler` on the reports made by `scenario`, collecting trips.
+    async fn run_with_trips<F, Fut>(
+        threshold: Threshold,
+        scenario: F,
+    ) -> (ErrorStats, Vec<(Trip, Duration)>)
+    where
+        F: FnOnce(ErrorReporter) -> Fut,
+        Fut: std::future::Future<Output = ()>,
+    {
+        let start = Instant::now();
+        let trips = Arc::new(Mutex::new(Vec::new()));
+        let (reporter, handler) = error_channel(16);
+        let recorded = Arc::clone(&trips);
+        let handler = tokio::spawn(
+            handler
+                .on_threshold(threshold, move |trip| {
+                    recorded.lock().unwrap().push((*trip, start.elapsed()))
+                })
+                .run(),
+        );
+        scenario(reporter).await;
+        let stats = handler.await.unwrap();
+        let trips = trips.lock().unwrap().clone();
+        (stats, trips)
+    }
+
+    /// Reports an error from `task` after each delay, in ms.
+    async fn report_after(reporter: &ErrorReporter, task: &'static str, delays: &[u64]) {
+        for &delay in delays {
+            sleep(Duration::from_millis(delay)).await;
+            assert!(reporter.report(task, "boom"));
+            // Lets the handler see the error at this instant.
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_counts_per_task() {
+        let (reporter, handler) = error_channel(16);
+        reporter.report("a", "first");
+        reporter.report("b", std::io::Error::other("second"));
+        reporter.report("a", String::from("third"));
+        drop(reporter);
+
+        let stats = handler.run().await;
+        assert_eq!(stats.total, 3);
+        assert_eq!(stats.by_task, BTreeMap::from([("a", 2), ("b", 1)]));
+        assert_eq!(stats.trips, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_trips_when_errors_within_window() {
+        let threshold = Threshold::new(3, Duration::from_millis(100));
+        let (stats, trips) = run_with_trips(threshold, |reporter| async move {
+            report_after(&reporter, "a", &[0, 40, 40]).await;
+        })
+        .await;
+
+        assert_eq!(stats.trips, 1);
+        assert_eq!(trips.len(), 1);
+        assert_eq!(
+            trips[0].0,
+            Trip {
+                threshold,
+                task: "a"
+            }
+        );
+        assert_eq!(trips[0].1, Duration::from_millis(80));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_spread_out_errors_do_not_trip() {
+        let threshold = Threshold::new(3, Duration::from_millis(100));
+        let (stats, trips) = run_with_trips(threshold, |reporter| async move {
+            // Never 3 errors within 100ms: the oldest leaves the window.
+            report_after(&reporter, "a", &[0, 60, 60, 60, 60]).await;
+        })
+        .await;
+
+        assert_eq!(stats.total, 5);
+        assert!(trips.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_window_starts_over_after_a_trip() {
+        let threshold = Threshold::new(2, Duration::from_millis(100));
+        let (stats, trips) = run_with_trips(threshold, |reporter| async move {
+            report_after(&reporter, "a", &[0, 10, 10, 10, 10]).await;
+        })
+        .await;
+
+        // Errors 1+2 and 3+4 trip it, error 5 starts a new window.
+        assert_eq!(stats.trips, 2);
+        let at: Vec<Duration> = trips.iter().map(|(_, at)| *at).collect();
+        assert_eq!(at, [10, 30].map(Duration::from_millis).to_vec());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_threshold_for_one_task() {
+        let threshold = Threshold::new(2, Duration::from_millis(100)).for_task("b");
+        let (stats, trips) = run_with_trips(threshold, |reporter| async move {
+            report_after(&reporter, "a", &[0, 0, 0]).await;
+            report_after(&reporter, "b", &[0]).await;
+            report_after(&reporter, "a", &[0]).await;
+            report_after(&reporter, "b", &[0]).await;
+        })
+        .await;
+
+        assert_eq!(stats.total, 6);
+        assert_eq!(trips.len(), 1);
+        assert_eq!(trips[0].0.task, "b");
+    }
+
+    #[tokio::test]
+    async fn test_full_channel_drops_without_blocking() {
+        let (reporter, handler) = error_channel(2);
+        assert!(reporter.report("a", "1"));
+        assert!(reporter.report("a", "2"));
+        assert!(!reporter.report("a", "3"));
+        drop(reporter);
+
+        let stats = handler.run().await;
+        assert_eq!(stats.total, 2);
+        assert_eq!(stats.dropped, 1);
+    }
+
+    #[tokio::test]
+    async fn test_report_after_handler_stopped() {
+        let (reporter, handler) = error_channel(2);
+        drop(handler);
+        assert!(!reporter.report("a", "lost"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_on_threshold_runs_the_phases() {
+        let phases = Arc::new(Mutex::new(Vec::new()));
+        let mut coordinator = ShutdownCoordinator::new();
+        let signal = coordinator.subscribe();
+        for (name, after) in [("drain", &[][..]), ("close", &["drain"][..])] {
+            let phases = Arc::clone(&phases);
+            coordinator.phase(name, after, Duration::from_millis(10), move || async move {
+                phases.lock().unwrap().push(name);
+            });
+        }
+        let (reporter, handler) = error_channel(16);
+        let handler = tokio::spawn(
+            handler
+                .shutdown_on(
+                    Threshold::new(2, Duration::from_millis(50)),
+                    coordinator.trigger(),
+                )
+                .run(),
+        );
+        let shutdown = tokio::spawn(coordinator.shutdown_when_triggered());
+
+        report_after(&reporter, "a", &[0]).await;
+        assert!(!*signal.borrow());
+        assert!(phases.lock().unwrap().is_empty());
+        report_after(&reporter, "a", &[10]).await;
+        let report = shutdown.await.unwrap().unwrap();
+
+        assert_eq!(report.order(), vec!["drain", "close"]);
+        assert_eq!(*phases.lock().unwrap(), vec!["drain", "close"]);
+        assert!(*signal.borrow());
+        drop(reporter);
+        assert_eq!(handler.await.unwrap().trips, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_error_reporter_example() {
+        let start = Instant::now();
+        let stats = error_reporter_example(&ExampleContext::captured())
+            .await
+            .unwrap();
+
+        // "ingest" fails at 20, 40 and 60ms, tripping the shutdown.
+        assert_eq!(start.elapsed(), Duration::from_millis(60));
+        assert_eq!(stats.trips, 1);
+        assert_eq!(stats.by_task.get("ingest"), Some(&3));
+        assert_eq!(stats.by_task.get("index"), Some(&1));
+        assert_eq!(stats.dropped, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancelled_error_reporter_example_shuts_down() {
+        let ctx = ExampleContext::captured();
+        let cancel = ctx.cancel_token().clone();
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(45)).await;
+            cancel.cancel();
+        });
+
+        let start = Instant::now();
+        let stats = error_reporter_example(&ctx).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_millis(45));
+        assert_eq!(stats.trips, 0);
+        assert_eq!(stats.by_task.get("ingest"), Some(&2));
+    }
+}
@@ -0,0 +1,248 @@
+//! Error handling patterns in async code
+//!
+//! `complex_async_function` returns `Box<dyn Error>`: convenient, but callers
+//! can only print the error. This module uses a custom error enum instead
+//! (what `thiserror` would generate, written by hand), so callers can match
+//! on what went wrong, and shows three ways errors flow through async code:
+//! - `?` propagation across await points, exactly like in sync code
+//! - `tokio::try_join!`: run futures concurrently and fail fast, dropping
+//!   (cancelling) the others on the first error
+//! - aggregation: run every task to completion and collect all the failures,
+//!   when one failure must not hide the others (e.g. validating a batch)
+
+use std::fmt;
+use std::num::ParseIntError;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+use tracing::{info, instrument, warn};
+
+use crate::example_context::{ExampleContext, LatencySimulator};
+
+/// How long [`load_score`] waits for the backend.
+pub const FETCH_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Id the simulated backend does not know.
+pub const MISSING_ID: u32 = 0;
+/// Id for which the simulated backend returns invalid data.
+pub const INVALID_ID: u32 = 7;
+/// Id for which the simulated backend never answers in time.
+pub const SLOW_ID: u32 = 13;
+
+/// Error loading a score.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadError {
+    NotFound(u32),
+    TimedOut(u32),
+    InvalidData { id: u32, source: ParseIntError },
+}
+
+impl LoadError {
+    /// Id of the item that failed to load.
+    pub fn id(&self) -> u32 {
+        match self {
+            LoadError::NotFound(id) | LoadError::TimedOut(id) => *id,
+            LoadError::InvalidData { id, .. } => *id,
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::NotFound(id) => write!(f, "item {} not found", id),
+            LoadError::TimedOut(id) => write!(f, "item {} timed out", id),
+            LoadError::InvalidData { id, .. } => write!(f, "item {} has invalid data", id),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::InvalidData { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Every failure of a batch, sorted by id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AggregateError {
+    pub errors: Vec<LoadError>,
+}
+
+impl fmt::Display for AggregateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} item(s) failed to load", self.errors.len())?;
+        for error in &self.errors {
+            write!(f, "; {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AggregateError {}
+
+/// Simulated backend: answers item `id` after `id` milliseconds, as
+/// simulated by `latency`.
+async fn fetch_raw(id: u32, latency: &LatencySimulator) -> Result<String, LoadError> {
+    match id {
+        MISSING_ID => {
+            latency.sleep(Duration::from_millis(5)).await;
+            Err(LoadError::NotFound(id))
+        }
+        INVALID_ID => {
+            latency.sleep(Duration::from_millis(10)).await;
+            Ok("seven".to_string())
+        }
+        SLOW_ID => {
+            latency.sleep(Duration::from_secs(10)).await;
+            Ok("130".to_string())
+        }
+        _ => {
+            latency.sleep(Duration::from_millis(u64::from(id))).await;
+            Ok((id * 10).to_string())
+        }
+    }
+}
+
+/// Loads the score of item `id`, propagating errors with `?` across awaits.
+pub async fn load_score(id: u32) -> Result<u32, LoadError> {
+    load_score_with(id, &LatencySimulator::default()).await
+}
+
+/// Same as [`load_score`], with the backend latencies simulated by
+/// `latency`.
+pub async fn load_score_with(id: u32, latency: &LatencySimulator) -> Result<u32, LoadError> {
+    // Two await points, two ways to fail: the timeout error is converted with
+    // `map_err`, the backend error is already a `LoadError`.
+    let raw = timeout(FETCH_TIMEOUT, fetch_raw(id, latency))
+        .await
+        .map_err(|_| LoadError::TimedOut(id))??;
+    raw.parse()
+        .map_err(|source| LoadError::InvalidData { id, source })
+}
+
+/// Loads two scores concurrently, failing as soon as either fails.
+pub async fn load_pair(first: u32, second: u32) -> Result<(u32, u32), LoadError> {
+    load_pair_with(first, second, &LatencySimulator::default()).await
+}
+
+/// Same as [`load_pair`], with the backend latencies simulated by `latency`.
+pub async fn load_pair_with(
+    first: u32,
+    second: u32,
+    latency: &LatencySimulator,
+) -> Result<(u32, u32), LoadError> {
+    tokio::try_join!(
+        load_score_with(first, latency),
+        load_score_with(second, latency)
+    )
+}
+
+/// Loads every score in its own task and collects all the failures instead of
+/// stopping at the first one.
+pub async fn load_all(ids: &[u32]) -> Result<Vec<u32>, AggregateError> {
+    load_all_with(ids, &LatencySimulator::default()).await
+}
+
+/// Same as [`load_all`], with the backend latencies simulated by `latency`.
+pub async fn load_all_with(
+    ids: &[u32],
+    latency: &LatencySimulator,
+) -> Result<Vec<u32>, AggregateError> {
+    let mut tasks = JoinSet::new();
+    for (index, &id) in ids.iter().enumerate() {
+        let latency = latency.clone();
+        tasks.spawn(async move { (index, load_score_with(id, &latency).await) });
+    }
+
+    let mut scores = vec![0; ids.len()];
+    let mut errors = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        let (index, result) = joined.expect("load task panicked");
+        match result {
+            Ok(score) => scores[index] = score,
+            Err(error) => errors.push(error),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(scores)
+    } else {
+        errors.sort_by_key(LoadError::id);
+        Err(AggregateError { errors })
+    }
+}
+
+/// Example: fail-fast and aggregated error handling
+#[instrument(skip_all)]
+pub async fn error_handling_example(ctx: &ExampleContext) {
+    let latency = ctx.latency();
+    match load_pair_with(3, 4, latency).await {
+        Ok((a, b)) => info!(a, b, "try_join! succeeded"),
+        Err(error) => warn!(%error, "try_join! failed"),
+    }
+    match load_pair_with(30, MISSING_ID, latency).await {
+        Ok((a, b)) => info!(a, b, "try_join! succeeded"),
+        Err(error) => warn!(%error, "try_join! failed fast"),
+    }
+    match load_all_with(&[1, MISSING_ID, INVALID_ID, 2, SLOW_ID], latency).await {
+        Ok(scores) => info!(?scores, "all loaded"),
+        Err(error) => warn!(%error, "aggregated failures"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+    use tokio::time::Instant;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_question_mark_across_awaits() {
+        assert_eq!(load_score(4).await, Ok(40));
+        assert_eq!(load_score(MISSING_ID).await, Err(LoadError::NotFound(0)));
+        assert_eq!(load_score(SLOW_ID).await, Err(LoadError::TimedOut(13)));
+
+        let error = load_score(INVALID_ID).await.unwrap_err();
+        assert!(matches!(error, LoadError::InvalidData { id: 7, .. }));
+        assert!(error.source().is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_try_join_fails_fast() {
+        let start = Instant::now();
+        let result = load_pair(40, MISSING_ID).await;
+        assert_eq!(result, Err(LoadError::NotFound(0)));
+        // Returned after the 5ms failure, without waiting 40ms for the other.
+        assert_eq!(start.elapsed(), Duration::from_millis(5));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_try_join_success_is_concurrent() {
+        let start = Instant::now();
+        assert_eq!(load_pair(20, 30).await, Ok((200, 300)));
+        assert_eq!(start.elapsed(), Duration::from_millis(30));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_load_all_aggregates_every_failure() {
+        let start = Instant::now();
+        let error = load_all(&[SLOW_ID, 1, INVALID_ID, MISSING_ID])
+            .await
+            .unwrap_err();
+
+        let ids: Vec<u32> = error.errors.iter().map(LoadError::id).collect();
+        assert_eq!(ids, vec![MISSING_ID, INVALID_ID, SLOW_ID]);
+        // Waited for the slowest task (the timeout) instead of the first error.
+        assert_eq!(start.elapsed(), FETCH_TIMEOUT);
+        assert!(error.to_string().starts_with("3 item(s) failed to load"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_load_all_keeps_input_order() {
+        assert_eq!(load_all(&[3, 1, 2]).await, Ok(vec![30, 10, 20]));
+    }
+}
@@ -0,0 +1,354 @@
+//! The context every registered example runs with
+//!
+//! Examples reach their environment through an [`ExampleContext`] instead of
+//! globals, so the runner decides what that environment is:
+//! - a [`Clock`], on tokio's time: virtual when the runtime's time is paused
+//! - an [`Output`] sink, printing lines or capturing them
+//! - a [`LatencySimulator`], scaling delays and adding seeded jitter
+//!   (chaos), deterministic for a given seed
+//! - a [`CancelToken`] stopping the run
+//! - [`Metrics`], named counters shared by every clone of the context
+//!
+//! The pieces are independent: a paused-time run capturing its output with
+//! jitter is just a context built that way. Clones share everything, so the
+//! runner keeps a clone to read the output and metrics once the example is
+//! done.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::runtime::sleep;
+
+pub use crate::context::CancelToken;
+
+/// Time as seen by an example, from the start of its run.
+#[derive(Debug, Clone, Copy)]
+pub struct Clock {
+    start: Instant,
+}
+
+impl Clock {
+    /// A clock starting now.
+    pub fn start() -> Self {
+        Clock {
+            start: Instant::now(),
+        }
+    }
+
+    pub fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    /// Time since the clock started.
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// Where an example's output lines go.
+#[derive(Debug, Clone)]
+pub enum Output {
+    /// Printed on standard output, as the binary does.
+    Stdout,
+    /// Kept in memory, for tests and reports.
+    Captured(Arc<Mutex<Vec<String>>>),
+}
+
+impl Output {
+    pub fn captured() -> Self {
+        Output::Captured(Arc::default())
+    }
+
+    pub fn line(&self, line: impl fmt::Display) {
+        match self {
+            Output::Stdout => println!("{}", line),
+            Output::Captured(lines) => lines.lock().unwrap().push(line.to_string()),
+        }
+    }
+
+    /// Lines captured so far; always empty for [`Output::Stdout`].
+    pub fn lines(&self) -> Vec<String> {
+        match self {
+            Output::Stdout => Vec::new(),
+            Output::Captured(lines) => lines.lock().unwrap().clone(),
+        }
+    }
+}
+
+/// Turns a nominal delay into a simulated one: `base * scale`, plus up to
+/// `jitter` drawn from a seeded generator.
+#[derive(Debug, Clone)]
+pub struct LatencySimulator {
+    scale: f64,
+    jitter: Duration,
+    state: Arc<AtomicU64>,
+}
+
+impl Default for LatencySimulator {
+    /// Delays as written, no jitter.
+    fn default() -> Self {
+        LatencySimulator {
+            scale: 1.0,
+            jitter: Duration::ZERO,
+            state: Arc::new(AtomicU64::new(1)),
+        }
+    }
+}
+
+impl LatencySimulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Multiplies every delay.
+    pub fn with_scale(mut self, scale: f64) -> Self {
+        assert!(scale >= 0.0, "negative latency scale");
+        self.scale = scale;
+        self
+    }
+
+    /// Adds up to `jitter` to every delay; the same seed gives the same
+    /// sequence of delays.
+    pub fn with_jitter(mut self, jitter: Duration, seed: u64) -> Self {
+        self.jitter = jitter;
+        // Mixed so that small seeds do not start with tiny values; xorshift
+        // never leaves zero.
+        self.state = Arc::new(AtomicU64::new((seed ^ 0x9E37_79B9_7F4A_7C15).max(1)));
+        self
+    }
+
+    /// The simulated delay for `base`.
+    pub fn delay(&self, base: Duration) -> Duration {
+        let scaled = base.mul_f64(self.scale);
+        if self.jitter.is_zero() {
+            return scaled;
+        }
+        let fraction = (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+        scaled + self.jitter.mul_f64(fraction)
+    }
+
+    /// Sleeps for the simulated delay of `base`, on whichever runtime polls
+    /// the task (see [`crate::runtime`]).
+    pub async fn sleep(&self, base: Duration) {
+        let delay = self.delay(base);
+        if !delay.is_zero() {
+            sleep(delay).await;
+        }
+    }
+
+    fn next(&self) -> u64 {
+        let step = |mut x: u64| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^ (x << 17)
+        };
+        let previous = self
+            .state
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| Some(step(x)))
+            .unwrap();
+        step(previous)
+    }
+}
+
+/// Named counters. Clones share the counters.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    counters: Arc<Mutex<BTreeMap<String, u64>>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn increment(&self, name: &str) {
+        self.add(name, 1);
+    }
+
+    pub fn add(&self, name: &str, value: u64) {
+        *self
+            .counters
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default() += value;
+    }
+
+    /// Value of a counter, 0 if never touched.
+    pub fn get(&self, name: &str) -> u64 {
+        self.counters
+            .lock()
+            .unwrap()
+            .get(name)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, u64> {
+        self.counters.lock().unwrap().clone()
+    }
+}
+
+/// Everything an example gets from its runner. Clones share the output,
+/// the jitter sequence, the cancellation and the metrics.
+#[derive(Debug, Clone)]
+pub struct ExampleContext {
+    clock: Clock,
+    output: Output,
+    latency: LatencySimulator,
+    cancel: CancelToken,
+    metrics: Metrics,
+}
+
+impl Default for ExampleContext {
+    fn default() -> Self {
+        ExampleContext::new(Output::Stdout)
+    }
+}
+
+impl ExampleContext {
+    /// A context writing to `output`, with a clock starting now, nominal
+    /// latencies, a fresh cancellation token and empty metrics.
+    pub fn new(output: Output) -> Self {
+        ExampleContext {
+            clock: Clock::start(),
+            output,
+            latency: LatencySimulator::default(),
+            cancel: CancelToken::new(),
+            metrics: Metrics::new(),
+        }
+    }
+
+    /// A context capturing its output.
+    pub fn captured() -> Self {
+        ExampleContext::new(Output::captured())
+    }
+
+    pub fn with_latency(mut self, latency: LatencySimulator) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    pub fn with_cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// The same context with a clock restarted now.
+    pub fn restarted(&self) -> Self {
+        ExampleContext {
+            clock: Clock::start(),
+            ..self.clone()
+        }
+    }
+
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    pub fn output(&self) -> &Output {
+        &self.output
+    }
+
+    pub fn latency(&self) -> &LatencySimulator {
+        &self.latency
+    }
+
+    pub fn cancel_token(&self) -> &CancelToken {
+        &self.cancel
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Writes the result of an example to the output and counts it.
+    pub fn report(&self, result: impl fmt::Debug) {
+        self.output.line(format_args!("{:?}", result));
+        self.metrics.increment("outputs");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_clock_follows_paused_time() {
+        let ctx = ExampleContext::captured();
+        sleep(Duration::from_secs(3600)).await;
+        assert_eq!(ctx.clock().elapsed(), Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn test_captured_output_is_shared_by_clones() {
+        let ctx = ExampleContext::captured();
+        ctx.clone().report((1, "a"));
+        ctx.output().line("done");
+        assert_eq!(ctx.output().lines(), ["(1, \"a\")", "done"]);
+        assert_eq!(ctx.metrics().get("outputs"), 1);
+        assert!(Output::Stdout.lines().is_empty());
+    }
+
+    #[test]
+    fn test_latency_scale() {
+        let latency = LatencySimulator::new().with_scale(0.5);
+        assert_eq!(latency.delay(millis(100)), millis(50));
+        assert_eq!(LatencySimulator::new().delay(millis(100)), millis(100));
+    }
+
+    #[test]
+    fn test_jitter_is_bounded_and_seeded() {
+        let delays = |seed| {
+            let latency = LatencySimulator::new().with_jitter(millis(10), seed);
+            (0..100)
+                .map(|_| latency.delay(millis(20)))
+                .collect::<Vec<_>>()
+        };
+        let first = delays(42);
+        assert!(first
+            .iter()
+            .all(|delay| (millis(20)..millis(30)).contains(delay)));
+        assert!(first.windows(2).any(|pair| pair[0] != pair[1]));
+        assert_eq!(first, delays(42));
+        assert_ne!(first, delays(7));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_latency_sleep() {
+        let ctx = ExampleContext::captured().with_latency(LatencySimulator::new().with_scale(2.0));
+        ctx.latency().sleep(millis(15)).await;
+        assert_eq!(ctx.clock().elapsed(), millis(30));
+    }
+
+    #[test]
+    fn test_metrics_add_up() {
+        let metrics = Metrics::new();
+        metrics.increment("runs");
+        metrics.clone().add("runs", 2);
+        assert_eq!(metrics.get("runs"), 3);
+        assert_eq!(metrics.get("missing"), 0);
+        assert_eq!(metrics.snapshot().len(), 1);
+    }
+
+    #[test]
+    fn test_cancellation_is_shared() {
+        let ctx = ExampleContext::captured();
+        ctx.clone().cancel_token().cancel();
+        assert!(ctx.cancel_token().is_cancelled());
+    }
+}
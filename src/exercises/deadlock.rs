@@ -0,0 +1,101 @@
+//! Exercise: fix the deadlock
+//!
+//! [`transfer`] locks the source account, then the destination account.
+//! Two concurrent transfers in opposite directions (A to B and B to A) can
+//! each hold their first lock while waiting forever for the second.
+//!
+//! Fix `transfer` so that concurrent transfers never deadlock, without
+//! removing the await point between the two locks (it stands for real work,
+//! such as an audit log write).
+//!
+//! Hint: a deadlock needs a cycle of waiters. Acquire locks in an order every
+//! caller agrees on.
+
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::sleep;
+
+/// A bank account protected by an async mutex.
+#[derive(Debug)]
+pub struct Account {
+    pub id: u32,
+    pub balance: Mutex<i64>,
+}
+
+impl Account {
+    pub fn new(id: u32, balance: i64) -> Self {
+        Account {
+            id,
+            balance: Mutex::new(balance),
+        }
+    }
+}
+
+/// Moves `amount` from `from` to `to`.
+pub async fn transfer(from: &Account, to: &Account, amount: i64) {
+    let mut from_balance = from.balance.lock().await;
+    sleep(Duration::from_millis(1)).await;
+    let mut to_balance = to.balance.lock().await;
+
+    *from_balance -= amount;
+    *to_balance += amount;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_single_transfer() {
+        let a = Account::new(1, 100);
+        let b = Account::new(2, 0);
+        transfer(&a, &b, 30).await;
+        assert_eq!(*a.balance.lock().await, 70);
+        assert_eq!(*b.balance.lock().await, 30);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_opposite_transfers_do_not_deadlock() {
+        let a = Account::new(1, 100);
+        let b = Account::new(2, 100);
+
+        let both = async { tokio::join!(transfer(&a, &b, 10), transfer(&b, &a, 30)) };
+        let result = tokio::time::timeout(Duration::from_secs(1), both).await;
+        assert!(result.is_ok(), "transfers deadlocked");
+
+        assert_eq!(*a.balance.lock().await, 120);
+        assert_eq!(*b.balance.lock().await, 80);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_many_concurrent_transfers() {
+        let accounts: Vec<_> = (0..4).map(|id| Account::new(id, 1_000)).collect();
+
+        // Every account sends 1 to every other account, all at once.
+        let transfers = (0..4)
+            .flat_map(|i| (0..4).map(move |j| (i, j)))
+            .filter(|(i, j)| i != j)
+            .map(|(i, j)| transfer(&accounts[i], &accounts[j], 1))
+            .collect();
+        let result = tokio::time::timeout(Duration::from_secs(1), join_all(transfers)).await;
+        assert!(result.is_ok(), "transfers deadlocked");
+
+        for account in &accounts {
+            assert_eq!(*account.balance.lock().await, 1_000);
+        }
+    }
+
+    /// Polls every future concurrently until all complete.
+    async fn join_all<F: std::future::Future<Output = ()>>(futures: Vec<F>) {
+        let mut futures: Vec<_> = futures.into_iter().map(Box::pin).collect();
+        std::future::poll_fn(|cx| {
+            futures.retain_mut(|future| future.as_mut().poll(cx).is_pending());
+            if futures.is_empty() {
+                std::task::Poll::Ready(())
+            } else {
+                std::task::Poll::Pending
+            }
+        })
+        .await
+    }
+}
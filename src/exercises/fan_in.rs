@@ -0,0 +1,41 @@
+//! Exercise: fan-in results from N tasks
+//!
+//! Implement [`fan_in`]: spawn one task per delay. Each task sleeps for its
+//! delay (in milliseconds) and sends `delay * 2` on a shared
+//! `tokio::sync::mpsc` channel. Collect the results in the order they
+//! arrive.
+//!
+//! Hints:
+//! - Clone the `Sender` for each task.
+//! - `recv()` returns `None` only when every sender has been dropped,
+//!   including the original one.
+
+/// Returns the doubled delays, in completion order.
+pub async fn fan_in(delays_ms: Vec<u64>) -> Vec<u64> {
+    todo!("spawn one task per delay and collect their results over an mpsc channel")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_results_in_completion_order() {
+        assert_eq!(fan_in(vec![30, 10, 20]).await, vec![20, 40, 60]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_tasks_run_concurrently() {
+        let start = Instant::now();
+        let results = fan_in(vec![100; 10]).await;
+        assert_eq!(results.len(), 10);
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_no_tasks() {
+        assert!(fan_in(Vec::new()).await.is_empty());
+    }
+}
@@ -0,0 +1,24 @@
+//! Worked exercises
+//!
+//! Each submodule contains skeleton async functions with `todo!()` bodies (or,
+//! for the deadlock exercise, a buggy body) and a pre-written test suite.
+//! The tests fail until the exercise is completed.
+//!
+//! The exercises are behind the `exercises` feature so the regular test
+//! suite stays green. To work on them:
+//!
+//! ```text
+//! cargo test --features exercises exercises::timeout_wrapper
+//! cargo test --features exercises exercises::fan_in
+//! cargo test --features exercises exercises::deadlock
+//! ```
+//!
+//! Every test runs with paused time: waiting is instantaneous, and a test
+//! that would hang (e.g. a deadlock) fails on a timeout instead.
+
+// Skeleton bodies do not use their parameters yet.
+#![allow(unused_variables)]
+
+pub mod deadlock;
+pub mod fan_in;
+pub mod timeout_wrapper;
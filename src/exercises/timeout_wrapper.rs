@@ -0,0 +1,61 @@
+//! Exercise: implement a timeout wrapper
+//!
+//! Implement [`with_timeout`] without using `tokio::time::timeout`: race the
+//! future against `tokio::time::sleep` with `tokio::select!`.
+//!
+//! Hints:
+//! - The losing branch of `select!` is dropped, which cancels it.
+//! - When the future wins, return its output in `Ok`.
+
+use std::future::Future;
+use std::time::Duration;
+
+/// Error returned when the wrapped future did not complete in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedOut;
+
+/// Runs `future`, giving up after `duration`.
+pub async fn with_timeout<F: Future>(duration: Duration, future: F) -> Result<F::Output, TimedOut> {
+    todo!("race `future` against a sleep of `duration`")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{sleep, Instant};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_completes_in_time() {
+        let result = with_timeout(Duration::from_millis(100), async {
+            sleep(Duration::from_millis(10)).await;
+            7
+        })
+        .await;
+        assert_eq!(result, Ok(7));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_times_out() {
+        let start = Instant::now();
+        let result = with_timeout(Duration::from_millis(50), async {
+            sleep(Duration::from_secs(10)).await;
+            7
+        })
+        .await;
+        assert_eq!(result, Err(TimedOut));
+        assert_eq!(start.elapsed(), Duration::from_millis(50));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slow_future_is_cancelled() {
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let result = with_timeout(Duration::from_millis(50), async move {
+            let _tx = tx;
+            std::future::pending::<()>().await
+        })
+        .await;
+        assert_eq!(result, Err(TimedOut));
+        // The future (and the sender it owned) was dropped.
+        assert!(rx.await.is_err());
+    }
+}
@@ -0,0 +1,142 @@
+//! Cooperative scheduling and task starvation
+//!
+//! Tokio schedules cooperatively: a task only gives its thread back at an
+//! await point that returns `Pending`. A tight loop without await points
+//! therefore starves every other task on its thread, which on a
+//! current-thread runtime means every other task.
+//!
+//! Two fixes, both inserting await points that actually yield:
+//! - `tokio::task::yield_now()`: yields every time it is awaited
+//! - `tokio::task::consume_budget()`: spends one unit of the task's coop
+//!   budget and yields only when the budget is exhausted, so hot loops pay
+//!   for the yield every ~128 iterations instead of on every one
+//!
+//! The functions below measure how many ticks a concurrent 1ms timer task
+//! manages to run while each loop variant is running. They must run on a
+//! current-thread runtime: on a multi-thread one, another worker would run
+//! the timer and hide the starvation.
+
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::runtime::Builder;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{info, instrument};
+
+use crate::example_context::ExampleContext;
+
+/// How a CPU-bound loop gives its thread back to the scheduler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopKind {
+    /// Never: no await point in the loop.
+    Starving,
+    /// `yield_now()` after every step.
+    YieldNow,
+    /// `consume_budget()` after every step.
+    ConsumeBudget,
+}
+
+/// Simulated CPU work: spins for `duration` without yielding.
+fn spin_for(duration: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        std::hint::spin_loop();
+    }
+}
+
+/// A CPU-bound loop of `steps` steps of `step` each.
+pub async fn cpu_loop(kind: LoopKind, steps: usize, step: Duration) {
+    for _ in 0..steps {
+        spin_for(step);
+        match kind {
+            LoopKind::Starving => {}
+            LoopKind::YieldNow => tokio::task::yield_now().await,
+            LoopKind::ConsumeBudget => tokio::task::consume_budget().await,
+        }
+    }
+}
+
+/// Runs the loop while a 1ms timer task counts its ticks, and returns the
+/// number of ticks that ran before the loop finished.
+///
+/// Must be called on a current-thread runtime.
+pub async fn timer_ticks_during(kind: LoopKind, steps: usize, step: Duration) -> usize {
+    let ticks = Arc::new(AtomicUsize::new(0));
+    let timer = {
+        let ticks = Arc::clone(&ticks);
+        tokio::spawn(async move {
+            let mut interval = interval(Duration::from_millis(1));
+            // Count ticks that ran, not ticks that were due.
+            interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            loop {
+                interval.tick().await;
+                ticks.fetch_add(1, Ordering::SeqCst);
+            }
+        })
+    };
+
+    cpu_loop(kind, steps, step).await;
+    // Read before the next await, which would let the timer catch up.
+    let observed = ticks.load(Ordering::SeqCst);
+    timer.abort();
+    observed
+}
+
+/// Example: the same loop starving, yielding, and budgeted
+///
+/// Synchronous: it builds the current-thread runtime the comparison needs,
+/// so call it from `spawn_blocking` when inside an async context.
+#[instrument(skip_all)]
+pub fn fairness_example(ctx: &ExampleContext) -> io::Result<Vec<(LoopKind, usize)>> {
+    let runtime = Builder::new_current_thread().enable_time().build()?;
+    let results = [
+        LoopKind::Starving,
+        LoopKind::YieldNow,
+        LoopKind::ConsumeBudget,
+    ]
+    .into_iter()
+    .take_while(|_| !ctx.cancel_token().is_cancelled())
+    .map(|kind| {
+        let ticks = runtime.block_on(timer_ticks_during(kind, 500, Duration::from_micros(100)));
+        info!(?kind, ticks, "timer ticks during a 50ms CPU-bound loop");
+        (kind, ticks)
+    })
+    .collect();
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEPS: usize = 500;
+    const STEP: Duration = Duration::from_micros(100);
+
+    #[tokio::test]
+    async fn test_tight_loop_starves_timer() {
+        assert_eq!(timer_ticks_during(LoopKind::Starving, STEPS, STEP).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_yield_now_lets_timer_progress() {
+        let ticks = timer_ticks_during(LoopKind::YieldNow, STEPS, STEP).await;
+        // The loop takes ~50ms; most ticks should run.
+        assert!(ticks >= 10, "only {} ticks", ticks);
+    }
+
+    #[tokio::test]
+    async fn test_consume_budget_yields_less_often() {
+        let budgeted = timer_ticks_during(LoopKind::ConsumeBudget, STEPS, STEP).await;
+        let yielding = timer_ticks_during(LoopKind::YieldNow, STEPS, STEP).await;
+        assert!(budgeted > 0);
+        assert!(budgeted < yielding, "{} >= {}", budgeted, yielding);
+    }
+
+    #[test]
+    fn test_fairness_example() {
+        let results = fairness_example(&ExampleContext::captured()).unwrap();
+        assert_eq!(results[0], (LoopKind::Starving, 0));
+        assert!(results[1].1 > 0);
+    }
+}
@@ -0,0 +1,234 @@
+//! Async test fixtures with setup and teardown (tests only)
+//!
+//! `Drop` cannot be async, so a resource needing async cleanup (stopping a
+//! server, removing a directory with `tokio::fs`) needs an explicit teardown
+//! call, which a panicking test never reaches. A [`Fixture`] pairs the
+//! resource with its async teardown:
+//! - [`Fixture::run`] runs the test body, then awaits the teardown, even if
+//!   the body panicked (the panic is caught, then resumed after teardown)
+//! - [`Fixture::teardown`] tears down explicitly
+//! - if neither was called, `Drop` runs the teardown on a helper thread with
+//!   its own runtime, bounded by a timeout. The test's runtime is blocked
+//!   meanwhile, so a teardown must not wait on its tasks: a server fixture
+//!   signals its server to stop (or aborts it) rather than awaiting its
+//!   `JoinHandle`
+//!
+//! The setup returns two parts: the resource handed to the test body (e.g.
+//! the server address), and the state the teardown consumes (e.g. the
+//! shutdown sender).
+
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use futures::FutureExt;
+use tokio::runtime::Builder;
+use tokio::time::timeout;
+use tracing::warn;
+
+/// How long `Drop` waits for a teardown that was not run explicitly.
+const DROP_TEARDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+type TeardownFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+type Teardown<S> = Box<dyn FnOnce(S) -> TeardownFuture + Send>;
+
+/// A resource of type `T` whose teardown consumes a state of type `S`.
+pub(crate) struct Fixture<T, S: Send + 'static> {
+    resource: T,
+    pending: Option<(S, Teardown<S>)>,
+}
+
+/// Runs `setup`, returning a fixture that will run `teardown` on its state.
+pub(crate) async fn fixture<T, S, D, DFut>(
+    setup: impl Future<Output = (T, S)>,
+    teardown: D,
+) -> Fixture<T, S>
+where
+    S: Send + 'static,
+    D: FnOnce(S) -> DFut + Send + 'static,
+    DFut: Future<Output = ()> + Send + 'static,
+{
+    let (resource, state) = setup.await;
+    let teardown: Teardown<S> = Box::new(move |state| Box::pin(teardown(state)));
+    Fixture {
+        resource,
+        pending: Some((state, teardown)),
+    }
+}
+
+impl<T, S: Send + 'static> Fixture<T, S> {
+    /// The resource handed to tests.
+    pub(crate) fn resource(&self) -> &T {
+        &self.resource
+    }
+
+    /// Runs the test body with a copy of the resource, then tears down,
+    /// whether the body returned or panicked.
+    pub(crate) async fn run<F, Fut, R>(self, body: F) -> R
+    where
+        T: Clone,
+        F: FnOnce(T) -> Fut,
+        Fut: Future<Output = R>,
+    {
+        let outcome = AssertUnwindSafe(body(self.resource.clone()))
+            .catch_unwind()
+            .await;
+        self.teardown().await;
+        outcome.unwrap_or_else(|payload| panic::resume_unwind(payload))
+    }
+
+    /// Tears down now.
+    pub(crate) async fn teardown(mut self) {
+        if let Some((state, teardown)) = self.pending.take() {
+            teardown(state).await;
+        }
+    }
+}
+
+impl<T, S: Send + 'static> Drop for Fixture<T, S> {
+    fn drop(&mut self) {
+        let Some((state, teardown)) = self.pending.take() else {
+            return;
+        };
+        let future = teardown(state);
+        // The current thread may be inside a runtime (which cannot block on
+        // another future) or unwinding: run the teardown elsewhere.
+        let finished = thread::spawn(move || {
+            let runtime = Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("failed to build teardown runtime");
+            runtime
+                .block_on(async { timeout(DROP_TEARDOWN_TIMEOUT, future).await })
+                .is_ok()
+        })
+        .join();
+        if !matches!(finished, Ok(true)) {
+            warn!("fixture teardown did not complete");
+        }
+    }
+}
+
+/// A fresh directory under the system temp dir, removed on teardown.
+pub(crate) async fn temp_dir() -> Fixture<PathBuf, PathBuf> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    fixture(
+        async {
+            let path = std::env::temp_dir().join(format!(
+                "async-course-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            tokio::fs::create_dir_all(&path)
+                .await
+                .expect("failed to create temp dir");
+            (path.clone(), path)
+        },
+        |path| async move {
+            let _ = tokio::fs::remove_dir_all(path).await;
+        },
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// A fixture recording its lifecycle in `log`.
+    async fn recorded(log: &Arc<Mutex<Vec<&'static str>>>) -> Fixture<u32, ()> {
+        let setup_log = Arc::clone(log);
+        let teardown_log = Arc::clone(log);
+        fixture(
+            async move {
+                setup_log.lock().unwrap().push("setup");
+                (7, ())
+            },
+            move |()| async move {
+                tokio::task::yield_now().await;
+                teardown_log.lock().unwrap().push("teardown");
+            },
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_run_tears_down_after_body() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let fixture = recorded(&log).await;
+        assert_eq!(*fixture.resource(), 7);
+
+        let body_log = Arc::clone(&log);
+        let doubled = fixture
+            .run(|value| async move {
+                body_log.lock().unwrap().push("body");
+                value * 2
+            })
+            .await;
+
+        assert_eq!(doubled, 14);
+        assert_eq!(*log.lock().unwrap(), vec!["setup", "body", "teardown"]);
+    }
+
+    #[tokio::test]
+    async fn test_run_tears_down_when_body_panics() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let fixture = recorded(&log).await;
+
+        let result = tokio::spawn(fixture.run(|_| async { panic!("test body failed") })).await;
+
+        assert!(result.unwrap_err().is_panic());
+        assert_eq!(*log.lock().unwrap(), vec!["setup", "teardown"]);
+    }
+
+    #[tokio::test]
+    async fn test_drop_runs_teardown() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        drop(recorded(&log).await);
+        assert_eq!(*log.lock().unwrap(), vec!["setup", "teardown"]);
+    }
+
+    #[tokio::test]
+    async fn test_explicit_teardown_runs_once() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        recorded(&log).await.teardown().await;
+        assert_eq!(*log.lock().unwrap(), vec!["setup", "teardown"]);
+    }
+
+    #[tokio::test]
+    async fn test_temp_dir_is_removed() {
+        let dir = temp_dir().await;
+        let path = dir.resource().clone();
+
+        dir.run(|path| async move {
+            tokio::fs::write(path.join("notes.txt"), "async")
+                .await
+                .unwrap();
+        })
+        .await;
+
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_temp_dir_is_removed_after_panic() {
+        let dir = temp_dir().await;
+        let path = dir.resource().clone();
+
+        let result = tokio::spawn(dir.run(|path| async move {
+            tokio::fs::write(path.join("notes.txt"), "async")
+                .await
+                .unwrap();
+            panic!("test body failed");
+        }))
+        .await;
+
+        assert!(result.is_err());
+        assert!(!path.exists());
+    }
+}
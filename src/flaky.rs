@@ -0,0 +1,237 @@
+//! Simulated flaky dependency for teaching retries
+//!
+//! `FlakyService` is a test double whose behavior is scripted call by call:
+//! succeed, fail, or take some time first. Tests describe a scenario
+//! declaratively ("fail twice, then succeed after 50ms") instead of
+//! maintaining ad-hoc counters in closures.
+//!
+//! [`retry_with_backoff`] is the retry example driven by it.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{info, instrument, warn};
+
+use crate::example_context::ExampleContext;
+
+/// What a single call to a [`FlakyService`] does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// Answer immediately.
+    Succeed,
+    /// Fail immediately.
+    Fail,
+    /// Answer after the given delay.
+    Delay(Duration),
+    /// Fail after the given delay.
+    DelayThenFail(Duration),
+    /// Never answer (the caller must time out or cancel).
+    Hang,
+}
+
+/// Error returned by a failing [`FlakyService`] call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlakyError {
+    /// 1-based number of the call that failed.
+    pub call: usize,
+}
+
+impl fmt::Display for FlakyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "simulated failure on call {}", self.call)
+    }
+}
+
+impl std::error::Error for FlakyError {}
+
+#[derive(Debug)]
+struct Inner {
+    name: String,
+    script: Mutex<VecDeque<Step>>,
+    otherwise: Step,
+    calls: AtomicUsize,
+}
+
+/// A dependency whose behavior is scripted per call.
+///
+/// Clones share the same script and call counter, so a clone can be moved
+/// into a spawned task.
+#[derive(Debug, Clone)]
+pub struct FlakyService {
+    inner: Arc<Inner>,
+}
+
+impl FlakyService {
+    /// Creates a service playing `script`, then succeeding for every call.
+    pub fn scripted(name: impl Into<String>, script: impl IntoIterator<Item = Step>) -> Self {
+        Self::scripted_otherwise(name, script, Step::Succeed)
+    }
+
+    /// Creates a service playing `script`, then doing `otherwise` for every call.
+    pub fn scripted_otherwise(
+        name: impl Into<String>,
+        script: impl IntoIterator<Item = Step>,
+        otherwise: Step,
+    ) -> Self {
+        FlakyService {
+            inner: Arc::new(Inner {
+                name: name.into(),
+                script: Mutex::new(script.into_iter().collect()),
+                otherwise,
+                calls: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Creates a service that always behaves the same way.
+    pub fn always(name: impl Into<String>, step: Step) -> Self {
+        Self::scripted_otherwise(name, [], step)
+    }
+
+    /// Calls the service, returning its name on success.
+    pub async fn call(&self) -> Result<String, FlakyError> {
+        let call = self.inner.calls.fetch_add(1, Ordering::SeqCst) + 1;
+        let step = self
+            .inner
+            .script
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or(self.inner.otherwise);
+
+        match step {
+            Step::Succeed => {}
+            Step::Fail => return Err(FlakyError { call }),
+            Step::Delay(delay) => sleep(delay).await,
+            Step::DelayThenFail(delay) => {
+                sleep(delay).await;
+                return Err(FlakyError { call });
+            }
+            Step::Hang => std::future::pending::<()>().await,
+        }
+        Ok(self.inner.name.clone())
+    }
+
+    /// Number of calls made so far, including failed and pending ones.
+    pub fn calls(&self) -> usize {
+        self.inner.calls.load(Ordering::SeqCst)
+    }
+}
+
+/// Example: retrying a fallible async operation with exponential backoff
+///
+/// Calls `operation` up to `max_attempts` times, sleeping `initial_backoff`
+/// after the first failure and doubling the delay after each subsequent one.
+/// Returns the last error if every attempt fails.
+pub async fn retry_with_backoff<F, Fut, T, E>(
+    max_attempts: usize,
+    initial_backoff: Duration,
+    mut operation: F,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: fmt::Display,
+{
+    let mut backoff = initial_backoff;
+    let mut attempt = 1;
+
+    loop {
+        match operation().await {
+            Ok(value) => return Ok(value),
+            Err(error) if attempt >= max_attempts => {
+                warn!(attempt, %error, "giving up");
+                return Err(error);
+            }
+            Err(error) => {
+                warn!(attempt, %error, ?backoff, "attempt failed, retrying");
+                sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Runs the retry example against a service failing twice before answering.
+#[instrument(skip_all)]
+pub async fn retry_example(ctx: &ExampleContext) -> Result<String, FlakyError> {
+    let delay = |ms| ctx.latency().delay(Duration::from_millis(ms));
+    let service = FlakyService::scripted(
+        "inventory",
+        [Step::Fail, Step::Fail, Step::Delay(delay(20))],
+    );
+
+    let result = retry_with_backoff(4, delay(10), || service.call()).await;
+    info!(calls = service.calls(), ?result, "retry finished");
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Instant;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_service_follows_script() {
+        let service = FlakyService::scripted(
+            "svc",
+            [
+                Step::Fail,
+                Step::Delay(Duration::from_millis(30)),
+                Step::DelayThenFail(Duration::from_millis(10)),
+            ],
+        );
+
+        assert_eq!(service.call().await, Err(FlakyError { call: 1 }));
+
+        let start = Instant::now();
+        assert_eq!(service.call().await, Ok("svc".to_string()));
+        assert_eq!(start.elapsed(), Duration::from_millis(30));
+
+        assert_eq!(service.call().await, Err(FlakyError { call: 3 }));
+        // Script exhausted: succeeds from now on.
+        assert_eq!(service.call().await, Ok("svc".to_string()));
+        assert_eq!(service.calls(), 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_hanging_call_never_answers() {
+        let service = FlakyService::always("svc", Step::Hang);
+        let result = tokio::time::timeout(Duration::from_secs(60), service.call()).await;
+        assert!(result.is_err());
+        assert_eq!(service.calls(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_succeeds_after_failures() {
+        let start = Instant::now();
+        let result = retry_example(&ExampleContext::captured()).await;
+        assert_eq!(result, Ok("inventory".to_string()));
+        // Backoffs of 10ms and 20ms, then a 20ms answer.
+        assert_eq!(start.elapsed(), Duration::from_millis(50));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_gives_up_after_max_attempts() {
+        let service = FlakyService::always("svc", Step::Fail);
+        let start = Instant::now();
+
+        let result = retry_with_backoff(3, Duration::from_millis(10), || service.call()).await;
+        assert_eq!(result, Err(FlakyError { call: 3 }));
+        assert_eq!(service.calls(), 3);
+        assert_eq!(start.elapsed(), Duration::from_millis(30));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_does_not_call_again_after_success() {
+        let service = FlakyService::scripted("svc", [Step::Succeed, Step::Fail]);
+        let result = retry_with_backoff(3, Duration::from_millis(10), || service.call()).await;
+        assert!(result.is_ok());
+        assert_eq!(service.calls(), 1);
+    }
+}
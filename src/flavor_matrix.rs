@@ -0,0 +1,203 @@
+//! Runtime-flavor compatibility matrix (tests only)
+//!
+//! Runs every runnable example of the course on a multi-thread runtime, a
+//! current-thread runtime, and a current-thread runtime with paused time, and
+//! reports the examples whose outcome depends on the setup. Typical
+//! flavor-specific bugs it catches:
+//! - `block_in_place` panics on a current-thread runtime
+//! - a task blocking its thread deadlocks a current-thread runtime, while
+//!   another worker hides the bug on a multi-thread one
+//! - code measuring wall-clock time (`std::time::Instant`) behaves
+//!   differently once time is paused
+//!
+//! The examples come from the registry, except the ones needing the network
+//! or building their own runtimes (which cannot run inside one).
+
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use tokio::runtime::{Builder, Runtime};
+use tokio::time::timeout;
+
+use crate::example_context::ExampleContext;
+use crate::registry::{course_examples, Example};
+
+/// How long an example may run before it is considered stuck. Virtual time
+/// when time is paused.
+const TIME_LIMIT: Duration = Duration::from_secs(10);
+
+/// The registered examples that can run on any runtime.
+fn runnable_examples() -> Vec<Example> {
+    course_examples()
+        .into_iter()
+        .filter(|example| !example.needs_network && !example.builds_runtime)
+        .collect()
+}
+
+/// A runtime configuration of the matrix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Setup {
+    MultiThread,
+    CurrentThread,
+    /// Time only auto-advances on a current-thread runtime.
+    CurrentThreadPaused,
+}
+
+impl Setup {
+    const ALL: [Setup; 3] = [
+        Setup::MultiThread,
+        Setup::CurrentThread,
+        Setup::CurrentThreadPaused,
+    ];
+
+    fn build(self) -> io::Result<Runtime> {
+        match self {
+            Setup::MultiThread => Builder::new_multi_thread()
+                .worker_threads(2)
+                .enable_all()
+                .build(),
+            Setup::CurrentThread => Builder::new_current_thread().enable_all().build(),
+            Setup::CurrentThreadPaused => Builder::new_current_thread()
+                .enable_all()
+                .start_paused(true)
+                .build(),
+        }
+    }
+}
+
+/// How an example ended on one setup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Outcome {
+    Completed,
+    Failed(String),
+    Panicked,
+    TimedOut,
+}
+
+/// Runs one example on a fresh runtime of the given setup.
+fn run_on(example: &Example, setup: Setup) -> Outcome {
+    let runtime = setup.build().expect("failed to build runtime");
+    // Spawning (rather than awaiting in `block_on`) turns a panic into a
+    // `JoinError` and runs the example as a task. The context is created on
+    // the runtime so that its clock follows the runtime's time.
+    let result = runtime.block_on(async {
+        let run = example.run(ExampleContext::captured());
+        timeout(TIME_LIMIT, tokio::spawn(run)).await
+    });
+    match result {
+        Ok(Ok(Ok(()))) => Outcome::Completed,
+        Ok(Ok(Err(error))) => Outcome::Failed(error.0),
+        Ok(Err(_)) => Outcome::Panicked,
+        Err(_) => Outcome::TimedOut,
+    }
+}
+
+/// Outcomes of one example on every setup.
+struct MatrixRow {
+    name: &'static str,
+    outcomes: Vec<(Setup, Outcome)>,
+}
+
+impl MatrixRow {
+    fn is_flavor_sensitive(&self) -> bool {
+        self.outcomes.windows(2).any(|pair| pair[0].1 != pair[1].1)
+    }
+}
+
+struct MatrixReport {
+    rows: Vec<MatrixRow>,
+}
+
+impl MatrixReport {
+    fn run(examples: &[Example]) -> Self {
+        let rows = examples
+            .iter()
+            .map(|example| MatrixRow {
+                name: example.name,
+                outcomes: Setup::ALL
+                    .iter()
+                    .filter(|&&setup| example.pausable || setup != Setup::CurrentThreadPaused)
+                    .map(|&setup| (setup, run_on(example, setup)))
+                    .collect(),
+            })
+            .collect();
+        MatrixReport { rows }
+    }
+
+    fn flavor_sensitive(&self) -> Vec<&'static str> {
+        self.rows
+            .iter()
+            .filter(|row| row.is_flavor_sensitive())
+            .map(|row| row.name)
+            .collect()
+    }
+}
+
+impl fmt::Display for MatrixReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in &self.rows {
+            write!(f, "{:<24}", row.name)?;
+            for (setup, outcome) in &row.outcomes {
+                write!(f, " {:?}={:?}", setup, outcome)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_examples_are_not_flavor_sensitive() {
+    let report = MatrixReport::run(&runnable_examples());
+    assert!(
+        report
+            .rows
+            .iter()
+            .flat_map(|row| &row.outcomes)
+            .all(|(_, outcome)| *outcome == Outcome::Completed),
+        "{}",
+        report
+    );
+    assert_eq!(report.flavor_sensitive(), Vec::<&str>::new(), "{}", report);
+}
+
+#[test]
+fn test_matrix_detects_flavor_sensitive_example() {
+    let examples = [
+        Example::new("block_in_place", "", Duration::ZERO, |_ctx| async {
+            tokio::task::block_in_place(|| std::thread::sleep(Duration::from_millis(1)));
+            Ok(())
+        }),
+        Example::new("yield_now", "", Duration::ZERO, |_ctx| async {
+            tokio::task::yield_now().await;
+            Ok(())
+        }),
+    ];
+    let report = MatrixReport::run(&examples);
+
+    assert_eq!(report.flavor_sensitive(), vec!["block_in_place"]);
+    assert_eq!(
+        report.rows[0].outcomes,
+        vec![
+            (Setup::MultiThread, Outcome::Completed),
+            (Setup::CurrentThread, Outcome::Panicked),
+            (Setup::CurrentThreadPaused, Outcome::Panicked),
+        ]
+    );
+}
+
+#[test]
+fn test_paused_time_does_not_wait_for_threads() {
+    let mut examples = runnable_examples();
+    examples.retain(|example| example.name == "waker");
+    // Run it on the paused setup anyway.
+    examples[0].pausable = true;
+    let report = MatrixReport::run(&examples);
+
+    assert_eq!(report.flavor_sensitive(), vec!["waker"]);
+    assert_eq!(
+        report.rows[0].outcomes[2],
+        (Setup::CurrentThreadPaused, Outcome::TimedOut)
+    );
+}
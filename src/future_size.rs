@@ -0,0 +1,210 @@
+//! Future size and state-machine layout
+//!
+//! The state machine generated for an `async fn` stores every local that is
+//! alive across an await point, plus the state of the future being awaited.
+//! Its size is known at compile time and paid wherever the future lives: on
+//! the stack of the caller, inside the parent future, or in the task.
+//!
+//! Holding a large array across an await makes the future at least that
+//! large, and so does every future awaiting it inline. `Box::pin` moves the
+//! large state machine to the heap, leaving a pointer in the parent.
+//!
+//! The sizes below are checked at compile time by `const` assertions (the
+//! build fails if they stop holding) and printed by [`future_size_example`].
+
+use std::future::Future;
+use std::mem::{size_of, size_of_val};
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{info, instrument};
+
+use crate::example_context::ExampleContext;
+
+/// Length of the buffer held by the examples below.
+pub const BUFFER_LEN: usize = 16 * 1024;
+
+/// Size of the future returned by `f`, usable in `const` context.
+pub const fn future_size_of<F, Fut>(_f: &F) -> usize
+where
+    F: FnOnce() -> Fut,
+    Fut: Future,
+{
+    size_of::<Fut>()
+}
+
+/// Holds a `BUFFER_LEN`-byte array across an await: it is stored in the
+/// future.
+pub async fn buffer_across_await() -> u64 {
+    let buffer = [1u8; BUFFER_LEN];
+    sleep(Duration::from_millis(1)).await;
+    buffer.iter().map(|&byte| u64::from(byte)).sum()
+}
+
+/// Same work, with the buffer dropped before the await: only the sum is
+/// stored in the future.
+pub async fn buffer_before_await() -> u64 {
+    let sum = {
+        let buffer = [1u8; BUFFER_LEN];
+        buffer.iter().map(|&byte| u64::from(byte)).sum()
+    };
+    sleep(Duration::from_millis(1)).await;
+    sum
+}
+
+/// Awaits [`buffer_across_await`] inline: its state machine is nested in
+/// this one, so this future is large too.
+pub async fn await_inline() -> u64 {
+    buffer_across_await().await
+}
+
+/// Awaits [`buffer_across_await`] through `Box::pin`: this future only holds
+/// the box pointer.
+pub async fn await_boxed() -> u64 {
+    Box::pin(buffer_across_await()).await
+}
+
+const _: () = assert!(future_size_of(&buffer_across_await) >= BUFFER_LEN);
+const _: () = assert!(future_size_of(&buffer_before_await) < BUFFER_LEN);
+const _: () = assert!(future_size_of(&await_inline) >= BUFFER_LEN);
+const _: () = assert!(future_size_of(&await_boxed) < 256);
+
+/// Size in bytes of one example's future.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FutureSize {
+    pub name: &'static str,
+    pub bytes: usize,
+}
+
+/// Sizes of the futures returned by the crate's examples, created with
+/// `ctx`.
+///
+/// Creating a future does not run it, so they are measured and dropped
+/// without being polled.
+pub fn example_future_sizes(ctx: &ExampleContext) -> Vec<FutureSize> {
+    vec![
+        FutureSize {
+            name: "async_state_machine_example",
+            bytes: size_of_val(&crate::async_state_machine_example(ctx)),
+        },
+        FutureSize {
+            name: "multiple_awaits_example",
+            bytes: size_of_val(&crate::multiple_awaits_example(ctx)),
+        },
+        FutureSize {
+            name: "variable_scoping_example",
+            bytes: size_of_val(&crate::variable_scoping_example(ctx)),
+        },
+        FutureSize {
+            name: "complex_async_function",
+            bytes: size_of_val(&crate::complex_async_function(1, String::new())),
+        },
+        FutureSize {
+            name: "fetch_data_from_api",
+            bytes: size_of_val(&crate::fetch_data_from_api("http://localhost")),
+        },
+        FutureSize {
+            name: "concurrent_execution_example",
+            bytes: size_of_val(&crate::concurrent_execution_example(ctx)),
+        },
+        FutureSize {
+            name: "async_sugar_example",
+            bytes: size_of_val(&crate::async_sugar_example(ctx)),
+        },
+        FutureSize {
+            name: "buffer_across_await",
+            bytes: size_of_val(&buffer_across_await()),
+        },
+        FutureSize {
+            name: "buffer_before_await",
+            bytes: size_of_val(&buffer_before_await()),
+        },
+        FutureSize {
+            name: "await_inline",
+            bytes: size_of_val(&await_inline()),
+        },
+        FutureSize {
+            name: "await_boxed",
+            bytes: size_of_val(&await_boxed()),
+        },
+    ]
+}
+
+/// Example: printing future sizes, then running the large and boxed futures
+#[instrument(skip_all)]
+pub async fn future_size_example(ctx: &ExampleContext) {
+    for FutureSize { name, bytes } in example_future_sizes(ctx) {
+        info!(name, bytes, "future size");
+    }
+
+    let boxed = Box::pin(buffer_across_await());
+    info!(
+        bytes = size_of_val(&boxed),
+        "Box::pin(buffer_across_await())"
+    );
+    let sum = boxed.await;
+    info!(sum, "boxed future completed");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_across_await_bloats_future() {
+        assert!(size_of_val(&buffer_across_await()) >= BUFFER_LEN);
+        assert!(size_of_val(&await_inline()) >= BUFFER_LEN);
+    }
+
+    #[test]
+    fn test_buffer_dropped_before_await_is_not_stored() {
+        assert!(size_of_val(&buffer_before_await()) < 256);
+    }
+
+    #[test]
+    fn test_box_pin_is_pointer_sized() {
+        assert_eq!(
+            size_of_val(&Box::pin(buffer_across_await())),
+            size_of::<usize>()
+        );
+        // The parent stores the box and a few bytes of state.
+        assert!(size_of_val(&await_boxed()) <= 4 * size_of::<usize>());
+    }
+
+    #[test]
+    fn test_const_size_matches_runtime_size() {
+        assert_eq!(
+            future_size_of(&buffer_across_await),
+            size_of_val(&buffer_across_await())
+        );
+    }
+
+    #[test]
+    fn test_example_future_sizes() {
+        let sizes = example_future_sizes(&ExampleContext::captured());
+        assert_eq!(sizes.len(), 11);
+        let size = |name| {
+            sizes
+                .iter()
+                .find(|size| size.name == name)
+                .unwrap_or_else(|| panic!("no size for {}", name))
+                .bytes
+        };
+
+        // Holding the buffer across the await stores it in the future;
+        // dropping it first does not.
+        assert!(size("buffer_across_await") >= BUFFER_LEN);
+        assert!(size("buffer_before_await") < BUFFER_LEN);
+        assert!(size("buffer_across_await") > size("buffer_before_await"));
+        // Awaiting inline nests the large future; boxing it does not.
+        assert!(size("await_inline") >= size("buffer_across_await"));
+        assert!(size("await_boxed") < size("buffer_before_await"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_large_and_boxed_futures_compute_the_same_value() {
+        let expected = BUFFER_LEN as u64;
+        assert_eq!(await_inline().await, expected);
+        assert_eq!(await_boxed().await, expected);
+        assert_eq!(buffer_before_await().await, expected);
+    }
+}
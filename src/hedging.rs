@@ -0,0 +1,312 @@
+//! First success wins: `race_ok` and hedged requests
+//!
+//! Two ways to ask several replicas the same question:
+//! - [`race_ok`] sends every request at once and returns the first success.
+//!   The requests still running are dropped, which cancels them. If every
+//!   request fails, all the errors come back, in the order they happened,
+//!   instead of only the last one.
+//! - [`hedged`] sends one request, and a backup only if the primary has not
+//!   answered after a delay (or has already failed). Most requests are
+//!   answered by the primary, so the tail latency improves for a few extra
+//!   requests, where `race_ok` doubles the load every time.
+//!
+//! Both poll their requests in a `FuturesUnordered`, so the requests run
+//! concurrently inside the caller's task: nothing is spawned, and the
+//! requests can borrow from the caller. `hedged` selects between the next
+//! completion and the hedging delay.
+//!
+//! The example hedges between replicas of the paginated API's
+//! [`MockClient`], a slow primary and a fast backup.
+
+use std::future::Future;
+use std::time::Duration;
+
+use futures::stream::{FuturesUnordered, StreamExt};
+use tokio::time::{sleep_until, Instant};
+use tracing::{debug, info, instrument};
+
+use crate::example_context::{Clock, ExampleContext};
+use crate::pagination::{FetchError, MockClient, Page, PageFetcher};
+
+/// Returns the first successful output of `futures`, dropping the others;
+/// all the errors, in completion order, if none succeeds.
+pub async fn race_ok<I, F, T, E>(futures: I) -> Result<T, Vec<E>>
+where
+    I: IntoIterator<Item = F>,
+    F: Future<Output = Result<T, E>>,
+{
+    let mut pending: FuturesUnordered<F> = futures.into_iter().collect();
+    let mut errors = Vec::new();
+    while let Some(result) = pending.next().await {
+        match result {
+            // Returning drops `pending`: the other futures are cancelled.
+            Ok(value) => return Ok(value),
+            Err(error) => errors.push(error),
+        }
+    }
+    Err(errors)
+}
+
+/// Calls `request(0)`, then `request(1)` if it has not answered after
+/// `delay`, and so on up to `max_attempts` requests in flight or done.
+///
+/// A failed request starts the next attempt right away instead of waiting
+/// for the delay. Returns the first success, dropping the requests still in
+/// flight; all the errors, in completion order, if every attempt fails.
+///
+/// # Panics
+///
+/// If `max_attempts` is 0.
+pub async fn hedged<R, F, T, E>(
+    delay: Duration,
+    max_attempts: usize,
+    mut request: R,
+) -> Result<T, Vec<E>>
+where
+    R: FnMut(usize) -> F,
+    F: Future<Output = Result<T, E>>,
+{
+    assert!(max_attempts > 0, "hedged needs at least one attempt");
+
+    let mut in_flight = FuturesUnordered::new();
+    in_flight.push(request(0));
+    let mut started = 1;
+    let mut errors = Vec::new();
+    let mut next_hedge = Instant::now() + delay;
+
+    loop {
+        let can_hedge = started < max_attempts;
+        tokio::select! {
+            biased;
+            Some(result) = in_flight.next() => match result {
+                Ok(value) => return Ok(value),
+                Err(error) => {
+                    errors.push(error);
+                    if can_hedge {
+                        debug!(attempt = started, "request failed, starting the next one");
+                        in_flight.push(request(started));
+                        started += 1;
+                        next_hedge = Instant::now() + delay;
+                    } else if in_flight.is_empty() {
+                        return Err(errors);
+                    }
+                }
+            },
+            _ = sleep_until(next_hedge), if can_hedge => {
+                debug!(attempt = started, "no answer yet, hedging");
+                in_flight.push(request(started));
+                started += 1;
+                next_hedge = Instant::now() + delay;
+            }
+        }
+    }
+}
+
+/// Latency and number of requests sent by one strategy of the example.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HedgingOutcome {
+    pub strategy: &'static str,
+    pub latency: Duration,
+    pub requests: usize,
+}
+
+/// Runs one strategy of the example and measures it.
+async fn measure<Fut>(
+    clock: &Clock,
+    strategy: &'static str,
+    replicas: &[MockClient],
+    run: Fut,
+) -> Result<HedgingOutcome, Vec<FetchError>>
+where
+    Fut: Future<Output = Result<Page, Vec<FetchError>>>,
+{
+    let start = clock.now();
+    let page = run.await?;
+    let outcome = HedgingOutcome {
+        strategy,
+        latency: start.elapsed(),
+        requests: replicas
+            .iter()
+            .map(|replica| replica.requested().len())
+            .sum(),
+    };
+    info!(strategy, items = page.items.len(), ?outcome.latency, outcome.requests, "answered");
+    Ok(outcome)
+}
+
+/// Example: asking a slow primary and a fast backup the same page, with
+/// `race_ok`, a hedged request, and a hedged request to a fast primary
+#[instrument(skip_all)]
+pub async fn hedging_example(ctx: &ExampleContext) -> Result<Vec<HedgingOutcome>, Vec<FetchError>> {
+    const URL: &str = "https://api.example.com/items?page=1";
+    let replica = |latency| {
+        MockClient::numbered("https://api.example.com/items", 1, 3)
+            .with_latency(ctx.latency().delay(Duration::from_millis(latency)))
+    };
+    let hedge_delay = Duration::from_millis(30);
+
+    let mut outcomes = Vec::new();
+
+    let replicas = [replica(100), replica(10)];
+    let race = race_ok(replicas.iter().map(|replica| replica.fetch_page(URL)));
+    outcomes.push(measure(ctx.clock(), "race_ok", &replicas, race).await?);
+
+    let replicas = [replica(100), replica(10)];
+    let hedge = hedged(hedge_delay, replicas.len(), |attempt| {
+        replicas[attempt].fetch_page(URL)
+    });
+    outcomes.push(measure(ctx.clock(), "hedged, slow primary", &replicas, hedge).await?);
+
+    let replicas = [replica(10), replica(10)];
+    let hedge = hedged(hedge_delay, replicas.len(), |attempt| {
+        replicas[attempt].fetch_page(URL)
+    });
+    outcomes.push(measure(ctx.clock(), "hedged, fast primary", &replicas, hedge).await?);
+
+    Ok(outcomes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::time::sleep;
+
+    const BASE: &str = "https://api.example.com/items";
+    const URL: &str = "https://api.example.com/items?page=1";
+
+    fn millis(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn replica(latency: u64) -> MockClient {
+        MockClient::numbered(BASE, 1, 2).with_latency(millis(latency))
+    }
+
+    /// A replica answering 404 after `latency`.
+    fn broken_replica(latency: u64) -> MockClient {
+        replica(latency).without(URL)
+    }
+
+    fn not_found() -> FetchError {
+        FetchError::Status {
+            url: URL.to_string(),
+            status: 404,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_race_ok_returns_the_first_success() {
+        let replicas = [broken_replica(5), replica(50), replica(20)];
+        let start = Instant::now();
+        let page = race_ok(replicas.iter().map(|replica| replica.fetch_page(URL)))
+            .await
+            .unwrap();
+
+        assert_eq!(page.url, URL);
+        assert_eq!(start.elapsed(), millis(20));
+        assert!(replicas
+            .iter()
+            .all(|replica| replica.requested().len() == 1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_race_ok_cancels_the_losers() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let request = |latency: u64| {
+            let finished = Arc::clone(&finished);
+            async move {
+                sleep(millis(latency)).await;
+                finished.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, ()>(latency)
+            }
+        };
+
+        assert_eq!(
+            race_ok([request(30), request(10), request(20)]).await,
+            Ok(10)
+        );
+        sleep(Duration::from_secs(1)).await;
+        assert_eq!(finished.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_race_ok_collects_every_error() {
+        let replicas = [broken_replica(30), broken_replica(10)];
+        let errors = race_ok(replicas.iter().map(|replica| replica.fetch_page(URL)))
+            .await
+            .unwrap_err();
+        assert_eq!(errors, vec![not_found(), not_found()]);
+
+        let none: Vec<std::future::Ready<Result<(), ()>>> = Vec::new();
+        assert_eq!(race_ok(none).await, Err(vec![]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_hedged_fast_primary_sends_one_request() {
+        let replicas = [replica(10), replica(10)];
+        let start = Instant::now();
+        hedged(millis(30), 2, |attempt| replicas[attempt].fetch_page(URL))
+            .await
+            .unwrap();
+
+        assert_eq!(start.elapsed(), millis(10));
+        assert_eq!(replicas[0].requested().len(), 1);
+        assert!(replicas[1].requested().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_hedged_backup_starts_after_the_delay() {
+        let replicas = [replica(100), replica(10)];
+        let start = Instant::now();
+        hedged(millis(30), 2, |attempt| replicas[attempt].fetch_page(URL))
+            .await
+            .unwrap();
+
+        // 30ms of hedging delay, then 10ms for the backup.
+        assert_eq!(start.elapsed(), millis(40));
+        assert_eq!(replicas[1].requested().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_hedged_failure_starts_the_backup_at_once() {
+        let replicas = [broken_replica(5), replica(10)];
+        let start = Instant::now();
+        hedged(millis(30), 2, |attempt| replicas[attempt].fetch_page(URL))
+            .await
+            .unwrap();
+        assert_eq!(start.elapsed(), millis(15));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_hedged_gives_up_after_max_attempts() {
+        let replicas = [broken_replica(50), broken_replica(10), replica(1)];
+        let start = Instant::now();
+        let errors = hedged(millis(20), 2, |attempt| replicas[attempt].fetch_page(URL))
+            .await
+            .unwrap_err();
+
+        assert_eq!(errors, vec![not_found(), not_found()]);
+        // The backup failed at 30ms; the primary was awaited until 50ms.
+        assert_eq!(start.elapsed(), millis(50));
+        assert!(replicas[2].requested().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_hedging_example() {
+        let outcomes = hedging_example(&ExampleContext::captured()).await.unwrap();
+        let summary: Vec<_> = outcomes
+            .iter()
+            .map(|outcome| (outcome.strategy, outcome.latency, outcome.requests))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("race_ok", millis(10), 2),
+                ("hedged, slow primary", millis(40), 2),
+                ("hedged, fast primary", millis(10), 1),
+            ]
+        );
+    }
+}
@@ -0,0 +1,748 @@
+//! A small HTTP server, the counterpart of `fetch_data_from_api` (feature
+//! "http-server")
+//!
+//! Built on raw hyper:
+//! - `GET /hello` answers right away, `GET /delay/:ms` after sleeping `ms`
+//!   milliseconds (up to [`MAX_DELAY_MS`])
+//! - `GET /items?page=N` serves [`ITEM_PAGES`] pages of items, one per line,
+//!   each linking to the next in a `Link: <url>; rel="next"` header, like
+//!   GitHub's paginated API
+//! - every handler runs in the scope of the request's `x-request-id` (see
+//!   [`crate::request_id`]), reused from the request or generated, and the
+//!   response echoes it. `GET /request-id` answers the ID its handler sees
+//! - hyper spawns a task per connection, so requests on different
+//!   connections are handled concurrently: ten `/delay/100` requests take
+//!   about 100ms, not a second. The server and its connections are spawned
+//!   with `spawn_named`, so `cargo run -- tasks` lists them
+//! - [`HttpServer::shutdown`] is graceful: the server stops accepting
+//!   connections, and waits for the requests in flight to complete
+//! - started with [`HttpServer::start_with_jobs`], `POST /jobs` enqueues its
+//!   body, a number up to [`MAX_JOB_VALUE`], into the bounded pipeline of
+//!   [`crate::pipeline`]. When the queue is full the server answers
+//!   `503 Service Unavailable` with a `Retry-After` header rather than
+//!   buffering: backpressure crosses the HTTP boundary, and the jobs held in
+//!   memory stay bounded by the queue capacity however hard clients push
+//!
+//! Bound to an ephemeral port, it lets the client examples be tested offline.
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::header::{HOST, LINK, RETRY_AFTER};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+use tracing::{debug, info, instrument, warn};
+
+use crate::adaptive_timeout::{AdaptiveFetcher, AdaptiveTimeoutConfig};
+use crate::example_context::ExampleContext;
+use crate::pipeline::{self, Input, Output, PipelineConfig};
+use crate::request_id::{
+    current_request_id, handle_with_request_id, request_id_header_value, REQUEST_ID_HEADER,
+};
+use crate::task_registry::spawn_named;
+
+/// Longest delay `/delay/:ms` accepts.
+pub const MAX_DELAY_MS: u64 = 10_000;
+
+/// Pages served by `/items`.
+pub const ITEM_PAGES: usize = 3;
+
+/// Items on each page of `/items`.
+pub const ITEMS_PER_PAGE: usize = 2;
+
+/// Seconds a client is asked to wait before retrying a rejected job.
+pub const RETRY_AFTER_SECS: u64 = 1;
+
+/// Largest job `POST /jobs` accepts: the pipeline squares it into a `u64`.
+pub const MAX_JOB_VALUE: u64 = u32::MAX as u64;
+
+/// Requests served, and how many were in flight at once.
+#[derive(Debug, Default)]
+struct Counters {
+    served: AtomicUsize,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+    jobs_accepted: AtomicUsize,
+    jobs_rejected: AtomicUsize,
+    max_queued: AtomicUsize,
+}
+
+/// State shared by the handlers.
+#[derive(Debug)]
+struct State {
+    counters: Arc<Counters>,
+    jobs: Option<mpsc::Sender<Input>>,
+}
+
+/// Counts a request in flight until dropped.
+struct InFlight(Arc<Counters>);
+
+impl InFlight {
+    fn start(counters: &Arc<Counters>) -> Self {
+        let in_flight = counters.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        counters
+            .max_in_flight
+            .fetch_max(in_flight, Ordering::SeqCst);
+        InFlight(Arc::clone(counters))
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+        self.0.served.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+fn text(status: StatusCode, body: impl Into<Body>) -> Response<Body> {
+    let mut response = Response::new(body.into());
+    *response.status_mut() = status;
+    response
+}
+
+/// Runs [`route`] in the scope of the request's ID, and echoes the ID in the
+/// response.
+async fn route_with_request_id(request: Request<Body>, state: Arc<State>) -> Response<Body> {
+    let headers = request.headers().clone();
+    let (id, mut response) = handle_with_request_id(&headers, || route(request, state)).await;
+    response
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, request_id_header_value(&id));
+    response
+}
+
+async fn route(request: Request<Body>, state: Arc<State>) -> Response<Body> {
+    let _in_flight = InFlight::start(&state.counters);
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    debug!(%method, path, "request");
+
+    if path == "/jobs" {
+        if method != Method::POST {
+            return text(StatusCode::METHOD_NOT_ALLOWED, "only POST is supported");
+        }
+        return match &state.jobs {
+            Some(jobs) => enqueue(request, jobs, &state.counters).await,
+            None => text(StatusCode::NOT_FOUND, "not found"),
+        };
+    }
+    if method != Method::GET {
+        return text(StatusCode::METHOD_NOT_ALLOWED, "only GET is supported");
+    }
+    if path == "/hello" {
+        return text(StatusCode::OK, "Hello, world!");
+    }
+    if path == "/request-id" {
+        let id = current_request_id().map(|id| id.to_string());
+        return text(StatusCode::OK, id.unwrap_or_default());
+    }
+    if path == "/items" {
+        return items(&request);
+    }
+    match path.strip_prefix("/delay/").map(str::parse::<u64>) {
+        Some(Ok(ms)) if ms <= MAX_DELAY_MS => {
+            sleep(Duration::from_millis(ms)).await;
+            text(StatusCode::OK, format!("Waited {}ms", ms))
+        }
+        Some(_) => text(
+            StatusCode::BAD_REQUEST,
+            format!("expected a delay of at most {}ms", MAX_DELAY_MS),
+        ),
+        None => text(StatusCode::NOT_FOUND, "not found"),
+    }
+}
+
+/// `GET /items?page=N`: page N of the items, 1-based, with a link to the
+/// next page unless it is the last one.
+fn items(request: &Request<Body>) -> Response<Body> {
+    let page = request
+        .uri()
+        .query()
+        .and_then(|query| query.strip_prefix("page="))
+        .and_then(|page| page.parse::<usize>().ok());
+    let Some(page) = page.filter(|page| (1..=ITEM_PAGES).contains(page)) else {
+        return text(StatusCode::NOT_FOUND, "no such page");
+    };
+
+    let first = (page - 1) * ITEMS_PER_PAGE;
+    let body: Vec<String> = (first..first + ITEMS_PER_PAGE)
+        .map(|item| format!("item-{}", item))
+        .collect();
+    let mut response = text(StatusCode::OK, body.join("\n"));
+    let host = request
+        .headers()
+        .get(HOST)
+        .and_then(|host| host.to_str().ok());
+    if let (true, Some(host)) = (page < ITEM_PAGES, host) {
+        let link = format!("<http://{}/items?page={}>; rel=\"next\"", host, page + 1);
+        response
+            .headers_mut()
+            .insert(LINK, link.parse().expect("valid header value"));
+    }
+    response
+}
+
+/// `POST /jobs`: queues the number in the body, or rejects it right away
+/// when the queue is full. Numbers whose square overflows are rejected
+/// before they reach the pipeline.
+async fn enqueue(
+    request: Request<Body>,
+    jobs: &mpsc::Sender<Input>,
+    counters: &Counters,
+) -> Response<Body> {
+    let body = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(body) => body,
+        Err(error) => return text(StatusCode::BAD_REQUEST, error.to_string()),
+    };
+    let Some(value) = std::str::from_utf8(&body)
+        .ok()
+        .and_then(|body| body.trim().parse::<u64>().ok())
+    else {
+        return text(StatusCode::BAD_REQUEST, "expected a number");
+    };
+    if value > MAX_JOB_VALUE {
+        return text(
+            StatusCode::BAD_REQUEST,
+            format!("expected a number of at most {}", MAX_JOB_VALUE),
+        );
+    }
+
+    // Reserve a slot before numbering the job, so rejected jobs leave no gap.
+    match jobs.try_reserve() {
+        Ok(permit) => {
+            let index = counters.jobs_accepted.fetch_add(1, Ordering::SeqCst);
+            permit.send(Input { index, value });
+            let queued = jobs.max_capacity() - jobs.capacity();
+            counters.max_queued.fetch_max(queued, Ordering::SeqCst);
+            text(StatusCode::ACCEPTED, format!("queued job {}", index))
+        }
+        Err(TrySendError::Full(())) => {
+            counters.jobs_rejected.fetch_add(1, Ordering::SeqCst);
+            let mut response = text(StatusCode::SERVICE_UNAVAILABLE, "queue full, retry later");
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, RETRY_AFTER_SECS.into());
+            response
+        }
+        Err(TrySendError::Closed(())) => {
+            warn!("pipeline closed");
+            text(StatusCode::SERVICE_UNAVAILABLE, "pipeline closed")
+        }
+    }
+}
+
+/// Spawns hyper's connection tasks as named tasks.
+#[derive(Debug, Clone, Copy)]
+struct NamedExecutor;
+
+impl<F> hyper::rt::Executor<F> for NamedExecutor
+where
+    F: std::future::Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    fn execute(&self, future: F) {
+        spawn_named("http connection", future);
+    }
+}
+
+/// A running server.
+#[derive(Debug)]
+pub struct HttpServer {
+    addr: SocketAddr,
+    counters: Arc<Counters>,
+    shutdown: oneshot::Sender<()>,
+    server: JoinHandle<hyper::Result<()>>,
+}
+
+impl HttpServer {
+    /// Binds `addr` and serves in a spawned task. Port 0 picks a free port.
+    pub fn start(addr: SocketAddr) -> hyper::Result<Self> {
+        Self::serve(addr, None)
+    }
+
+    /// Like [`start`](Self::start), also serving `POST /jobs`, which queues
+    /// jobs into `jobs`. The sender is dropped when the server shuts down.
+    pub fn start_with_jobs(addr: SocketAddr, jobs: mpsc::Sender<Input>) -> hyper::Result<Self> {
+        Self::serve(addr, Some(jobs))
+    }
+
+    fn serve(addr: SocketAddr, jobs: Option<mpsc::Sender<Input>>) -> hyper::Result<Self> {
+        let counters = Arc::new(Counters::default());
+        let state = Arc::new(State {
+            counters: Arc::clone(&counters),
+            jobs,
+        });
+        let make_service = make_service_fn(move |_| {
+            let state = Arc::clone(&state);
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let state = Arc::clone(&state);
+                    async move { Ok::<_, Infallible>(route_with_request_id(request, state).await) }
+                }))
+            }
+        });
+
+        let server = Server::try_bind(&addr)?
+            .executor(NamedExecutor)
+            .serve(make_service);
+        let addr = server.local_addr();
+        let (shutdown, shutdown_rx) = oneshot::channel();
+        let server = spawn_named(
+            "http server",
+            server.with_graceful_shutdown(async {
+                let _ = shutdown_rx.await;
+            }),
+        );
+        info!(%addr, "listening");
+        Ok(HttpServer {
+            addr,
+            counters,
+            shutdown,
+            server,
+        })
+    }
+
+    /// Starts a server on a free port of localhost.
+    pub fn start_local() -> hyper::Result<Self> {
+        Self::start(SocketAddr::from(([127, 0, 0, 1], 0)))
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// URL of `path` on this server.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    /// Requests completed so far.
+    pub fn requests_served(&self) -> usize {
+        self.counters.served.load(Ordering::SeqCst)
+    }
+
+    /// Most requests handled at the same time so far.
+    pub fn max_in_flight(&self) -> usize {
+        self.counters.max_in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Jobs queued by `POST /jobs` so far.
+    pub fn jobs_accepted(&self) -> usize {
+        self.counters.jobs_accepted.load(Ordering::SeqCst)
+    }
+
+    /// Jobs turned away with a 503 because the queue was full.
+    pub fn jobs_rejected(&self) -> usize {
+        self.counters.jobs_rejected.load(Ordering::SeqCst)
+    }
+
+    /// Most jobs waiting in the queue at once so far.
+    pub fn max_queued(&self) -> usize {
+        self.counters.max_queued.load(Ordering::SeqCst)
+    }
+
+    /// Stops accepting connections, without waiting for the server's task:
+    /// for callers that cannot await it, like a test fixture's teardown.
+    pub fn stop(self) {
+        let _ = self.shutdown.send(());
+    }
+
+    /// Stops accepting connections and waits for the requests in flight.
+    pub async fn shutdown(self) -> hyper::Result<()> {
+        let _ = self.shutdown.send(());
+        self.server.await.expect("server task panicked")
+    }
+}
+
+/// Example: serving concurrent requests, then shutting down gracefully
+#[instrument(skip_all)]
+pub async fn http_server_example(
+    ctx: &ExampleContext,
+) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let server = HttpServer::start_local()?;
+    // The client example, behind an adaptive timeout.
+    let fetcher = AdaptiveFetcher::new(AdaptiveTimeoutConfig::default(), 3);
+    let mut bodies = vec![fetcher.fetch(&server.url("/hello")).await?];
+
+    let started = ctx.clock().now();
+    let delay = ctx.latency().delay(Duration::from_millis(100));
+    let url = server.url(&format!("/delay/{}", delay.as_millis()));
+    let delayed = futures::future::join_all((0..5).map(|_| fetcher.fetch(&url))).await;
+    for body in delayed {
+        bodies.push(body?);
+    }
+    info!(
+        elapsed = ?started.elapsed(),
+        max_in_flight = server.max_in_flight(),
+        ?delay,
+        "five delayed requests served concurrently"
+    );
+
+    server.shutdown().await?;
+    Ok(bodies)
+}
+
+/// Jobs accepted and rejected under overload, and the pipeline's outputs.
+#[derive(Debug)]
+pub struct BackpressureReport {
+    pub accepted: usize,
+    pub rejected: usize,
+    pub max_queued: usize,
+    pub outputs: Vec<Output>,
+}
+
+/// Submits `value` to `POST /jobs`, returning the response status.
+async fn submit(client: &reqwest::Client, url: &str, value: u64) -> Result<u16, String> {
+    let response = client
+        .post(url)
+        .body(value.to_string())
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(response.status().as_u16())
+}
+
+/// Example: 50 jobs submitted at once to a pipeline queueing at most 4
+#[instrument(skip_all)]
+pub async fn backpressure_example(
+    ctx: &ExampleContext,
+) -> Result<BackpressureReport, Box<dyn std::error::Error + Send + Sync>> {
+    let config = PipelineConfig {
+        capacity: 4,
+        transform_delay: ctx.latency().delay(Duration::from_millis(20)),
+        ..PipelineConfig::default()
+    };
+    let (jobs, stages) = pipeline::spawn_stages(config);
+    let server = HttpServer::start_with_jobs(SocketAddr::from(([127, 0, 0, 1], 0)), jobs)?;
+    let client = reqwest::Client::new();
+    let url = server.url("/jobs");
+
+    let statuses =
+        futures::future::join_all((0..50).map(|value| submit(&client, &url, value))).await;
+    let rejected = statuses.iter().filter(|status| **status == Ok(503)).count();
+    info!(
+        accepted = server.jobs_accepted(),
+        rejected,
+        max_queued = server.max_queued(),
+        "overload answered with 503 instead of buffering"
+    );
+
+    let (accepted, max_queued) = (server.jobs_accepted(), server.max_queued());
+    // Shutting down drops the sender: the pipeline drains and ends. Close the
+    // client's keep-alive connections first, or the graceful shutdown waits
+    // on them.
+    drop(client);
+    server.shutdown().await?;
+    let outputs = stages.await.map_err(|_| "pipeline panicked")?;
+    Ok(BackpressureReport {
+        accepted,
+        rejected,
+        max_queued,
+        outputs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    async fn get(client: &reqwest::Client, url: String) -> (StatusCode, String) {
+        let response = client.get(url).send().await.unwrap();
+        let status = StatusCode::from_u16(response.status().as_u16()).unwrap();
+        (status, response.text().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_routes() {
+        let server = HttpServer::start_local().unwrap();
+        let client = reqwest::Client::new();
+
+        assert_eq!(
+            get(&client, server.url("/hello")).await,
+            (StatusCode::OK, "Hello, world!".to_string())
+        );
+        assert_eq!(
+            get(&client, server.url("/delay/20")).await,
+            (StatusCode::OK, "Waited 20ms".to_string())
+        );
+        assert_eq!(
+            get(&client, server.url("/delay/soon")).await.0,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            get(&client, server.url("/delay/10001")).await.0,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            get(&client, server.url("/nope")).await.0,
+            StatusCode::NOT_FOUND
+        );
+
+        let response = client.post(server.url("/hello")).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 405);
+
+        assert_eq!(server.requests_served(), 6);
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_items_pages_link_to_the_next() {
+        let server = HttpServer::start_local().unwrap();
+        let client = reqwest::Client::new();
+
+        let first = client
+            .get(server.url("/items?page=1"))
+            .send()
+            .await
+            .unwrap();
+        let link = first.headers()["link"].to_str().unwrap().to_string();
+        assert_eq!(
+            link,
+            format!("<{}>; rel=\"next\"", server.url("/items?page=2"))
+        );
+        assert_eq!(first.text().await.unwrap(), "item-0\nitem-1");
+
+        let last = client
+            .get(server.url("/items?page=3"))
+            .send()
+            .await
+            .unwrap();
+        assert!(last.headers().get("link").is_none());
+        assert_eq!(last.text().await.unwrap(), "item-4\nitem-5");
+
+        for missing in ["/items", "/items?page=0", "/items?page=4"] {
+            assert_eq!(
+                get(&client, server.url(missing)).await.0,
+                StatusCode::NOT_FOUND,
+                "{}",
+                missing
+            );
+        }
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_handled_concurrently() {
+        let server = HttpServer::start_local().unwrap();
+        let client = reqwest::Client::new();
+        let start = Instant::now();
+        let responses =
+            futures::future::join_all((0..10).map(|_| get(&client, server.url("/delay/100"))))
+                .await;
+
+        assert!(responses
+            .iter()
+            .all(|(status, _)| *status == StatusCode::OK));
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert_eq!(server.max_in_flight(), 10);
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown_completes_in_flight_requests() {
+        let server = HttpServer::start_local().unwrap();
+        let client = reqwest::Client::new();
+        let url = server.url("/delay/100");
+        let request = tokio::spawn({
+            let client = client.clone();
+            async move { get(&client, url).await }
+        });
+        while server.max_in_flight() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let addr = server.local_addr();
+        server.shutdown().await.unwrap();
+        assert_eq!(
+            request.await.unwrap(),
+            (StatusCode::OK, "Waited 100ms".to_string())
+        );
+        // No longer accepting connections.
+        assert!(client
+            .get(format!("http://{}/hello", addr))
+            .send()
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bind_error() {
+        let server = HttpServer::start_local().unwrap();
+        assert!(HttpServer::start(server.local_addr()).is_err());
+        server.shutdown().await.unwrap();
+    }
+
+    fn slow_pipeline() -> PipelineConfig {
+        PipelineConfig {
+            capacity: 4,
+            transform_delay: Duration::from_millis(50),
+            ..PipelineConfig::default()
+        }
+    }
+
+    fn start_with_jobs(
+        config: PipelineConfig,
+    ) -> (HttpServer, crate::runtime::JoinHandle<Vec<Output>>) {
+        let (jobs, stages) = pipeline::spawn_stages(config);
+        let server =
+            HttpServer::start_with_jobs(SocketAddr::from(([127, 0, 0, 1], 0)), jobs).unwrap();
+        (server, stages)
+    }
+
+    #[tokio::test]
+    async fn test_overload_is_rejected_not_buffered() {
+        let (server, stages) = start_with_jobs(slow_pipeline());
+        let client = reqwest::Client::new();
+        let url = server.url("/jobs");
+
+        let responses = futures::future::join_all(
+            (0..200).map(|value| client.post(&url).body(value.to_string()).send()),
+        )
+        .await;
+        let mut accepted = 0;
+        for response in responses {
+            let response = response.unwrap();
+            match response.status().as_u16() {
+                202 => accepted += 1,
+                503 => assert_eq!(response.headers()["retry-after"], "1"),
+                status => panic!("unexpected status {}", status),
+            }
+        }
+
+        assert_eq!(accepted, server.jobs_accepted());
+        assert_eq!(server.jobs_accepted() + server.jobs_rejected(), 200);
+        assert!(server.jobs_rejected() > 0, "overload was rejected");
+        assert!(server.max_queued() <= 4, "queue stayed bounded");
+        server.shutdown().await.unwrap();
+        stages.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_queue_stays_bounded_under_sustained_overload() {
+        let (server, stages) = start_with_jobs(slow_pipeline());
+        let client = reqwest::Client::new();
+        let url = server.url("/jobs");
+
+        let mut accepted_per_wave = Vec::new();
+        for _ in 0..5 {
+            let before = server.jobs_accepted();
+            futures::future::join_all((0..50).map(|value| submit(&client, &url, value))).await;
+            accepted_per_wave.push(server.jobs_accepted() - before);
+            assert!(server.max_queued() <= 4);
+            sleep(Duration::from_millis(100)).await;
+        }
+
+        // The pipeline drains between waves: every wave gets jobs in, but
+        // never more than the free capacity.
+        assert!(
+            accepted_per_wave.iter().all(|&accepted| accepted > 0),
+            "{:?}",
+            accepted_per_wave
+        );
+        assert_eq!(server.jobs_accepted() + server.jobs_rejected(), 250);
+        server.shutdown().await.unwrap();
+        stages.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_accepted_jobs_are_all_processed() {
+        let (server, stages) = start_with_jobs(PipelineConfig::default());
+        let client = reqwest::Client::new();
+        let url = server.url("/jobs");
+        for value in 1..=3 {
+            assert_eq!(submit(&client, &url, value).await, Ok(202));
+        }
+
+        server.shutdown().await.unwrap();
+        let outputs = stages.await.unwrap();
+        let values: Vec<(usize, u64)> = outputs
+            .iter()
+            .map(|output| (output.index, output.value))
+            .collect();
+        assert_eq!(values, vec![(0, 1), (1, 4), (2, 9)]);
+    }
+
+    #[tokio::test]
+    async fn test_jobs_route_errors() {
+        let (server, stages) = start_with_jobs(PipelineConfig::default());
+        let client = reqwest::Client::new();
+
+        assert_eq!(
+            get(&client, server.url("/jobs")).await.0,
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        let response = client
+            .post(server.url("/jobs"))
+            .body("seven")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 400);
+        server.shutdown().await.unwrap();
+        stages.await.unwrap();
+
+        let without_jobs = HttpServer::start_local().unwrap();
+        assert_eq!(
+            submit(&client, &without_jobs.url("/jobs"), 1).await,
+            Ok(404)
+        );
+        without_jobs.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_oversized_job_is_rejected_without_closing_the_pipeline() {
+        let (server, stages) = start_with_jobs(PipelineConfig::default());
+        let client = reqwest::Client::new();
+        let url = server.url("/jobs");
+
+        assert_eq!(submit(&client, &url, MAX_JOB_VALUE + 1).await, Ok(400));
+        assert_eq!(submit(&client, &url, MAX_JOB_VALUE).await, Ok(202));
+        assert_eq!(submit(&client, &url, 3).await, Ok(202));
+
+        server.shutdown().await.unwrap();
+        let outputs = stages.await.unwrap();
+        let values: Vec<u64> = outputs.iter().map(|output| output.value).collect();
+        assert_eq!(values, vec![MAX_JOB_VALUE * MAX_JOB_VALUE, 9]);
+    }
+
+    #[tokio::test]
+    async fn test_closed_pipeline_is_unavailable() {
+        let (jobs, receiver) = mpsc::channel(4);
+        drop(receiver);
+        let server =
+            HttpServer::start_with_jobs(SocketAddr::from(([127, 0, 0, 1], 0)), jobs).unwrap();
+        let client = reqwest::Client::new();
+
+        assert_eq!(submit(&client, &server.url("/jobs"), 1).await, Ok(503));
+        assert_eq!(server.jobs_rejected(), 0, "not an overload");
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_backpressure_example() {
+        let report = backpressure_example(&ExampleContext::captured())
+            .await
+            .unwrap();
+        assert_eq!(report.accepted + report.rejected, 50);
+        assert!(report.rejected > 0);
+        assert!(report.max_queued <= 4);
+        assert_eq!(report.outputs.len(), report.accepted);
+    }
+
+    #[tokio::test]
+    async fn test_http_server_example() {
+        let bodies = http_server_example(&ExampleContext::captured())
+            .await
+            .unwrap();
+        assert_eq!(bodies[0], "Hello, world!");
+        assert_eq!(&bodies[1..], vec!["Waited 100ms"; 5]);
+    }
+}
@@ -0,0 +1,165 @@
+//! Tracing setup for the examples
+//!
+//! Every example is instrumented with `#[tracing::instrument]` and emits
+//! `tracing` events instead of printing. [`init_tracing`] installs a
+//! subscriber that prints those events, filtered by the `RUST_LOG`
+//! environment variable (default: `info`).
+//!
+//! Each span is also reported when it closes, with two timings:
+//! - `time.busy`: time spent inside `poll`
+//! - `time.idle`: time spent suspended at await points
+//!
+//! For an async function, a large `time.idle` and a tiny `time.busy` is the
+//! state machine at work: the task spends its life waiting, not running.
+//!
+//! The subscriber also captures the events of a run started with
+//! [`capture_into`] into an [`Output`], one line per event: that is how a
+//! captured example run gets its tracing events into its report. The run is
+//! wrapped in a span holding the output, and every event under that span is
+//! captured, including those of the tasks spawned in it with the current
+//! span (`.in_current_span()`, `.instrument(..)`).
+//!
+//! With the `console` feature, a `tokio-console` layer is installed as well.
+//! It needs the `tokio_unstable` cfg:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg tokio_unstable" cargo run --features console
+//! tokio-console
+//! ```
+
+use std::fmt::{self, Write};
+use std::future::Future;
+
+use tracing::field::{Field, Visit};
+use tracing::instrument::Instrumented;
+use tracing::{Event, Instrument, Subscriber};
+use tracing_subscriber::filter::{filter_fn, FilterExt};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+
+use crate::example_context::Output;
+
+/// Default filter when `RUST_LOG` is not set.
+const DEFAULT_FILTER: &str = "info";
+
+/// Installs the global tracing subscriber.
+///
+/// Safe to call more than once: only the first call installs a subscriber.
+pub fn init_tracing() {
+    install(std::io::stdout);
+}
+
+/// Same as [`init_tracing`], printing the events on stderr so that stdout
+/// only holds what the binary prints itself (the JSON report of `--output
+/// json`).
+pub fn init_tracing_to_stderr() {
+    install(std::io::stderr);
+}
+
+/// Target of the span [`capture_into`] wraps a run in.
+const CAPTURE_TARGET: &str = "capture";
+
+fn filter() -> EnvFilter {
+    EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER))
+}
+
+fn install<W>(writer: W)
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let registry = subscriber(writer);
+
+    #[cfg(feature = "console")]
+    let registry = registry.with(console_subscriber::spawn());
+
+    let _ = registry.try_init();
+}
+
+/// The subscriber [`install`] installs, without the console layer.
+pub(crate) fn subscriber<W>(writer: W) -> impl Subscriber + for<'a> LookupSpan<'a> + Send + Sync
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let fmt_layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_target(false)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_filter(filter());
+
+    // The capture span is invisible to the fmt layer.
+    let capture_layer = CaptureLayer
+        .with_filter(filter().or(filter_fn(|metadata| metadata.target() == CAPTURE_TARGET)));
+
+    tracing_subscriber::registry()
+        .with(fmt_layer)
+        .with(capture_layer)
+}
+
+/// Runs `future` with the events it emits also written to `output`.
+///
+/// Needs the subscriber of [`init_tracing`]: without it, nothing is
+/// captured.
+pub fn capture_into<F: Future>(output: Output, future: F) -> Instrumented<F> {
+    let span = tracing::trace_span!(target: CAPTURE_TARGET, "capture");
+    span.with_subscriber(|(id, dispatch)| {
+        if let Some(span) = dispatch
+            .downcast_ref::<Registry>()
+            .and_then(|registry| registry.span(id))
+        {
+            span.extensions_mut().insert(Captured(output));
+        }
+    });
+    future.instrument(span)
+}
+
+/// Extension of a capture span: where its events go.
+struct Captured(Output);
+
+/// Writes every event under a capture span to the span's output, as
+/// `LEVEL span:span: message field=value`.
+struct CaptureLayer;
+
+impl<S> Layer<S> for CaptureLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let Some(scope) = ctx.event_scope(event) else {
+            return;
+        };
+        let mut spans = Vec::new();
+        for span in scope {
+            let output = match span.extensions().get::<Captured>() {
+                Some(Captured(output)) => output.clone(),
+                None => {
+                    spans.push(span.name());
+                    continue;
+                }
+            };
+            let mut line = format!("{} ", event.metadata().level());
+            for name in spans.iter().rev() {
+                let _ = write!(line, "{}: ", name);
+            }
+            event.record(&mut LineVisitor(&mut line));
+            output.line(line.trim_end());
+            return;
+        }
+    }
+}
+
+/// Appends the message of an event, then its fields.
+struct LineVisitor<'a>(&'a mut String);
+
+impl Visit for LineVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let _ = if field.name() == "message" {
+            write!(self.0, "{:?} ", value)
+        } else {
+            write!(self.0, "{}={:?} ", field.name(), value)
+        };
+    }
+}
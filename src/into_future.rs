@@ -0,0 +1,315 @@
+//! Awaitable builders with `IntoFuture`
+//!
+//! `.await` does not require a `Future`: it calls `IntoFuture::into_future`
+//! on its operand first (every `Future` implements `IntoFuture` as itself).
+//! A builder implementing `IntoFuture` can therefore be awaited directly,
+//! as reqwest's `RequestBuilder` is:
+//!
+//! ```
+//! use rust_async_await_course_example::into_future::Client;
+//!
+//! fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let runtime = tokio::runtime::Runtime::new()?;
+//!     let client = Client::new();
+//!     let response =
+//!         runtime.block_on(async { client.request("/hello").header("x-name", "Ferris").await })?;
+//!     assert_eq!(response.body, "Hello, Ferris!");
+//!     Ok(())
+//! }
+//! ```
+//!
+//! The builder stays lazy: nothing is sent until it is awaited (or
+//! [`RequestBuilder::send`] is awaited, the explicit spelling). Errors made
+//! while building, like an invalid header name, are kept in the builder and
+//! returned when the request is sent, so every builder method stays
+//! chainable. `into_future` returns a boxed future here, as reqwest does;
+//! a hand-written future type would avoid the allocation.
+
+use std::fmt;
+use std::future::IntoFuture;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use tokio::time::{sleep, timeout};
+use tracing::{debug, info, instrument};
+
+use crate::example_context::ExampleContext;
+
+/// Error of a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestError {
+    /// A header name given to the builder is invalid.
+    InvalidHeader(String),
+    /// No response within the request's timeout.
+    TimedOut(Duration),
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::InvalidHeader(name) => write!(f, "invalid header name {:?}", name),
+            RequestError::TimedOut(limit) => write!(f, "request timed out after {:?}", limit),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+/// Response of the simulated server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+/// A client for a simulated server answering after a fixed latency:
+/// `/hello` greets the `x-name` header, anything else is a 404.
+#[derive(Debug, Clone)]
+pub struct Client {
+    latency: Duration,
+    sent: Arc<AtomicUsize>,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Self::with_latency(Duration::from_millis(10))
+    }
+
+    pub fn with_latency(latency: Duration) -> Self {
+        Client {
+            latency,
+            sent: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Starts building a request to `path`.
+    pub fn request(&self, path: impl Into<String>) -> RequestBuilder {
+        RequestBuilder {
+            client: self.clone(),
+            path: path.into(),
+            headers: Vec::new(),
+            timeout: None,
+            error: None,
+        }
+    }
+
+    /// Requests sent so far.
+    pub fn requests_sent(&self) -> usize {
+        self.sent.load(Ordering::SeqCst)
+    }
+
+    async fn respond(&self, path: &str, headers: &[(String, String)]) -> Response {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+        sleep(self.latency).await;
+        if path != "/hello" {
+            return Response {
+                status: 404,
+                body: format!("{} not found", path),
+            };
+        }
+        let name = headers
+            .iter()
+            .find(|(name, _)| name == "x-name")
+            .map_or("world", |(_, value)| value.as_str());
+        Response {
+            status: 200,
+            body: format!("Hello, {}!", name),
+        }
+    }
+}
+
+/// A request being built. Await it, or its [`send`](Self::send), to send it.
+#[derive(Debug)]
+#[must_use = "a request builder does nothing unless awaited"]
+pub struct RequestBuilder {
+    client: Client,
+    path: String,
+    headers: Vec<(String, String)>,
+    timeout: Option<Duration>,
+    /// First error made while building, returned on send.
+    error: Option<RequestError>,
+}
+
+impl RequestBuilder {
+    /// Adds a header. A name that is empty or contains whitespace fails the
+    /// request when it is sent.
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            self.error
+                .get_or_insert(RequestError::InvalidHeader(name.into()));
+        } else {
+            self.headers.push((name.to_ascii_lowercase(), value.into()));
+        }
+        self
+    }
+
+    pub fn timeout(mut self, limit: Duration) -> Self {
+        self.timeout = Some(limit);
+        self
+    }
+
+    /// Sends the request.
+    pub async fn send(self) -> Result<Response, RequestError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        debug!(path = %self.path, headers = self.headers.len(), "sending");
+        let response = self.client.respond(&self.path, &self.headers);
+        match self.timeout {
+            Some(limit) => timeout(limit, response)
+                .await
+                .map_err(|_| RequestError::TimedOut(limit)),
+            None => Ok(response.await),
+        }
+    }
+}
+
+impl IntoFuture for RequestBuilder {
+    type Output = Result<Response, RequestError>;
+    type IntoFuture = BoxFuture<'static, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(self.send())
+    }
+}
+
+/// Example: the same request sent with `.send().await` and with `.await`
+#[instrument(skip_all)]
+pub async fn into_future_example(
+    ctx: &ExampleContext,
+) -> Result<(Response, Response), RequestError> {
+    let client = Client::with_latency(ctx.latency().delay(Duration::from_millis(10)));
+
+    let sent = client
+        .request("/hello")
+        .header("x-name", "Ferris")
+        .send()
+        .await?;
+    let awaited = client.request("/hello").header("x-name", "Ferris").await?;
+    info!(?sent, ?awaited, "same response either way");
+
+    let error = client
+        .request("/hello")
+        .header("bad name", "x")
+        .await
+        .unwrap_err();
+    info!(%error, "builder error returned on await");
+
+    Ok((sent, awaited))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_send_and_await_are_the_same() {
+        let client = Client::new();
+        let sent = client.request("/hello").send().await;
+        let awaited = client.request("/hello").await;
+
+        assert_eq!(sent, awaited);
+        assert_eq!(
+            awaited,
+            Ok(Response {
+                status: 200,
+                body: "Hello, world!".into()
+            })
+        );
+        assert_eq!(client.requests_sent(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_headers_reach_the_server() {
+        let client = Client::new();
+        let response = client
+            .request("/hello")
+            .header("X-Name", "Ferris")
+            .await
+            .unwrap();
+        assert_eq!(response.body, "Hello, Ferris!");
+
+        let missing = client.request("/nope").await.unwrap();
+        assert_eq!(missing.status, 404);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_builder_is_lazy() {
+        let client = Client::new();
+        let builder = client.request("/hello").header("x-name", "later");
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(client.requests_sent(), 0);
+
+        let future = builder.into_future();
+        assert_eq!(client.requests_sent(), 0, "into_future does not poll");
+        future.await.unwrap();
+        assert_eq!(client.requests_sent(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_builder_error_returned_on_send() {
+        let client = Client::new();
+        let error = client
+            .request("/hello")
+            .header("", "empty")
+            .header("two words", "x")
+            .timeout(Duration::from_millis(1))
+            .await
+            .unwrap_err();
+
+        assert_eq!(error, RequestError::InvalidHeader(String::new()));
+        assert_eq!(client.requests_sent(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout_on_both_paths() {
+        let client = Client::with_latency(Duration::from_millis(50));
+        let limit = Duration::from_millis(20);
+
+        let sent = client.request("/hello").timeout(limit).send().await;
+        let awaited = client.request("/hello").timeout(limit).await;
+        assert_eq!(sent, Err(RequestError::TimedOut(limit)));
+        assert_eq!(awaited, Err(RequestError::TimedOut(limit)));
+        assert_eq!(
+            awaited.unwrap_err().to_string(),
+            "request timed out after 20ms"
+        );
+    }
+
+    /// Accepts anything `.await` accepts.
+    async fn await_any<F: IntoFuture>(awaitable: F) -> F::Output {
+        awaitable.await
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_generic_over_into_future() {
+        let client = Client::new();
+        assert!(await_any(client.request("/hello")).await.is_ok());
+        // A plain future is its own `IntoFuture`.
+        assert_eq!(await_any(async { 7 }).await, 7);
+    }
+
+    #[tokio::test]
+    async fn test_builder_future_is_spawnable() {
+        let client = Client::with_latency(Duration::ZERO);
+        let task = tokio::spawn(client.request("/hello").into_future());
+        assert_eq!(task.await.unwrap().unwrap().status, 200);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_into_future_example() {
+        let (sent, awaited) = into_future_example(&ExampleContext::captured())
+            .await
+            .unwrap();
+        assert_eq!(sent, awaited);
+        assert_eq!(sent.body, "Hello, Ferris!");
+    }
+}
@@ -0,0 +1,119 @@
+//! Joining branches with a timeout each
+//!
+//! `tokio::join!` waits for every branch: a single one that hangs stalls the
+//! whole join. Wrapping the join in one `timeout` throws away the results of
+//! the branches that did complete. [`join_with_timeouts!`] gives each branch
+//! its own limit instead, and returns one `Result<T, Elapsed>` per branch:
+//!
+//! ```
+//! use std::time::Duration;
+//! use rust_async_await_course_example::join_with_timeouts;
+//!
+//! # #[tokio::main(flavor = "current_thread", start_paused = true)]
+//! # async fn main() {
+//! let (fast, stuck) = join_with_timeouts!(
+//!     async { 1 } => Duration::from_millis(10),
+//!     std::future::pending::<u32>() => Duration::from_millis(50),
+//! );
+//! assert_eq!(fast, Ok(1));
+//! assert!(stuck.is_err());
+//! # }
+//! ```
+//!
+//! Branches still run concurrently, on the current task, and the join
+//! completes once every branch completed or ran out of time: after the
+//! longest of `min(latency, limit)`. A branch that times out is dropped,
+//! cancelled at its current await point.
+
+/// Like `tokio::join!`, with a timeout per branch: `future => limit`.
+///
+/// Evaluates to a tuple of `Result<T, tokio::time::error::Elapsed>`, one per
+/// branch, in order.
+#[macro_export]
+macro_rules! join_with_timeouts {
+    ($($future:expr => $limit:expr),+ $(,)?) => {
+        ::tokio::join!($(::tokio::time::timeout($limit, $future)),+)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    use tokio::time::{sleep, Instant};
+
+    async fn after(ms: u64, value: u32) -> u32 {
+        sleep(Duration::from_millis(ms)).await;
+        value
+    }
+
+    fn millis(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_all_branches_in_time() {
+        let start = Instant::now();
+        let (a, b, c) = join_with_timeouts!(
+            after(100, 1) => millis(150),
+            after(50, 2) => millis(150),
+            after(75, 3) => millis(150),
+        );
+
+        assert_eq!((a, b, c), (Ok(1), Ok(2), Ok(3)));
+        assert_eq!(start.elapsed(), millis(100), "branches ran concurrently");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slow_branch_times_out_alone() {
+        let start = Instant::now();
+        let (slow, fast) = join_with_timeouts!(
+            after(100, 1) => millis(30),
+            after(50, 2) => millis(60),
+        );
+
+        assert!(slow.is_err());
+        assert_eq!(fast, Ok(2), "the result of the other branch is kept");
+        assert_eq!(start.elapsed(), millis(50));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stuck_branch_bounded_by_its_limit() {
+        let start = Instant::now();
+        let (stuck, done) = join_with_timeouts!(
+            std::future::pending::<()>() => millis(80),
+            after(10, 7) => millis(20),
+        );
+
+        assert!(stuck.is_err());
+        assert_eq!(done, Ok(7));
+        assert_eq!(start.elapsed(), millis(80));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timed_out_branch_is_cancelled() {
+        let finished = AtomicBool::new(false);
+        let (result,) = join_with_timeouts!(
+            async {
+                sleep(millis(100)).await;
+                finished.store(true, Ordering::SeqCst);
+            } => millis(10)
+        );
+
+        assert!(result.is_err());
+        sleep(millis(200)).await;
+        assert!(!finished.load(Ordering::SeqCst), "dropped, never resumed");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ready_branch_beats_zero_timeout() {
+        // `timeout` polls the future before checking the deadline.
+        let (ready, pending) = join_with_timeouts!(
+            async { 1 } => Duration::ZERO,
+            after(1, 2) => Duration::ZERO,
+        );
+        assert_eq!(ready, Ok(1));
+        assert!(pending.is_err());
+    }
+}
@@ -1,5 +1,66 @@
+pub mod adaptive_timeout;
+pub mod async_drop;
+pub mod async_traits;
+pub mod cancel_safety;
+pub mod coalescing;
+pub mod context;
+pub mod dedicated_runtime;
+pub mod error_reporter;
+pub mod errors;
+pub mod example_context;
+#[cfg(feature = "exercises")]
+pub mod exercises;
+pub mod fairness;
+#[cfg(test)]
+mod fixtures;
+pub mod flaky;
+#[cfg(test)]
+mod flavor_matrix;
+pub mod future_size;
+pub mod hedging;
+#[cfg(any(test, feature = "http-server"))]
+pub mod http_server;
+pub mod instrumentation;
+pub mod into_future;
+pub mod join_timeouts;
+pub mod loadgen;
+pub mod memory_budget;
+pub mod oneshot;
+pub mod pagination;
+pub mod panics;
+pub mod pause;
+pub mod pipeline;
+pub mod plugins;
+pub mod poll_trace;
+pub mod progress;
+pub mod registry;
+pub mod report;
+pub mod request_id;
+pub mod runtime;
+pub mod runtimes;
+pub mod sandbox;
+pub mod scheduler;
+pub mod select_patterns;
+pub mod send_sync;
+pub mod shutdown;
+pub mod strategies;
+pub mod sync_bridge;
+pub mod sync_primitives;
+pub mod task_registry;
+pub mod threads_vs_async;
+pub mod transaction;
+pub mod unordered;
+pub mod waker;
+pub mod warmup;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+use example_context::ExampleContext;
+use request_id::TracedClient;
+use runtime::sleep;
 use std::time::Duration;
-use tokio::time::sleep;
+use tokio::sync::OnceCell;
+use tracing::{info, info_span, instrument, warn, Instrument};
 
 /// Example 1: Simple async state machine
 ///
@@ -10,18 +71,19 @@ use tokio::time::sleep;
 /// - Completed state
 ///
 /// The compiler generates code that can be paused at await points and resumed later.
-pub async fn async_state_machine_example() {
-    println!("  Starting async state machine...");
+#[instrument(skip_all)]
+pub async fn async_state_machine_example(ctx: &ExampleContext) {
+    info!("Starting async state machine...");
 
     // State transition 1: Before await
-    let start_time = std::time::Instant::now();
+    let start_time = ctx.clock().now();
 
     // Await point - function suspends here and yields control
-    sleep(Duration::from_millis(100)).await;
+    ctx.latency().sleep(Duration::from_millis(100)).await;
 
     // State transition 2: After await
     let elapsed = start_time.elapsed();
-    println!("  Completed after {:?}", elapsed);
+    info!(?elapsed, "Completed");
 }
 
 /// Example 2: Multiple await points
@@ -35,25 +97,26 @@ pub async fn async_state_machine_example() {
 /// 3. After second sleep
 /// 4. After third sleep
 /// 5. Completed
-pub async fn multiple_awaits_example() {
-    println!("  Starting task with multiple awaits...");
+#[instrument(skip_all)]
+pub async fn multiple_awaits_example(ctx: &ExampleContext) {
+    info!("Starting task with multiple awaits...");
 
     // First await point
-    println!("  Awaiting first operation...");
-    sleep(Duration::from_millis(50)).await;
-    println!("  First operation completed");
+    info!("Awaiting first operation...");
+    ctx.latency().sleep(Duration::from_millis(50)).await;
+    info!("First operation completed");
 
     // Second await point
-    println!("  Awaiting second operation...");
-    sleep(Duration::from_millis(50)).await;
-    println!("  Second operation completed");
+    info!("Awaiting second operation...");
+    ctx.latency().sleep(Duration::from_millis(50)).await;
+    info!("Second operation completed");
 
     // Third await point
-    println!("  Awaiting third operation...");
-    sleep(Duration::from_millis(50)).await;
-    println!("  Third operation completed");
+    info!("Awaiting third operation...");
+    ctx.latency().sleep(Duration::from_millis(50)).await;
+    info!("Third operation completed");
 
-    println!("  All operations finished!");
+    info!("All operations finished!");
 }
 
 /// Example 3: Variable scoping across await boundaries
@@ -65,28 +128,29 @@ pub async fn multiple_awaits_example() {
 /// - Variables before an await that aren't used after can be dropped
 /// - Variables needed after an await are moved into the Future's state
 /// - This affects what types can be used (must be Send for multi-threaded runtimes)
-pub async fn variable_scoping_example() {
-    println!("  Demonstrating variable scoping across awaits...");
+#[instrument(skip_all)]
+pub async fn variable_scoping_example(ctx: &ExampleContext) {
+    info!("Demonstrating variable scoping across awaits...");
 
     // Variable defined before await, used after
     let important_value = 42;
-    println!("  Before await: important_value = {}", important_value);
+    info!(important_value, "Before await");
 
     {
         // Variable scoped to this block, dropped before await
         let temporary_value = "temporary";
-        println!("  Temporary value: {}", temporary_value);
+        info!(temporary_value, "Temporary value");
     } // temporary_value dropped here
 
     // Await point - important_value must be stored in Future state
-    sleep(Duration::from_millis(50)).await;
+    ctx.latency().sleep(Duration::from_millis(50)).await;
 
     // important_value is still available after await
-    println!("  After await: important_value = {}", important_value);
+    info!(important_value, "After await");
 
     // New variable created after await
     let result = important_value * 2;
-    println!("  Computed result: {}", result);
+    info!(result, "Computed result");
 }
 
 /// Example 4: Complex async function with error handling
@@ -96,11 +160,12 @@ pub async fn variable_scoping_example() {
 /// - Returning Result types from async functions
 /// - Multiple await points with error propagation
 /// - Generic types in async functions
+#[instrument]
 pub async fn complex_async_function(
     id: u32,
     data: String,
 ) -> Result<String, Box<dyn std::error::Error>> {
-    println!("  Processing request with id: {}, data: {}", id, data);
+    info!("Processing request");
 
     // Simulate async validation
     sleep(Duration::from_millis(30)).await;
@@ -120,68 +185,99 @@ pub async fn complex_async_function(
     Ok(processed_data)
 }
 
+/// The client of `fetch_data_from_api`, built by its first call.
+static API_CLIENT: OnceCell<reqwest::Client> = OnceCell::const_new();
+
+/// The shared client of `fetch_data_from_api`, built once however many
+/// callers ask concurrently.
+///
+/// Pooled connections belong to the runtime that opened them, and this client
+/// outlives runtimes (every test has its own), so it keeps no idle
+/// connections.
+pub async fn api_client() -> Result<&'static reqwest::Client, reqwest::Error> {
+    API_CLIENT
+        .get_or_try_init(|| async {
+            info!("Building the API client");
+            reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .pool_max_idle_per_host(0)
+                .build()
+        })
+        .await
+}
+
 /// Example 5: Real-world async HTTP request
 ///
 /// This demonstrates using async with external libraries (reqwest).
 /// Shows how async/await integrates with I/O operations.
 ///
-/// Note: This makes a real network request. For tests, you might want to mock this.
+/// The request carries the `x-request-id` of the enclosing scope, or a new
+/// one (see `request_id`), and errors name it.
+///
+/// Note: This makes a real network request. The tests fetch from the
+/// in-process server of `http_server` instead.
+#[instrument]
 pub async fn fetch_data_from_api(url: &str) -> Result<String, Box<dyn std::error::Error>> {
-    println!("  Fetching data from: {}", url);
+    info!("Fetching data");
 
-    // Create HTTP client
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(10))
-        .build()?;
+    // Get the HTTP client, built by the first call
+    let client = TracedClient::from_client(api_client().await?.clone());
 
-    // Make async HTTP request
-    let response = client.get(url).send().await?;
+    // Make async HTTP request, checking the status and reading the body
+    let (id, body) = client.get_text(url).await?;
 
-    // Check response status
-    if !response.status().is_success() {
-        return Err(format!("HTTP error: {}", response.status()).into());
-    }
-
-    // Get response body as text
-    let body = response.text().await?;
-
-    println!("  Successfully fetched {} bytes", body.len());
+    info!(request_id = %id, bytes = body.len(), "Successfully fetched");
     Ok(body)
 }
 
 /// Helper function to demonstrate concurrent execution
 ///
 /// This shows how multiple async tasks can run concurrently using tokio::join!
-pub async fn concurrent_execution_example() {
-    println!("  Starting concurrent tasks...");
+#[instrument(skip_all)]
+pub async fn concurrent_execution_example(ctx: &ExampleContext) {
+    info!("Starting concurrent tasks...");
 
     let task1 = async {
-        sleep(Duration::from_millis(100)).await;
-        println!("  Task 1 completed");
+        ctx.latency().sleep(Duration::from_millis(100)).await;
+        info!("Task 1 completed");
         1
-    };
+    }
+    .instrument(info_span!("task", id = 1));
 
     let task2 = async {
-        sleep(Duration::from_millis(50)).await;
-        println!("  Task 2 completed");
+        ctx.latency().sleep(Duration::from_millis(50)).await;
+        info!("Task 2 completed");
         2
-    };
+    }
+    .instrument(info_span!("task", id = 2));
 
     let task3 = async {
-        sleep(Duration::from_millis(75)).await;
-        println!("  Task 3 completed");
+        ctx.latency().sleep(Duration::from_millis(75)).await;
+        info!("Task 3 completed");
         3
-    };
-
-    // All tasks run concurrently and complete when all are done
-    let (result1, result2, result3) = tokio::join!(task1, task2, task3);
-
-    println!(
-        "  All tasks completed: {} + {} + {} = {}",
-        result1,
-        result2,
-        result3,
-        result1 + result2 + result3
+    }
+    .instrument(info_span!("task", id = 3));
+
+    // All tasks run concurrently and complete when all are done.
+    // Each one has its own span, so the trace shows them interleaving.
+    // Each one also has its own timeout: a task that hangs cannot stall the
+    // join, and the results of the others are kept.
+    let limit = Duration::from_millis(150);
+    let (result1, result2, result3) =
+        join_with_timeouts!(task1 => limit, task2 => limit, task3 => limit);
+
+    let results = [result1, result2, result3].map(|result| {
+        result.unwrap_or_else(|_| {
+            warn!(?limit, "Task timed out");
+            0
+        })
+    });
+    info!(
+        "All tasks completed: {} + {} + {} = {}",
+        results[0],
+        results[1],
+        results[2],
+        results.iter().sum::<i32>()
     );
 }
 
@@ -189,15 +285,16 @@ pub async fn concurrent_execution_example() {
 ///
 /// This demonstrates that async functions are syntactic sugar for functions
 /// returning impl Future<Output = T>
-pub async fn async_sugar_example() -> i32 {
-    sleep(Duration::from_millis(10)).await;
+#[instrument(skip_all)]
+pub async fn async_sugar_example(ctx: &ExampleContext) -> i32 {
+    ctx.latency().sleep(Duration::from_millis(10)).await;
     42
 }
 
 // The above is equivalent to:
-// pub fn async_sugar_example() -> impl Future<Output = i32> {
-//     async {
-//         sleep(Duration::from_millis(10)).await;
+// pub fn async_sugar_example(ctx: &ExampleContext) -> impl Future<Output = i32> + '_ {
+//     async move {
+//         ctx.latency().sleep(Duration::from_millis(10)).await;
 //         42
 //     }
 // }
@@ -209,19 +306,19 @@ mod tests {
     #[tokio::test]
     async fn test_async_state_machine() {
         // This should complete without panicking
-        async_state_machine_example().await;
+        async_state_machine_example(&ExampleContext::captured()).await;
     }
 
     #[tokio::test]
     async fn test_multiple_awaits() {
         // This should complete all awaits successfully
-        multiple_awaits_example().await;
+        multiple_awaits_example(&ExampleContext::captured()).await;
     }
 
     #[tokio::test]
     async fn test_variable_scoping() {
         // Variables should be properly scoped across awaits
-        variable_scoping_example().await;
+        variable_scoping_example(&ExampleContext::captured()).await;
     }
 
     #[tokio::test]
@@ -239,15 +336,65 @@ mod tests {
 
     #[tokio::test]
     async fn test_concurrent_execution() {
-        concurrent_execution_example().await;
+        concurrent_execution_example(&ExampleContext::captured()).await;
     }
 
     #[tokio::test]
     async fn test_async_sugar() {
-        let result = async_sugar_example().await;
+        let result = async_sugar_example(&ExampleContext::captured()).await;
         assert_eq!(result, 42);
     }
 
-    // Note: We don't test fetch_data_from_api in unit tests as it requires network access
-    // In a real project, you'd use mocking or integration tests for this
+    #[tokio::test]
+    async fn test_fetch_data_from_api() {
+        let server = http_server::HttpServer::start_local().unwrap();
+        let body = fetch_data_from_api(&server.url("/hello")).await.unwrap();
+        assert_eq!(body, "Hello, world!");
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_from_api_sends_the_request_id() {
+        use request_id::{with_request_id, RequestId};
+
+        let server = http_server::HttpServer::start_local().unwrap();
+        let body = with_request_id(
+            RequestId::new("fetch-1"),
+            fetch_data_from_api(&server.url("/request-id")),
+        )
+        .await
+        .unwrap();
+        assert_eq!(body, "fetch-1");
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_api_client_is_built_once() {
+        let (first, second) = tokio::join!(api_client(), api_client());
+        let first = first.unwrap();
+        assert!(std::ptr::eq(first, second.unwrap()));
+        assert!(std::ptr::eq(first, api_client().await.unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_from_api_http_error() {
+        let server = http_server::HttpServer::start_local().unwrap();
+        let error = fetch_data_from_api(&server.url("/missing"))
+            .await
+            .unwrap_err();
+        assert!(
+            error.to_string().ends_with("] HTTP error: 404 Not Found"),
+            "{}",
+            error
+        );
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fetch_data_from_api_connection_refused() {
+        let server = http_server::HttpServer::start_local().unwrap();
+        let url = server.url("/hello");
+        server.shutdown().await.unwrap();
+        assert!(fetch_data_from_api(&url).await.is_err());
+    }
 }
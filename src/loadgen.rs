@@ -0,0 +1,914 @@
+//! Throughput/latency load generator (`cargo run -- loadgen`)
+//!
+//! Two ways to load a server, which answer different questions:
+//! - open loop: requests are fired at a fixed rate whatever the server does,
+//!   like independent users. Latency is measured from the time a request was
+//!   *scheduled*, so time spent queueing behind a slow server is counted
+//!   (measuring from the actual send would hide it: "coordinated omission").
+//!   In-flight requests are capped; a request over the cap is dropped rather
+//!   than delayed, which would turn the loop into a closed one.
+//! - closed loop: a fixed number of workers each send a request, wait for
+//!   the answer, and send the next. Throughput adapts to the server, and
+//!   latency stays low even when the server is saturated.
+//!
+//! Latencies go into a [`LatencyHistogram`] with log-linear buckets (8 per
+//! power of two, so at most 12.5% error) instead of being stored one by one.
+//!
+//! The default target is an in-process [`SimulatedServer`] with a bounded
+//! number of concurrent requests. `--target http-server` starts the example
+//! server of [`crate::http_server`] on a local port and loads its
+//! `/delay/10` route over real HTTP; any HTTP URL can be targeted instead.
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{interval_at, sleep, Instant};
+use tracing::{info, instrument};
+
+use crate::example_context::ExampleContext;
+
+/// Sub-buckets per power of two.
+const SUB_BUCKETS: u64 = 8;
+/// Enough buckets for any `u64` number of microseconds.
+const BUCKETS: usize = 62 * SUB_BUCKETS as usize;
+
+/// Highest open-loop rate accepted, in requests per second.
+pub const MAX_RATE: u32 = 1_000_000;
+
+/// Usage of the `loadgen` subcommand.
+pub const USAGE: &str = "\
+usage: loadgen [--mode open|closed] [--rate N] [--concurrency N]
+               [--duration-ms N] [--max-in-flight N]
+               [--target simulated|http-server|URL] [--export PATH]
+
+  --mode           open: fixed request rate; closed: fixed number of workers (default open)
+  --rate           open loop: requests per second, up to 1000000 (default 200)
+  --concurrency    closed loop: number of workers (default 4)
+  --duration-ms    how long to send requests (default 1000)
+  --max-in-flight  open loop: requests over this cap are dropped (default 1000)
+  --target         simulated: in-process simulated server (default)
+                   http-server: the example HTTP server on a local port (feature http-server)
+                   URL: GET this URL
+  --url            same as --target URL
+  --export         write the report as JSON to this file";
+
+/// Latency histogram with log-linear buckets, in microseconds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    buckets: Vec<u64>,
+    count: u64,
+    sum_micros: u64,
+    min_micros: u64,
+    max_micros: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            buckets: vec![0; BUCKETS],
+            count: 0,
+            sum_micros: 0,
+            min_micros: u64::MAX,
+            max_micros: 0,
+        }
+    }
+}
+
+fn bucket_index(micros: u64) -> usize {
+    if micros < SUB_BUCKETS {
+        return micros as usize;
+    }
+    let exponent = 63 - u64::from(micros.leading_zeros());
+    let mantissa = micros >> (exponent - 3);
+    ((exponent - 2) * SUB_BUCKETS + mantissa - SUB_BUCKETS) as usize
+}
+
+/// Largest value falling in bucket `index`.
+fn bucket_upper_bound(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return index;
+    }
+    let exponent = index / SUB_BUCKETS + 2;
+    let mantissa = index % SUB_BUCKETS + SUB_BUCKETS;
+    ((mantissa + 1) << (exponent - 3)) - 1
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        self.buckets[bucket_index(micros)] += 1;
+        self.count += 1;
+        self.sum_micros = self.sum_micros.saturating_add(micros);
+        self.min_micros = self.min_micros.min(micros);
+        self.max_micros = self.max_micros.max(micros);
+    }
+
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        for (bucket, count) in self.buckets.iter_mut().zip(&other.buckets) {
+            *bucket += count;
+        }
+        self.count += other.count;
+        self.sum_micros = self.sum_micros.saturating_add(other.sum_micros);
+        self.min_micros = self.min_micros.min(other.min_micros);
+        self.max_micros = self.max_micros.max(other.max_micros);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn min(&self) -> Duration {
+        Duration::from_micros(if self.count == 0 { 0 } else { self.min_micros })
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.max_micros)
+    }
+
+    pub fn mean(&self) -> Duration {
+        Duration::from_micros(self.sum_micros.checked_div(self.count).unwrap_or(0))
+    }
+
+    /// Latency under which `percent`% of the requests completed, rounded up
+    /// to the bucket's upper bound (but never above the maximum).
+    pub fn percentile(&self, percent: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((percent / 100.0 * self.count as f64).ceil() as u64).clamp(1, self.count);
+        let mut seen = 0;
+        for (index, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_micros(bucket_upper_bound(index).min(self.max_micros));
+            }
+        }
+        self.max()
+    }
+}
+
+/// A server handling a bounded number of requests at a time, each taking a
+/// fixed service time. Requests over the capacity queue up.
+#[derive(Debug)]
+pub struct SimulatedServer {
+    capacity: Semaphore,
+    service_time: Duration,
+}
+
+impl SimulatedServer {
+    pub fn new(capacity: usize, service_time: Duration) -> Self {
+        SimulatedServer {
+            capacity: Semaphore::new(capacity),
+            service_time,
+        }
+    }
+
+    /// Handles one request. The capacity can be exhausted, but is never
+    /// closed, so this never fails.
+    pub async fn handle(&self) {
+        let _permit = self.capacity.acquire().await.expect("semaphore closed");
+        sleep(self.service_time).await;
+    }
+}
+
+/// Where requests are sent.
+#[derive(Debug, Clone)]
+pub enum Target {
+    Simulated(Arc<SimulatedServer>),
+    Http {
+        client: reqwest::Client,
+        url: String,
+    },
+}
+
+impl Target {
+    /// Sends one request; `Err` describes a failed request.
+    async fn send(&self) -> Result<(), String> {
+        match self {
+            Target::Simulated(server) => {
+                server.handle().await;
+                Ok(())
+            }
+            Target::Http { client, url } => {
+                let response = client
+                    .get(url)
+                    .send()
+                    .await
+                    .map_err(|error| error.to_string())?;
+                if !response.status().is_success() {
+                    return Err(format!("HTTP {}", response.status()));
+                }
+                response.bytes().await.map_err(|error| error.to_string())?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// How load is generated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// A fixed rate, in requests per second.
+    Open { rate: u32 },
+    /// A fixed number of workers sending back-to-back.
+    Closed { concurrency: usize },
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Open { rate } => write!(f, "open loop at {} req/s", rate),
+            Mode::Closed { concurrency } => write!(f, "closed loop with {} workers", concurrency),
+        }
+    }
+}
+
+/// Configuration of a load run.
+#[derive(Debug, Clone)]
+pub struct LoadConfig {
+    pub mode: Mode,
+    /// How long requests are sent; the run then waits for the ones in flight.
+    pub duration: Duration,
+    /// Open loop: requests fired while this many are in flight are dropped.
+    pub max_in_flight: usize,
+}
+
+impl Default for LoadConfig {
+    fn default() -> Self {
+        LoadConfig {
+            mode: Mode::Open { rate: 200 },
+            duration: Duration::from_secs(1),
+            max_in_flight: 1000,
+        }
+    }
+}
+
+/// Result of a load run.
+#[derive(Debug, Clone)]
+pub struct LoadReport {
+    pub mode: Mode,
+    /// From the first request to the last answer.
+    pub elapsed: Duration,
+    pub sent: u64,
+    pub succeeded: u64,
+    pub failed: u64,
+    /// Open loop: requests not sent because of the in-flight cap.
+    pub dropped: u64,
+    /// Latencies of the successful requests.
+    pub latencies: LatencyHistogram,
+}
+
+impl LoadReport {
+    fn new(mode: Mode) -> Self {
+        LoadReport {
+            mode,
+            elapsed: Duration::ZERO,
+            sent: 0,
+            succeeded: 0,
+            failed: 0,
+            dropped: 0,
+            latencies: LatencyHistogram::default(),
+        }
+    }
+
+    fn record(&mut self, latency: Duration, result: Result<(), String>) {
+        match result {
+            Ok(()) => {
+                self.succeeded += 1;
+                self.latencies.record(latency);
+            }
+            Err(_) => self.failed += 1,
+        }
+    }
+
+    /// Successful requests per second.
+    pub fn throughput(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+        self.succeeded as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// The report as a JSON object.
+    pub fn to_json(&self) -> String {
+        let (mode, rate, concurrency) = match self.mode {
+            Mode::Open { rate } => ("open", rate.to_string(), "null".to_string()),
+            Mode::Closed { concurrency } => ("closed", "null".to_string(), concurrency.to_string()),
+        };
+        let micros = |latency: Duration| latency.as_micros();
+        format!(
+            concat!(
+                "{{\"mode\":\"{}\",\"rate\":{},\"concurrency\":{},\"elapsed_ms\":{},",
+                "\"sent\":{},\"succeeded\":{},\"failed\":{},\"dropped\":{},",
+                "\"throughput\":{:.1},\"latency_us\":{{\"min\":{},\"mean\":{},",
+                "\"p50\":{},\"p90\":{},\"p99\":{},\"p999\":{},\"max\":{}}}}}"
+            ),
+            mode,
+            rate,
+            concurrency,
+            self.elapsed.as_millis(),
+            self.sent,
+            self.succeeded,
+            self.failed,
+            self.dropped,
+            self.throughput(),
+            micros(self.latencies.min()),
+            micros(self.latencies.mean()),
+            micros(self.latencies.percentile(50.0)),
+            micros(self.latencies.percentile(90.0)),
+            micros(self.latencies.percentile(99.0)),
+            micros(self.latencies.percentile(99.9)),
+            micros(self.latencies.max()),
+        )
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} for {:?}", self.mode, self.elapsed)?;
+        writeln!(
+            f,
+            "  requests: {} sent, {} succeeded, {} failed, {} dropped",
+            self.sent, self.succeeded, self.failed, self.dropped
+        )?;
+        writeln!(f, "  throughput: {:.1} req/s", self.throughput())?;
+        write!(
+            f,
+            "  latency: min {:?}, mean {:?}, p50 {:?}, p90 {:?}, p99 {:?}, p99.9 {:?}, max {:?}",
+            self.latencies.min(),
+            self.latencies.mean(),
+            self.latencies.percentile(50.0),
+            self.latencies.percentile(90.0),
+            self.latencies.percentile(99.0),
+            self.latencies.percentile(99.9),
+            self.latencies.max()
+        )
+    }
+}
+
+/// Fires requests at `rate` per second for `config.duration`.
+async fn open_loop(target: &Target, rate: u32, config: &LoadConfig) -> LoadReport {
+    let mut report = LoadReport::new(Mode::Open { rate });
+    // At least a nanosecond: `interval_at` panics on a zero period.
+    let period = Duration::from_secs_f64(1.0 / f64::from(rate.max(1))).max(Duration::from_nanos(1));
+    let start = Instant::now();
+    let deadline = start + config.duration;
+    let in_flight = Arc::new(Semaphore::new(config.max_in_flight));
+    let mut requests = JoinSet::new();
+
+    // The default `Burst` behavior catches up on missed ticks, keeping the
+    // rate even if this loop falls behind.
+    let mut ticks = interval_at(start, period);
+    loop {
+        let scheduled = ticks.tick().await;
+        if scheduled >= deadline {
+            break;
+        }
+        report.sent += 1;
+        let Ok(permit) = Arc::clone(&in_flight).try_acquire_owned() else {
+            report.dropped += 1;
+            continue;
+        };
+        let target = target.clone();
+        requests.spawn(async move {
+            let result = target.send().await;
+            drop(permit);
+            // From the scheduled time: queueing delays count.
+            (scheduled.elapsed(), result)
+        });
+    }
+
+    while let Some(joined) = requests.join_next().await {
+        let (latency, result) = joined.expect("request task panicked");
+        report.record(latency, result);
+    }
+    report.elapsed = start.elapsed();
+    report
+}
+
+/// Runs `concurrency` workers sending back-to-back for `config.duration`.
+async fn closed_loop(target: &Target, concurrency: usize, config: &LoadConfig) -> LoadReport {
+    let start = Instant::now();
+    let deadline = start + config.duration;
+    let mut workers = JoinSet::new();
+    for _ in 0..concurrency {
+        let target = target.clone();
+        workers.spawn(async move {
+            let mut report = LoadReport::new(Mode::Closed { concurrency });
+            while Instant::now() < deadline {
+                let sent_at = Instant::now();
+                report.sent += 1;
+                let result = target.send().await;
+                report.record(sent_at.elapsed(), result);
+            }
+            report
+        });
+    }
+
+    let mut report = LoadReport::new(Mode::Closed { concurrency });
+    while let Some(joined) = workers.join_next().await {
+        let worker = joined.expect("worker panicked");
+        report.sent += worker.sent;
+        report.succeeded += worker.succeeded;
+        report.failed += worker.failed;
+        report.latencies.merge(&worker.latencies);
+    }
+    report.elapsed = start.elapsed();
+    report
+}
+
+/// Generates load on `target` as configured.
+#[instrument(skip(target))]
+pub async fn generate_load(target: &Target, config: &LoadConfig) -> LoadReport {
+    let report = match config.mode {
+        Mode::Open { rate } => open_loop(target, rate, config).await,
+        Mode::Closed { concurrency } => closed_loop(target, concurrency, config).await,
+    };
+    info!(
+        sent = report.sent,
+        succeeded = report.succeeded,
+        dropped = report.dropped,
+        throughput = report.throughput(),
+        p99 = ?report.latencies.percentile(99.0),
+        "load run finished"
+    );
+    report
+}
+
+/// Error of the `loadgen` subcommand.
+#[derive(Debug)]
+pub enum LoadgenError {
+    InvalidArgs(String),
+    Client(reqwest::Error),
+    #[cfg(any(test, feature = "http-server"))]
+    Server(hyper::Error),
+    Export(io::Error),
+}
+
+impl fmt::Display for LoadgenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadgenError::InvalidArgs(message) => write!(f, "invalid arguments: {}", message),
+            LoadgenError::Client(error) => write!(f, "failed to build HTTP client: {}", error),
+            #[cfg(any(test, feature = "http-server"))]
+            LoadgenError::Server(error) => write!(f, "failed to start HTTP server: {}", error),
+            LoadgenError::Export(error) => write!(f, "failed to export report: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for LoadgenError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadgenError::InvalidArgs(_) => None,
+            LoadgenError::Client(error) => Some(error),
+            #[cfg(any(test, feature = "http-server"))]
+            LoadgenError::Server(error) => Some(error),
+            LoadgenError::Export(error) => Some(error),
+        }
+    }
+}
+
+/// Target named on the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetArg {
+    Simulated,
+    /// The example server of `http_server`, started for the run.
+    HttpServer,
+    Url(String),
+}
+
+/// Parsed command line of the `loadgen` subcommand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadgenArgs {
+    pub mode: Mode,
+    pub duration: Duration,
+    pub max_in_flight: usize,
+    pub target: TargetArg,
+    pub export: Option<PathBuf>,
+}
+
+impl LoadgenArgs {
+    /// Parses the arguments following `loadgen`.
+    pub fn parse(args: &[String]) -> Result<Self, LoadgenError> {
+        fn number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, LoadgenError> {
+            value.parse().map_err(|_| {
+                LoadgenError::InvalidArgs(format!("{} expects a number, got {:?}", flag, value))
+            })
+        }
+
+        let mut mode = "open".to_string();
+        let mut rate = 200;
+        let mut concurrency = 4;
+        let defaults = LoadConfig::default();
+        let mut parsed = LoadgenArgs {
+            mode: defaults.mode,
+            duration: defaults.duration,
+            max_in_flight: defaults.max_in_flight,
+            target: TargetArg::Simulated,
+            export: None,
+        };
+
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| LoadgenError::InvalidArgs(format!("{} expects a value", flag)))?;
+            match flag.as_str() {
+                "--mode" => mode = value.clone(),
+                "--rate" => rate = number(flag, value)?,
+                "--concurrency" => concurrency = number(flag, value)?,
+                "--duration-ms" => parsed.duration = Duration::from_millis(number(flag, value)?),
+                "--max-in-flight" => parsed.max_in_flight = number(flag, value)?,
+                "--target" => {
+                    parsed.target = match value.as_str() {
+                        "simulated" => TargetArg::Simulated,
+                        "http-server" => TargetArg::HttpServer,
+                        url if url.starts_with("http://") || url.starts_with("https://") => {
+                            TargetArg::Url(url.to_string())
+                        }
+                        _ => {
+                            return Err(LoadgenError::InvalidArgs(format!(
+                                "--target is simulated, http-server or a URL, got {:?}",
+                                value
+                            )))
+                        }
+                    }
+                }
+                "--url" => parsed.target = TargetArg::Url(value.clone()),
+                "--export" => parsed.export = Some(PathBuf::from(value)),
+                _ => return Err(LoadgenError::InvalidArgs(format!("unknown flag {}", flag))),
+            }
+        }
+
+        if rate > MAX_RATE {
+            return Err(LoadgenError::InvalidArgs(format!(
+                "--rate is at most {}, got {}",
+                MAX_RATE, rate
+            )));
+        }
+        parsed.mode = match mode.as_str() {
+            "open" if rate > 0 => Mode::Open { rate },
+            "closed" if concurrency > 0 => Mode::Closed { concurrency },
+            "open" | "closed" => {
+                return Err(LoadgenError::InvalidArgs(
+                    "--rate and --concurrency must be positive".to_string(),
+                ))
+            }
+            _ => {
+                return Err(LoadgenError::InvalidArgs(format!(
+                    "--mode is open or closed, got {:?}",
+                    mode
+                )))
+            }
+        };
+        Ok(parsed)
+    }
+}
+
+fn http_target(url: String) -> Result<Target, LoadgenError> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .map_err(LoadgenError::Client)?;
+    Ok(Target::Http { client, url })
+}
+
+/// Runs the `loadgen` subcommand: parses `args`, generates the load, and
+/// exports the report if asked.
+pub async fn run_cli(args: &[String]) -> Result<LoadReport, LoadgenError> {
+    let args = LoadgenArgs::parse(args)?;
+    let config = LoadConfig {
+        mode: args.mode,
+        duration: args.duration,
+        max_in_flight: args.max_in_flight,
+    };
+
+    let report = match args.target {
+        TargetArg::Simulated => {
+            let server = SimulatedServer::new(4, Duration::from_millis(10));
+            generate_load(&Target::Simulated(Arc::new(server)), &config).await
+        }
+        TargetArg::Url(url) => generate_load(&http_target(url)?, &config).await,
+        #[cfg(any(test, feature = "http-server"))]
+        TargetArg::HttpServer => {
+            let server =
+                crate::http_server::HttpServer::start_local().map_err(LoadgenError::Server)?;
+            let report = generate_load(&http_target(server.url("/delay/10"))?, &config).await;
+            server.shutdown().await.map_err(LoadgenError::Server)?;
+            report
+        }
+        #[cfg(not(any(test, feature = "http-server")))]
+        TargetArg::HttpServer => {
+            return Err(LoadgenError::InvalidArgs(
+                "--target http-server needs the http-server feature".to_string(),
+            ))
+        }
+    };
+    if let Some(path) = args.export {
+        tokio::fs::write(path, report.to_json())
+            .await
+            .map_err(LoadgenError::Export)?;
+    }
+    Ok(report)
+}
+
+/// Example: open and closed loop against a server saturated at 100 req/s
+#[instrument(skip_all)]
+pub async fn loadgen_example(ctx: &ExampleContext) -> [LoadReport; 2] {
+    // 2 requests at a time, 20ms each: at most 100 req/s.
+    let service_time = ctx.latency().delay(Duration::from_millis(20));
+    let target = Target::Simulated(Arc::new(SimulatedServer::new(2, service_time)));
+    let duration = Duration::from_millis(300);
+
+    // Offered 200 req/s: requests queue up and latency grows with the queue.
+    let open = generate_load(
+        &target,
+        &LoadConfig {
+            mode: Mode::Open { rate: 200 },
+            duration,
+            ..LoadConfig::default()
+        },
+    )
+    .await;
+    // Two workers: the server is saturated, but nothing queues.
+    let closed = generate_load(
+        &target,
+        &LoadConfig {
+            mode: Mode::Closed { concurrency: 2 },
+            duration,
+            ..LoadConfig::default()
+        },
+    )
+    .await;
+
+    for report in [&open, &closed] {
+        for line in report.to_string().lines() {
+            info!("{}", line);
+        }
+    }
+    [open, closed]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::temp_dir;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    fn simulated(capacity: usize, service_ms: u64) -> Target {
+        Target::Simulated(Arc::new(SimulatedServer::new(
+            capacity,
+            Duration::from_millis(service_ms),
+        )))
+    }
+
+    #[test]
+    fn test_bucket_bounds() {
+        for micros in [0, 1, 7, 8, 15, 16, 17, 1000, 123_456, u64::MAX / 2] {
+            let index = bucket_index(micros);
+            assert!(bucket_upper_bound(index) >= micros);
+            assert!(index == 0 || bucket_upper_bound(index - 1) < micros);
+        }
+        assert_eq!(bucket_index(u64::MAX), BUCKETS - 1);
+    }
+
+    #[test]
+    fn test_histogram_percentiles() {
+        let mut histogram = LatencyHistogram::default();
+        for ms in 1..=100 {
+            histogram.record(Duration::from_millis(ms));
+        }
+
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.min(), Duration::from_millis(1));
+        assert_eq!(histogram.max(), Duration::from_millis(100));
+        assert_eq!(histogram.mean(), Duration::from_micros(50_500));
+        for (percent, exact) in [(50.0, 50), (90.0, 90), (99.0, 99)] {
+            let estimate = histogram.percentile(percent).as_secs_f64() * 1000.0;
+            assert!(
+                estimate >= f64::from(exact) && estimate <= f64::from(exact) * 1.125,
+                "p{} = {}ms",
+                percent,
+                estimate
+            );
+        }
+        assert_eq!(histogram.percentile(100.0), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_histogram_merge() {
+        let mut first = LatencyHistogram::default();
+        let mut second = LatencyHistogram::default();
+        first.record(Duration::from_millis(1));
+        second.record(Duration::from_millis(3));
+        first.merge(&second);
+        assert_eq!(first.count(), 2);
+        assert_eq!(first.min(), Duration::from_millis(1));
+        assert_eq!(first.max(), Duration::from_millis(3));
+        assert_eq!(LatencyHistogram::default().percentile(50.0), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_open_loop_under_capacity() {
+        let config = LoadConfig {
+            mode: Mode::Open { rate: 100 },
+            ..LoadConfig::default()
+        };
+        let report = generate_load(&simulated(4, 5), &config).await;
+
+        assert_eq!(report.sent, 100);
+        assert_eq!(report.succeeded, 100);
+        assert_eq!(report.dropped, 0);
+        // Nothing queues: every request takes the service time.
+        assert_eq!(report.latencies.max(), Duration::from_millis(5));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_open_loop_counts_queueing_closed_loop_does_not() {
+        // One request at a time, 20ms each: at most 50 req/s.
+        let target = simulated(1, 20);
+        let open = generate_load(
+            &target,
+            &LoadConfig {
+                mode: Mode::Open { rate: 100 },
+                ..LoadConfig::default()
+            },
+        )
+        .await;
+        let closed = generate_load(
+            &target,
+            &LoadConfig {
+                mode: Mode::Closed { concurrency: 1 },
+                ..LoadConfig::default()
+            },
+        )
+        .await;
+
+        // The open loop's queue grows for the whole second: the last
+        // requests wait about a second.
+        assert_eq!(open.succeeded, 100);
+        assert!(open.latencies.percentile(99.0) > Duration::from_millis(900));
+        assert!(open.throughput() <= 50.0);
+        // The closed loop never queues.
+        assert_eq!(closed.latencies.max(), Duration::from_millis(20));
+        assert_eq!(closed.succeeded, 50);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_open_loop_drops_over_in_flight_cap() {
+        let config = LoadConfig {
+            mode: Mode::Open { rate: 100 },
+            max_in_flight: 5,
+            ..LoadConfig::default()
+        };
+        let report = generate_load(&simulated(1, 20), &config).await;
+
+        assert_eq!(report.sent, 100);
+        assert!(report.dropped > 0);
+        assert_eq!(report.succeeded + report.dropped, 100);
+        // At most 5 in flight: at most 5 requests of queueing.
+        assert!(report.latencies.max() <= Duration::from_millis(5 * 20 + 10));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_closed_loop_scales_with_workers() {
+        let target = simulated(4, 10);
+        let mut throughputs = Vec::new();
+        for concurrency in [1, 2, 4] {
+            let config = LoadConfig {
+                mode: Mode::Closed { concurrency },
+                ..LoadConfig::default()
+            };
+            throughputs.push(generate_load(&target, &config).await.succeeded);
+        }
+        assert_eq!(throughputs, vec![100, 200, 400]);
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            LoadgenArgs::parse(&[]).unwrap().mode,
+            Mode::Open { rate: 200 }
+        );
+
+        let parsed = LoadgenArgs::parse(&args(
+            "--mode closed --concurrency 8 --duration-ms 250 --url http://localhost:3000/ --export out.json",
+        ))
+        .unwrap();
+        assert_eq!(parsed.mode, Mode::Closed { concurrency: 8 });
+        assert_eq!(parsed.duration, Duration::from_millis(250));
+        assert_eq!(
+            parsed.target,
+            TargetArg::Url("http://localhost:3000/".to_string())
+        );
+        assert_eq!(
+            LoadgenArgs::parse(&args("--target http-server"))
+                .unwrap()
+                .target,
+            TargetArg::HttpServer
+        );
+        assert_eq!(
+            LoadgenArgs::parse(&args("--target http://localhost:3000/"))
+                .unwrap()
+                .target,
+            TargetArg::Url("http://localhost:3000/".to_string())
+        );
+        assert_eq!(
+            LoadgenArgs::parse(&args("--rate 1000000")).unwrap().mode,
+            Mode::Open { rate: MAX_RATE }
+        );
+        assert_eq!(parsed.export, Some(PathBuf::from("out.json")));
+
+        for invalid in [
+            "--mode sideways",
+            "--rate fast",
+            "--rate 0",
+            "--rate 1000001",
+            "--rate 4000000000",
+            "--target localhost",
+            "--verbose yes",
+            "--rate",
+        ] {
+            assert!(
+                matches!(
+                    LoadgenArgs::parse(&args(invalid)),
+                    Err(LoadgenError::InvalidArgs(_))
+                ),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_open_loop_at_the_highest_rate() {
+        let config = LoadConfig {
+            mode: Mode::Open { rate: u32::MAX },
+            duration: Duration::from_micros(10),
+            max_in_flight: 1,
+        };
+        let report = generate_load(&simulated(1, 0), &config).await;
+        assert!(report.sent > 0);
+    }
+
+    #[tokio::test]
+    async fn test_load_on_the_http_server() {
+        let report = run_cli(&args(
+            "--target http-server --mode closed --concurrency 4 --duration-ms 200",
+        ))
+        .await
+        .unwrap();
+
+        assert!(report.succeeded > 0);
+        assert_eq!(report.failed, 0);
+        assert!(report.latencies.min() >= Duration::from_millis(10));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_json_export() {
+        temp_dir()
+            .await
+            .run(|dir| async move {
+                let path = dir.join("report.json");
+                let report = run_cli(&args(&format!(
+                    "--mode open --rate 50 --duration-ms 100 --export {}",
+                    path.display()
+                )))
+                .await
+                .unwrap();
+                let json = tokio::fs::read_to_string(&path).await.unwrap();
+                assert_eq!(json, report.to_json());
+            })
+            .await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_json_fields() {
+        let report = run_cli(&args("--mode open --rate 50 --duration-ms 100"))
+            .await
+            .unwrap();
+        let json = report.to_json();
+
+        assert!(json.starts_with("{\"mode\":\"open\",\"rate\":50,\"concurrency\":null,"));
+        assert!(json.contains("\"sent\":5,\"succeeded\":5,\"failed\":0,\"dropped\":0"));
+        assert!(json.contains("\"p99\":10000,"));
+        assert!(json.ends_with("}}"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_loadgen_example() {
+        let [open, closed] = loadgen_example(&ExampleContext::captured()).await;
+        assert!(open.latencies.percentile(99.0) > closed.latencies.percentile(99.0));
+        assert_eq!(closed.latencies.max(), Duration::from_millis(20));
+    }
+}
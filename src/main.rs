@@ -1,8 +1,31 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
 use rust_async_await_course_example::{
-    async_state_machine_example, complex_async_function, fetch_data_from_api,
-    multiple_awaits_example, variable_scoping_example,
+    example_context::{ExampleContext, Metrics},
+    instrumentation::{init_tracing, init_tracing_to_stderr},
+    loadgen,
+    poll_trace::Traced,
+    progress,
+    registry::course_examples,
+    report::{ExampleRunResult, ExampleStatus, OutputFormat, RunOptions, RunReport},
+    sandbox, task_registry,
 };
 
+/// Usage of the binary.
+const USAGE: &str = "\
+usage: rust-async-await-course-example [--output text|json] [--isolate] [--time-limit-ms N]
+       rust-async-await-course-example --example NAME
+       rust-async-await-course-example loadgen [options]
+       rust-async-await-course-example progress
+       rust-async-await-course-example tasks
+
+  --output         text: print each example as it runs (default)
+                   json: print a JSON report of the run once every example has run
+  --isolate        run each example in a child process, killed after the time limit
+  --time-limit-ms  time limit of an isolated example, implies --isolate (default 30000)
+  --example        run only this example, its output on stdout and traces on stderr";
+
 /// Main entry point demonstrating various async/await patterns in Rust.
 ///
 /// This example showcases:
@@ -10,44 +33,167 @@ use rust_async_await_course_example::{
 /// - Multiple await points in a single function
 /// - Variable scoping across await boundaries
 /// - Real-world async patterns with tokio runtime
+///
+/// The examples come from the registry (`src/registry.rs`), in order, each
+/// wrapped in `Traced` to count its polls and wakes. Each one runs with a
+/// context printing its result and sharing the run's metrics.
+///
+/// `cargo run -- --output json` prints a machine-readable report instead, and
+/// `--isolate` runs every example in a child process (`src/sandbox.rs`).
+/// `cargo run -- loadgen [options]` runs the load generator, `cargo run --
+/// progress` renders the progress of simulated downloads, and `cargo run --
+/// tasks` lists named tasks as they come and go.
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("loadgen") {
+        init_tracing();
+        match loadgen::run_cli(&args[1..]).await {
+            Ok(report) => println!("{}", report),
+            Err(e) => {
+                eprintln!("Error: {}\n\n{}", e, loadgen::USAGE);
+                std::process::exit(2);
+            }
+        }
+        return;
+    }
+    if args.first().map(String::as_str) == Some("progress") {
+        init_tracing();
+        progress::run_cli().await;
+        return;
+    }
+    if args.first().map(String::as_str) == Some("tasks") {
+        init_tracing();
+        task_registry::run_cli().await;
+        return;
+    }
+
+    let options = match RunOptions::parse(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("Error: {}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if let Some(name) = options.example {
+        init_tracing_to_stderr();
+        std::process::exit(run_one(&name).await);
+    }
+
+    match options.output {
+        OutputFormat::Text => {
+            init_tracing();
+            run_text(options.isolation).await;
+        }
+        OutputFormat::Json => {
+            init_tracing_to_stderr();
+            println!("{}", run_json(options.isolation).await.to_json());
+        }
+    }
+}
+
+/// Runs the example `name` alone, and returns the exit code: 0 if it
+/// succeeded, 1 if it failed, 2 if there is no such example.
+async fn run_one(name: &str) -> i32 {
+    let registry = course_examples();
+    let Some(example) = registry.get(name) else {
+        eprintln!("Error: no example named {:?}", name);
+        return 2;
+    };
+    match example.run(ExampleContext::default()).await {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            1
+        }
+    }
+}
+
+/// The binary itself, which isolated examples run in.
+fn current_exe() -> PathBuf {
+    std::env::current_exe().unwrap_or_else(|e| {
+        eprintln!("Error: cannot locate the binary to isolate examples: {}", e);
+        std::process::exit(2);
+    })
+}
+
+/// Runs the example `name` in a child process of `exe`.
+async fn run_isolated(name: &'static str, exe: &Path, limit: Duration) -> ExampleRunResult {
+    sandbox::run_in_child(name, sandbox::example_command(exe, name), limit).await
+}
+
+/// Runs every example, printing as it goes; in child processes with
+/// `isolation`.
+async fn run_text(isolation: Option<Duration>) {
     println!("=== Rust Async/Await Course Examples ===\n");
 
-    // Example 1: Simple async state machine
-    println!("1. Async State Machine Example:");
-    async_state_machine_example().await;
-    println!();
-
-    // Example 2: Multiple await points
-    println!("2. Multiple Awaits Example:");
-    multiple_awaits_example().await;
-    println!();
-
-    // Example 3: Variable scoping across awaits
-    println!("3. Variable Scoping Example:");
-    variable_scoping_example().await;
-    println!();
-
-    // Example 4: Complex async function with error handling
-    println!("4. Complex Async Function Example:");
-    match complex_async_function(42, "test-data".to_string()).await {
-        Ok(result) => println!("Result: {}", result),
-        Err(e) => println!("Error: {}", e),
-    }
-    println!();
-
-    // Example 5: Real HTTP request (optional - commented to avoid network dependency)
-    // Uncomment to test with real network calls
-    println!("5. HTTP Request Example (simulated):");
-    match fetch_data_from_api("https://api.github.com/repos/rust-lang/rust").await {
-        Ok(data) => println!(
-            "Fetched data (first 100 chars): {}...",
-            &data[..data.len().min(100)]
-        ),
-        Err(e) => println!("Failed to fetch data: {}", e),
-    }
-    println!();
-
-    println!("=== All examples completed ===");
+    let registry = course_examples();
+    println!(
+        "Running {} examples (about {:?})\n",
+        registry.len(),
+        registry.estimated_total()
+    );
+
+    let metrics = Metrics::new();
+    let isolation = isolation.map(|limit| (current_exe(), limit));
+    for (number, example) in registry.iter().enumerate() {
+        println!(
+            "{}. {} ({}):",
+            number + 1,
+            example.description,
+            example.name
+        );
+        if let Some((exe, limit)) = &isolation {
+            let result = run_isolated(example.name, exe, *limit).await;
+            for line in &result.log_lines {
+                println!("{}", line);
+            }
+            let outcome = match result.status {
+                ExampleStatus::Succeeded => "completed",
+                ExampleStatus::Failed(e) => {
+                    println!("Error: {}", e);
+                    "failed"
+                }
+            };
+            metrics.increment(outcome);
+            println!(
+                "{}: child process done in {:?}",
+                example.name, result.duration
+            );
+        } else {
+            let ctx = ExampleContext::default().with_metrics(metrics.clone());
+            let (result, report) = Traced::new(example.name, example.run_to_result(ctx)).await;
+            if let ExampleStatus::Failed(e) = result.status {
+                println!("Error: {}", e);
+            }
+            println!(
+                "{} (estimated {:?})",
+                report.summary(),
+                example.estimated_duration
+            );
+        }
+        println!();
+    }
+
+    println!(
+        "=== All examples completed: {} succeeded, {} failed ===",
+        metrics.get("completed"),
+        metrics.get("failed")
+    );
+}
+
+/// Runs every example with a captured output, or in child processes with
+/// `isolation`, and reports the run.
+async fn run_json(isolation: Option<Duration>) -> RunReport {
+    let isolation = isolation.map(|limit| (current_exe(), limit));
+    let mut report = RunReport::new();
+    for example in course_examples().iter() {
+        let result = match &isolation {
+            Some((exe, limit)) => run_isolated(example.name, exe, *limit).await,
+            None => example.run_to_result(ExampleContext::captured()).await,
+        };
+        report.push(result);
+    }
+    report
 }
@@ -0,0 +1,423 @@
+//! Memory-pressure aware buffering
+//!
+//! A bounded channel limits the number of buffered items, not their size:
+//! 100 slots of 10 MiB payloads is 1 GiB. This pipeline bounds memory instead:
+//! - A `Semaphore` holds one permit per byte of the memory budget. The
+//!   producer acquires permits for a payload before sending it, so it waits
+//!   (backpressure) when the budget is exhausted.
+//! - Permits travel with the payload and are released when it is dropped
+//!   after being flushed, so the budget covers everything in flight: queued
+//!   and batched.
+//! - A buffered-bytes gauge is watched by the consumer, which shrinks its
+//!   batch size under pressure (flushing sooner frees memory sooner) and
+//!   grows it back when pressure goes away.
+//! - A partial batch is flushed after a short linger: holding it while the
+//!   producer waits for the budget it occupies would deadlock the pipeline.
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::time::{sleep, timeout};
+use tracing::{debug, info, instrument};
+
+use crate::example_context::ExampleContext;
+
+/// Pressure above which the batch size is halved.
+const HIGH_WATER: f64 = 0.75;
+/// Pressure below which the batch size is doubled.
+const LOW_WATER: f64 = 0.25;
+
+/// Configuration of the buffered pipeline.
+#[derive(Debug, Clone, Copy)]
+pub struct BufferConfig {
+    /// Maximum number of payload bytes in flight.
+    budget_bytes: usize,
+    /// Batch size when there is no memory pressure.
+    max_batch_items: usize,
+    /// Smallest batch size under pressure.
+    min_batch_items: usize,
+    /// Simulated time to write one batch downstream.
+    pub flush_delay: Duration,
+    /// How long a partial batch waits for more payloads before being flushed.
+    pub linger: Duration,
+}
+
+impl BufferConfig {
+    /// A configuration bounding the payloads in flight to `budget_bytes`,
+    /// with batches of 2 to 16 payloads.
+    ///
+    /// The budget is one semaphore permit per byte, so it must be between 1
+    /// and `Semaphore::MAX_PERMITS`.
+    pub fn new(budget_bytes: usize) -> Result<Self, BufferError> {
+        if budget_bytes == 0 {
+            return Err(BufferError::EmptyBudget);
+        }
+        if budget_bytes > Semaphore::MAX_PERMITS {
+            return Err(BufferError::BudgetTooLarge(budget_bytes));
+        }
+        Ok(BufferConfig {
+            budget_bytes,
+            max_batch_items: 16,
+            min_batch_items: 2,
+            flush_delay: Duration::from_millis(5),
+            linger: Duration::from_millis(10),
+        })
+    }
+
+    /// Sets the batch sizes: `max` without memory pressure, shrinking down
+    /// to `min` under pressure. `min` must be between 1 and `max`.
+    pub fn with_batch_items(mut self, min: usize, max: usize) -> Result<Self, BufferError> {
+        if min == 0 || min > max {
+            return Err(BufferError::InvalidBatchItems { min, max });
+        }
+        self.min_batch_items = min;
+        self.max_batch_items = max;
+        Ok(self)
+    }
+
+    /// Maximum number of payload bytes in flight.
+    pub fn budget_bytes(&self) -> usize {
+        self.budget_bytes
+    }
+
+    /// Batch size when there is no memory pressure.
+    pub fn max_batch_items(&self) -> usize {
+        self.max_batch_items
+    }
+
+    /// Smallest batch size under pressure.
+    pub fn min_batch_items(&self) -> usize {
+        self.min_batch_items
+    }
+}
+
+/// Error of the buffered pipeline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BufferError {
+    EmptyBudget,
+    /// The budget exceeds the permits a semaphore can hold.
+    BudgetTooLarge(usize),
+    /// The smallest batch size is 0 or above the largest.
+    InvalidBatchItems {
+        min: usize,
+        max: usize,
+    },
+    /// The payload needs more permits than can be acquired at once.
+    PayloadTooLarge(usize),
+}
+
+impl fmt::Display for BufferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BufferError::EmptyBudget => write!(f, "memory budget is empty"),
+            BufferError::BudgetTooLarge(bytes) => write!(
+                f,
+                "memory budget of {} bytes exceeds the maximum of {}",
+                bytes,
+                Semaphore::MAX_PERMITS
+            ),
+            BufferError::InvalidBatchItems { min, max } => write!(
+                f,
+                "batch sizes from {} to {} items: expected 1 <= min <= max",
+                min, max
+            ),
+            BufferError::PayloadTooLarge(bytes) => {
+                write!(f, "payload of {} bytes cannot be budgeted", bytes)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BufferError {}
+
+/// Gauge of payload bytes currently buffered, with its high-water mark.
+#[derive(Debug, Default)]
+pub struct BufferedBytes {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl BufferedBytes {
+    fn add(&self, bytes: usize) {
+        let now = self.current.fetch_add(bytes, Ordering::SeqCst) + bytes;
+        self.peak.fetch_max(now, Ordering::SeqCst);
+    }
+
+    fn sub(&self, bytes: usize) {
+        self.current.fetch_sub(bytes, Ordering::SeqCst);
+    }
+
+    /// Bytes currently buffered.
+    pub fn current(&self) -> usize {
+        self.current.load(Ordering::SeqCst)
+    }
+
+    /// Highest value the gauge reached.
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::SeqCst)
+    }
+}
+
+/// A payload holding its share of the memory budget until it is dropped.
+struct Buffered {
+    payload: Vec<u8>,
+    gauge: Arc<BufferedBytes>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Drop for Buffered {
+    fn drop(&mut self) {
+        self.gauge.sub(self.payload.len());
+    }
+}
+
+/// What happened during a pipeline run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BufferStats {
+    /// Highest number of payload bytes in flight at once.
+    pub peak_buffered_bytes: usize,
+    /// Number of payloads in each flushed batch, in order.
+    pub batch_sizes: Vec<usize>,
+    /// Number of times the producer had to wait for budget.
+    pub producer_waits: usize,
+    /// Total payload bytes delivered downstream.
+    pub delivered_bytes: usize,
+}
+
+/// Adapts the batch size to the current memory pressure.
+fn next_batch_size(current: usize, pressure: f64, config: &BufferConfig) -> usize {
+    if pressure >= HIGH_WATER {
+        (current / 2).max(config.min_batch_items)
+    } else if pressure < LOW_WATER {
+        (current * 2).min(config.max_batch_items)
+    } else {
+        current
+    }
+}
+
+/// Example: streams `payloads` through a memory-budgeted pipeline.
+///
+/// Fails without sending the rest of the payloads if one needs more than
+/// `u32::MAX` permits, the most a semaphore hands out at once.
+#[instrument(skip(payloads), fields(payloads = payloads.len()))]
+pub async fn run_buffered_pipeline(
+    payloads: Vec<Vec<u8>>,
+    config: BufferConfig,
+) -> Result<BufferStats, BufferError> {
+    let budget = Arc::new(Semaphore::new(config.budget_bytes));
+    let gauge = Arc::new(BufferedBytes::default());
+    // The semaphore bounds the channel's memory, so the channel itself can be
+    // unbounded.
+    let (tx, mut rx) = mpsc::unbounded_channel::<Buffered>();
+
+    let producer = {
+        let gauge = Arc::clone(&gauge);
+        tokio::spawn(async move {
+            let mut waits = 0;
+            for payload in payloads {
+                // A payload larger than the whole budget would wait forever;
+                // let it through alone by taking the entire budget.
+                let bytes = match u32::try_from(payload.len().min(config.budget_bytes)) {
+                    Ok(bytes) => bytes,
+                    Err(_) => return Err(BufferError::PayloadTooLarge(payload.len())),
+                };
+                let permit = match Arc::clone(&budget).try_acquire_many_owned(bytes) {
+                    Ok(permit) => permit,
+                    Err(_) => {
+                        waits += 1;
+                        debug!(bytes, "budget exhausted, waiting");
+                        Arc::clone(&budget)
+                            .acquire_many_owned(bytes)
+                            .await
+                            .expect("semaphore is never closed")
+                    }
+                };
+
+                gauge.add(payload.len());
+                let item = Buffered {
+                    payload,
+                    gauge: Arc::clone(&gauge),
+                    _permit: permit,
+                };
+                if tx.send(item).is_err() {
+                    break;
+                }
+            }
+            Ok(waits)
+        })
+    };
+
+    let mut batch_size = config.max_batch_items;
+    let mut batch: Vec<Buffered> = Vec::with_capacity(batch_size);
+    let mut batch_sizes = Vec::new();
+    let mut delivered_bytes = 0;
+
+    loop {
+        // With a partial batch, wait at most `linger` for the next payload:
+        // the producer may be waiting for the budget this batch is holding.
+        let (closed, lingered) = if batch.is_empty() {
+            match rx.recv().await {
+                Some(item) => {
+                    batch.push(item);
+                    (false, false)
+                }
+                None => (true, false),
+            }
+        } else {
+            match timeout(config.linger, rx.recv()).await {
+                Ok(Some(item)) => {
+                    batch.push(item);
+                    (false, false)
+                }
+                Ok(None) => (true, false),
+                Err(_) => (false, true),
+            }
+        };
+
+        let pressure = gauge.current() as f64 / config.budget_bytes as f64;
+        batch_size = next_batch_size(batch_size, pressure, &config);
+
+        if !batch.is_empty() && (batch.len() >= batch_size || closed || lingered) {
+            debug!(items = batch.len(), pressure, lingered, "flushing batch");
+            sleep(config.flush_delay).await;
+            delivered_bytes += batch.iter().map(|item| item.payload.len()).sum::<usize>();
+            batch_sizes.push(batch.len());
+            // Dropping the items releases their budget.
+            batch.clear();
+        }
+        if closed {
+            break;
+        }
+    }
+
+    let producer_waits = producer.await.expect("producer panicked")?;
+    let stats = BufferStats {
+        peak_buffered_bytes: gauge.peak(),
+        batch_sizes,
+        producer_waits,
+        delivered_bytes,
+    };
+    info!(
+        peak = stats.peak_buffered_bytes,
+        waits = stats.producer_waits,
+        batches = stats.batch_sizes.len(),
+        "pipeline finished"
+    );
+    Ok(stats)
+}
+
+/// Runs the pipeline with 64 payloads of 64 KiB under a 1 MiB budget.
+/// The flush delay and the linger follow the latencies of `ctx`.
+#[instrument(skip_all)]
+pub async fn memory_budget_example(ctx: &ExampleContext) -> Result<BufferStats, BufferError> {
+    let payloads = vec![vec![0u8; 64 * 1024]; 64];
+    let mut config = BufferConfig::new(1024 * 1024)?;
+    config.flush_delay = ctx.latency().delay(config.flush_delay);
+    config.linger = ctx.latency().delay(config.linger);
+    run_buffered_pipeline(payloads, config).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KIB: usize = 1024;
+
+    fn config(budget_bytes: usize) -> BufferConfig {
+        BufferConfig::new(budget_bytes).unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_budget_is_never_exceeded() {
+        let payloads = vec![vec![1u8; 256 * KIB]; 40];
+        let stats = run_buffered_pipeline(payloads, config(1024 * KIB))
+            .await
+            .unwrap();
+
+        assert!(stats.peak_buffered_bytes <= 1024 * KIB);
+        assert!(stats.producer_waits > 0);
+        assert_eq!(stats.delivered_bytes, 40 * 256 * KIB);
+        assert_eq!(stats.batch_sizes.iter().sum::<usize>(), 40);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_batches_shrink_under_pressure() {
+        let payloads = vec![vec![1u8; 128 * KIB]; 64];
+        let stats = run_buffered_pipeline(payloads, config(1024 * KIB))
+            .await
+            .unwrap();
+
+        // 16 payloads of 128 KiB would be 2 MiB, twice the budget: batches
+        // must be flushed before reaching the maximum size.
+        assert!(stats.batch_sizes.iter().all(|&size| size < 16));
+        assert!(stats.batch_sizes.iter().any(|&size| size <= 4));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_full_batches_without_pressure() {
+        let payloads = vec![vec![1u8; KIB]; 64];
+        let stats = run_buffered_pipeline(payloads, config(64 * 1024 * KIB))
+            .await
+            .unwrap();
+
+        assert_eq!(stats.batch_sizes, vec![16, 16, 16, 16]);
+        assert_eq!(stats.producer_waits, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_payload_larger_than_budget_still_flows() {
+        let payloads = vec![vec![1u8; 4 * KIB], vec![1u8; 64 * KIB], vec![1u8; 4 * KIB]];
+        let stats = run_buffered_pipeline(payloads, config(16 * KIB))
+            .await
+            .unwrap();
+
+        assert_eq!(stats.delivered_bytes, 72 * KIB);
+    }
+
+    #[test]
+    fn test_budget_is_validated() {
+        assert_eq!(BufferConfig::new(0).unwrap_err(), BufferError::EmptyBudget);
+        assert_eq!(
+            BufferConfig::new(Semaphore::MAX_PERMITS + 1).unwrap_err(),
+            BufferError::BudgetTooLarge(Semaphore::MAX_PERMITS + 1)
+        );
+        let config = BufferConfig::new(Semaphore::MAX_PERMITS).unwrap();
+        assert_eq!(config.budget_bytes(), Semaphore::MAX_PERMITS);
+    }
+
+    #[test]
+    fn test_batch_items_are_validated() {
+        assert_eq!(
+            config(1000).with_batch_items(0, 4).unwrap_err(),
+            BufferError::InvalidBatchItems { min: 0, max: 4 }
+        );
+        assert_eq!(
+            config(1000).with_batch_items(8, 4).unwrap_err(),
+            BufferError::InvalidBatchItems { min: 8, max: 4 }
+        );
+        let config = config(1000).with_batch_items(4, 4).unwrap();
+        assert_eq!((config.min_batch_items(), config.max_batch_items()), (4, 4));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_payload_needing_too_many_permits_fails() {
+        // Zeroed and never written: the pages are not touched.
+        let huge = u32::MAX as usize + 1;
+        let payloads = vec![vec![1u8; KIB], vec![0u8; huge], vec![1u8; KIB]];
+        let result = run_buffered_pipeline(payloads, config(Semaphore::MAX_PERMITS)).await;
+
+        assert_eq!(result.unwrap_err(), BufferError::PayloadTooLarge(huge));
+    }
+
+    #[test]
+    fn test_next_batch_size() {
+        let config = config(1000);
+        assert_eq!(next_batch_size(16, 0.9, &config), 8);
+        assert_eq!(next_batch_size(2, 0.9, &config), 2);
+        assert_eq!(next_batch_size(8, 0.5, &config), 8);
+        assert_eq!(next_batch_size(8, 0.1, &config), 16);
+        assert_eq!(next_batch_size(16, 0.0, &config), 16);
+    }
+}
@@ -0,0 +1,298 @@
+//! A oneshot channel written from scratch
+//!
+//! `tokio::sync::oneshot` hides how a channel wakes its receiver. This one
+//! makes it explicit with a small state machine behind a `Mutex`:
+//!
+//! ```text
+//! Empty --recv polled--> Waiting(waker) --send--> Sent(value) --recv polled--> Taken
+//!   |                       |
+//!   +--------sender dropped-+--> Closed (recv returns RecvError)
+//! ```
+//!
+//! - Polling the receiver before the value is there stores the waker.
+//! - `send` stores the value, then wakes the stored waker (if any).
+//! - Dropping the sender without sending also wakes the receiver, which
+//!   would otherwise wait forever.
+//! - `send` fails, giving the value back, when the receiver is gone.
+//!
+//! The waker is taken out of the lock before calling `wake`, like in the
+//! `waker` module, so the woken task can lock the state immediately.
+
+use std::fmt;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+use tracing::{info, instrument};
+
+use crate::example_context::ExampleContext;
+
+/// Error returned by the [`Receiver`] when the [`Sender`] was dropped without
+/// sending.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("sender dropped without sending")
+    }
+}
+
+impl std::error::Error for RecvError {}
+
+enum State<T> {
+    /// Nothing sent, receiver not polled yet.
+    Empty,
+    /// Nothing sent, receiver polled with this waker.
+    Waiting(Waker),
+    /// Value sent, not received yet.
+    Sent(T),
+    /// Value received.
+    Taken,
+    /// Sender dropped without sending.
+    Closed,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    /// Set when the receiver is dropped, so `send` can fail early.
+    receiver_dropped: AtomicBool,
+}
+
+/// Sending half: sends one value.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+    sent: bool,
+}
+
+/// Receiving half: a future resolving to the sent value.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Creates a oneshot channel.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State::Empty),
+        receiver_dropped: AtomicBool::new(false),
+    });
+    (
+        Sender {
+            shared: Arc::clone(&shared),
+            sent: false,
+        },
+        Receiver { shared },
+    )
+}
+
+impl<T> Sender<T> {
+    /// Sends the value and wakes the receiver.
+    ///
+    /// Returns the value back if the receiver was dropped.
+    pub fn send(mut self, value: T) -> Result<(), T> {
+        if self.is_closed() {
+            return Err(value);
+        }
+        self.sent = true;
+        let previous = mem::replace(&mut *self.shared.state.lock().unwrap(), State::Sent(value));
+        if let State::Waiting(waker) = previous {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    /// Whether the receiver was dropped.
+    pub fn is_closed(&self) -> bool {
+        self.shared.receiver_dropped.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.sent {
+            return;
+        }
+        let previous = mem::replace(&mut *self.shared.state.lock().unwrap(), State::Closed);
+        if let State::Waiting(waker) = previous {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.shared.state.lock().unwrap();
+        match mem::replace(&mut *state, State::Taken) {
+            State::Sent(value) => Poll::Ready(Ok(value)),
+            State::Closed => {
+                *state = State::Closed;
+                Poll::Ready(Err(RecvError))
+            }
+            State::Empty => {
+                *state = State::Waiting(cx.waker().clone());
+                Poll::Pending
+            }
+            State::Waiting(waker) => {
+                // The future may have moved to another task since the last
+                // poll: keep the most recent waker.
+                let waker = if waker.will_wake(cx.waker()) {
+                    waker
+                } else {
+                    cx.waker().clone()
+                };
+                *state = State::Waiting(waker);
+                Poll::Pending
+            }
+            State::Taken => panic!("oneshot receiver polled after completion"),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver_dropped.store(true, Ordering::Release);
+    }
+}
+
+/// Example: a value sent from a task, and a sender dropped without sending
+#[instrument(skip_all)]
+pub async fn oneshot_example(
+    ctx: &ExampleContext,
+) -> (Result<u32, RecvError>, Result<u32, RecvError>) {
+    let (tx, rx) = channel();
+    let latency = ctx.latency().clone();
+    tokio::spawn(async move {
+        latency.sleep(Duration::from_millis(20)).await;
+        let _ = tx.send(42);
+    });
+    let sent = rx.await;
+    info!(?sent, "value received");
+
+    let (tx, rx) = channel::<u32>();
+    let latency = ctx.latency().clone();
+    tokio::spawn(async move {
+        latency.sleep(Duration::from_millis(20)).await;
+        drop(tx);
+    });
+    let dropped = rx.await;
+    info!(?dropped, "sender dropped");
+
+    (sent, dropped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::task::Wake;
+
+    /// A waker counting how many times it was woken.
+    #[derive(Default)]
+    struct CountingWaker {
+        wakes: AtomicUsize,
+    }
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.wakes.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn counting_waker() -> (Arc<CountingWaker>, Waker) {
+        let counter = Arc::new(CountingWaker::default());
+        (Arc::clone(&counter), Waker::from(counter))
+    }
+
+    fn poll_once<T>(rx: &mut Receiver<T>, waker: &Waker) -> Poll<Result<T, RecvError>> {
+        Pin::new(rx).poll(&mut Context::from_waker(waker))
+    }
+
+    #[test]
+    fn test_send_before_poll() {
+        let (tx, mut rx) = channel();
+        tx.send("hello").unwrap();
+
+        let (counter, waker) = counting_waker();
+        assert_eq!(poll_once(&mut rx, &waker), Poll::Ready(Ok("hello")));
+        assert_eq!(counter.wakes.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_poll_before_send() {
+        let (tx, mut rx) = channel();
+        let (counter, waker) = counting_waker();
+
+        assert_eq!(poll_once(&mut rx, &waker), Poll::Pending);
+        assert_eq!(counter.wakes.load(Ordering::SeqCst), 0);
+
+        tx.send(7).unwrap();
+        assert_eq!(counter.wakes.load(Ordering::SeqCst), 1);
+        assert_eq!(poll_once(&mut rx, &waker), Poll::Ready(Ok(7)));
+    }
+
+    #[test]
+    fn test_drop_without_send() {
+        let (tx, mut rx) = channel::<u32>();
+        let (counter, waker) = counting_waker();
+
+        assert_eq!(poll_once(&mut rx, &waker), Poll::Pending);
+        drop(tx);
+        assert_eq!(counter.wakes.load(Ordering::SeqCst), 1);
+        assert_eq!(poll_once(&mut rx, &waker), Poll::Ready(Err(RecvError)));
+    }
+
+    #[test]
+    fn test_drop_without_send_before_poll() {
+        let (tx, mut rx) = channel::<u32>();
+        drop(tx);
+        let (_, waker) = counting_waker();
+        assert_eq!(poll_once(&mut rx, &waker), Poll::Ready(Err(RecvError)));
+    }
+
+    #[test]
+    fn test_send_to_dropped_receiver_returns_value() {
+        let (tx, rx) = channel();
+        drop(rx);
+        assert!(tx.is_closed());
+        assert_eq!(tx.send(3), Err(3));
+    }
+
+    #[test]
+    fn test_only_latest_waker_is_woken() {
+        let (tx, mut rx) = channel();
+        let (first, first_waker) = counting_waker();
+        let (second, second_waker) = counting_waker();
+
+        assert_eq!(poll_once(&mut rx, &first_waker), Poll::Pending);
+        assert_eq!(poll_once(&mut rx, &second_waker), Poll::Pending);
+        tx.send(()).unwrap();
+
+        assert_eq!(first.wakes.load(Ordering::SeqCst), 0);
+        assert_eq!(second.wakes.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_across_tasks() {
+        let (sent, dropped) = oneshot_example(&ExampleContext::captured()).await;
+        assert_eq!(sent, Ok(42));
+        assert_eq!(dropped, Err(RecvError));
+    }
+
+    #[test]
+    fn test_across_threads() {
+        let (tx, rx) = channel();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            tx.send(String::from("from a thread")).unwrap();
+        });
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        assert_eq!(runtime.block_on(rx), Ok(String::from("from a thread")));
+    }
+}
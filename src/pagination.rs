@@ -0,0 +1,358 @@
+//! Paginated APIs as streams
+//!
+//! A paginated endpoint returns one page at a time, with a link to the next
+//! one (GitHub sends it in a `Link: <url>; rel="next"` header). Exposing it
+//! as a `Stream` of pages lets the caller consume it like an iterator:
+//! [`fetch_paginated`] builds one with `futures::stream::unfold`, whose state
+//! is the URL of the next page.
+//!
+//! The stream is lazy: a page is requested only when the consumer polls for
+//! it, so `.take(1)` costs one request whatever the number of pages. It ends
+//! after the page without a `next` link, or after the first error.
+//!
+//! Pages are fetched through the [`PageFetcher`] trait: `reqwest::Client`
+//! implements it over HTTP, and [`MockClient`] serves canned pages and
+//! records requests for the tests. Over HTTP, the tests follow the `/items`
+//! pages of the example server in `http_server`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use tokio::time::sleep;
+use tracing::{debug, info, instrument};
+
+use crate::example_context::ExampleContext;
+
+/// One page of results.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    pub url: String,
+    pub items: Vec<String>,
+    /// URL of the next page, `None` on the last one.
+    pub next: Option<String>,
+}
+
+/// Error fetching a page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FetchError {
+    /// The server answered with an error status.
+    Status { url: String, status: u16 },
+    /// The request failed before a response.
+    Transport { url: String, message: String },
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::Status { url, status } => write!(f, "HTTP {} for {}", status, url),
+            FetchError::Transport { url, message } => {
+                write!(f, "request to {} failed: {}", url, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+/// Fetches a single page.
+pub trait PageFetcher {
+    fn fetch_page(&self, url: &str) -> impl Future<Output = Result<Page, FetchError>> + Send;
+}
+
+/// Extracts the `rel="next"` URL of a `Link` header.
+pub fn parse_next_link(header: &str) -> Option<String> {
+    header.split(',').find_map(|link| {
+        let (url, params) = link.trim().split_once(';')?;
+        let is_next = params
+            .split(';')
+            .any(|param| param.trim() == "rel=\"next\"");
+        let url = url.trim().strip_prefix('<')?.strip_suffix('>')?;
+        is_next.then(|| url.to_string())
+    })
+}
+
+/// Over HTTP: one item per line of the body, the next page in the `Link`
+/// header.
+impl PageFetcher for reqwest::Client {
+    async fn fetch_page(&self, url: &str) -> Result<Page, FetchError> {
+        let transport = |error: reqwest::Error| FetchError::Transport {
+            url: url.to_string(),
+            message: error.to_string(),
+        };
+        let response = self.get(url).send().await.map_err(transport)?;
+        if !response.status().is_success() {
+            return Err(FetchError::Status {
+                url: url.to_string(),
+                status: response.status().as_u16(),
+            });
+        }
+        let next = response
+            .headers()
+            .get(reqwest::header::LINK)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_next_link);
+        let body = response.text().await.map_err(transport)?;
+        Ok(Page {
+            url: url.to_string(),
+            items: body.lines().map(str::to_string).collect(),
+            next,
+        })
+    }
+}
+
+/// Serves canned pages after a fixed latency; any other URL is a 404.
+#[derive(Debug, Clone, Default)]
+pub struct MockClient {
+    pages: HashMap<String, Page>,
+    latency: Duration,
+    requested: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockClient {
+    /// `pages` pages of `per_page` items, at `{base}?page=1`, `{base}?page=2`...
+    pub fn numbered(base: &str, pages: usize, per_page: usize) -> Self {
+        let url = |page: usize| format!("{}?page={}", base, page);
+        let pages = (1..=pages)
+            .map(|page| {
+                let first = (page - 1) * per_page;
+                let page = Page {
+                    url: url(page),
+                    items: (first..first + per_page)
+                        .map(|item| format!("item-{}", item))
+                        .collect(),
+                    next: (page < pages).then(|| url(page + 1)),
+                };
+                (page.url.clone(), page)
+            })
+            .collect();
+        MockClient {
+            pages,
+            latency: Duration::from_millis(10),
+            requested: Arc::default(),
+        }
+    }
+
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Removes a page: requesting it fails with a 404.
+    pub fn without(mut self, url: &str) -> Self {
+        self.pages.remove(url);
+        self
+    }
+
+    /// URLs requested so far, in order.
+    pub fn requested(&self) -> Vec<String> {
+        self.requested.lock().unwrap().clone()
+    }
+}
+
+impl PageFetcher for MockClient {
+    async fn fetch_page(&self, url: &str) -> Result<Page, FetchError> {
+        self.requested.lock().unwrap().push(url.to_string());
+        sleep(self.latency).await;
+        self.pages.get(url).cloned().ok_or(FetchError::Status {
+            url: url.to_string(),
+            status: 404,
+        })
+    }
+}
+
+/// Stream of the pages starting at `url`, following `next` links as the
+/// consumer pulls them.
+pub fn fetch_paginated<'a, F: PageFetcher>(
+    fetcher: &'a F,
+    url: impl Into<String>,
+) -> impl Stream<Item = Result<Page, FetchError>> + 'a {
+    stream::unfold(Some(url.into()), move |next| async move {
+        let url = next?;
+        debug!(%url, "fetching page");
+        match fetcher.fetch_page(&url).await {
+            Ok(page) => {
+                let next = page.next.clone();
+                Some((Ok(page), next))
+            }
+            // Yield the error, then end the stream.
+            Err(error) => Some((Err(error), None)),
+        }
+    })
+}
+
+/// Stream of the items of every page starting at `url`.
+pub fn fetch_items<'a, F: PageFetcher>(
+    fetcher: &'a F,
+    url: impl Into<String>,
+) -> impl Stream<Item = Result<String, FetchError>> + 'a {
+    fetch_paginated(fetcher, url)
+        .map_ok(|page| stream::iter(page.items.into_iter().map(Ok)))
+        .try_flatten()
+}
+
+/// Example: every item of 3 pages, then only the first 2 items, from a
+/// server answering with the latency of `ctx`
+#[instrument(skip_all)]
+pub async fn pagination_example(ctx: &ExampleContext) -> Result<(Vec<String>, usize), FetchError> {
+    let latency = ctx.latency().delay(Duration::from_millis(10));
+    let client = MockClient::numbered("https://api.example.com/items", 3, 3).with_latency(latency);
+    let items: Vec<String> = fetch_items(&client, "https://api.example.com/items?page=1")
+        .try_collect()
+        .await?;
+    info!(
+        items = items.len(),
+        requests = client.requested().len(),
+        "all pages"
+    );
+
+    let lazy = MockClient::numbered("https://api.example.com/items", 3, 3).with_latency(latency);
+    let first: Vec<String> = fetch_items(&lazy, "https://api.example.com/items?page=1")
+        .take(2)
+        .try_collect()
+        .await?;
+    info!(
+        ?first,
+        requests = lazy.requested().len(),
+        "first 2 items only"
+    );
+
+    Ok((items, lazy.requested().len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::pin;
+
+    use super::*;
+
+    const BASE: &str = "https://api.test/items";
+
+    fn page(n: usize) -> String {
+        format!("{}?page={}", BASE, n)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_follows_next_links_to_the_last_page() {
+        let client = MockClient::numbered(BASE, 3, 2);
+        let pages: Vec<Page> = fetch_paginated(&client, page(1))
+            .try_collect()
+            .await
+            .unwrap();
+
+        let urls: Vec<&str> = pages.iter().map(|page| page.url.as_str()).collect();
+        assert_eq!(urls, [page(1), page(2), page(3)]);
+        assert_eq!(pages[2].next, None);
+        assert_eq!(client.requested(), [page(1), page(2), page(3)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pages_fetched_only_when_pulled() {
+        let client = MockClient::numbered(BASE, 5, 2);
+        let mut pages = pin!(fetch_paginated(&client, page(1)));
+        assert!(
+            client.requested().is_empty(),
+            "creating the stream fetches nothing"
+        );
+
+        pages.next().await.unwrap().unwrap();
+        assert_eq!(client.requested(), [page(1)]);
+
+        sleep(Duration::from_secs(1)).await;
+        assert_eq!(client.requested().len(), 1, "no prefetching while idle");
+
+        pages.next().await.unwrap().unwrap();
+        assert_eq!(client.requested(), [page(1), page(2)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_take_stops_fetching() {
+        let client = MockClient::numbered(BASE, 10, 3);
+        let items: Vec<String> = fetch_items(&client, page(1))
+            .take(4)
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert_eq!(items, ["item-0", "item-1", "item-2", "item-3"]);
+        assert_eq!(client.requested(), [page(1), page(2)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stream_ends_after_an_error() {
+        let client = MockClient::numbered(BASE, 3, 1).without(&page(2));
+        let results: Vec<_> = fetch_paginated(&client, page(1)).collect().await;
+
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        assert_eq!(
+            results[1],
+            Err(FetchError::Status {
+                url: page(2),
+                status: 404
+            })
+        );
+        assert_eq!(
+            client.requested(),
+            [page(1), page(2)],
+            "page 3 never requested"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_single_page() {
+        let client = MockClient::numbered(BASE, 1, 2).with_latency(Duration::ZERO);
+        let items: Vec<String> = fetch_items(&client, page(1)).try_collect().await.unwrap();
+        assert_eq!(items, ["item-0", "item-1"]);
+    }
+
+    #[test]
+    fn test_parse_next_link() {
+        let header = "<https://api.github.com/repos?page=3>; rel=\"next\", \
+                      <https://api.github.com/repos?page=50>; rel=\"last\"";
+        assert_eq!(
+            parse_next_link(header).as_deref(),
+            Some("https://api.github.com/repos?page=3")
+        );
+        assert_eq!(parse_next_link("<https://a/?page=1>; rel=\"prev\""), None);
+        assert_eq!(parse_next_link(""), None);
+    }
+
+    #[tokio::test]
+    async fn test_reqwest_follows_link_headers() {
+        let server = crate::http_server::HttpServer::start_local().unwrap();
+        let client = reqwest::Client::new();
+        let pages: Vec<Page> = fetch_paginated(&client, server.url("/items?page=1"))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(pages.len(), 3);
+        let items: Vec<&String> = pages.iter().flat_map(|page| &page.items).collect();
+        assert_eq!(
+            items,
+            ["item-0", "item-1", "item-2", "item-3", "item-4", "item-5"]
+        );
+
+        let missing = fetch_paginated(&client, server.url("/items?page=9"))
+            .try_collect::<Vec<_>>()
+            .await;
+        assert!(matches!(
+            missing,
+            Err(FetchError::Status { status: 404, .. })
+        ));
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pagination_example() {
+        let (items, lazy_requests) = pagination_example(&ExampleContext::captured())
+            .await
+            .unwrap();
+        assert_eq!(items.len(), 9);
+        assert_eq!(items[8], "item-8");
+        assert_eq!(lazy_requests, 1);
+    }
+}
@@ -0,0 +1,232 @@
+//! Panics in async code
+//!
+//! A panic inside a spawned task does not bring down the runtime: tokio
+//! catches it at the task boundary, the worker thread moves on to the next
+//! task, and the panic comes back as a `JoinError` when the task is awaited.
+//! `JoinError::is_panic` tells a panic from a cancellation (`abort`), and
+//! `into_panic` hands back the payload, either to log it or to re-raise it in
+//! the awaiting task with `std::panic::resume_unwind` ([`join_or_resume`]).
+//!
+//! A future that is awaited rather than spawned has no such boundary: its
+//! panic unwinds through the caller. `FutureExt::catch_unwind` adds one
+//! ([`catch_panic`]). It requires `UnwindSafe`, which most futures are not;
+//! `AssertUnwindSafe` is the usual escape hatch, and means "no state this
+//! future shares is left broken by the panic".
+//!
+//! [`supervise`] puts it together: workers run as tasks of a `JoinSet`, and
+//! the supervisor logs whichever panics while its siblings keep running.
+//!
+//! Panics still print through the panic hook (stderr) when they happen.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::time::Duration;
+
+use futures::FutureExt;
+use tokio::task::{JoinError, JoinHandle, JoinSet};
+use tokio::time::sleep;
+use tracing::{error, info, instrument};
+
+use crate::example_context::ExampleContext;
+
+/// The message of a panic payload: `panic!` produces a `&str` or a `String`.
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "non-string panic payload".to_string()
+    }
+}
+
+/// Awaits a task, re-raising its panic in the current task as if the task's
+/// code had run inline. Cancellation is still returned as an error.
+pub async fn join_or_resume<T>(handle: JoinHandle<T>) -> Result<T, JoinError> {
+    match handle.await {
+        Err(error) if error.is_panic() => std::panic::resume_unwind(error.into_panic()),
+        result => result,
+    }
+}
+
+/// Awaits `future` in place, turning a panic into the panic's message.
+pub async fn catch_panic<F: Future>(future: F) -> Result<F::Output, String> {
+    AssertUnwindSafe(future)
+        .catch_unwind()
+        .await
+        .map_err(|payload| panic_message(&*payload))
+}
+
+/// How the workers of [`supervise`] ended.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SupervisorReport {
+    /// Workers that returned, with their result, in completion order.
+    pub completed: Vec<(&'static str, u32)>,
+    /// Workers that panicked, with the panic message.
+    pub panicked: Vec<(&'static str, String)>,
+}
+
+/// Runs every worker as a task and waits for all of them, logging panics
+/// as they are detected. A panicking worker does not stop its siblings.
+pub async fn supervise<F>(workers: Vec<(&'static str, F)>) -> SupervisorReport
+where
+    F: Future<Output = u32> + Send + 'static,
+{
+    let mut tasks = JoinSet::new();
+    let mut names = HashMap::new();
+    for (name, worker) in workers {
+        let handle = tasks.spawn(worker);
+        names.insert(handle.id(), name);
+    }
+
+    let mut report = SupervisorReport::default();
+    while let Some(result) = tasks.join_next_with_id().await {
+        match result {
+            Ok((id, value)) => {
+                let name = names[&id];
+                info!(worker = name, value, "worker finished");
+                report.completed.push((name, value));
+            }
+            Err(error) => {
+                let name = names[&error.id()];
+                let message = if error.is_panic() {
+                    panic_message(&*error.into_panic())
+                } else {
+                    "cancelled".to_string()
+                };
+                error!(worker = name, %message, "worker panicked");
+                report.panicked.push((name, message));
+            }
+        }
+    }
+    report
+}
+
+/// A worker sleeping for `delay`, then returning `value` or panicking.
+async fn worker(delay: Duration, value: Option<u32>) -> u32 {
+    sleep(delay).await;
+    match value {
+        Some(value) => value,
+        None => panic!("worker gave up after {:?}", delay),
+    }
+}
+
+/// Example: a panicking task observed, caught and supervised
+#[instrument(skip_all)]
+pub async fn panics_example(ctx: &ExampleContext) -> SupervisorReport {
+    let error = tokio::spawn(async { panic!("task panicked") })
+        .await
+        .unwrap_err();
+    info!(
+        is_panic = error.is_panic(),
+        message = panic_message(&*error.into_panic()),
+        "joined a panicked task"
+    );
+    let after = tokio::spawn(async { 1 + 1 }).await.unwrap();
+    info!(after, "the runtime is still running tasks");
+
+    let caught = catch_panic(async { panic!("awaited future panicked") }).await;
+    info!(?caught, "caught without spawning");
+
+    let delay = |ms| ctx.latency().delay(Duration::from_millis(ms));
+    supervise(vec![
+        ("fast", worker(delay(5), Some(1)).boxed()),
+        ("crashing", worker(delay(10), None).boxed()),
+        ("slow", worker(delay(20), Some(3)).boxed()),
+    ])
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_panicking_task_does_not_stop_the_runtime() {
+        let error = tokio::spawn(async { panic!("boom") }).await.unwrap_err();
+        assert!(error.is_panic());
+        assert!(!error.is_cancelled());
+        assert_eq!(panic_message(&*error.into_panic()), "boom");
+
+        assert_eq!(tokio::spawn(async { 7 }).await.unwrap(), 7);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_panic_isolated_on_a_single_thread() {
+        // The only thread of the runtime survives the panic.
+        let panicked = tokio::spawn(async { panic!("boom") });
+        let sibling = tokio::spawn(async { "alive" });
+        assert!(panicked.await.unwrap_err().is_panic());
+        assert_eq!(sibling.await.unwrap(), "alive");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_abort_is_not_a_panic() {
+        let handle = tokio::spawn(sleep(Duration::from_secs(60)));
+        handle.abort();
+        let error = handle.await.unwrap_err();
+        assert!(error.is_cancelled());
+        assert!(!error.is_panic());
+    }
+
+    #[test]
+    fn test_panic_message_payloads() {
+        assert_eq!(panic_message(&"static"), "static");
+        assert_eq!(panic_message(&format!("formatted {}", 1)), "formatted 1");
+        assert_eq!(panic_message(&42), "non-string panic payload");
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "re-raised")]
+    async fn test_join_or_resume_reraises_the_panic() {
+        let handle = tokio::spawn(async { panic!("re-raised") });
+        let _ = join_or_resume(handle).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_join_or_resume_passes_values_and_cancellation() {
+        assert_eq!(join_or_resume(tokio::spawn(async { 3 })).await.unwrap(), 3);
+
+        let handle = tokio::spawn(sleep(Duration::from_secs(60)));
+        handle.abort();
+        assert!(join_or_resume(handle).await.unwrap_err().is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_catch_panic_of_an_awaited_future() {
+        let caught = catch_panic(async { panic!("caught {}", "inline") }).await;
+        assert_eq!(caught, Err("caught inline".to_string()));
+        assert_eq!(catch_panic(async { 5 }).await, Ok(5));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_supervisor_detects_panic_while_siblings_keep_running() {
+        let start = tokio::time::Instant::now();
+        let report = supervise(vec![
+            ("first", worker(Duration::from_millis(10), Some(1)).boxed()),
+            ("crashing", worker(Duration::from_millis(20), None).boxed()),
+            (
+                "after_crash",
+                worker(Duration::from_millis(50), Some(2)).boxed(),
+            ),
+        ])
+        .await;
+
+        assert_eq!(
+            report.panicked,
+            [("crashing", "worker gave up after 20ms".to_string())]
+        );
+        // The last sibling ran to completion, long after the panic.
+        assert_eq!(report.completed, [("first", 1), ("after_crash", 2)]);
+        assert_eq!(start.elapsed(), Duration::from_millis(50));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_panics_example() {
+        let report = panics_example(&ExampleContext::captured()).await;
+        assert_eq!(report.completed, [("fast", 1), ("slow", 3)]);
+        assert_eq!(report.panicked.len(), 1);
+    }
+}
@@ -0,0 +1,239 @@
+//! Graceful pause/resume of background workers
+//!
+//! A `PauseController` owns a `watch` channel holding the paused flag. Workers
+//! hold a `PauseToken` (a `watch::Receiver`) and call
+//! [`PauseToken::wait_if_paused`] at safe points between awaits. Work that is
+//! already in progress finishes; no new work starts until the controller
+//! resumes.
+//!
+//! `watch` is a good fit because workers only care about the latest value,
+//! and every receiver is notified when it changes.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+use tracing::{info, info_span, instrument, Instrument};
+
+use crate::example_context::ExampleContext;
+
+/// Controls whether the workers holding its tokens are allowed to make progress.
+#[derive(Debug)]
+pub struct PauseController {
+    paused: watch::Sender<bool>,
+}
+
+impl PauseController {
+    /// Creates a controller in the running (not paused) state.
+    pub fn new() -> Self {
+        let (paused, _) = watch::channel(false);
+        PauseController { paused }
+    }
+
+    /// Returns a token for a worker to check at its safe points.
+    pub fn token(&self) -> PauseToken {
+        PauseToken {
+            paused: self.paused.subscribe(),
+        }
+    }
+
+    /// Pauses all workers at their next safe point.
+    pub fn pause(&self) {
+        self.paused.send_replace(true);
+    }
+
+    /// Lets paused workers continue.
+    pub fn resume(&self) {
+        self.paused.send_replace(false);
+    }
+
+    /// Returns whether workers are currently asked to pause.
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+}
+
+impl Default for PauseController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A worker's view of a [`PauseController`].
+#[derive(Debug, Clone)]
+pub struct PauseToken {
+    paused: watch::Receiver<bool>,
+}
+
+impl PauseToken {
+    /// Waits until the controller is not paused.
+    ///
+    /// Returns immediately when running. If the controller is dropped while
+    /// paused, the worker is released so it cannot hang forever.
+    pub async fn wait_if_paused(&mut self) {
+        let _ = self.paused.wait_for(|paused| !paused).await;
+    }
+}
+
+/// A small pool of workers processing jobs from a shared queue.
+///
+/// Each worker receives a job, then waits at a safe point if paused, then
+/// processes it. A paused pool therefore never starts new work.
+pub struct PausableWorkerPool {
+    jobs: mpsc::Sender<u32>,
+    processed: Arc<Mutex<Vec<u32>>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl PausableWorkerPool {
+    /// Spawns `worker_count` workers, each taking `job_duration` per job.
+    pub fn spawn(worker_count: usize, job_duration: Duration, token: PauseToken) -> Self {
+        let (jobs, receiver) = mpsc::channel(64);
+        let receiver = Arc::new(tokio::sync::Mutex::new(receiver));
+        let processed = Arc::new(Mutex::new(Vec::new()));
+
+        let workers = (0..worker_count)
+            .map(|id| {
+                let receiver = Arc::clone(&receiver);
+                let processed = Arc::clone(&processed);
+                let mut token = token.clone();
+
+                tokio::spawn(
+                    async move {
+                        loop {
+                            let job = receiver.lock().await.recv().await;
+                            let Some(job) = job else {
+                                break;
+                            };
+
+                            // Safe point: nothing is half-done here.
+                            token.wait_if_paused().await;
+
+                            info!(job, "processing job");
+                            sleep(job_duration).await;
+                            processed.lock().unwrap().push(job);
+                        }
+                    }
+                    .instrument(info_span!("worker", id)),
+                )
+            })
+            .collect();
+
+        PausableWorkerPool {
+            jobs,
+            processed,
+            workers,
+        }
+    }
+
+    /// Queues a job for the workers.
+    pub async fn submit(&self, job: u32) {
+        self.jobs
+            .send(job)
+            .await
+            .expect("workers stop only after the pool is shut down");
+    }
+
+    /// Returns the jobs processed so far, in completion order.
+    pub fn processed(&self) -> Vec<u32> {
+        self.processed.lock().unwrap().clone()
+    }
+
+    /// Closes the queue and waits for the workers to finish the remaining jobs.
+    ///
+    /// The workers must not be paused, otherwise this waits until they are
+    /// resumed.
+    pub async fn shutdown(self) -> Vec<u32> {
+        drop(self.jobs);
+        for worker in self.workers {
+            worker.await.expect("worker panicked");
+        }
+        let processed = self.processed.lock().unwrap().clone();
+        processed
+    }
+}
+
+/// Example: pausing and resuming a worker pool
+#[instrument(skip_all)]
+pub async fn pause_resume_example(ctx: &ExampleContext) {
+    let controller = PauseController::new();
+    let job_duration = ctx.latency().delay(Duration::from_millis(20));
+    let pool = PausableWorkerPool::spawn(2, job_duration, controller.token());
+
+    for job in 0..4 {
+        pool.submit(job).await;
+    }
+    ctx.latency().sleep(Duration::from_millis(30)).await;
+
+    info!("Pausing workers...");
+    controller.pause();
+    ctx.latency().sleep(Duration::from_millis(100)).await;
+    info!(
+        "In-flight jobs finished, {} job(s) processed, nothing new started",
+        pool.processed().len()
+    );
+
+    for job in 4..6 {
+        pool.submit(job).await;
+    }
+
+    info!("Resuming workers...");
+    controller.resume();
+    let processed = pool.shutdown().await;
+    info!("Processed jobs: {:?}", processed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_no_work_processed_while_paused() {
+        let controller = PauseController::new();
+        controller.pause();
+        let pool = PausableWorkerPool::spawn(2, Duration::from_millis(10), controller.token());
+
+        for job in 0..4 {
+            pool.submit(job).await;
+        }
+        sleep(Duration::from_secs(10)).await;
+        assert!(pool.processed().is_empty());
+
+        controller.resume();
+        let mut processed = pool.shutdown().await;
+        processed.sort();
+        assert_eq!(processed, vec![0, 1, 2, 3]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pause_lets_in_flight_work_finish() {
+        let controller = PauseController::new();
+        let pool = PausableWorkerPool::spawn(1, Duration::from_millis(10), controller.token());
+
+        for job in 0..5 {
+            pool.submit(job).await;
+        }
+        // Jobs 0 and 1 are done, job 2 is in progress.
+        sleep(Duration::from_millis(25)).await;
+        controller.pause();
+        assert!(controller.is_paused());
+
+        sleep(Duration::from_secs(10)).await;
+        assert_eq!(pool.processed(), vec![0, 1, 2]);
+
+        controller.resume();
+        assert_eq!(pool.shutdown().await, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dropping_controller_releases_workers() {
+        let controller = PauseController::new();
+        controller.pause();
+        let pool = PausableWorkerPool::spawn(1, Duration::from_millis(10), controller.token());
+        pool.submit(7).await;
+
+        drop(controller);
+        assert_eq!(pool.shutdown().await, vec![7]);
+    }
+}
@@ -0,0 +1,258 @@
+//! Multi-stage producer/consumer pipeline
+//!
+//! Three stages, each running as its own task, connected by bounded `mpsc`
+//! channels:
+//!
+//! ```text
+//! generator --(Input)--> transformer --(Output)--> sink
+//! ```
+//!
+//! - Bounded channels give backpressure: a fast stage waits when the next one
+//!   falls `capacity` items behind, so memory stays bounded.
+//! - Shutdown propagates downstream: when the generator runs out of items it
+//!   drops its sender, the transformer's `recv` returns `None` once the
+//!   channel is drained, it drops its own sender, and so on. No extra
+//!   shutdown signal is needed.
+//! - A stage whose `send` fails knows its consumer is gone and stops early,
+//!   so shutdown also propagates upstream.
+//! - Each stage handles items one at a time in channel order, so the output
+//!   order is the input order.
+//!
+//! Stages are spawned through [`crate::runtime`], and `tokio::sync::mpsc`
+//! does not depend on tokio's runtime: the pipeline also runs on smol. On
+//! tokio, they are listed by name in the task registry (`cargo run -- tasks`).
+
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, info, info_span, instrument, Instrument};
+
+use crate::example_context::ExampleContext;
+use crate::runtime::{sleep, spawn_named, JoinHandle};
+
+/// An item flowing from the generator to the transformer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Input {
+    /// Position of the item in the source.
+    pub index: usize,
+    pub value: u64,
+}
+
+/// An item produced by the transformer and collected by the sink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Output {
+    pub index: usize,
+    pub input: u64,
+    /// The input squared.
+    pub value: u64,
+}
+
+/// Channel capacity and simulated per-item work of each stage.
+#[derive(Debug, Clone, Copy)]
+pub struct PipelineConfig {
+    /// Capacity of each channel between two stages.
+    pub capacity: usize,
+    pub generate_delay: Duration,
+    pub transform_delay: Duration,
+    pub sink_delay: Duration,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        PipelineConfig {
+            capacity: 4,
+            generate_delay: Duration::from_millis(1),
+            transform_delay: Duration::from_millis(5),
+            sink_delay: Duration::from_millis(2),
+        }
+    }
+}
+
+/// Runs `items` through the pipeline with the default configuration.
+pub async fn run_pipeline(items: Vec<u64>) -> Vec<Output> {
+    run_pipeline_with(items, PipelineConfig::default()).await
+}
+
+/// Runs `items` through the pipeline and returns the sink's outputs, in
+/// input order.
+#[instrument(skip(items), fields(items = items.len()))]
+pub async fn run_pipeline_with(items: Vec<u64>, config: PipelineConfig) -> Vec<Output> {
+    let (input_tx, stages) = spawn_stages(config);
+    let generator = spawn_named(
+        "pipeline generator",
+        generate(items, input_tx, config.generate_delay).instrument(info_span!("generator")),
+    );
+
+    generator.await.expect("generator panicked");
+    let outputs = stages.await.expect("stages panicked");
+    info!(outputs = outputs.len(), "pipeline finished");
+    outputs
+}
+
+/// Spawns the transformer and the sink, fed through the returned sender in
+/// place of the generator. The handle returns the sink's outputs once every
+/// sender is dropped and the stages are drained.
+pub fn spawn_stages(config: PipelineConfig) -> (mpsc::Sender<Input>, JoinHandle<Vec<Output>>) {
+    let (input_tx, input_rx) = mpsc::channel(config.capacity);
+    let (output_tx, output_rx) = mpsc::channel(config.capacity);
+
+    let transformer = spawn_named(
+        "pipeline transformer",
+        transform(input_rx, output_tx, config.transform_delay)
+            .instrument(info_span!("transformer")),
+    );
+    let sink = spawn_named(
+        "pipeline sink",
+        collect(output_rx, config.sink_delay).instrument(info_span!("sink")),
+    );
+    let stages = spawn_named("pipeline stages", async move {
+        transformer.await.expect("transformer panicked");
+        sink.await.expect("sink panicked")
+    });
+    (input_tx, stages)
+}
+
+/// Stage 1: emits the items, then drops its sender to signal the end.
+async fn generate(items: Vec<u64>, tx: mpsc::Sender<Input>, delay: Duration) {
+    for (index, value) in items.into_iter().enumerate() {
+        sleep(delay).await;
+        if tx.send(Input { index, value }).await.is_err() {
+            debug!(index, "transformer gone, stopping");
+            return;
+        }
+    }
+    debug!("source exhausted");
+}
+
+/// Stage 2: transforms items until the generator is done.
+async fn transform(mut rx: mpsc::Receiver<Input>, tx: mpsc::Sender<Output>, delay: Duration) {
+    while let Some(input) = rx.recv().await {
+        sleep(delay).await;
+        let output = Output {
+            index: input.index,
+            input: input.value,
+            value: input.value * input.value,
+        };
+        if tx.send(output).await.is_err() {
+            debug!(index = input.index, "sink gone, stopping");
+            return;
+        }
+    }
+    debug!("input closed");
+}
+
+/// Stage 3: collects outputs until the transformer is done.
+async fn collect(mut rx: mpsc::Receiver<Output>, delay: Duration) -> Vec<Output> {
+    let mut outputs = Vec::new();
+    while let Some(output) = rx.recv().await {
+        sleep(delay).await;
+        outputs.push(output);
+    }
+    debug!("output closed");
+    outputs
+}
+
+/// Example: running ten items through the three stages, each stage taking
+/// the latencies of `ctx`
+#[instrument(skip_all)]
+pub async fn pipeline_example(ctx: &ExampleContext) -> Vec<Output> {
+    let defaults = PipelineConfig::default();
+    let config = PipelineConfig {
+        generate_delay: ctx.latency().delay(defaults.generate_delay),
+        transform_delay: ctx.latency().delay(defaults.transform_delay),
+        sink_delay: ctx.latency().delay(defaults.sink_delay),
+        ..defaults
+    };
+    let outputs = run_pipeline_with((1..=10).collect(), config).await;
+    let values: Vec<u64> = outputs.iter().map(|output| output.value).collect();
+    info!(?values, "squared values");
+    outputs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Instant;
+
+    fn config(capacity: usize) -> PipelineConfig {
+        PipelineConfig {
+            capacity,
+            generate_delay: Duration::from_millis(10),
+            transform_delay: Duration::from_millis(10),
+            sink_delay: Duration::from_millis(10),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_outputs_keep_input_order() {
+        let items = vec![5, 3, 9, 1, 7];
+        let outputs = run_pipeline(items.clone()).await;
+
+        let indices: Vec<usize> = outputs.iter().map(|output| output.index).collect();
+        assert_eq!(indices, vec![0, 1, 2, 3, 4]);
+        let inputs: Vec<u64> = outputs.iter().map(|output| output.input).collect();
+        assert_eq!(inputs, items);
+        assert!(outputs
+            .iter()
+            .all(|output| output.value == output.input * output.input));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_empty_source_shuts_down() {
+        assert_eq!(run_pipeline(Vec::new()).await, Vec::new());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stages_overlap() {
+        let start = Instant::now();
+        let outputs = run_pipeline_with((0..20).collect(), config(4)).await;
+        assert_eq!(outputs.len(), 20);
+
+        // Sequentially, 20 items through three 10ms stages would take 600ms.
+        // Pipelined, one item leaves the sink every 10ms once the pipeline is
+        // full: 20 items plus 2 stages to fill it.
+        assert_eq!(start.elapsed(), Duration::from_millis(220));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slowest_stage_sets_throughput() {
+        let mut config = config(4);
+        config.transform_delay = Duration::from_millis(50);
+
+        let start = Instant::now();
+        run_pipeline_with((0..10).collect(), config).await;
+        let elapsed = start.elapsed();
+
+        assert!(elapsed >= Duration::from_millis(10 * 50));
+        assert!(elapsed < Duration::from_millis(10 * 70));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_bounded_channel_applies_backpressure() {
+        let (tx, mut rx) = mpsc::channel(2);
+        let generator = tokio::spawn(generate((0..10).collect(), tx, Duration::ZERO));
+
+        // Nobody consumes: the generator fills the channel, then waits.
+        sleep(Duration::from_secs(1)).await;
+        assert!(!generator.is_finished());
+        assert_eq!(rx.len(), 2);
+
+        let mut received = Vec::new();
+        while let Some(input) = rx.recv().await {
+            received.push(input.index);
+        }
+        assert_eq!(received, (0..10).collect::<Vec<_>>());
+        generator.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_closed_sink_stops_upstream_stages() {
+        let (input_tx, input_rx) = mpsc::channel(2);
+        let (output_tx, output_rx) = mpsc::channel(2);
+        let generator = tokio::spawn(generate((0..1000).collect(), input_tx, Duration::ZERO));
+        let transformer = tokio::spawn(transform(input_rx, output_tx, Duration::ZERO));
+
+        drop(output_rx);
+        transformer.await.unwrap();
+        generator.await.unwrap();
+    }
+}
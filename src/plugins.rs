@@ -0,0 +1,401 @@
+//! Async trait object registry for plugins
+//!
+//! Plugins are `Box<dyn ExamplePlugin>` objects with an async lifecycle
+//! (`setup`, `run`, `teardown`), registered at startup and orchestrated by a
+//! [`PluginRunner`]. The runner only knows the trait: every call goes through
+//! the vtable to a boxed future (see `async_traits` for why the boxing is
+//! needed).
+//!
+//! Lifecycle rules enforced by the runner:
+//! - plugins are set up in registration order
+//! - a plugin whose setup failed is not run, and no later plugin is set up
+//! - every plugin that was set up is torn down, in reverse order, even if a
+//!   `run` failed
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tracing::{info, instrument, warn};
+
+use crate::example_context::{ExampleContext, LatencySimulator};
+
+/// Error returned by a plugin lifecycle step.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginError(pub String);
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for PluginError {}
+
+/// A plugin with an async lifecycle.
+#[async_trait]
+pub trait ExamplePlugin: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Acquires resources. Defaults to doing nothing.
+    async fn setup(&mut self) -> Result<(), PluginError> {
+        Ok(())
+    }
+
+    async fn run(&mut self) -> Result<(), PluginError>;
+
+    /// Releases resources. Defaults to doing nothing.
+    async fn teardown(&mut self) -> Result<(), PluginError> {
+        Ok(())
+    }
+}
+
+/// What happened to one plugin during a run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginReport {
+    pub name: String,
+    pub setup: Result<(), PluginError>,
+    /// `None` when the plugin was not run.
+    pub run: Option<Result<(), PluginError>>,
+    /// `None` when the plugin was not set up, so had nothing to tear down.
+    pub teardown: Option<Result<(), PluginError>>,
+}
+
+impl PluginReport {
+    /// Whether every lifecycle step that ran succeeded, and `run` did run.
+    pub fn succeeded(&self) -> bool {
+        self.setup.is_ok()
+            && matches!(self.run, Some(Ok(())))
+            && !matches!(self.teardown, Some(Err(_)))
+    }
+}
+
+/// Registers plugins at startup and drives their lifecycle.
+#[derive(Default)]
+pub struct PluginRunner {
+    plugins: Vec<Box<dyn ExamplePlugin>>,
+}
+
+impl PluginRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a plugin; plugins run in registration order.
+    pub fn register(&mut self, plugin: Box<dyn ExamplePlugin>) -> &mut Self {
+        self.plugins.push(plugin);
+        self
+    }
+
+    /// Names of the registered plugins, in order.
+    pub fn names(&self) -> Vec<&str> {
+        self.plugins.iter().map(|plugin| plugin.name()).collect()
+    }
+
+    /// Sets up, runs, and tears down every plugin.
+    ///
+    /// Returns one report per plugin that was attempted, in registration
+    /// order.
+    #[instrument(skip(self), fields(plugins = self.plugins.len()))]
+    pub async fn run_all(&mut self) -> Vec<PluginReport> {
+        let mut reports = Vec::new();
+
+        for plugin in self.plugins.iter_mut() {
+            let setup = plugin.setup().await;
+            let ready = setup.is_ok();
+            if let Err(error) = &setup {
+                warn!(plugin = plugin.name(), %error, "setup failed, not starting later plugins");
+            }
+            reports.push(PluginReport {
+                name: plugin.name().to_string(),
+                setup,
+                run: None,
+                teardown: None,
+            });
+            if !ready {
+                break;
+            }
+        }
+
+        let set_up = reports.iter().filter(|report| report.setup.is_ok()).count();
+
+        for (plugin, report) in self.plugins.iter_mut().zip(reports.iter_mut()).take(set_up) {
+            let result = plugin.run().await;
+            if let Err(error) = &result {
+                warn!(plugin = plugin.name(), %error, "run failed");
+            }
+            report.run = Some(result);
+        }
+
+        for (plugin, report) in self
+            .plugins
+            .iter_mut()
+            .zip(reports.iter_mut())
+            .take(set_up)
+            .rev()
+        {
+            report.teardown = Some(plugin.teardown().await);
+        }
+
+        reports
+    }
+}
+
+/// Boxed future returned by the closure of an [`FnPlugin`].
+pub type PluginFuture = Pin<Box<dyn Future<Output = Result<(), PluginError>> + Send>>;
+
+/// Adapts an async function into a plugin with no setup or teardown.
+pub struct FnPlugin<F> {
+    name: String,
+    run: F,
+}
+
+impl<F> FnPlugin<F>
+where
+    F: FnMut() -> PluginFuture + Send + Sync,
+{
+    pub fn new(name: impl Into<String>, run: F) -> Self {
+        FnPlugin {
+            name: name.into(),
+            run,
+        }
+    }
+}
+
+#[async_trait]
+impl<F> ExamplePlugin for FnPlugin<F>
+where
+    F: FnMut() -> PluginFuture + Send + Sync,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn run(&mut self) -> Result<(), PluginError> {
+        (self.run)().await
+    }
+}
+
+/// A plugin holding a simulated connection between setup and teardown.
+pub struct ConnectionPlugin {
+    name: String,
+    connection: Option<String>,
+    requests: u32,
+    latency: LatencySimulator,
+}
+
+impl ConnectionPlugin {
+    pub fn new(name: impl Into<String>, requests: u32) -> Self {
+        ConnectionPlugin {
+            name: name.into(),
+            connection: None,
+            requests,
+            latency: LatencySimulator::default(),
+        }
+    }
+
+    /// Simulates the connection and the requests with `latency`.
+    pub fn with_latency(mut self, latency: LatencySimulator) -> Self {
+        self.latency = latency;
+        self
+    }
+}
+
+#[async_trait]
+impl ExamplePlugin for ConnectionPlugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn setup(&mut self) -> Result<(), PluginError> {
+        self.latency.sleep(Duration::from_millis(10)).await;
+        self.connection = Some(format!("{}-connection", self.name));
+        info!(plugin = %self.name, "connected");
+        Ok(())
+    }
+
+    async fn run(&mut self) -> Result<(), PluginError> {
+        let connection = self
+            .connection
+            .as_ref()
+            .ok_or_else(|| PluginError("run before setup".to_string()))?;
+        for request in 1..=self.requests {
+            self.latency.sleep(Duration::from_millis(5)).await;
+            info!(plugin = %self.name, %connection, request, "request sent");
+        }
+        Ok(())
+    }
+
+    async fn teardown(&mut self) -> Result<(), PluginError> {
+        if let Some(connection) = self.connection.take() {
+            info!(plugin = %self.name, %connection, "disconnected");
+        }
+        Ok(())
+    }
+}
+
+/// Example: registering plugins at startup and running them
+#[instrument(skip_all)]
+pub async fn plugin_registry_example(ctx: &ExampleContext) -> Vec<PluginReport> {
+    let state_machine_ctx = ctx.clone();
+    let mut runner = PluginRunner::new();
+    runner
+        .register(Box::new(
+            ConnectionPlugin::new("database", 2).with_latency(ctx.latency().clone()),
+        ))
+        .register(Box::new(FnPlugin::new("state-machine", move || {
+            let ctx = state_machine_ctx.clone();
+            Box::pin(async move {
+                crate::async_state_machine_example(&ctx).await;
+                Ok(())
+            })
+        })))
+        .register(Box::new(FnPlugin::new("complex-function", || {
+            Box::pin(async {
+                crate::complex_async_function(7, "plugin".to_string())
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| PluginError(e.to_string()))
+            })
+        })));
+
+    info!(plugins = ?runner.names(), "plugins registered");
+    let reports = runner.run_all().await;
+    for report in &reports {
+        info!(plugin = %report.name, succeeded = report.succeeded(), "plugin finished");
+    }
+    reports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// A plugin recording its lifecycle calls, for tests.
+    struct RecordingPlugin {
+        name: &'static str,
+        log: Arc<Mutex<Vec<String>>>,
+        fail_setup: bool,
+        fail_run: bool,
+    }
+
+    #[async_trait]
+    impl ExamplePlugin for RecordingPlugin {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn setup(&mut self) -> Result<(), PluginError> {
+            tokio::task::yield_now().await;
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("setup {}", self.name));
+            if self.fail_setup {
+                return Err(PluginError(format!("{} setup failed", self.name)));
+            }
+            Ok(())
+        }
+
+        async fn run(&mut self) -> Result<(), PluginError> {
+            tokio::task::yield_now().await;
+            self.log.lock().unwrap().push(format!("run {}", self.name));
+            if self.fail_run {
+                return Err(PluginError(format!("{} run failed", self.name)));
+            }
+            Ok(())
+        }
+
+        async fn teardown(&mut self) -> Result<(), PluginError> {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("teardown {}", self.name));
+            Ok(())
+        }
+    }
+
+    fn recording(
+        name: &'static str,
+        log: &Arc<Mutex<Vec<String>>>,
+        fail_setup: bool,
+        fail_run: bool,
+    ) -> Box<dyn ExamplePlugin> {
+        Box::new(RecordingPlugin {
+            name,
+            log: Arc::clone(log),
+            fail_setup,
+            fail_run,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut runner = PluginRunner::new();
+        runner
+            .register(recording("a", &log, false, false))
+            .register(recording("b", &log, false, false));
+        assert_eq!(runner.names(), vec!["a", "b"]);
+
+        let reports = runner.run_all().await;
+        assert!(reports.iter().all(PluginReport::succeeded));
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                "setup a",
+                "setup b",
+                "run a",
+                "run b",
+                "teardown b",
+                "teardown a"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_teardown_runs_after_run_failure() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut runner = PluginRunner::new();
+        runner
+            .register(recording("a", &log, false, true))
+            .register(recording("b", &log, false, false));
+
+        let reports = runner.run_all().await;
+        assert!(!reports[0].succeeded());
+        assert!(reports[1].succeeded());
+        assert_eq!(reports[0].teardown, Some(Ok(())));
+        assert!(log.lock().unwrap().contains(&"teardown a".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_setup_failure_stops_startup() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut runner = PluginRunner::new();
+        runner
+            .register(recording("a", &log, false, false))
+            .register(recording("b", &log, true, false))
+            .register(recording("c", &log, false, false));
+
+        let reports = runner.run_all().await;
+        assert_eq!(reports.len(), 2);
+        assert!(reports[1].setup.is_err());
+        assert_eq!(reports[1].run, None);
+        assert_eq!(reports[1].teardown, None);
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["setup a", "setup b", "run a", "teardown a"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_plugin_registry_example() {
+        let reports = plugin_registry_example(&ExampleContext::captured()).await;
+        let names: Vec<_> = reports.iter().map(|report| report.name.as_str()).collect();
+        assert_eq!(names, vec!["database", "state-machine", "complex-function"]);
+        assert!(reports.iter().all(PluginReport::succeeded));
+    }
+}
@@ -0,0 +1,308 @@
+//! Poll visualizer: a wrapper future recording every poll and wake
+//!
+//! [`Traced`] wraps any future and sits between it and the executor:
+//! - every `poll` is timestamped and logged, with its outcome
+//! - the executor's waker is wrapped in a counting waker before being passed
+//!   down, so every `wake` of the inner future is counted
+//! - once the inner future completes, its output comes back together with a
+//!   [`PollReport`] of the whole run
+//!
+//! From the outside a future is a black box: `Pending` does not say where it
+//! is suspended. Wrapping an await in [`checkpoint`] labels it, and the poll
+//! that returned `Pending` is attributed to the innermost checkpoint pending
+//! at that time, so the report shows the state machine stepping through its
+//! states ("suspended at the first sleep", "at the second", ...).
+
+use std::cell::Cell;
+use std::fmt;
+use std::future::{poll_fn, Future};
+use std::pin::{pin, Pin};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tracing::{debug, info, instrument};
+
+use crate::example_context::{ExampleContext, LatencySimulator};
+
+thread_local! {
+    /// Label of the checkpoint whose future returned `Pending` last on this
+    /// thread. Polls are synchronous, so the [`Traced`] wrapper polling a
+    /// future reads it right after the poll, on the same thread.
+    static PENDING_AT: Cell<Option<&'static str>> = const { Cell::new(None) };
+}
+
+/// Awaits `future`, labelling the state it suspends in with `label`.
+pub async fn checkpoint<F: Future>(label: &'static str, future: F) -> F::Output {
+    let mut future = pin!(future);
+    poll_fn(|cx| {
+        let poll = future.as_mut().poll(cx);
+        if poll.is_pending() {
+            PENDING_AT.with(|pending_at| pending_at.set(Some(label)));
+        }
+        poll
+    })
+    .await
+}
+
+/// Outcome of one poll.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PollOutcome {
+    /// Suspended, at the given checkpoint if any.
+    Pending(Option<&'static str>),
+    Ready,
+}
+
+/// One call to `poll`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PollRecord {
+    /// Time since the first poll.
+    pub at: Duration,
+    /// Wakes received since the previous poll.
+    pub wakes_before: usize,
+    pub outcome: PollOutcome,
+}
+
+/// Every poll of a traced future.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PollReport {
+    pub name: &'static str,
+    pub polls: Vec<PollRecord>,
+    /// Total wakes, including any after completion.
+    pub wakes: usize,
+    /// Time between the first poll and completion.
+    pub elapsed: Duration,
+}
+
+impl PollReport {
+    /// The checkpoints the future was suspended at, in order, without
+    /// repeating a checkpoint polled several times in a row.
+    pub fn states(&self) -> Vec<&'static str> {
+        let mut states: Vec<&'static str> = Vec::new();
+        for record in &self.polls {
+            if let PollOutcome::Pending(Some(label)) = record.outcome {
+                if states.last() != Some(&label) {
+                    states.push(label);
+                }
+            }
+        }
+        states
+    }
+
+    /// One-line summary.
+    pub fn summary(&self) -> String {
+        format!(
+            "{}: {} poll(s), {} wake(s) in {:?}",
+            self.name,
+            self.polls.len(),
+            self.wakes,
+            self.elapsed
+        )
+    }
+}
+
+impl fmt::Display for PollReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.summary())?;
+        for (index, record) in self.polls.iter().enumerate() {
+            write!(
+                f,
+                "  poll #{} at {:?} after {} wake(s): ",
+                index + 1,
+                record.at,
+                record.wakes_before
+            )?;
+            match record.outcome {
+                PollOutcome::Pending(Some(label)) => writeln!(f, "Pending at {}", label)?,
+                PollOutcome::Pending(None) => writeln!(f, "Pending")?,
+                PollOutcome::Ready => writeln!(f, "Ready")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Forwards wakes to the executor's waker, counting them.
+struct CountingWaker {
+    name: &'static str,
+    wakes: Arc<AtomicUsize>,
+    inner: Waker,
+}
+
+impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let wakes = self.wakes.fetch_add(1, Ordering::Relaxed) + 1;
+        debug!(name = self.name, wakes, "wake");
+        self.inner.wake_by_ref();
+    }
+}
+
+/// A future recording every poll and wake of the future it wraps.
+pub struct Traced<F> {
+    name: &'static str,
+    inner: Pin<Box<F>>,
+    started: Option<Instant>,
+    wakes: Arc<AtomicUsize>,
+    wakes_seen: usize,
+    polls: Vec<PollRecord>,
+}
+
+impl<F: Future> Traced<F> {
+    pub fn new(name: &'static str, inner: F) -> Self {
+        Traced {
+            name,
+            inner: Box::pin(inner),
+            started: None,
+            wakes: Arc::new(AtomicUsize::new(0)),
+            wakes_seen: 0,
+            polls: Vec::new(),
+        }
+    }
+}
+
+impl<F: Future> Future for Traced<F> {
+    type Output = (F::Output, PollReport);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // `Traced` is `Unpin`: the inner future is pinned in its box.
+        let this = self.get_mut();
+        let started = *this.started.get_or_insert_with(Instant::now);
+
+        let waker = Waker::from(Arc::new(CountingWaker {
+            name: this.name,
+            wakes: Arc::clone(&this.wakes),
+            inner: cx.waker().clone(),
+        }));
+        PENDING_AT.with(|pending_at| pending_at.set(None));
+        let poll = this.inner.as_mut().poll(&mut Context::from_waker(&waker));
+        // Left set, so an enclosing `Traced` sees the same checkpoint.
+        let pending_at = PENDING_AT.with(Cell::get);
+
+        let wakes = this.wakes.load(Ordering::Relaxed);
+        let record = PollRecord {
+            at: started.elapsed(),
+            wakes_before: wakes - this.wakes_seen,
+            outcome: match poll {
+                Poll::Pending => PollOutcome::Pending(pending_at),
+                Poll::Ready(_) => PollOutcome::Ready,
+            },
+        };
+        this.wakes_seen = wakes;
+        debug!(
+            name = this.name,
+            poll = this.polls.len() + 1,
+            at = ?record.at,
+            outcome = ?record.outcome,
+            "poll"
+        );
+        this.polls.push(record);
+
+        poll.map(|output| {
+            let report = PollReport {
+                name: this.name,
+                polls: std::mem::take(&mut this.polls),
+                wakes,
+                elapsed: started.elapsed(),
+            };
+            (output, report)
+        })
+    }
+}
+
+/// Three sleeps of `latency`, each labelled: four states including the
+/// start.
+async fn three_steps(latency: &LatencySimulator) -> u32 {
+    let sleep = |ms| latency.sleep(Duration::from_millis(ms));
+    checkpoint("first sleep", sleep(10)).await;
+    let mut total = 1;
+    checkpoint("second sleep", sleep(20)).await;
+    total += 2;
+    checkpoint("third sleep", sleep(30)).await;
+    total + 3
+}
+
+/// Example: watching a future step through its states
+#[instrument(skip_all)]
+pub async fn poll_trace_example(ctx: &ExampleContext) -> PollReport {
+    let (total, report) = Traced::new("three_steps", three_steps(ctx.latency())).await;
+    info!(total, states = ?report.states(), "{}", report.summary());
+    for line in report.to_string().lines().skip(1) {
+        info!("{}", line);
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_states_of_three_steps() {
+        let report = poll_trace_example(&ExampleContext::captured()).await;
+
+        assert_eq!(
+            report.states(),
+            vec!["first sleep", "second sleep", "third sleep"]
+        );
+        assert_eq!(report.polls.len(), 4);
+        assert_eq!(report.wakes, 3);
+        assert_eq!(report.elapsed, Duration::from_millis(60));
+
+        let at: Vec<Duration> = report.polls.iter().map(|record| record.at).collect();
+        assert_eq!(
+            at,
+            [0, 10, 30, 60].map(Duration::from_millis).to_vec(),
+            "polled once per state, when its sleep elapsed"
+        );
+        assert_eq!(report.polls[0].wakes_before, 0);
+        assert!(report.polls[1..]
+            .iter()
+            .all(|record| record.wakes_before == 1));
+    }
+
+    #[tokio::test]
+    async fn test_ready_future_is_polled_once() {
+        let (output, report) = Traced::new("ready", async { 7 }).await;
+        assert_eq!(output, 7);
+        assert_eq!(report.polls.len(), 1);
+        assert_eq!(report.polls[0].outcome, PollOutcome::Ready);
+        assert_eq!(report.wakes, 0);
+    }
+
+    #[tokio::test]
+    async fn test_unlabelled_pending() {
+        let ((), report) = Traced::new("yield", tokio::task::yield_now()).await;
+        assert_eq!(
+            report.polls.iter().map(|r| r.outcome).collect::<Vec<_>>(),
+            vec![PollOutcome::Pending(None), PollOutcome::Ready]
+        );
+        assert_eq!(report.wakes, 1);
+        assert!(report.states().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_nested_traced_sees_inner_checkpoint() {
+        let latency = LatencySimulator::default();
+        let inner = Traced::new("inner", three_steps(&latency));
+        let ((total, inner_report), outer_report) = Traced::new("outer", inner).await;
+
+        assert_eq!(total, 6);
+        assert_eq!(inner_report.states(), outer_report.states());
+        assert_eq!(outer_report.wakes, 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_report_display() {
+        let report = poll_trace_example(&ExampleContext::captured()).await;
+        let text = report.to_string();
+        assert!(text.starts_with("three_steps: 4 poll(s), 3 wake(s)"));
+        assert!(text.contains("poll #2 at 10ms after 1 wake(s): Pending at second sleep"));
+        assert!(text.ends_with("Ready\n"));
+    }
+}
@@ -0,0 +1,428 @@
+//! Progress of many concurrent workers merged into one (`cargo run -- progress`)
+//!
+//! Each worker (a download, say) gets a [`ProgressReporter`] publishing how
+//! many of its units are done on a `watch` channel. A [`ProgressAggregator`]
+//! holds the receiving ends and merges them into an [`OverallProgress`]: the
+//! total percentage weighted by each worker's size, and an ETA extrapolated
+//! from the rate observed since the aggregator started.
+//!
+//! `watch` keeps only the latest value, which is what a progress display
+//! wants: a slow renderer skips intermediate values instead of falling
+//! behind, and workers never block on it. Receivers can be cloned, so the
+//! aggregator can be too, and several consumers (the CLI rendering a bar and
+//! a test asserting on the numbers) each follow the same workers.
+//!
+//! A worker is finished when its reporter is dropped; its last value stays
+//! readable, and [`ProgressAggregator::next`] returns `None` once every
+//! worker is finished.
+
+use std::fmt;
+use std::time::Duration;
+
+use futures::future::select_all;
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tracing::{info, instrument};
+
+use crate::example_context::{CancelToken, ExampleContext, LatencySimulator};
+
+/// Width of the rendered progress bar, in characters.
+const BAR_WIDTH: usize = 20;
+
+/// Progress of one worker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub done: u64,
+    pub total: u64,
+}
+
+/// The sending end of a worker's progress.
+#[derive(Debug)]
+pub struct ProgressReporter {
+    progress: watch::Sender<Progress>,
+}
+
+impl ProgressReporter {
+    /// Adds `units` to the work done, capped at the total.
+    pub fn advance(&self, units: u64) {
+        self.progress.send_modify(|progress| {
+            progress.done = (progress.done + units).min(progress.total);
+        });
+    }
+
+    /// Sets the work done, capped at the total.
+    pub fn set(&self, done: u64) {
+        self.progress
+            .send_modify(|progress| progress.done = done.min(progress.total));
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Worker {
+    name: String,
+    progress: watch::Receiver<Progress>,
+    finished: bool,
+}
+
+/// One worker in an [`OverallProgress`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerProgress {
+    pub name: String,
+    pub progress: Progress,
+    /// Its reporter was dropped.
+    pub finished: bool,
+}
+
+/// Progress of all the workers of an aggregator.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverallProgress {
+    pub done: u64,
+    pub total: u64,
+    /// Time since the aggregator started.
+    pub elapsed: Duration,
+    /// Time left at the rate observed so far; `None` before any progress.
+    pub eta: Option<Duration>,
+    pub workers: Vec<WorkerProgress>,
+}
+
+impl OverallProgress {
+    /// Percentage of the units done, 100 when there is nothing to do.
+    pub fn percent(&self) -> f64 {
+        if self.total == 0 {
+            return 100.0;
+        }
+        self.done as f64 * 100.0 / self.total as f64
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.done == self.total
+    }
+}
+
+impl fmt::Display for OverallProgress {
+    /// `[#########           ]  45.0% 90/200 ETA 1.2s`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let filled = (self.percent() / 100.0 * BAR_WIDTH as f64).round() as usize;
+        write!(
+            f,
+            "[{}{}] {:5.1}% {}/{}",
+            "#".repeat(filled),
+            " ".repeat(BAR_WIDTH - filled),
+            self.percent(),
+            self.done,
+            self.total
+        )?;
+        match self.eta {
+            Some(eta) => write!(f, " ETA {:.1}s", eta.as_secs_f64()),
+            None => write!(f, " ETA -"),
+        }
+    }
+}
+
+/// Merges the progress of several workers.
+#[derive(Debug, Clone)]
+pub struct ProgressAggregator {
+    started: Instant,
+    workers: Vec<Worker>,
+}
+
+impl Default for ProgressAggregator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgressAggregator {
+    /// An aggregator without workers, measuring time from now.
+    pub fn new() -> Self {
+        ProgressAggregator {
+            started: Instant::now(),
+            workers: Vec::new(),
+        }
+    }
+
+    /// Registers a worker with `total` units of work, and returns its
+    /// reporter.
+    ///
+    /// Workers must be added before the aggregator is cloned: clones only
+    /// follow the workers added so far.
+    pub fn add_worker(&mut self, name: impl Into<String>, total: u64) -> ProgressReporter {
+        let (progress, receiver) = watch::channel(Progress { done: 0, total });
+        self.workers.push(Worker {
+            name: name.into(),
+            progress: receiver,
+            finished: false,
+        });
+        ProgressReporter { progress }
+    }
+
+    /// The current progress, without waiting.
+    pub fn snapshot(&self) -> OverallProgress {
+        let workers: Vec<WorkerProgress> = self
+            .workers
+            .iter()
+            .map(|worker| WorkerProgress {
+                name: worker.name.clone(),
+                progress: *worker.progress.borrow(),
+                finished: worker.finished || worker.progress.has_changed().is_err(),
+            })
+            .collect();
+        let done = workers.iter().map(|worker| worker.progress.done).sum();
+        let total = workers.iter().map(|worker| worker.progress.total).sum();
+        let elapsed = self.started.elapsed();
+
+        let eta = if done == total {
+            Some(Duration::ZERO)
+        } else if done == 0 || elapsed.is_zero() {
+            None
+        } else {
+            // Units per second so far, applied to the units left.
+            Some(elapsed.mul_f64((total - done) as f64 / done as f64))
+        };
+
+        OverallProgress {
+            done,
+            total,
+            elapsed,
+            eta,
+            workers,
+        }
+    }
+
+    /// Waits until a worker reports progress or finishes, and returns the
+    /// new overall progress; `None` once every worker is finished.
+    pub async fn next(&mut self) -> Option<OverallProgress> {
+        let changes: Vec<_> = self
+            .workers
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, worker)| !worker.finished)
+            .map(|(index, worker)| {
+                Box::pin(async move { (index, worker.progress.changed().await) })
+            })
+            .collect();
+        if changes.is_empty() {
+            return None;
+        }
+
+        let ((index, changed), _, _) = select_all(changes).await;
+        if changed.is_err() {
+            // The reporter is gone: the worker's last value is final.
+            self.workers[index].finished = true;
+        }
+        Some(self.snapshot())
+    }
+
+    /// Calls `render` on every update until every worker is finished, and
+    /// returns the final progress.
+    pub async fn follow(mut self, mut render: impl FnMut(&OverallProgress)) -> OverallProgress {
+        while let Some(progress) = self.next().await {
+            render(&progress);
+        }
+        self.snapshot()
+    }
+}
+
+/// A simulated download of `size` kilobytes at `speed` kilobytes per tick of
+/// 10ms, abandoned when `cancel` is cancelled.
+async fn download(
+    reporter: ProgressReporter,
+    size: u64,
+    speed: u64,
+    latency: LatencySimulator,
+    cancel: CancelToken,
+) {
+    let mut received = 0;
+    while received < size && !cancel.is_cancelled() {
+        latency.sleep(Duration::from_millis(10)).await;
+        received += speed;
+        reporter.set(received);
+    }
+}
+
+/// Downloads of different sizes and speeds, followed by one aggregator.
+///
+/// The ticks go through `ctx`'s latency and the downloads stop early when it
+/// is cancelled. `render` is called on every update; the final progress is
+/// returned.
+pub async fn simulated_downloads(
+    ctx: &ExampleContext,
+    render: impl FnMut(&OverallProgress),
+) -> OverallProgress {
+    let mut aggregator = ProgressAggregator::new();
+    let downloads = [
+        ("small.iso", 100, 10),
+        ("medium.iso", 300, 15),
+        ("large.iso", 600, 20),
+    ];
+    for (name, size, speed) in downloads {
+        tokio::spawn(download(
+            aggregator.add_worker(name, size),
+            size,
+            speed,
+            ctx.latency().clone(),
+            ctx.cancel_token().clone(),
+        ));
+    }
+    aggregator.follow(render).await
+}
+
+/// Runs the `progress` subcommand: renders the simulated downloads on
+/// stdout, one line per update.
+pub async fn run_cli() -> OverallProgress {
+    simulated_downloads(&ExampleContext::default(), |progress| {
+        println!("{}", progress)
+    })
+    .await
+}
+
+/// Example: three downloads merged into one progress bar
+#[instrument(skip_all)]
+pub async fn progress_example(ctx: &ExampleContext) -> OverallProgress {
+    let mut updates = 0;
+    let progress = simulated_downloads(ctx, |progress| {
+        updates += 1;
+        if updates % 10 == 0 {
+            info!("{}", progress);
+        }
+    })
+    .await;
+    info!(updates, "{}", progress);
+    progress
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::sleep;
+
+    use super::*;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_percentage_is_weighted_by_size() {
+        let mut aggregator = ProgressAggregator::new();
+        let small = aggregator.add_worker("small", 100);
+        let large = aggregator.add_worker("large", 300);
+
+        small.set(100);
+        large.advance(50);
+        let progress = aggregator.snapshot();
+        assert_eq!((progress.done, progress.total), (150, 400));
+        assert_eq!(progress.percent(), 37.5);
+        assert!(!progress.is_complete());
+        assert_eq!(
+            progress.workers[0].progress,
+            Progress {
+                done: 100,
+                total: 100
+            }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_eta_follows_the_observed_rate() {
+        let mut aggregator = ProgressAggregator::new();
+        let reporter = aggregator.add_worker("download", 100);
+        assert_eq!(aggregator.snapshot().eta, None);
+
+        sleep(secs(1)).await;
+        reporter.set(25);
+        let progress = aggregator.snapshot();
+        assert_eq!(progress.elapsed, secs(1));
+        assert_eq!(progress.eta, Some(secs(3)));
+
+        reporter.advance(500);
+        assert_eq!(aggregator.snapshot().done, 100);
+        assert_eq!(aggregator.snapshot().eta, Some(Duration::ZERO));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_next_ends_when_every_worker_is_finished() {
+        let mut aggregator = ProgressAggregator::new();
+        let first = aggregator.add_worker("first", 10);
+        let second = aggregator.add_worker("second", 10);
+
+        first.set(4);
+        assert_eq!(aggregator.next().await.unwrap().done, 4);
+
+        drop(first);
+        let progress = aggregator.next().await.unwrap();
+        assert!(progress.workers[0].finished && !progress.workers[1].finished);
+        assert_eq!(progress.done, 4);
+
+        second.set(10);
+        drop(second);
+        assert_eq!(aggregator.next().await.unwrap().done, 14);
+        assert!(aggregator
+            .next()
+            .await
+            .is_some_and(|p| p.workers[1].finished));
+        assert_eq!(aggregator.next().await, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_consumers_each_follow_the_workers() {
+        let mut aggregator = ProgressAggregator::new();
+        let reporter = aggregator.add_worker("download", 50);
+        let slow = aggregator.clone();
+
+        let fast = tokio::spawn(aggregator.follow(|_| {}));
+        let slow = tokio::spawn(async move {
+            // Skips the intermediate values: only the latest one is kept.
+            sleep(secs(10)).await;
+            let mut updates = 0;
+            let progress = slow.follow(|_| updates += 1).await;
+            (progress, updates)
+        });
+        for _ in 0..5 {
+            sleep(Duration::from_millis(10)).await;
+            reporter.advance(10);
+        }
+        drop(reporter);
+
+        let fast = fast.await.unwrap();
+        let (slow, updates) = slow.await.unwrap();
+        assert!(fast.is_complete() && slow.is_complete());
+        assert_eq!(fast.done, slow.done);
+        assert!(updates <= 2, "{} updates", updates);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_simulated_downloads() {
+        let mut rendered = Vec::new();
+        let ctx = ExampleContext::captured();
+        let progress = simulated_downloads(&ctx, |progress| rendered.push(progress.clone())).await;
+
+        assert!(progress.is_complete());
+        assert_eq!(progress.total, 1000);
+        // The large download takes 30 ticks of 10ms.
+        assert_eq!(progress.elapsed, Duration::from_millis(300));
+        assert!(progress.workers.iter().all(|worker| worker.finished));
+        assert!(rendered.windows(2).all(|pair| pair[0].done <= pair[1].done));
+    }
+
+    #[test]
+    fn test_rendering() {
+        let progress = OverallProgress {
+            done: 90,
+            total: 200,
+            elapsed: secs(1),
+            eta: Some(Duration::from_millis(1222)),
+            workers: Vec::new(),
+        };
+        assert_eq!(
+            progress.to_string(),
+            "[#########           ]  45.0% 90/200 ETA 1.2s"
+        );
+        let empty = OverallProgress {
+            total: 0,
+            done: 0,
+            eta: None,
+            ..progress
+        };
+        assert_eq!(empty.to_string(), "[####################] 100.0% 0/0 ETA -");
+    }
+}
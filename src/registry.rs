@@ -0,0 +1,1022 @@
+//! Registry of the course examples
+//!
+//! Every example is registered once, in [`course_examples`], with a name,
+//! a description and an estimated duration. The binary runs the registry in
+//! order, and the runtime-flavor matrix tests run every example of the
+//! registry that fits in a test runtime. Adding an example is therefore a
+//! one-place change, and a test fails if a public `*_example` function was
+//! forgotten.
+//!
+//! Examples have different return types; the registry erases them behind a
+//! boxed future resolving to `Result<(), ExampleError>`, the same way the
+//! plugin runner erases plugins behind a trait object. Each run receives an
+//! [`ExampleContext`]: the example reports its result through the context's
+//! output, and the runner picks the clock, latencies, cancellation and
+//! metrics.
+
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+use crate::example_context::{Clock, ExampleContext, Output};
+use crate::instrumentation::capture_into;
+use crate::report::{ExampleRunResult, ExampleStatus};
+use futures::future::BoxFuture;
+
+use crate::{
+    adaptive_timeout, async_drop, async_state_machine_example, async_sugar_example, async_traits,
+    cancel_safety, coalescing, complex_async_function, concurrent_execution_example, context,
+    dedicated_runtime, error_reporter, errors, fairness, fetch_data_from_api, flaky, future_size,
+    hedging, into_future, loadgen, memory_budget, multiple_awaits_example, oneshot, pagination,
+    panics, pause, pipeline, plugins, poll_trace, progress, runtime, runtimes, sandbox, scheduler,
+    select_patterns, send_sync, shutdown, strategies, sync_bridge, sync_primitives, task_registry,
+    threads_vs_async, transaction, unordered, variable_scoping_example, waker, warmup,
+};
+
+/// The future an example returns once erased.
+pub type ExampleFuture = BoxFuture<'static, Result<(), ExampleError>>;
+
+/// Error of a failed example.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExampleError(pub String);
+
+impl ExampleError {
+    /// Wraps any displayable error.
+    pub fn new(error: impl fmt::Display) -> Self {
+        ExampleError(error.to_string())
+    }
+}
+
+impl fmt::Display for ExampleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ExampleError {}
+
+/// A registered example.
+pub struct Example {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Rough wall-clock duration of a run.
+    pub estimated_duration: Duration,
+    /// Makes real network requests.
+    pub needs_network: bool,
+    /// Builds its own runtimes, so runs on a blocking thread and cannot run
+    /// inside a test runtime.
+    pub builds_runtime: bool,
+    /// Whether the example can run with paused time. Not the case when it
+    /// waits on an OS thread: the paused clock jumps straight to the next
+    /// timer instead of waiting for the thread.
+    pub pausable: bool,
+    run: Box<dyn Fn(ExampleContext) -> ExampleFuture + Send + Sync>,
+}
+
+impl Example {
+    pub fn new<F, Fut>(
+        name: &'static str,
+        description: &'static str,
+        estimated_duration: Duration,
+        run: F,
+    ) -> Self
+    where
+        F: Fn(ExampleContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), ExampleError>> + Send + 'static,
+    {
+        Example {
+            name,
+            description,
+            estimated_duration,
+            needs_network: false,
+            builds_runtime: false,
+            pausable: true,
+            run: Box::new(move |ctx| Box::pin(run(ctx))),
+        }
+    }
+
+    pub fn needs_network(self) -> Self {
+        Example {
+            needs_network: true,
+            ..self
+        }
+    }
+
+    /// Marks a synchronous example building its own runtimes; `run` calls it
+    /// on a blocking thread.
+    pub fn builds_runtime(self) -> Self {
+        Example {
+            builds_runtime: true,
+            ..self
+        }
+    }
+
+    pub fn not_pausable(self) -> Self {
+        Example {
+            pausable: false,
+            ..self
+        }
+    }
+
+    /// Starts a run of the example with `ctx`.
+    ///
+    /// The run fails once the context's cancellation token is cancelled, and
+    /// counts as `completed` or `failed` in its metrics. With jitter
+    /// configured, the example starts after a random delay.
+    pub fn run(&self, ctx: ExampleContext) -> ExampleFuture {
+        let run = (self.run)(ctx.clone());
+        Box::pin(async move {
+            let result = tokio::select! {
+                biased;
+                _ = ctx.cancel_token().cancelled() => Err(ExampleError::new("cancelled")),
+                result = async {
+                    ctx.latency().sleep(Duration::ZERO).await;
+                    run.await
+                } => result,
+            };
+            let outcome = if result.is_ok() {
+                "completed"
+            } else {
+                "failed"
+            };
+            ctx.metrics().increment(outcome);
+            result
+        })
+    }
+
+    /// Runs the example with `ctx` and sums the run up: its duration, its
+    /// status and the lines it wrote to the context's output.
+    ///
+    /// When the output is captured, so are the tracing events of the run,
+    /// interleaved with the lines in the order they happened.
+    pub async fn run_to_result(&self, ctx: ExampleContext) -> ExampleRunResult {
+        let clock = Clock::start();
+        let run = self.run(ctx.clone());
+        let result = match ctx.output() {
+            Output::Stdout => run.await,
+            output @ Output::Captured(_) => capture_into(output.clone(), run).await,
+        };
+        ExampleRunResult {
+            name: self.name,
+            duration: clock.elapsed(),
+            status: match result {
+                Ok(()) => ExampleStatus::Succeeded,
+                Err(e) => ExampleStatus::Failed(e.0),
+            },
+            log_lines: ctx.output().lines(),
+        }
+    }
+}
+
+/// Runs a synchronous example on a blocking thread and reports its result.
+async fn run_blocking<T, E>(
+    ctx: ExampleContext,
+    example: fn(&ExampleContext) -> Result<T, E>,
+) -> Result<(), ExampleError>
+where
+    T: fmt::Debug + Send + 'static,
+    E: fmt::Display + Send + 'static,
+{
+    let blocking_ctx = ctx.clone();
+    let result = tokio::task::spawn_blocking(move || example(&blocking_ctx))
+        .await
+        .map_err(ExampleError::new)?
+        .map_err(ExampleError::new)?;
+    ctx.report(result);
+    Ok(())
+}
+
+/// Examples in registration order, with unique names.
+#[derive(Default)]
+pub struct ExampleRegistry {
+    examples: Vec<Example>,
+}
+
+impl ExampleRegistry {
+    pub fn new() -> Self {
+        ExampleRegistry::default()
+    }
+
+    /// Adds an example at the end.
+    ///
+    /// # Panics
+    ///
+    /// If an example with the same name is already registered.
+    pub fn register(&mut self, example: Example) -> &mut Self {
+        assert!(
+            self.get(example.name).is_none(),
+            "example {} registered twice",
+            example.name
+        );
+        self.examples.push(example);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Example> {
+        self.examples.iter().find(|example| example.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Example> {
+        self.examples.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.examples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.examples.is_empty()
+    }
+
+    /// Sum of the estimated durations.
+    pub fn estimated_total(&self) -> Duration {
+        self.examples
+            .iter()
+            .map(|example| example.estimated_duration)
+            .sum()
+    }
+}
+
+impl IntoIterator for ExampleRegistry {
+    type Item = Example;
+    type IntoIter = std::vec::IntoIter<Example>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.examples.into_iter()
+    }
+}
+
+fn millis(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+/// Every example of the course, in the order the binary runs them.
+///
+/// Names are the function names without the `_example` suffix.
+pub fn course_examples() -> ExampleRegistry {
+    let mut registry = ExampleRegistry::new();
+    registry
+        .register(Example::new(
+            "async_state_machine",
+            "Async functions compiled into state machines",
+            millis(100),
+            |ctx| async move {
+                async_state_machine_example(&ctx).await;
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "multiple_awaits",
+            "Several await points in one function",
+            millis(150),
+            |ctx| async move {
+                multiple_awaits_example(&ctx).await;
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "variable_scoping",
+            "Variables living across await points",
+            millis(50),
+            |ctx| async move {
+                variable_scoping_example(&ctx).await;
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "complex_async_function",
+            "Parameters, Result and errors in async functions",
+            millis(100),
+            |ctx| async move {
+                let result = complex_async_function(42, "test-data".to_string())
+                    .await
+                    .map_err(ExampleError::new)?;
+                ctx.report(result);
+                Ok(())
+            },
+        ))
+        .register(
+            Example::new(
+                "fetch_data_from_api",
+                "A real HTTP request with reqwest",
+                millis(1000),
+                |ctx| async move {
+                    let data = fetch_data_from_api("https://api.github.com/repos/rust-lang/rust")
+                        .await
+                        .map_err(ExampleError::new)?;
+                    ctx.output().line(&data[..data.len().min(100)]);
+                    Ok(())
+                },
+            )
+            .needs_network(),
+        )
+        .register(Example::new(
+            "concurrent_execution",
+            "Concurrent futures with tokio::join!",
+            millis(100),
+            |ctx| async move {
+                concurrent_execution_example(&ctx).await;
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "async_sugar",
+            "async fn as sugar for impl Future",
+            millis(10),
+            |ctx| async move {
+                ctx.report(async_sugar_example(&ctx).await);
+                Ok(())
+            },
+        ))
+        .register(
+            Example::new(
+                "waker",
+                "A future woken from another thread",
+                millis(50),
+                |ctx| async move {
+                    ctx.report(waker::waker_example(&ctx).await);
+                    Ok(())
+                },
+            )
+            // Completed by a `std::thread`.
+            .not_pausable(),
+        )
+        .register(Example::new(
+            "select_patterns",
+            "tokio::select! patterns",
+            millis(250),
+            |ctx| async move {
+                select_patterns::select_patterns_example(&ctx).await;
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "pause_resume",
+            "Pausing and resuming background workers",
+            millis(150),
+            |ctx| async move {
+                pause::pause_resume_example(&ctx).await;
+                Ok(())
+            },
+        ))
+        .register(
+            Example::new(
+                "runtimes",
+                "Explicit runtime configuration",
+                millis(400),
+                |ctx| run_blocking(ctx, runtimes::runtimes_example),
+            )
+            .builds_runtime(),
+        )
+        .register(Example::new(
+            "retry",
+            "Retrying a flaky dependency",
+            millis(50),
+            |ctx| async move {
+                ctx.report(
+                    flaky::retry_example(&ctx)
+                        .await
+                        .map_err(ExampleError::new)?,
+                );
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "memory_budget",
+            "Memory-budgeted buffering",
+            millis(200),
+            |ctx| async move {
+                ctx.report(
+                    memory_budget::memory_budget_example(&ctx)
+                        .await
+                        .map_err(ExampleError::new)?,
+                );
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "async_traits",
+            "Async functions in traits",
+            millis(50),
+            |ctx| async move {
+                async_traits::async_traits_example(&ctx).await;
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "plugin_registry",
+            "Async plugin registry",
+            millis(200),
+            |ctx| async move {
+                ctx.report(plugins::plugin_registry_example(&ctx).await);
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "rc_dropped_before_await",
+            "A Send future thanks to an Rc dropped before awaiting",
+            millis(10),
+            |ctx| async move {
+                ctx.report(send_sync::rc_dropped_before_await_example(&ctx).await);
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "unordered",
+            "Ordered vs unordered completion",
+            millis(150),
+            |ctx| async move {
+                unordered::unordered_example(&ctx).await;
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "future_size",
+            "Future sizes and Box::pin",
+            millis(5),
+            |ctx| async move {
+                future_size::future_size_example(&ctx).await;
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "pipeline",
+            "Multi-stage pipeline",
+            millis(100),
+            |ctx| async move {
+                ctx.report(pipeline::pipeline_example(&ctx).await);
+                Ok(())
+            },
+        ))
+        .register(
+            Example::new(
+                "dedicated_runtime",
+                "Dedicated runtime for latency-sensitive work",
+                millis(250),
+                |ctx| run_blocking(ctx, dedicated_runtime::dedicated_runtime_example),
+            )
+            .builds_runtime(),
+        )
+        .register(
+            Example::new(
+                "fairness",
+                "Cooperative yielding and starvation",
+                millis(150),
+                |ctx| run_blocking(ctx, fairness::fairness_example),
+            )
+            .builds_runtime(),
+        )
+        .register(Example::new(
+            "error_handling",
+            "Error handling patterns",
+            millis(100),
+            |ctx| async move {
+                errors::error_handling_example(&ctx).await;
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "oneshot",
+            "A oneshot channel written from scratch",
+            millis(50),
+            |ctx| async move {
+                let (sent, dropped) = oneshot::oneshot_example(&ctx).await;
+                ctx.report(sent.map_err(ExampleError::new)?);
+                // The second channel's sender is dropped on purpose.
+                if dropped.is_ok() {
+                    return Err(ExampleError::new("dropped sender delivered a value"));
+                }
+                Ok(())
+            },
+        ))
+        .register(
+            Example::new(
+                "coalescing",
+                "Coalesced wakeups",
+                millis(10),
+                |ctx| async move {
+                    ctx.report(coalescing::coalescing_example().await);
+                    Ok(())
+                },
+            )
+            // Consumes updates from a `std::thread`.
+            .not_pausable(),
+        )
+        .register(Example::new(
+            "poll_trace",
+            "Every poll and wake of a future",
+            millis(60),
+            |ctx| async move {
+                ctx.report(poll_trace::poll_trace_example(&ctx).await);
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "loadgen",
+            "Open- and closed-loop load with latency percentiles",
+            millis(800),
+            |ctx| async move {
+                ctx.report(loadgen::loadgen_example(&ctx).await);
+                Ok(())
+            },
+        ))
+        .register(
+            Example::new(
+                "sync_bridge",
+                "Calling async code from sync code",
+                millis(100),
+                |ctx| run_blocking(ctx, sync_bridge::sync_bridge_example),
+            )
+            .builds_runtime(),
+        )
+        .register(Example::new(
+            "adaptive_timeout",
+            "Timeouts following observed latencies",
+            millis(850),
+            |ctx| async move {
+                ctx.report(adaptive_timeout::adaptive_timeout_example(&ctx).await);
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "error_reporter",
+            "Background errors reported to a central handler",
+            millis(60),
+            |ctx| async move {
+                ctx.report(
+                    error_reporter::error_reporter_example(&ctx)
+                        .await
+                        .map_err(ExampleError::new)?,
+                );
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "scheduler",
+            "Periodic tasks, drift and missed-tick behaviors",
+            millis(330),
+            |ctx| async move {
+                ctx.report(scheduler::scheduler_example(&ctx).await);
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "shutdown",
+            "Ordered shutdown phases with dependencies",
+            millis(80),
+            |ctx| async move {
+                ctx.report(
+                    shutdown::shutdown_example(&ctx)
+                        .await
+                        .map_err(ExampleError::new)?,
+                );
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "async_drop",
+            "Drop across awaits and async cleanup patterns",
+            millis(120),
+            |ctx| async move {
+                ctx.report(async_drop::async_drop_example(&ctx).await);
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "into_future",
+            "Awaiting a request builder through IntoFuture",
+            millis(20),
+            |ctx| async move {
+                ctx.report(
+                    into_future::into_future_example(&ctx)
+                        .await
+                        .map_err(ExampleError::new)?,
+                );
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "strategies",
+            "Sequential vs join_all vs spawned vs FuturesUnordered",
+            millis(530),
+            |ctx| async move {
+                ctx.report(strategies::strategies_example(&ctx).await);
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "transaction",
+            "Commit or roll back a transaction around an async closure",
+            millis(10),
+            |ctx| async move {
+                ctx.report(transaction::transaction_example().await);
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "pagination",
+            "Paginated API as a lazy stream of pages",
+            millis(40),
+            |ctx| async move {
+                ctx.report(
+                    pagination::pagination_example(&ctx)
+                        .await
+                        .map_err(ExampleError::new)?,
+                );
+                Ok(())
+            },
+        ))
+        .register(
+            Example::new(
+                "runtime_agnostic",
+                "The same examples on tokio and, with runtime-smol, on smol",
+                millis(300),
+                |ctx| {
+                    run_blocking(ctx, |ctx| {
+                        Ok::<_, std::convert::Infallible>(runtime::runtime_agnostic_example(ctx))
+                    })
+                },
+            )
+            .builds_runtime(),
+        )
+        .register(Example::new(
+            "warmup",
+            "Eager warm-up vs lazy initialization, first-request latency",
+            millis(120),
+            |ctx| async move {
+                ctx.report(warmup::warmup_example(&ctx).await);
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "context",
+            "Request context with deadline and cancellation through nested calls",
+            millis(160),
+            |ctx| async move {
+                ctx.report(context::context_example(&ctx).await);
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "sync_primitives",
+            "Notify, Barrier and OnceCell",
+            millis(60),
+            |ctx| async move {
+                ctx.report(
+                    sync_primitives::sync_primitives_example(&ctx)
+                        .await
+                        .map_err(ExampleError::new)?,
+                );
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "panics",
+            "Panicking tasks, catch_unwind and a supervisor",
+            millis(30),
+            |ctx| async move {
+                ctx.report(panics::panics_example(&ctx).await);
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "cancel_safety",
+            "A transfer cancelled at every await point, invariants checked",
+            millis(20),
+            |ctx| async move {
+                ctx.report(cancel_safety::cancel_safety_example().await);
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "progress",
+            "Progress of concurrent downloads merged into one bar with an ETA",
+            millis(300),
+            |ctx| async move {
+                ctx.report(progress::progress_example(&ctx).await);
+                Ok(())
+            },
+        ))
+        .register(
+            Example::new(
+                "sandbox",
+                "Child processes with a wall-clock limit: success, failure, hang",
+                millis(250),
+                |ctx| async move {
+                    ctx.report(sandbox::sandbox_example().await);
+                    Ok(())
+                },
+            )
+            // Waits on child processes.
+            .not_pausable(),
+        )
+        .register(Example::new(
+            "hedging",
+            "race_ok and hedged requests against a slow and a fast replica",
+            millis(70),
+            |ctx| async move {
+                ctx.report(hedging::hedging_example(&ctx).await.map_err(|errors| {
+                    ExampleError::new(format!("every request failed: {:?}", errors))
+                })?);
+                Ok(())
+            },
+        ))
+        .register(Example::new(
+            "task_registry",
+            "Named tasks listed in a registry until they complete or are aborted",
+            millis(100),
+            |ctx| async move {
+                ctx.report(task_registry::task_registry_example(&ctx).await);
+                Ok(())
+            },
+        ))
+        .register(
+            Example::new(
+                "threads_vs_async",
+                "The same fetch/process workload on OS threads and on tokio tasks",
+                millis(150),
+                |ctx| run_blocking(ctx, threads_vs_async::threads_vs_async_example),
+            )
+            .builds_runtime(),
+        );
+
+    #[cfg(any(test, feature = "http-server"))]
+    registry
+        .register(
+            Example::new(
+                "http_server",
+                "HTTP server with concurrent handlers and graceful shutdown",
+                millis(150),
+                |ctx| async move {
+                    ctx.report(
+                        crate::http_server::http_server_example(&ctx)
+                            .await
+                            .map_err(ExampleError::new)?,
+                    );
+                    Ok(())
+                },
+            )
+            // Waits on sockets.
+            .not_pausable(),
+        )
+        .register(
+            Example::new(
+                "backpressure",
+                "HTTP endpoint answering 503 when the pipeline queue is full",
+                millis(250),
+                |ctx| async move {
+                    ctx.report(
+                        crate::http_server::backpressure_example(&ctx)
+                            .await
+                            .map_err(ExampleError::new)?,
+                    );
+                    Ok(())
+                },
+            )
+            .not_pausable(),
+        );
+
+    #[cfg(feature = "websocket")]
+    registry.register(
+        Example::new(
+            "websocket",
+            "Websocket broadcast chat",
+            millis(100),
+            |ctx| async move {
+                ctx.report(
+                    crate::websocket::websocket_example()
+                        .await
+                        .map_err(ExampleError::new)?,
+                );
+                Ok(())
+            },
+        )
+        // Waits on sockets.
+        .not_pausable(),
+    );
+
+    registry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use tracing::instrument::WithSubscriber;
+
+    /// Public example functions deliberately not registered.
+    const NOT_REGISTERED: &[&str] = &[
+        // `!Send` on purpose, see the compile_fail doc test of `send_sync`.
+        "rc_across_await_example",
+    ];
+
+    /// Names of the `pub fn *_example` and `pub async fn *_example` functions
+    /// of a source file.
+    fn public_examples(source: &str) -> Vec<String> {
+        source
+            .lines()
+            .filter_map(|line| {
+                let signature = line
+                    .strip_prefix("pub async fn ")
+                    .or_else(|| line.strip_prefix("pub fn "))?;
+                let name = &signature[..signature.find(['(', '<'])?];
+                name.ends_with("_example").then(|| name.to_string())
+            })
+            .collect()
+    }
+
+    fn source_files(dir: &Path, files: &mut Vec<std::path::PathBuf>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                source_files(&path, files);
+            } else if path.extension().is_some_and(|extension| extension == "rs") {
+                files.push(path);
+            }
+        }
+    }
+
+    #[test]
+    fn test_every_public_example_is_registered() {
+        let registry = course_examples();
+        let mut files = Vec::new();
+        source_files(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("src"),
+            &mut files,
+        );
+
+        let mut missing = Vec::new();
+        for file in files {
+            // Exercises are for students to fill in, and feature-gated
+            // modules are only registered with their feature.
+            let relative = file.strip_prefix(env!("CARGO_MANIFEST_DIR")).unwrap();
+            if relative.starts_with("src/exercises")
+                || (!cfg!(feature = "websocket") && relative.ends_with("websocket.rs"))
+            {
+                continue;
+            }
+            for function in public_examples(&fs::read_to_string(&file).unwrap()) {
+                let name = function.trim_end_matches("_example");
+                if registry.get(name).is_none() && !NOT_REGISTERED.contains(&function.as_str()) {
+                    missing.push(function);
+                }
+            }
+        }
+        assert_eq!(missing, Vec::<String>::new(), "examples not registered");
+    }
+
+    #[test]
+    fn test_public_examples_parsing() {
+        let source = "pub async fn a_example() {}\n\
+                      pub fn b_example() -> io::Result<()> {}\n\
+                      pub async fn helper() {}\n\
+                      // pub fn commented_example() {}\n\
+                      fn private_example() {}";
+        assert_eq!(public_examples(source), vec!["a_example", "b_example"]);
+    }
+
+    #[test]
+    fn test_course_examples() {
+        let registry = course_examples();
+        assert_eq!(registry.iter().next().unwrap().name, "async_state_machine");
+        assert!(registry.get("fetch_data_from_api").unwrap().needs_network);
+        assert!(registry.get("fairness").unwrap().builds_runtime);
+        assert!(!registry.get("waker").unwrap().pausable);
+        assert!(registry.estimated_total() >= Duration::from_secs(1));
+        assert!(registry
+            .iter()
+            .all(|example| example.estimated_duration > Duration::ZERO));
+    }
+
+    #[test]
+    #[should_panic(expected = "example twice registered twice")]
+    fn test_duplicate_name_panics() {
+        let example = || Example::new("twice", "", Duration::ZERO, |_ctx| async { Ok(()) });
+        ExampleRegistry::new()
+            .register(example())
+            .register(example());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_run_erases_output_and_errors() {
+        let mut registry = ExampleRegistry::new();
+        registry
+            .register(Example::new("ok", "", Duration::ZERO, |_ctx| async {
+                Ok(())
+            }))
+            .register(Example::new("failing", "", Duration::ZERO, |_ctx| async {
+                Err(ExampleError::new("boom"))
+            }))
+            .register(Example::new("sync", "", Duration::ZERO, |ctx| {
+                run_blocking(ctx, |_| Err::<(), _>("blocking failure"))
+            }));
+
+        let ctx = ExampleContext::captured();
+        assert_eq!(registry.len(), 3);
+        assert_eq!(registry.get("ok").unwrap().run(ctx.clone()).await, Ok(()));
+        assert_eq!(
+            registry.get("failing").unwrap().run(ctx.clone()).await,
+            Err(ExampleError("boom".to_string()))
+        );
+        assert_eq!(
+            registry.get("sync").unwrap().run(ctx.clone()).await,
+            Err(ExampleError("blocking failure".to_string()))
+        );
+        assert_eq!(ctx.metrics().get("completed"), 1);
+        assert_eq!(ctx.metrics().get("failed"), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_examples_report_through_the_context() {
+        let ctx = ExampleContext::captured();
+        let registry = course_examples();
+        let example = registry.get("async_sugar").unwrap();
+        example.run(ctx.clone()).await.unwrap();
+        example.run(ctx.clone()).await.unwrap();
+
+        assert_eq!(ctx.output().lines(), ["42", "42"]);
+        assert_eq!(ctx.metrics().get("outputs"), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_run_to_result() {
+        let example = Example::new("talkative", "", Duration::ZERO, |ctx| async move {
+            ctx.report("first");
+            tracing::info_span!("step").in_scope(|| tracing::info!(after = "first", "an event"));
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            tracing::debug!("filtered out");
+            ctx.output().line("second");
+            Err(ExampleError::new("boom"))
+        });
+        let result = example
+            .run_to_result(ExampleContext::captured())
+            .with_subscriber(crate::instrumentation::subscriber(std::io::sink))
+            .await;
+
+        assert_eq!(
+            result,
+            ExampleRunResult {
+                name: "talkative",
+                duration: Duration::from_millis(30),
+                status: ExampleStatus::Failed("boom".to_string()),
+                log_lines: vec![
+                    "\"first\"".to_string(),
+                    "INFO step: an event after=\"first\"".to_string(),
+                    "second".to_string()
+                ],
+            }
+        );
+        assert!(example
+            .run_to_result(ExampleContext::default())
+            .await
+            .log_lines
+            .is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancelled_context_stops_the_run() {
+        let example = Example::new("slow", "", Duration::ZERO, |_ctx| async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(())
+        });
+        let ctx = ExampleContext::captured();
+        let run = tokio::spawn(example.run(ctx.clone()));
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        ctx.cancel_token().cancel();
+
+        assert_eq!(run.await.unwrap(), Err(ExampleError::new("cancelled")));
+        assert_eq!(ctx.clock().elapsed(), Duration::from_secs(1));
+        assert_eq!(ctx.metrics().get("failed"), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_jitter_delays_the_start() {
+        use crate::example_context::LatencySimulator;
+
+        let ctx = ExampleContext::captured()
+            .with_latency(LatencySimulator::new().with_jitter(Duration::from_millis(50), 3));
+        let example = Example::new("instant", "", Duration::ZERO, |ctx| async move {
+            ctx.report(ctx.clock().elapsed());
+            Ok(())
+        });
+        example.run(ctx.clone()).await.unwrap();
+
+        let started = ctx.clock().elapsed();
+        assert!(started > Duration::ZERO && started <= Duration::from_millis(50));
+        assert_eq!(ctx.output().lines(), [format!("{:?}", started)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_course_examples_take_the_context_latency() {
+        use crate::example_context::LatencySimulator;
+
+        let examples = course_examples();
+        let example = examples.get("multiple_awaits").unwrap();
+        let ctx = ExampleContext::captured().with_latency(LatencySimulator::new().with_scale(0.1));
+        example.run(ctx.clone()).await.unwrap();
+
+        // Three awaits of 50ms, scaled down tenfold.
+        assert_eq!(ctx.clock().elapsed(), Duration::from_millis(15));
+    }
+}
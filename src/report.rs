@@ -0,0 +1,289 @@
+//! Machine-readable run reports (`cargo run -- --output json`)
+//!
+//! Each example run ends in an [`ExampleRunResult`]: its name, how long it
+//! took, whether it succeeded, and the lines it wrote to its output. A
+//! [`RunReport`] gathers the results of a whole run and serializes them as
+//! one JSON object, so a run can be checked by a script instead of read.
+//!
+//! In JSON mode the examples write to a captured output rather than to
+//! stdout, and tracing events go to stderr: stdout holds the report and
+//! nothing else.
+
+use std::fmt;
+use std::time::Duration;
+
+/// How the binary prints the results of a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Free-form text, each example printing as it runs.
+    #[default]
+    Text,
+    /// A single JSON report once every example has run.
+    Json,
+}
+
+/// What the binary runs, and how.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RunOptions {
+    pub output: OutputFormat,
+    /// Runs each example in a child process with this wall-clock limit.
+    pub isolation: Option<Duration>,
+    /// Runs only this example, in process.
+    pub example: Option<String>,
+}
+
+impl RunOptions {
+    /// Parses the binary's arguments:
+    /// `[--output text|json] [--isolate] [--time-limit-ms N] [--example NAME]`.
+    ///
+    /// `--time-limit-ms` implies `--isolate`.
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = RunOptions::default();
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            if flag == "--isolate" {
+                options
+                    .isolation
+                    .get_or_insert(crate::sandbox::DEFAULT_TIME_LIMIT);
+                continue;
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| format!("{} expects a value", flag))?;
+            match flag.as_str() {
+                "--output" => {
+                    options.output = match value.as_str() {
+                        "text" => OutputFormat::Text,
+                        "json" => OutputFormat::Json,
+                        _ => return Err(format!("--output is text or json, got {:?}", value)),
+                    }
+                }
+                "--time-limit-ms" => {
+                    let millis = value
+                        .parse()
+                        .map_err(|_| format!("{} expects a number, got {:?}", flag, value))?;
+                    options.isolation = Some(Duration::from_millis(millis));
+                }
+                "--example" => options.example = Some(value.clone()),
+                _ => return Err(format!("unexpected argument {}", flag)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// How an example run ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExampleStatus {
+    Succeeded,
+    Failed(String),
+}
+
+impl ExampleStatus {
+    pub fn is_success(&self) -> bool {
+        matches!(self, ExampleStatus::Succeeded)
+    }
+}
+
+/// The outcome of one example run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExampleRunResult {
+    pub name: &'static str,
+    pub duration: Duration,
+    pub status: ExampleStatus,
+    /// Lines the example wrote to its output and the tracing events of the
+    /// run, in order; empty when it printed them.
+    pub log_lines: Vec<String>,
+}
+
+impl ExampleRunResult {
+    /// The result as a JSON object.
+    pub fn to_json(&self) -> String {
+        let (status, error) = match &self.status {
+            ExampleStatus::Succeeded => ("succeeded", "null".to_string()),
+            ExampleStatus::Failed(error) => ("failed", json_string(error)),
+        };
+        let log_lines: Vec<String> = self
+            .log_lines
+            .iter()
+            .map(|line| json_string(line))
+            .collect();
+        format!(
+            "{{\"name\":{},\"duration_ms\":{},\"status\":\"{}\",\"error\":{},\"log_lines\":[{}]}}",
+            json_string(self.name),
+            self.duration.as_millis(),
+            status,
+            error,
+            log_lines.join(",")
+        )
+    }
+}
+
+/// The results of a run, in the order the examples ran.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunReport {
+    pub results: Vec<ExampleRunResult>,
+}
+
+impl RunReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, result: ExampleRunResult) {
+        self.results.push(result);
+    }
+
+    pub fn succeeded(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|result| result.status.is_success())
+            .count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.succeeded()
+    }
+
+    /// Sum of the run durations.
+    pub fn total_duration(&self) -> Duration {
+        self.results.iter().map(|result| result.duration).sum()
+    }
+
+    /// The report as a JSON object.
+    pub fn to_json(&self) -> String {
+        let results: Vec<String> = self.results.iter().map(ExampleRunResult::to_json).collect();
+        format!(
+            "{{\"succeeded\":{},\"failed\":{},\"duration_ms\":{},\"examples\":[{}]}}",
+            self.succeeded(),
+            self.failed(),
+            self.total_duration().as_millis(),
+            results.join(",")
+        )
+    }
+}
+
+impl fmt::Display for RunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} succeeded, {} failed in {:?}",
+            self.succeeded(),
+            self.failed(),
+            self.total_duration()
+        )
+    }
+}
+
+/// `s` as a JSON string literal.
+fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if u32::from(c) < 0x20 => json.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    fn result(name: &'static str, status: ExampleStatus, lines: &[&str]) -> ExampleRunResult {
+        ExampleRunResult {
+            name,
+            duration: Duration::from_millis(120),
+            status,
+            log_lines: lines.iter().map(|line| line.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_options_parsing() {
+        assert_eq!(RunOptions::parse(&args("")), Ok(RunOptions::default()));
+        assert_eq!(
+            RunOptions::parse(&args("--output json")).unwrap().output,
+            OutputFormat::Json
+        );
+        assert_eq!(
+            RunOptions::parse(&args("--output text --isolate")),
+            Ok(RunOptions {
+                output: OutputFormat::Text,
+                isolation: Some(crate::sandbox::DEFAULT_TIME_LIMIT),
+                example: None,
+            })
+        );
+        assert_eq!(
+            RunOptions::parse(&args("--time-limit-ms 500 --isolate"))
+                .unwrap()
+                .isolation,
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(
+            RunOptions::parse(&args("--example waker")).unwrap().example,
+            Some("waker".to_string())
+        );
+        assert!(RunOptions::parse(&args("--output xml")).is_err());
+        assert!(RunOptions::parse(&args("--output")).is_err());
+        assert!(RunOptions::parse(&args("--time-limit-ms soon")).is_err());
+        assert!(RunOptions::parse(&args("--verbose")).is_err());
+    }
+
+    #[test]
+    fn test_json_escaping() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(
+            json_string("(1, \"a\")\\\n\t\u{1}"),
+            "\"(1, \\\"a\\\")\\\\\\n\\t\\u0001\""
+        );
+    }
+
+    #[test]
+    fn test_result_json() {
+        let ok = result("oneshot", ExampleStatus::Succeeded, &["Ok(\"pong\")"]);
+        assert_eq!(
+            ok.to_json(),
+            "{\"name\":\"oneshot\",\"duration_ms\":120,\"status\":\"succeeded\",\
+             \"error\":null,\"log_lines\":[\"Ok(\\\"pong\\\")\"]}"
+        );
+
+        let failed = result("flaky", ExampleStatus::Failed("timed out".into()), &[]);
+        assert!(failed
+            .to_json()
+            .contains("\"status\":\"failed\",\"error\":\"timed out\",\"log_lines\":[]"));
+    }
+
+    #[test]
+    fn test_report_counts_and_json() {
+        let mut report = RunReport::new();
+        report.push(result("a", ExampleStatus::Succeeded, &["1", "2"]));
+        report.push(result("b", ExampleStatus::Failed("boom".into()), &[]));
+
+        assert_eq!((report.succeeded(), report.failed()), (1, 1));
+        assert_eq!(report.total_duration(), Duration::from_millis(240));
+        assert_eq!(report.to_string(), "1 succeeded, 1 failed in 240ms");
+
+        let json = report.to_json();
+        assert!(json.starts_with(
+            "{\"succeeded\":1,\"failed\":1,\"duration_ms\":240,\"examples\":[{\"name\":\"a\""
+        ));
+        assert!(json.ends_with("\"log_lines\":[]}]}"));
+        assert_eq!(
+            RunReport::new().to_json(),
+            "{\"succeeded\":0,\"failed\":0,\"duration_ms\":0,\"examples\":[]}"
+        );
+    }
+}
@@ -0,0 +1,342 @@
+//! Per-request tracing IDs through the HTTP stack
+//!
+//! Each request gets an `x-request-id` header. The client sends the ID that
+//! is in scope (or generates one), the server side reuses the incoming ID (or
+//! generates one), and on both sides the ID is:
+//! - stored in a tokio task-local, so any nested async call can read it
+//!   without threading it through every function signature
+//! - attached to a `tracing` span wrapping the request
+//! - included in error messages, so a failure can be matched with server logs
+//!
+//! Task-locals are scoped to a future: [`with_request_id`] sets the value for
+//! everything awaited inside it, across every await point.
+//!
+//! `fetch_data_from_api` sends its requests through a [`TracedClient`], and
+//! the example server of `http_server` runs every handler through
+//! [`handle_with_request_id`] and echoes the ID in its responses.
+
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reqwest::header::{HeaderMap, HeaderValue};
+use tracing::Instrument;
+
+/// Name of the header carrying the request ID.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    static REQUEST_ID: RequestId;
+}
+
+/// Identifier correlating a request across client, server, and logs.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// Generates an ID unique within this process.
+    pub fn generate() -> Self {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let sequence = COUNTER.fetch_add(1, Ordering::Relaxed);
+        RequestId(format!("{:x}-{:04x}", millis, sequence))
+    }
+
+    /// Wraps an existing ID, e.g. one received from a caller.
+    pub fn new(id: impl Into<String>) -> Self {
+        RequestId(id.into())
+    }
+
+    /// Returns the ID as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Runs `future` with `id` as the current request ID.
+pub async fn with_request_id<F: Future>(id: RequestId, future: F) -> F::Output {
+    let span = tracing::info_span!("request", request_id = %id);
+    REQUEST_ID.scope(id, future.instrument(span)).await
+}
+
+/// Returns the request ID of the enclosing [`with_request_id`] scope, if any.
+pub fn current_request_id() -> Option<RequestId> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Error carrying the ID of the request that failed.
+#[derive(Debug)]
+pub struct RequestError {
+    pub request_id: RequestId,
+    pub message: String,
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[request {}] {}", self.request_id, self.message)
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+/// Client side: an HTTP client that propagates the current request ID.
+#[derive(Debug, Clone)]
+pub struct TracedClient {
+    client: reqwest::Client,
+}
+
+impl TracedClient {
+    /// Creates a client with the same timeout as `fetch_data_from_api`.
+    pub fn new() -> Result<Self, reqwest::Error> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()?;
+        Ok(TracedClient { client })
+    }
+
+    /// Wraps an existing client; clones of a reqwest client share its pool.
+    pub fn from_client(client: reqwest::Client) -> Self {
+        TracedClient { client }
+    }
+
+    /// Sends a GET request to `url` and discards the response, so the first
+    /// real request reuses a pooled connection instead of paying for the
+    /// connection setup. See [`crate::warmup`].
+    pub async fn warm_up(&self, url: &str) -> Result<(), RequestError> {
+        self.get_text(url).await.map(|_| ())
+    }
+
+    /// Sends a GET request and returns the request ID and the body.
+    ///
+    /// The ID of the enclosing scope is reused; outside of any scope a new ID
+    /// is generated.
+    pub async fn get_text(&self, url: &str) -> Result<(RequestId, String), RequestError> {
+        let id = current_request_id().unwrap_or_else(RequestId::generate);
+        let span = tracing::info_span!("http_client", request_id = %id, url);
+
+        let result = async {
+            tracing::info!("sending request");
+            let response = self
+                .client
+                .get(url)
+                .header(REQUEST_ID_HEADER, id.as_str())
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+
+            if !response.status().is_success() {
+                return Err(format!("HTTP error: {}", response.status()));
+            }
+            response.text().await.map_err(|e| e.to_string())
+        }
+        .instrument(span)
+        .await;
+
+        match result {
+            Ok(body) => Ok((id, body)),
+            Err(message) => Err(RequestError {
+                request_id: id,
+                message,
+            }),
+        }
+    }
+}
+
+/// Server side: returns the incoming request ID, or a new one if the header
+/// is missing or not valid UTF-8.
+pub fn request_id_from_headers(headers: &HeaderMap) -> RequestId {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty())
+        .map(RequestId::new)
+        .unwrap_or_else(RequestId::generate)
+}
+
+/// Server side middleware: runs `handler` in the scope of the request's ID.
+///
+/// Returns the ID so the server can echo it in the response headers (see
+/// [`request_id_header_value`]).
+pub async fn handle_with_request_id<F, Fut, T>(headers: &HeaderMap, handler: F) -> (RequestId, T)
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = T>,
+{
+    let id = request_id_from_headers(headers);
+    let output = with_request_id(id.clone(), handler()).await;
+    (id, output)
+}
+
+/// Converts a request ID into a header value for the response.
+pub fn request_id_header_value(id: &RequestId) -> HeaderValue {
+    HeaderValue::from_str(id.as_str()).unwrap_or_else(|_| HeaderValue::from_static("invalid"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{fixture, Fixture};
+    use crate::http_server::HttpServer;
+    use std::net::SocketAddr;
+
+    /// The example server on an ephemeral port, stopped on teardown.
+    ///
+    /// The teardown only signals the server: its task runs on the test's
+    /// runtime, which a teardown run by `Drop` cannot wait on.
+    async fn start_server() -> Fixture<SocketAddr, HttpServer> {
+        fixture(
+            async {
+                let server = HttpServer::start_local().unwrap();
+                (server.local_addr(), server)
+            },
+            |server| async move { server.stop() },
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_dropped_server_fixture_stops_the_server() {
+        let server = start_server().await;
+        let addr = *server.resource();
+        let dropped_at = std::time::Instant::now();
+        drop(server);
+        assert!(dropped_at.elapsed() < Duration::from_secs(1));
+
+        // The server stops accepting once this runtime polls its task.
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while tokio::net::TcpStream::connect(addr).await.is_ok() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("server still accepting after teardown");
+    }
+
+    #[tokio::test]
+    async fn test_request_id_propagates_end_to_end() {
+        start_server()
+            .await
+            .run(|addr| async move {
+                let client = TracedClient::new().unwrap();
+                let id = RequestId::new("course-42");
+
+                // The server's handler reads the ID from its task-local.
+                let (sent_id, body) = with_request_id(id.clone(), async {
+                    client
+                        .get_text(&format!("http://{}/request-id", addr))
+                        .await
+                })
+                .await
+                .unwrap();
+
+                assert_eq!(sent_id, id);
+                assert_eq!(body, "course-42");
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_request_id_generated_outside_scope() {
+        start_server()
+            .await
+            .run(|addr| async move {
+                let client = TracedClient::new().unwrap();
+
+                let (id, body) = client
+                    .get_text(&format!("http://{}/request-id", addr))
+                    .await
+                    .unwrap();
+                assert_eq!(body, id.as_str());
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_server_echoes_or_generates_the_request_id() {
+        start_server()
+            .await
+            .run(|addr| async move {
+                let client = reqwest::Client::new();
+                let url = format!("http://{}/hello", addr);
+
+                let echoed = client
+                    .get(&url)
+                    .header(REQUEST_ID_HEADER, "echo-1")
+                    .send()
+                    .await
+                    .unwrap();
+                assert_eq!(echoed.headers()[REQUEST_ID_HEADER], "echo-1");
+
+                let generated = client.get(&url).send().await.unwrap();
+                let id = generated.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+                assert!(!id.is_empty());
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_request_id_in_error_message() {
+        start_server()
+            .await
+            .run(|addr| async move {
+                let client = TracedClient::new().unwrap();
+
+                let error = with_request_id(RequestId::new("failing-7"), async {
+                    client.get_text(&format!("http://{}/missing", addr)).await
+                })
+                .await
+                .unwrap_err();
+
+                assert_eq!(error.request_id, RequestId::new("failing-7"));
+                assert_eq!(
+                    error.to_string(),
+                    "[request failing-7] HTTP error: 404 Not Found"
+                );
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_warm_up() {
+        start_server()
+            .await
+            .run(|addr| async move {
+                let client = TracedClient::new().unwrap();
+                client
+                    .warm_up(&format!("http://{}/hello", addr))
+                    .await
+                    .unwrap();
+                assert!(client
+                    .warm_up(&format!("http://{}/missing", addr))
+                    .await
+                    .is_err());
+            })
+            .await;
+    }
+
+    #[test]
+    fn test_request_id_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert_ne!(request_id_from_headers(&headers).as_str(), "");
+
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("abc"));
+        assert_eq!(request_id_from_headers(&headers), RequestId::new("abc"));
+    }
+
+    #[tokio::test]
+    async fn test_no_request_id_outside_scope() {
+        assert_eq!(current_request_id(), None);
+        let inside = with_request_id(RequestId::new("x"), async { current_request_id() }).await;
+        assert_eq!(inside, Some(RequestId::new("x")));
+    }
+}
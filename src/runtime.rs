@@ -0,0 +1,282 @@
+//! Runtime-agnostic examples: tokio or smol
+//!
+//! Are the patterns of the course tokio-specific? Most are not: `async fn`,
+//! `join!`, channels and state machines belong to Rust and `futures`, and
+//! only a few operations need a runtime. The examples needing nothing but
+//! [`sleep`] and [`spawn`] go through this module, which picks the runtime
+//! polling the current task:
+//! - inside a tokio runtime, `tokio::time::sleep` and `tokio::spawn`
+//!   (paused-time tests keep working)
+//! - anywhere else, with the `runtime-smol` feature, `smol::Timer` and
+//!   `smol::spawn`
+//!
+//! [`Runtime::block_on`] then runs the very same example functions on either
+//! runtime (`cargo run --features runtime-smol`). tokio's channels and locks
+//! (`tokio::sync`) need no runtime at all, so the pipeline example runs on
+//! smol unchanged.
+//!
+//! Spawned tasks are joined through [`JoinHandle`], which smooths over one
+//! difference: dropping a smol `Task` cancels it, dropping a tokio
+//! `JoinHandle` detaches the task. Here both detach, as tokio does.
+
+use std::fmt;
+use std::future::Future;
+#[cfg(feature = "runtime-smol")]
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "runtime-smol")]
+use futures::FutureExt;
+use tracing::{info, instrument};
+
+use crate::example_context::ExampleContext;
+
+/// A runtime the runtime-agnostic examples can run on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Runtime {
+    Tokio,
+    #[cfg(feature = "runtime-smol")]
+    Smol,
+}
+
+impl Runtime {
+    /// Every runtime enabled in this build.
+    pub const ALL: &'static [Runtime] = &[
+        Runtime::Tokio,
+        #[cfg(feature = "runtime-smol")]
+        Runtime::Smol,
+    ];
+
+    /// Runs `future` to completion on a new instance of this runtime.
+    ///
+    /// Synchronous: a tokio runtime cannot be started from within another.
+    pub fn block_on<F: Future>(self, future: F) -> F::Output {
+        match self {
+            Runtime::Tokio => tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .expect("failed to build a tokio runtime")
+                .block_on(future),
+            #[cfg(feature = "runtime-smol")]
+            Runtime::Smol => smol::block_on(future),
+        }
+    }
+
+    /// The runtime polling the current task, tokio if none is detected.
+    fn current() -> Runtime {
+        #[cfg(feature = "runtime-smol")]
+        if tokio::runtime::Handle::try_current().is_err() {
+            return Runtime::Smol;
+        }
+        Runtime::Tokio
+    }
+}
+
+impl fmt::Display for Runtime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Runtime::Tokio => f.write_str("tokio"),
+            #[cfg(feature = "runtime-smol")]
+            Runtime::Smol => f.write_str("smol"),
+        }
+    }
+}
+
+/// Waits until `duration` has elapsed, on the current runtime.
+pub async fn sleep(duration: Duration) {
+    match Runtime::current() {
+        Runtime::Tokio => tokio::time::sleep(duration).await,
+        #[cfg(feature = "runtime-smol")]
+        Runtime::Smol => {
+            smol::Timer::after(duration).await;
+        }
+    }
+}
+
+/// Spawns `future` as a new task on the current runtime.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let inner = match Runtime::current() {
+        Runtime::Tokio => Inner::Tokio(tokio::spawn(future)),
+        #[cfg(feature = "runtime-smol")]
+        Runtime::Smol => Inner::Smol(Some(smol::spawn(AssertUnwindSafe(future).catch_unwind()))),
+    };
+    JoinHandle(inner)
+}
+
+/// Like [`spawn`], listing the task as `name` in the global task registry
+/// (`crate::task_registry`) while it runs on tokio.
+pub fn spawn_named<F>(name: &str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let inner = match Runtime::current() {
+        Runtime::Tokio => Inner::Tokio(crate::task_registry::spawn_named(name, future)),
+        #[cfg(feature = "runtime-smol")]
+        Runtime::Smol => Inner::Smol(Some(smol::spawn(AssertUnwindSafe(future).catch_unwind()))),
+    };
+    JoinHandle(inner)
+}
+
+/// Handle to a task spawned with [`spawn`]. Awaiting it returns the task's
+/// output, or the payload of its panic, like `std::thread::JoinHandle::join`.
+/// A tokio task cancelled by its runtime shutting down returns the
+/// `tokio::task::JoinError` as the payload. Dropping it detaches the task.
+#[derive(Debug)]
+pub struct JoinHandle<T>(Inner<T>);
+
+#[derive(Debug)]
+enum Inner<T> {
+    Tokio(tokio::task::JoinHandle<T>),
+    /// `None` once detached.
+    #[cfg(feature = "runtime-smol")]
+    Smol(Option<smol::Task<std::thread::Result<T>>>),
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = std::thread::Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match &mut self.0 {
+            Inner::Tokio(handle) => Pin::new(handle).poll(cx).map(|result| {
+                result.map_err(|error| {
+                    if error.is_panic() {
+                        error.into_panic()
+                    } else {
+                        // Never aborted through this handle, but the runtime
+                        // cancels its tasks when shutting down.
+                        Box::new(error)
+                    }
+                })
+            }),
+            #[cfg(feature = "runtime-smol")]
+            Inner::Smol(task) => Pin::new(task.as_mut().expect("polled after completion")).poll(cx),
+        }
+    }
+}
+
+#[cfg(feature = "runtime-smol")]
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        if let Inner::Smol(task) = &mut self.0 {
+            if let Some(task) = task.take() {
+                task.detach();
+            }
+        }
+    }
+}
+
+/// Example: the same example functions on every enabled runtime
+///
+/// Synchronous; call it from `spawn_blocking` when inside an async context.
+#[instrument(skip_all)]
+pub fn runtime_agnostic_example(ctx: &ExampleContext) -> Vec<(Runtime, Duration)> {
+    Runtime::ALL
+        .iter()
+        .map(|&runtime| {
+            let start = Instant::now();
+            let outputs = runtime.block_on(async {
+                crate::multiple_awaits_example(ctx).await;
+                crate::pipeline::pipeline_example(ctx).await
+            });
+            let elapsed = start.elapsed();
+            info!(%runtime, ?elapsed, outputs = outputs.len(), "ran on");
+            (runtime, elapsed)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn test_same_examples_on_every_runtime() {
+        for &runtime in Runtime::ALL {
+            let (squares, sum) = runtime.block_on(async {
+                let outputs = crate::pipeline::run_pipeline(vec![1, 2, 3]).await;
+                let squares: Vec<u64> = outputs.iter().map(|output| output.value).collect();
+                let ctx = ExampleContext::captured();
+                (squares, crate::async_sugar_example(&ctx).await)
+            });
+            assert_eq!(squares, [1, 4, 9], "{}", runtime);
+            assert_eq!(sum, 42, "{}", runtime);
+        }
+    }
+
+    #[test]
+    fn test_sleep_waits_on_every_runtime() {
+        for &runtime in Runtime::ALL {
+            let start = Instant::now();
+            runtime.block_on(sleep(Duration::from_millis(20)));
+            assert!(start.elapsed() >= Duration::from_millis(20), "{}", runtime);
+        }
+    }
+
+    #[test]
+    fn test_spawned_panic_is_returned() {
+        for &runtime in Runtime::ALL {
+            let result = runtime.block_on(async { spawn(async { panic!("boom") }).await });
+            let payload = result.unwrap_err();
+            assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"), "{}", runtime);
+        }
+    }
+
+    #[test]
+    fn test_task_cancelled_by_shutdown_is_an_error() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let handle = {
+            let _guard = runtime.enter();
+            spawn(std::future::pending::<()>())
+        };
+        drop(runtime);
+
+        let payload = futures::executor::block_on(handle).unwrap_err();
+        let error = payload.downcast_ref::<tokio::task::JoinError>().unwrap();
+        assert!(error.is_cancelled());
+    }
+
+    #[test]
+    fn test_dropped_handle_detaches() {
+        for &runtime in Runtime::ALL {
+            let finished = Arc::new(AtomicBool::new(false));
+            let flag = finished.clone();
+            runtime.block_on(async move {
+                drop(spawn(async move {
+                    sleep(Duration::from_millis(5)).await;
+                    flag.store(true, Ordering::SeqCst);
+                }));
+                sleep(Duration::from_millis(50)).await;
+            });
+            assert!(finished.load(Ordering::SeqCst), "{}", runtime);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_tokio_paused_time_still_applies() {
+        let start = tokio::time::Instant::now();
+        sleep(Duration::from_secs(60)).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(60));
+        assert_eq!(spawn(async { 7 }).await.unwrap(), 7);
+    }
+
+    #[test]
+    fn test_runtime_agnostic_example() {
+        let timings = runtime_agnostic_example(&ExampleContext::captured());
+        assert_eq!(timings.len(), Runtime::ALL.len());
+        assert!(timings
+            .iter()
+            .all(|(_, elapsed)| *elapsed >= Duration::from_millis(150)));
+    }
+}
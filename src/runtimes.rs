@@ -0,0 +1,256 @@
+//! Runtime configuration: multi-thread vs current-thread
+//!
+//! `#[tokio::main]` hides the runtime. This module builds runtimes explicitly
+//! with `tokio::runtime::Builder` and runs the same workload on both flavors:
+//! - multi-thread: a pool of worker threads with work stealing; CPU-bound
+//!   tasks can run in parallel
+//! - current-thread: every task runs on the thread calling `block_on`; tasks
+//!   only interleave at await points
+//!
+//! It also shows the two bridges students need when mixing sync and async
+//! code: `Runtime::block_on` (sync code waiting on async code) and
+//! `Handle::current().spawn` (sync code called from within a runtime
+//! spawning async work).
+//!
+//! Everything here is synchronous: building or dropping a runtime inside an
+//! async context panics, so async callers must go through `spawn_blocking`.
+
+use std::collections::BTreeSet;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::runtime::{Builder, Handle, Runtime};
+use tokio::task::JoinHandle;
+use tracing::{info, instrument};
+
+use crate::example_context::{ExampleContext, LatencySimulator};
+
+/// Prefix of the names of the worker threads of runtimes built by this
+/// module: each thread gets its own number, `course-worker-0` and so on.
+pub const WORKER_THREAD_NAME: &str = "course-worker";
+
+/// Which runtime flavor a workload ran on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flavor {
+    MultiThread,
+    CurrentThread,
+}
+
+/// Measurements of one workload run.
+#[derive(Debug, Clone)]
+pub struct WorkloadReport {
+    pub flavor: Flavor,
+    /// Wall-clock time of the whole workload.
+    pub elapsed: Duration,
+    /// Distinct names of the threads the tasks ran on.
+    pub thread_names: BTreeSet<String>,
+}
+
+/// Builds a multi-thread runtime with `worker_threads` numbered workers.
+pub fn build_multi_thread_runtime(worker_threads: usize) -> io::Result<Runtime> {
+    let next_id = AtomicUsize::new(0);
+    Builder::new_multi_thread()
+        .worker_threads(worker_threads)
+        .thread_name_fn(move || {
+            let id = next_id.fetch_add(1, Ordering::Relaxed);
+            format!("{}-{}", WORKER_THREAD_NAME, id)
+        })
+        .enable_time()
+        .enable_io()
+        .build()
+}
+
+/// Builds a current-thread runtime.
+///
+/// Only the time driver is enabled: without `enable_time`, `sleep` would
+/// panic, and this workload never touches sockets, so `enable_io` is not
+/// needed.
+pub fn build_current_thread_runtime() -> io::Result<Runtime> {
+    Builder::new_current_thread().enable_time().build()
+}
+
+/// Simulated CPU work that does not yield to the runtime.
+fn busy_work(iterations: u64) -> u64 {
+    (0..iterations).fold(0u64, |acc, i| {
+        std::hint::black_box(acc.wrapping_mul(31).wrapping_add(i))
+    })
+}
+
+/// Spawns `tasks` tasks, each doing some CPU work then a short sleep
+/// simulated by `latency`, and reports the threads they ran on.
+#[instrument(skip(runtime, latency))]
+pub fn run_workload(
+    runtime: &Runtime,
+    flavor: Flavor,
+    tasks: usize,
+    cpu_iterations: u64,
+    latency: &LatencySimulator,
+) -> WorkloadReport {
+    let start = Instant::now();
+
+    let thread_names = runtime.block_on(async {
+        let handles: Vec<_> = (0..tasks)
+            .map(|_| {
+                let latency = latency.clone();
+                tokio::spawn(async move {
+                    busy_work(cpu_iterations);
+                    latency.sleep(Duration::from_millis(10)).await;
+                    std::thread::current()
+                        .name()
+                        .unwrap_or("<unnamed>")
+                        .to_string()
+                })
+            })
+            .collect();
+
+        let mut names = BTreeSet::new();
+        for handle in handles {
+            names.insert(handle.await.expect("workload task panicked"));
+        }
+        names
+    });
+
+    WorkloadReport {
+        flavor,
+        elapsed: start.elapsed(),
+        thread_names,
+    }
+}
+
+/// Runs the same workload on a multi-thread and a current-thread runtime.
+pub fn compare_flavors(
+    tasks: usize,
+    cpu_iterations: u64,
+    latency: &LatencySimulator,
+) -> io::Result<[WorkloadReport; 2]> {
+    let multi = build_multi_thread_runtime(4)?;
+    let multi_report = run_workload(&multi, Flavor::MultiThread, tasks, cpu_iterations, latency);
+
+    let current = build_current_thread_runtime()?;
+    let current_report = run_workload(
+        &current,
+        Flavor::CurrentThread,
+        tasks,
+        cpu_iterations,
+        latency,
+    );
+
+    Ok([multi_report, current_report])
+}
+
+/// A synchronous function meant to be called from code running inside a
+/// runtime (e.g. a sync callback invoked by an async task).
+///
+/// `Handle::current()` finds the runtime of the caller; it panics when called
+/// outside of one.
+pub fn spawn_with_current_handle(value: u32) -> JoinHandle<u32> {
+    Handle::current().spawn(async move {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        value * 2
+    })
+}
+
+/// Drives async code from sync code with `Runtime::block_on`, including work
+/// spawned through `Handle::current()` and through an explicit handle.
+pub fn block_on_from_sync_code(runtime: &Runtime) -> (u32, u32) {
+    // Inside block_on, the runtime is "current".
+    let from_current = runtime.block_on(async {
+        spawn_with_current_handle(21)
+            .await
+            .expect("spawned task panicked")
+    });
+
+    // Outside of block_on, spawn through an explicit handle instead.
+    let handle = runtime.handle().clone();
+    let task = handle.spawn(async { 7 });
+    let from_handle = runtime.block_on(task).expect("spawned task panicked");
+
+    (from_current, from_handle)
+}
+
+/// Example: configuring runtimes and comparing flavors
+///
+/// Synchronous; call it from `spawn_blocking` when inside an async context.
+#[instrument(skip_all)]
+pub fn runtimes_example(ctx: &ExampleContext) -> io::Result<()> {
+    info!("Running 8 CPU-heavy tasks on both runtime flavors...");
+    for report in compare_flavors(8, 2_000_000, ctx.latency())? {
+        info!(
+            "{:?}: {:?} on {} thread(s) {:?}",
+            report.flavor,
+            report.elapsed,
+            report.thread_names.len(),
+            report.thread_names
+        );
+    }
+
+    let runtime = build_current_thread_runtime()?;
+    let (from_current, from_handle) = block_on_from_sync_code(&runtime);
+    info!(
+        "block_on from sync code: Handle::current().spawn -> {}, handle.spawn -> {}",
+        from_current, from_handle
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_current_thread_runs_on_one_thread() {
+        let runtime = build_current_thread_runtime().unwrap();
+        let report = run_workload(
+            &runtime,
+            Flavor::CurrentThread,
+            8,
+            1_000,
+            &LatencySimulator::default(),
+        );
+        assert_eq!(report.thread_names.len(), 1);
+        assert!(!report
+            .thread_names
+            .iter()
+            .any(|name| name.starts_with(WORKER_THREAD_NAME)));
+    }
+
+    #[test]
+    fn test_multi_thread_uses_named_workers() {
+        let runtime = build_multi_thread_runtime(2).unwrap();
+        let report = run_workload(
+            &runtime,
+            Flavor::MultiThread,
+            8,
+            1_000,
+            &LatencySimulator::default(),
+        );
+        // Each worker has its own name: the tasks ran on one or both of them.
+        assert!((1..=2).contains(&report.thread_names.len()));
+        assert!(report
+            .thread_names
+            .iter()
+            .all(|name| name.starts_with(&format!("{}-", WORKER_THREAD_NAME))));
+    }
+
+    #[test]
+    fn test_compare_flavors() {
+        let [multi, current] = compare_flavors(4, 1_000, &LatencySimulator::default()).unwrap();
+        assert_eq!(multi.flavor, Flavor::MultiThread);
+        assert_eq!(current.flavor, Flavor::CurrentThread);
+        // Every task sleeps 10ms, so no run can be faster than that.
+        assert!(current.elapsed >= Duration::from_millis(10));
+        assert!(multi.elapsed >= Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_block_on_from_sync_code() {
+        let runtime = build_multi_thread_runtime(1).unwrap();
+        assert_eq!(block_on_from_sync_code(&runtime), (42, 7));
+    }
+
+    #[test]
+    #[should_panic(expected = "no reactor running")]
+    fn test_handle_current_outside_runtime_panics() {
+        let _handle = spawn_with_current_handle(1);
+    }
+}
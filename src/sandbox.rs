@@ -0,0 +1,230 @@
+//! Running each example in its own process (`cargo run -- --isolate`)
+//!
+//! In process, one example can take the whole run down: a panic in the main
+//! task aborts the binary, and an exercise awaiting something that never
+//! happens hangs it forever. Isolated, each example runs in a child process
+//! (the binary itself, with `--example NAME`) under a wall-clock limit:
+//! - the child's stdout is read line by line as it runs, so the lines
+//!   printed before a hang or a crash are kept
+//! - a child exceeding the limit is killed, and its run fails with a timeout
+//! - once the child is gone, its output is read for a short while more: a
+//!   process it started may still hold the pipe open, and must not hang the
+//!   run past its limit
+//! - a non-zero exit status (1 for a failed example, 101 for a panic, or a
+//!   signal) fails the run, and the next example starts anyway
+//!
+//! `tokio::process` makes the child a future like any other: waiting for it
+//! does not block a thread, so the limit is a plain `timeout`, and reading
+//! its output is a task running concurrently with the wait.
+
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::time::{timeout, Instant};
+use tracing::{info, instrument, warn};
+
+use crate::report::{ExampleRunResult, ExampleStatus};
+
+/// Wall-clock limit of an isolated example unless `--time-limit-ms` is given.
+pub const DEFAULT_TIME_LIMIT: Duration = Duration::from_secs(30);
+
+/// How long the output of an exited child is still read. A process the child
+/// started with inherited stdio keeps the pipe open after it exits.
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The command running the example `name` of the binary `program` alone.
+pub fn example_command(program: &Path, name: &str) -> Command {
+    let mut command = Command::new(program);
+    command.arg("--example").arg(name);
+    command
+}
+
+/// Runs `command` as the example `name`, killing it after `limit`.
+///
+/// The run succeeds if the process exits successfully within the limit; its
+/// log lines are what it printed on stdout. Stderr (traces, panic messages)
+/// is inherited.
+#[instrument(skip(command))]
+pub async fn run_in_child(
+    name: &'static str,
+    mut command: Command,
+    limit: Duration,
+) -> ExampleRunResult {
+    let started = Instant::now();
+    let result = |status, log_lines| ExampleRunResult {
+        name,
+        duration: started.elapsed(),
+        status,
+        log_lines,
+    };
+
+    let mut child = match command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            return result(
+                ExampleStatus::Failed(format!("could not start: {}", e)),
+                Vec::new(),
+            )
+        }
+    };
+
+    let stdout = child.stdout.take().expect("stdout is piped");
+    // Shared with the reader, so that the lines survive aborting it.
+    let log_lines = Arc::new(Mutex::new(Vec::new()));
+    let mut reader = tokio::spawn({
+        let log_lines = Arc::clone(&log_lines);
+        async move {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                log_lines.lock().unwrap().push(line);
+            }
+        }
+    });
+
+    let status = match timeout(limit, child.wait()).await {
+        Ok(Ok(status)) if status.success() => ExampleStatus::Succeeded,
+        Ok(Ok(status)) => {
+            warn!(%status, "example exited with an error");
+            ExampleStatus::Failed(format!("exited with {}", status))
+        }
+        Ok(Err(e)) => ExampleStatus::Failed(e.to_string()),
+        Err(_) => {
+            warn!(?limit, "example timed out, killing it");
+            if let Err(e) = child.kill().await {
+                warn!(%e, "could not kill the example");
+            }
+            ExampleStatus::Failed(format!("timed out after {:?}", limit))
+        }
+    };
+
+    // The pipe is closed once the child is gone, unless a process it started
+    // inherited it.
+    if timeout(OUTPUT_DRAIN_TIMEOUT, &mut reader).await.is_err() {
+        warn!("example output still open after exit, no longer reading it");
+        reader.abort();
+    }
+    let log_lines = std::mem::take(&mut *log_lines.lock().unwrap());
+    result(status, log_lines)
+}
+
+fn shell(script: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(script);
+    command
+}
+
+/// Example: a well-behaved, a failing and a hanging child, one after the
+/// other
+///
+/// Stand-ins for examples, as shell commands; the last one would never end.
+#[instrument]
+pub async fn sandbox_example() -> Vec<ExampleRunResult> {
+    let limit = Duration::from_millis(200);
+
+    let mut results = Vec::new();
+    for (name, script) in [
+        ("well_behaved", "echo 42"),
+        ("failing", "echo partial; exit 101"),
+        ("hanging", "echo started; exec sleep 60"),
+    ] {
+        let result = run_in_child(name, shell(script), limit).await;
+        info!(name, status = ?result.status, "child done");
+        results.push(result);
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_captures_stdout_of_a_successful_child() {
+        let result = run_in_child("ok", shell("echo one; echo two"), Duration::from_secs(10)).await;
+        assert_eq!(result.name, "ok");
+        assert_eq!(result.status, ExampleStatus::Succeeded);
+        assert_eq!(result.log_lines, lines(&["one", "two"]));
+    }
+
+    #[tokio::test]
+    async fn test_failing_exit_status() {
+        let result = run_in_child(
+            "panicking",
+            shell("echo before; exit 101"),
+            Duration::from_secs(10),
+        )
+        .await;
+        assert_eq!(
+            result.status,
+            ExampleStatus::Failed("exited with exit status: 101".to_string())
+        );
+        assert_eq!(result.log_lines, lines(&["before"]));
+    }
+
+    #[tokio::test]
+    async fn test_hanging_child_is_killed_at_the_limit() {
+        let limit = Duration::from_millis(200);
+        let result = run_in_child("hanging", shell("echo started; exec sleep 60"), limit).await;
+
+        assert_eq!(
+            result.status,
+            ExampleStatus::Failed("timed out after 200ms".to_string())
+        );
+        assert_eq!(result.log_lines, lines(&["started"]));
+        assert!(result.duration >= limit && result.duration < Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn test_grandchild_holding_stdout_does_not_hang_the_run() {
+        let limit = Duration::from_millis(200);
+        // The shell is killed at the limit, but the background sleep keeps
+        // its stdout open.
+        let result =
+            run_in_child("grandchild", shell("sleep 60 & echo started; wait"), limit).await;
+
+        assert_eq!(
+            result.status,
+            ExampleStatus::Failed("timed out after 200ms".to_string())
+        );
+        assert_eq!(result.log_lines, lines(&["started"]));
+        assert!(result.duration < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_missing_program() {
+        let result = run_in_child(
+            "missing",
+            example_command(Path::new("/nonexistent/program"), "missing"),
+            Duration::from_secs(1),
+        )
+        .await;
+        assert!(matches!(
+            result.status,
+            ExampleStatus::Failed(error) if error.starts_with("could not start")
+        ));
+    }
+
+    #[test]
+    fn test_example_command() {
+        let command = example_command(Path::new("/bin/course"), "waker");
+        let command = command.as_std();
+        assert_eq!(command.get_program(), "/bin/course");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["--example", "waker"]
+        );
+    }
+}
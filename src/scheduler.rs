@@ -0,0 +1,276 @@
+//! Periodic tasks: `interval`, drift, and missed ticks
+//!
+//! A loop around `sleep(period)` drifts: every iteration lasts the period
+//! plus the time the handler took. `tokio::time::interval` schedules ticks
+//! at `start + n * period` instead, so a handler's duration does not shift
+//! the schedule, as long as it is shorter than the period.
+//!
+//! When a handler runs longer than the period, ticks are missed, and the
+//! interval's `MissedTickBehavior` decides what happens next (with a 10ms
+//! period and the handler of the tick at 10ms taking 25ms):
+//! - `Burst` (the default) fires the missed ticks back to back to catch up:
+//!   0, 10, 35, 35, 40, 50
+//! - `Delay` restarts the schedule from the late tick: 0, 10, 35, 45, 55, 65
+//! - `Skip` fires once, then goes back to the original schedule, skipping
+//!   the missed ticks: 0, 10, 35, 40, 50, 60
+//!
+//! [`schedule_periodic`] runs a task on an interval until its
+//! [`PeriodicHandle`] is stopped or dropped.
+
+use std::future::Future;
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, sleep, Instant, MissedTickBehavior};
+use tracing::{debug, info, instrument};
+
+use crate::example_context::ExampleContext;
+use crate::task_registry::spawn_named;
+
+/// Times, since the first, at which a `sleep(period)` loop runs a handler
+/// taking each of `handler_durations`.
+pub async fn sleep_loop_times(period: Duration, handler_durations: &[Duration]) -> Vec<Duration> {
+    let start = Instant::now();
+    let mut times = Vec::new();
+    for (index, &duration) in handler_durations.iter().enumerate() {
+        if index > 0 {
+            sleep(period).await;
+        }
+        times.push(start.elapsed());
+        sleep(duration).await;
+    }
+    times
+}
+
+/// Times, since the first, at which an interval with `behavior` ticks, each
+/// tick running a handler taking the next of `handler_durations`.
+pub async fn tick_times(
+    period: Duration,
+    behavior: MissedTickBehavior,
+    handler_durations: &[Duration],
+) -> Vec<Duration> {
+    let mut interval = interval(period);
+    interval.set_missed_tick_behavior(behavior);
+    let start = Instant::now();
+    let mut times = Vec::new();
+    for &duration in handler_durations {
+        interval.tick().await;
+        times.push(start.elapsed());
+        sleep(duration).await;
+    }
+    times
+}
+
+/// Handle of a task scheduled by [`schedule_periodic`]. Dropping it stops
+/// the task too, without waiting.
+#[derive(Debug)]
+pub struct PeriodicHandle {
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<usize>,
+}
+
+impl PeriodicHandle {
+    /// Stops the loop, letting a run in progress complete, and returns the
+    /// number of runs.
+    pub async fn stop(self) -> usize {
+        let _ = self.stop.send(());
+        self.task.await.expect("periodic task panicked")
+    }
+}
+
+/// Runs `task` every `period`, starting now, until the returned handle is
+/// stopped. The loop is listed as "periodic" in the task registry.
+///
+/// Uses `MissedTickBehavior::Skip`: a run longer than the period is followed
+/// by one run right away, then the schedule resumes where it was.
+pub fn schedule_periodic<F, Fut>(period: Duration, mut task: F) -> PeriodicHandle
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let (stop, mut stopped) = oneshot::channel::<()>();
+    let task = spawn_named("periodic", async move {
+        let mut interval = interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let mut runs = 0;
+        loop {
+            // The run itself is outside the `select!`, so stopping never
+            // cancels it halfway.
+            tokio::select! {
+                // Also completes with an error when the handle is dropped.
+                _ = &mut stopped => break,
+                _ = interval.tick() => {}
+            }
+            runs += 1;
+            debug!(runs, "periodic run");
+            task().await;
+        }
+        runs
+    });
+    PeriodicHandle { stop, task }
+}
+
+/// Example: drift of a sleep loop, and the three missed-tick behaviors
+///
+/// The runs take the latencies of `ctx`; the period does not change.
+#[instrument(skip_all)]
+pub async fn scheduler_example(ctx: &ExampleContext) -> Vec<(&'static str, Vec<Duration>)> {
+    let period = Duration::from_millis(10);
+    // The second run takes 25ms: longer than the period.
+    let durations = [0, 25, 0, 0, 0, 0].map(|ms| ctx.latency().delay(Duration::from_millis(ms)));
+
+    let mut schedules = vec![("sleep loop", sleep_loop_times(period, &durations).await)];
+    for (name, behavior) in [
+        ("Burst", MissedTickBehavior::Burst),
+        ("Delay", MissedTickBehavior::Delay),
+        ("Skip", MissedTickBehavior::Skip),
+    ] {
+        schedules.push((name, tick_times(period, behavior, &durations).await));
+    }
+    for (name, times) in &schedules {
+        info!(?times, "{}", name);
+    }
+
+    let handle = schedule_periodic(period, || async {});
+    ctx.latency().sleep(Duration::from_millis(45)).await;
+    let runs = handle.stop().await;
+    info!(runs, "periodic task stopped after 45ms");
+
+    schedules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn ms(values: &[u64]) -> Vec<Duration> {
+        values.iter().copied().map(Duration::from_millis).collect()
+    }
+
+    const PERIOD: Duration = Duration::from_millis(10);
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sleep_loop_drifts() {
+        let times = sleep_loop_times(PERIOD, &ms(&[3, 3, 3, 3])).await;
+        // Each iteration is 13ms, not 10.
+        assert_eq!(times, ms(&[0, 13, 26, 39]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_interval_does_not_drift() {
+        for behavior in [
+            MissedTickBehavior::Burst,
+            MissedTickBehavior::Delay,
+            MissedTickBehavior::Skip,
+        ] {
+            let times = tick_times(PERIOD, behavior, &ms(&[3, 3, 3, 3])).await;
+            assert_eq!(times, ms(&[0, 10, 20, 30]), "{:?}", behavior);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_burst_catches_up() {
+        let times = tick_times(PERIOD, MissedTickBehavior::Burst, &ms(&[0, 25, 0, 0, 0, 0])).await;
+        // Ticks due at 20 and 30 both fire at 35.
+        assert_eq!(times, ms(&[0, 10, 35, 35, 40, 50]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_delay_restarts_from_late_tick() {
+        let times = tick_times(PERIOD, MissedTickBehavior::Delay, &ms(&[0, 25, 0, 0, 0, 0])).await;
+        assert_eq!(times, ms(&[0, 10, 35, 45, 55, 65]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_skip_resumes_original_schedule() {
+        let times = tick_times(PERIOD, MissedTickBehavior::Skip, &ms(&[0, 25, 0, 0, 0, 0])).await;
+        assert_eq!(times, ms(&[0, 10, 35, 40, 50, 60]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_handler_slower_than_period_every_time() {
+        // With every handler taking 15ms, no behavior can keep up: all
+        // three tick back to back, at the pace of the handler.
+        let slow = ms(&[15, 15, 15, 15]);
+        assert_eq!(
+            tick_times(PERIOD, MissedTickBehavior::Burst, &slow).await,
+            ms(&[0, 15, 30, 45])
+        );
+        assert_eq!(
+            tick_times(PERIOD, MissedTickBehavior::Delay, &slow).await,
+            ms(&[0, 15, 30, 45])
+        );
+        assert_eq!(
+            tick_times(PERIOD, MissedTickBehavior::Skip, &slow).await,
+            ms(&[0, 15, 30, 45])
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_schedule_periodic_until_stopped() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&runs);
+        let handle = schedule_periodic(PERIOD, move || {
+            let counted = Arc::clone(&counted);
+            async move {
+                counted.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+
+        sleep(Duration::from_millis(35)).await;
+        // Runs at 0, 10, 20 and 30.
+        assert_eq!(handle.stop().await, 4);
+        assert_eq!(runs.load(Ordering::SeqCst), 4);
+
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 4, "no runs after stop");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stop_lets_the_current_run_complete() {
+        let completed = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&completed);
+        let handle = schedule_periodic(PERIOD, move || {
+            let counted = Arc::clone(&counted);
+            async move {
+                sleep(Duration::from_millis(5)).await;
+                counted.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+
+        // Mid-way through the run started at 10ms.
+        sleep(Duration::from_millis(12)).await;
+        let start = Instant::now();
+        assert_eq!(handle.stop().await, 2);
+        assert_eq!(start.elapsed(), Duration::from_millis(3));
+        assert_eq!(completed.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dropping_the_handle_stops_the_task() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&runs);
+        let handle = schedule_periodic(PERIOD, move || {
+            let counted = Arc::clone(&counted);
+            async move {
+                counted.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        sleep(Duration::from_millis(15)).await;
+        drop(handle);
+
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_scheduler_example() {
+        let schedules = scheduler_example(&ExampleContext::captured()).await;
+        let names: Vec<_> = schedules.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, vec!["sleep loop", "Burst", "Delay", "Skip"]);
+        assert_eq!(schedules[0].1, ms(&[0, 10, 45, 55, 65, 75]));
+    }
+}
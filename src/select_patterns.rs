@@ -0,0 +1,378 @@
+//! `tokio::select!` patterns
+//!
+//! `select!` polls several futures concurrently and runs the branch of the
+//! first one to complete. Every other branch is dropped, which is both its
+//! power (cancellation for free) and its main pitfall.
+//!
+//! This module covers:
+//! - Racing two operations
+//! - `select!` in a loop with a shutdown branch
+//! - `biased;` mode (deterministic branch order instead of random)
+//! - Branch preconditions (`, if condition`) and the `else` branch
+//! - Re-creating a future on every loop iteration vs pinning it outside
+
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::sleep;
+use tracing::{info, instrument};
+
+use crate::example_context::ExampleContext;
+
+/// Which operation won a race.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RaceWinner {
+    First,
+    Second,
+}
+
+/// Example 1: racing two operations
+///
+/// Both sleeps run concurrently; the branch of the first one to finish is
+/// executed and the other future is dropped (cancelled).
+#[instrument]
+pub async fn race_two_operations(first: Duration, second: Duration) -> RaceWinner {
+    tokio::select! {
+        _ = sleep(first) => {
+            info!("First operation finished first ({:?})", first);
+            RaceWinner::First
+        }
+        _ = sleep(second) => {
+            info!("Second operation finished first ({:?})", second);
+            RaceWinner::Second
+        }
+    }
+}
+
+/// Example 2: `select!` in a loop with a shutdown branch
+///
+/// Processes messages until either the channel closes or a shutdown signal
+/// is received, returning everything processed so far. The shutdown branch
+/// is listed first under `biased;` so it takes priority over pending messages.
+#[instrument(skip_all)]
+pub async fn process_until_shutdown(
+    mut messages: mpsc::Receiver<u32>,
+    mut shutdown: oneshot::Receiver<()>,
+) -> Vec<u32> {
+    let mut processed = Vec::new();
+
+    loop {
+        tokio::select! {
+            biased;
+            _ = &mut shutdown => {
+                info!("Shutdown received after {} message(s)", processed.len());
+                break;
+            }
+            message = messages.recv() => match message {
+                Some(value) => {
+                    info!("Processing message {}", value);
+                    processed.push(value);
+                }
+                None => {
+                    info!("Channel closed");
+                    break;
+                }
+            },
+        }
+    }
+
+    processed
+}
+
+/// Example 3: `biased;` mode
+///
+/// By default, `select!` picks a random branch among those that are ready, to
+/// avoid starving any of them. With `biased;`, branches are polled top to
+/// bottom, so when several are ready the first one always wins.
+///
+/// Both branches here are always ready. Returns how many times each branch
+/// won over `rounds` selections.
+#[instrument]
+pub async fn biased_selection(rounds: usize) -> (usize, usize) {
+    let mut first_wins = 0;
+    let mut second_wins = 0;
+
+    for _ in 0..rounds {
+        tokio::select! {
+            biased;
+            _ = std::future::ready(()) => first_wins += 1,
+            _ = std::future::ready(()) => second_wins += 1,
+        }
+    }
+
+    info!(
+        "biased: first branch won {} time(s), second {} time(s)",
+        first_wins, second_wins
+    );
+    (first_wins, second_wins)
+}
+
+/// Same as [`biased_selection`] without `biased;`: ready branches are picked
+/// at random.
+#[instrument]
+pub async fn unbiased_selection(rounds: usize) -> (usize, usize) {
+    let mut first_wins = 0;
+    let mut second_wins = 0;
+
+    for _ in 0..rounds {
+        tokio::select! {
+            _ = std::future::ready(()) => first_wins += 1,
+            _ = std::future::ready(()) => second_wins += 1,
+        }
+    }
+
+    info!(
+        "unbiased: first branch won {} time(s), second {} time(s)",
+        first_wins, second_wins
+    );
+    (first_wins, second_wins)
+}
+
+/// Example 4: branch preconditions
+///
+/// A branch with `, if condition` is disabled when the condition is false.
+/// A branch whose pattern does not match (e.g. `Some(v)` receiving `None`)
+/// is disabled too. When every branch is disabled, the `else` branch runs.
+///
+/// Receives from two channels, taking at most `budget` items from `low`,
+/// until both channels are exhausted.
+#[instrument(skip(high, low))]
+pub async fn drain_with_budget(
+    mut high: mpsc::Receiver<u32>,
+    mut low: mpsc::Receiver<u32>,
+    budget: usize,
+) -> (Vec<u32>, Vec<u32>) {
+    let mut from_high = Vec::new();
+    let mut from_low = Vec::new();
+
+    loop {
+        tokio::select! {
+            Some(value) = high.recv() => from_high.push(value),
+            Some(value) = low.recv(), if from_low.len() < budget => from_low.push(value),
+            else => {
+                info!(
+                    "All branches disabled: {} high, {} low (budget {})",
+                    from_high.len(),
+                    from_low.len(),
+                    budget
+                );
+                break;
+            }
+        }
+    }
+
+    (from_high, from_low)
+}
+
+/// Outcome of a receive loop guarded by an overall deadline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadlineOutcome {
+    /// Messages received before the loop ended.
+    pub received: Vec<u32>,
+    /// Whether the loop ended because the deadline fired.
+    pub deadline_hit: bool,
+}
+
+/// Example 5a (pitfall): creating the deadline future inside the loop
+///
+/// `sleep(deadline)` is a new future on every iteration, so the timer
+/// restarts each time a message arrives. As long as messages keep arriving
+/// more often than `deadline`, the deadline never fires.
+#[instrument(skip(messages))]
+pub async fn deadline_recreated_each_iteration(
+    mut messages: mpsc::Receiver<u32>,
+    deadline: Duration,
+) -> DeadlineOutcome {
+    let mut received = Vec::new();
+
+    loop {
+        tokio::select! {
+            _ = sleep(deadline) => {
+                return DeadlineOutcome { received, deadline_hit: true };
+            }
+            message = messages.recv() => match message {
+                Some(value) => received.push(value),
+                None => return DeadlineOutcome { received, deadline_hit: false },
+            },
+        }
+    }
+}
+
+/// Example 5b (fix): pinning the deadline future outside the loop
+///
+/// The same `Sleep` is polled by reference on every iteration, so its timer
+/// keeps running across iterations and fires `deadline` after the loop
+/// started.
+#[instrument(skip(messages))]
+pub async fn deadline_pinned_outside_loop(
+    mut messages: mpsc::Receiver<u32>,
+    deadline: Duration,
+) -> DeadlineOutcome {
+    let mut received = Vec::new();
+    let timer = sleep(deadline);
+    tokio::pin!(timer);
+
+    loop {
+        tokio::select! {
+            _ = &mut timer => {
+                return DeadlineOutcome { received, deadline_hit: true };
+            }
+            message = messages.recv() => match message {
+                Some(value) => received.push(value),
+                None => return DeadlineOutcome { received, deadline_hit: false },
+            },
+        }
+    }
+}
+
+/// Sends `count` numbered messages, waiting `interval` before each one.
+async fn send_every(tx: mpsc::Sender<u32>, count: u32, interval: Duration) {
+    for i in 0..count {
+        sleep(interval).await;
+        if tx.send(i).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Runs every `select!` pattern of this module, with the latencies of `ctx`.
+#[instrument(skip_all)]
+pub async fn select_patterns_example(ctx: &ExampleContext) {
+    let delay = |ms| ctx.latency().delay(Duration::from_millis(ms));
+
+    info!("Racing two operations...");
+    race_two_operations(delay(50), delay(20)).await;
+
+    info!("Looping until shutdown...");
+    let (tx, rx) = mpsc::channel(8);
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+    let (period, latency) = (delay(10), ctx.latency().clone());
+    tokio::spawn(async move {
+        send_every(tx.clone(), 3, period).await;
+        latency.sleep(Duration::from_millis(10)).await;
+        let _ = shutdown_tx.send(());
+    });
+    process_until_shutdown(rx, shutdown_rx).await;
+
+    info!("Comparing biased and unbiased selection...");
+    biased_selection(100).await;
+    unbiased_selection(100).await;
+
+    info!("Pitfall: re-creating the deadline on each iteration...");
+    let (tx, rx) = mpsc::channel(8);
+    tokio::spawn(send_every(tx, 5, delay(20)));
+    let outcome = deadline_recreated_each_iteration(rx, delay(50)).await;
+    info!(
+        "Re-created deadline: received {:?}, deadline hit: {}",
+        outcome.received, outcome.deadline_hit
+    );
+
+    let (tx, rx) = mpsc::channel(8);
+    tokio::spawn(send_every(tx, 5, delay(20)));
+    let outcome = deadline_pinned_outside_loop(rx, delay(50)).await;
+    info!(
+        "Pinned deadline: received {:?}, deadline hit: {}",
+        outcome.received, outcome.deadline_hit
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Instant;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_race_two_operations() {
+        let start = Instant::now();
+        let winner =
+            race_two_operations(Duration::from_millis(50), Duration::from_millis(20)).await;
+        assert_eq!(winner, RaceWinner::Second);
+        assert_eq!(start.elapsed(), Duration::from_millis(20));
+
+        let winner =
+            race_two_operations(Duration::from_millis(10), Duration::from_millis(20)).await;
+        assert_eq!(winner, RaceWinner::First);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_process_until_shutdown() {
+        let (tx, rx) = mpsc::channel(8);
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+
+        tokio::spawn(async move {
+            send_every(tx.clone(), 3, Duration::from_millis(10)).await;
+            sleep(Duration::from_millis(10)).await;
+            let _ = shutdown_tx.send(());
+            // Keep the sender alive so only the shutdown branch can end the loop.
+            sleep(Duration::from_secs(60)).await;
+            drop(tx);
+        });
+
+        let start = Instant::now();
+        let processed = process_until_shutdown(rx, shutdown_rx).await;
+        assert_eq!(processed, vec![0, 1, 2]);
+        assert_eq!(start.elapsed(), Duration::from_millis(40));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_process_until_channel_closed() {
+        let (tx, rx) = mpsc::channel(8);
+        let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+        tokio::spawn(send_every(tx, 2, Duration::from_millis(10)));
+
+        let processed = process_until_shutdown(rx, shutdown_rx).await;
+        assert_eq!(processed, vec![0, 1]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_biased_selection_always_picks_first() {
+        assert_eq!(biased_selection(100).await, (100, 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unbiased_selection_picks_both() {
+        let (first, second) = unbiased_selection(1000).await;
+        assert_eq!(first + second, 1000);
+        assert!(first > 0 && second > 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_drain_with_budget() {
+        let (high_tx, high_rx) = mpsc::channel(8);
+        let (low_tx, low_rx) = mpsc::channel(8);
+        for i in 0..3 {
+            high_tx.send(i).await.unwrap();
+        }
+        for i in 10..15 {
+            low_tx.send(i).await.unwrap();
+        }
+        drop(high_tx);
+        // low_tx stays alive: only the budget precondition can disable it.
+
+        let (from_high, from_low) = drain_with_budget(high_rx, low_rx, 2).await;
+        assert_eq!(from_high, vec![0, 1, 2]);
+        assert_eq!(from_low, vec![10, 11]);
+        drop(low_tx);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_recreated_deadline_never_fires() {
+        let (tx, rx) = mpsc::channel(8);
+        tokio::spawn(send_every(tx, 5, Duration::from_millis(20)));
+
+        let outcome = deadline_recreated_each_iteration(rx, Duration::from_millis(50)).await;
+        assert!(!outcome.deadline_hit);
+        assert_eq!(outcome.received, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pinned_deadline_fires() {
+        let (tx, rx) = mpsc::channel(8);
+        tokio::spawn(send_every(tx, 5, Duration::from_millis(20)));
+
+        let start = Instant::now();
+        let outcome = deadline_pinned_outside_loop(rx, Duration::from_millis(50)).await;
+        assert!(outcome.deadline_hit);
+        assert_eq!(outcome.received, vec![0, 1]);
+        assert_eq!(start.elapsed(), Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,196 @@
+//! Compile-time `Send`/`Sync` assertions for futures
+//!
+//! `tokio::spawn` on a multi-thread runtime requires `Send` futures, because a
+//! task may be resumed on a different worker thread after any await. A future
+//! is `Send` only if everything it holds across an await point is `Send`.
+//!
+//! [`assert_send`] turns that rule into a compile-time check: it does nothing
+//! at runtime, but a call only compiles if the future is `Send`. The tests of
+//! this module use it on the crate's public async functions, so the "Send
+//! chapter" of the course is verified by the compiler.
+//!
+//! [`rc_across_await_example`] is deliberately `!Send`:
+//!
+//! ```compile_fail
+//! use rust_async_await_course_example::example_context::ExampleContext;
+//! use rust_async_await_course_example::send_sync::{assert_send, rc_across_await_example};
+//!
+//! assert_send(&rc_across_await_example(&ExampleContext::default()));
+//! ```
+//!
+//! [`rc_dropped_before_await_example`] does the same work but drops the `Rc`
+//! before awaiting, and is `Send` again.
+
+use std::future::Future;
+use std::rc::Rc;
+use std::time::Duration;
+use tracing::{info, instrument};
+
+use crate::example_context::ExampleContext;
+
+/// Compiles only if `F` is a `Send` future.
+pub fn assert_send<F: Future + Send>(_future: &F) {}
+
+/// Compiles only if `T` is `Send` and `Sync`.
+pub fn assert_send_sync<T: Send + Sync>() {}
+
+/// Holds an `Rc` across an await point: the future is `!Send`.
+///
+/// It can still run on a single thread, e.g. with `tokio::task::spawn_local`
+/// inside a `LocalSet`, but not with `tokio::spawn`.
+#[instrument(skip_all)]
+pub async fn rc_across_await_example(ctx: &ExampleContext) -> usize {
+    let shared = Rc::new(vec![1, 2, 3]);
+    ctx.latency().sleep(Duration::from_millis(10)).await;
+    // `shared` is used after the await, so it lives in the future's state.
+    info!(strong_count = Rc::strong_count(&shared), "after await");
+    shared.len()
+}
+
+/// Same computation, with the `Rc` confined to a block that ends before the
+/// await: the future is `Send`.
+#[instrument(skip_all)]
+pub async fn rc_dropped_before_await_example(ctx: &ExampleContext) -> usize {
+    let len = {
+        let shared = Rc::new(vec![1, 2, 3]);
+        shared.len()
+    };
+    ctx.latency().sleep(Duration::from_millis(10)).await;
+    info!(len, "after await");
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::async_traits::{fetch_all, InMemorySource};
+    use crate::flaky::{FlakyService, Step};
+    use crate::pause::{PauseController, PauseToken};
+    use crate::plugins::PluginRunner;
+    use crate::request_id::{with_request_id, RequestId, TracedClient};
+    use crate::{
+        adaptive_timeout, async_drop, async_state_machine_example, async_sugar_example,
+        async_traits, cancel_safety, coalescing, complex_async_function,
+        concurrent_execution_example, context, error_reporter, errors, fetch_data_from_api, flaky,
+        future_size, hedging, into_future, loadgen, memory_budget, multiple_awaits_example,
+        pagination, panics, pause, pipeline, plugins, poll_trace, progress, sandbox, scheduler,
+        select_patterns, shutdown, strategies, sync_primitives, task_registry, transaction,
+        unordered, variable_scoping_example, waker, warmup,
+    };
+    use tokio::sync::{mpsc, oneshot};
+
+    #[test]
+    fn test_lib_examples_are_send() {
+        let ctx = ExampleContext::captured();
+        assert_send(&async_state_machine_example(&ctx));
+        assert_send(&multiple_awaits_example(&ctx));
+        assert_send(&variable_scoping_example(&ctx));
+        assert_send(&complex_async_function(1, String::new()));
+        assert_send(&fetch_data_from_api("http://localhost"));
+        assert_send(&concurrent_execution_example(&ctx));
+        assert_send(&async_sugar_example(&ctx));
+    }
+
+    #[test]
+    fn test_module_examples_are_send() {
+        let ctx = ExampleContext::captured();
+        assert_send(&waker::waker_example(&ctx));
+        assert_send(&select_patterns::select_patterns_example(&ctx));
+        assert_send(&pause::pause_resume_example(&ctx));
+        assert_send(&flaky::retry_example(&ctx));
+        assert_send(&memory_budget::memory_budget_example(&ctx));
+        assert_send(&async_traits::async_traits_example(&ctx));
+        assert_send(&plugins::plugin_registry_example(&ctx));
+        assert_send(&unordered::unordered_example(&ctx));
+        assert_send(&future_size::future_size_example(&ctx));
+        assert_send(&pipeline::pipeline_example(&ctx));
+        assert_send(&errors::error_handling_example(&ctx));
+        assert_send(&crate::oneshot::oneshot_example(&ctx));
+        assert_send(&coalescing::coalescing_example());
+        assert_send(&poll_trace::poll_trace_example(&ctx));
+        assert_send(&loadgen::loadgen_example(&ctx));
+        assert_send(&adaptive_timeout::adaptive_timeout_example(&ctx));
+        assert_send(&error_reporter::error_reporter_example(&ctx));
+        assert_send(&scheduler::scheduler_example(&ctx));
+        assert_send(&shutdown::shutdown_example(&ctx));
+        assert_send(&async_drop::async_drop_example(&ctx));
+        assert_send(&into_future::into_future_example(&ctx));
+        assert_send(&strategies::strategies_example(&ctx));
+        assert_send(&transaction::transaction_example());
+        assert_send(&pagination::pagination_example(&ctx));
+        assert_send(&warmup::warmup_example(&ctx));
+        assert_send(&context::context_example(&ctx));
+        assert_send(&cancel_safety::cancel_safety_example());
+        assert_send(&panics::panics_example(&ctx));
+        assert_send(&sync_primitives::sync_primitives_example(&ctx));
+        assert_send(&progress::progress_example(&ctx));
+        assert_send(&sandbox::sandbox_example());
+        assert_send(&hedging::hedging_example(&ctx));
+        assert_send(&task_registry::task_registry_example(&ctx));
+        assert_send(&crate::http_server::http_server_example(&ctx));
+        assert_send(&crate::http_server::backpressure_example(&ctx));
+        assert_send(&rc_dropped_before_await_example(&ctx));
+    }
+
+    #[test]
+    fn test_module_functions_are_send() {
+        let (_tx, rx) = mpsc::channel(1);
+        let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+        assert_send(&select_patterns::process_until_shutdown(rx, shutdown_rx));
+
+        let service = FlakyService::always("svc", Step::Succeed);
+        assert_send(&service.call());
+
+        // A native async trait method is Send here because the concrete
+        // implementation is known and its future happens to be Send.
+        let source = InMemorySource::default();
+        assert_send(&fetch_all(&source, &[]));
+
+        let mut runner = PluginRunner::new();
+        assert_send(&runner.run_all());
+
+        let client = TracedClient::new().unwrap();
+        assert_send(&client.get_text("http://localhost"));
+        assert_send(&with_request_id(RequestId::new("id"), async {}));
+
+        let mut aggregator = progress::ProgressAggregator::new();
+        assert_send(&aggregator.next());
+    }
+
+    #[test]
+    fn test_shared_types_are_send_and_sync() {
+        assert_send_sync::<FlakyService>();
+        assert_send_sync::<PauseController>();
+        assert_send_sync::<PauseToken>();
+        assert_send_sync::<task_registry::TaskRegistry>();
+        assert_send_sync::<TracedClient>();
+        assert_send_sync::<waker::PollMetrics>();
+    }
+
+    #[tokio::test]
+    async fn test_non_send_future_runs_on_local_set() {
+        // `tokio::spawn` of `rc_across_await_example` would not compile;
+        // `spawn_local` keeps the task on the current thread.
+        let local = tokio::task::LocalSet::new();
+        let len = local
+            .run_until(async {
+                tokio::task::spawn_local(async {
+                    rc_across_await_example(&ExampleContext::captured()).await
+                })
+                .await
+            })
+            .await
+            .unwrap();
+        assert_eq!(len, 3);
+    }
+
+    #[tokio::test]
+    async fn test_send_future_can_be_spawned() {
+        let len = tokio::spawn(async {
+            rc_dropped_before_await_example(&ExampleContext::captured()).await
+        })
+        .await
+        .unwrap();
+        assert_eq!(len, 3);
+    }
+}
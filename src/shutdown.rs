@@ -0,0 +1,557 @@
+//! Ordered shutdown: named phases with dependencies
+//!
+//! Tearing a service down in the wrong order loses work: closing the
+//! connections before the writers flushed drops the last writes, and
+//! flushing before the workers drained misses what they still produce. A
+//! [`ShutdownCoordinator`] makes the order explicit:
+//! - tasks [`subscribe`](ShutdownCoordinator::subscribe) to a `watch`
+//!   signal, set when shutdown starts
+//! - code not owning the coordinator, like an error handler, requests a
+//!   shutdown through a [`ShutdownTrigger`], which
+//!   [`shutdown_when_triggered`](ShutdownCoordinator::shutdown_when_triggered)
+//!   waits for
+//! - each phase has a name, the phases it runs after, a timeout, and an
+//!   action (e.g. stop intake, drain workers, flush writers, close
+//!   connections)
+//! - [`shutdown`](ShutdownCoordinator::shutdown) sets the signal, then runs
+//!   the phases one at a time in dependency (topological) order, phases
+//!   without an order between them in registration order
+//! - a phase exceeding its timeout is abandoned and the next ones still run,
+//!   so one stuck phase cannot hang the whole teardown
+//!
+//! Unknown dependencies and cycles are reported before any phase runs.
+
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{timeout, Instant};
+use tracing::{info, instrument, warn};
+
+use crate::example_context::ExampleContext;
+
+type Action = Box<dyn FnOnce() -> BoxFuture<'static, ()> + Send>;
+
+struct Phase {
+    name: &'static str,
+    after: Vec<&'static str>,
+    timeout: Duration,
+    action: Action,
+}
+
+/// Error preventing a shutdown plan from running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShutdownError {
+    /// A phase runs after a phase that was not registered.
+    UnknownDependency {
+        phase: &'static str,
+        dependency: &'static str,
+    },
+    /// The phases form a cycle and cannot be ordered.
+    Cycle(Vec<&'static str>),
+}
+
+impl fmt::Display for ShutdownError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShutdownError::UnknownDependency { phase, dependency } => {
+                write!(f, "phase {} runs after unknown phase {}", phase, dependency)
+            }
+            ShutdownError::Cycle(phases) => {
+                write!(f, "cycle between phases {}", phases.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for ShutdownError {}
+
+/// How a phase ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhaseOutcome {
+    Completed(Duration),
+    /// Abandoned after its timeout.
+    TimedOut(Duration),
+}
+
+/// The phases run by a shutdown, in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShutdownReport {
+    pub phases: Vec<(&'static str, PhaseOutcome)>,
+}
+
+impl ShutdownReport {
+    /// Names of the phases, in the order they ran.
+    pub fn order(&self) -> Vec<&'static str> {
+        self.phases.iter().map(|(name, _)| *name).collect()
+    }
+
+    /// Names of the phases abandoned after their timeout.
+    pub fn timed_out(&self) -> Vec<&'static str> {
+        self.phases
+            .iter()
+            .filter(|(_, outcome)| matches!(outcome, PhaseOutcome::TimedOut(_)))
+            .map(|(name, _)| *name)
+            .collect()
+    }
+}
+
+/// Handle requesting a shutdown from a [`ShutdownCoordinator`]. Cheap to
+/// clone.
+#[derive(Debug, Clone)]
+pub struct ShutdownTrigger(Arc<watch::Sender<bool>>);
+
+impl ShutdownTrigger {
+    /// Requests the shutdown. Later requests do nothing.
+    pub fn request(&self) {
+        self.0.send_replace(true);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.0.borrow()
+    }
+}
+
+/// Signals shutdown to tasks, then tears down in phases.
+pub struct ShutdownCoordinator {
+    signal: watch::Sender<bool>,
+    requested: Arc<watch::Sender<bool>>,
+    phases: Vec<Phase>,
+}
+
+impl Default for ShutdownCoordinator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShutdownCoordinator {
+    pub fn new() -> Self {
+        ShutdownCoordinator {
+            signal: watch::channel(false).0,
+            requested: Arc::new(watch::channel(false).0),
+            phases: Vec::new(),
+        }
+    }
+
+    /// A receiver whose value turns `true` when shutdown starts.
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.signal.subscribe()
+    }
+
+    /// A handle requesting the shutdown [`shutdown_when_triggered`] waits
+    /// for.
+    ///
+    /// [`shutdown_when_triggered`]: ShutdownCoordinator::shutdown_when_triggered
+    pub fn trigger(&self) -> ShutdownTrigger {
+        ShutdownTrigger(Arc::clone(&self.requested))
+    }
+
+    /// Adds a phase running `action` after the phases named in `after`,
+    /// abandoned if it takes longer than `timeout`.
+    ///
+    /// # Panics
+    ///
+    /// If a phase with the same name is already added.
+    pub fn phase<F, Fut>(
+        &mut self,
+        name: &'static str,
+        after: &[&'static str],
+        timeout: Duration,
+        action: F,
+    ) -> &mut Self
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        assert!(
+            self.phases.iter().all(|phase| phase.name != name),
+            "phase {} added twice",
+            name
+        );
+        self.phases.push(Phase {
+            name,
+            after: after.to_vec(),
+            timeout,
+            action: Box::new(move || action().boxed()),
+        });
+        self
+    }
+
+    /// The order the phases would run in.
+    pub fn order(&self) -> Result<Vec<&'static str>, ShutdownError> {
+        let indices = self.ordered_indices()?;
+        Ok(indices.into_iter().map(|i| self.phases[i].name).collect())
+    }
+
+    /// Indices of the phases in topological order: repeatedly takes the
+    /// first phase, in registration order, whose dependencies all ran.
+    fn ordered_indices(&self) -> Result<Vec<usize>, ShutdownError> {
+        for phase in &self.phases {
+            if let Some(&dependency) = phase
+                .after
+                .iter()
+                .find(|&&dependency| self.phases.iter().all(|p| p.name != dependency))
+            {
+                return Err(ShutdownError::UnknownDependency {
+                    phase: phase.name,
+                    dependency,
+                });
+            }
+        }
+
+        let mut done: Vec<&'static str> = Vec::new();
+        let mut order = Vec::new();
+        let mut remaining: Vec<usize> = (0..self.phases.len()).collect();
+        while !remaining.is_empty() {
+            let ready = remaining.iter().position(|&i| {
+                self.phases[i]
+                    .after
+                    .iter()
+                    .all(|dependency| done.contains(dependency))
+            });
+            let Some(position) = ready else {
+                let cycle = remaining.iter().map(|&i| self.phases[i].name).collect();
+                return Err(ShutdownError::Cycle(cycle));
+            };
+            let index = remaining.remove(position);
+            done.push(self.phases[index].name);
+            order.push(index);
+        }
+        Ok(order)
+    }
+
+    /// Waits until a [`ShutdownTrigger`] requests the shutdown, then runs it.
+    ///
+    /// The phases are checked first: an invalid plan is reported right away.
+    pub async fn shutdown_when_triggered(self) -> Result<ShutdownReport, ShutdownError> {
+        self.ordered_indices()?;
+        let mut requested = self.requested.subscribe();
+        // The coordinator holds the sender: the channel never closes.
+        let _ = requested.wait_for(|&requested| requested).await;
+        info!("shutdown requested");
+        self.shutdown().await
+    }
+
+    /// Signals shutdown and runs every phase in order.
+    ///
+    /// Nothing runs, and the signal is not set, if the phases cannot be
+    /// ordered.
+    pub async fn shutdown(self) -> Result<ShutdownReport, ShutdownError> {
+        let order = self.ordered_indices()?;
+        info!(phases = order.len(), "shutting down");
+        self.signal.send_replace(true);
+
+        let mut phases: Vec<Option<Phase>> = self.phases.into_iter().map(Some).collect();
+        let mut report = ShutdownReport { phases: Vec::new() };
+        for index in order {
+            let phase = phases[index].take().expect("each phase runs once");
+            let start = Instant::now();
+            let outcome = match timeout(phase.timeout, (phase.action)()).await {
+                Ok(()) => PhaseOutcome::Completed(start.elapsed()),
+                Err(_) => {
+                    warn!(phase = phase.name, timeout = ?phase.timeout, "phase timed out");
+                    PhaseOutcome::TimedOut(phase.timeout)
+                }
+            };
+            info!(phase = phase.name, ?outcome, "phase done");
+            report.phases.push((phase.name, outcome));
+        }
+        Ok(report)
+    }
+}
+
+impl fmt::Debug for ShutdownCoordinator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<_> = self.phases.iter().map(|phase| phase.name).collect();
+        f.debug_struct("ShutdownCoordinator")
+            .field("phases", &names)
+            .finish()
+    }
+}
+
+/// Example: intake, workers, a buffered writer and a connection, torn down
+/// in order
+///
+/// Returns the report, the number of jobs accepted by the intake, and the
+/// number written: the same, as nothing is torn down too early.
+///
+/// Every step takes the latencies of `ctx`, and the intake also stops when
+/// `ctx` is cancelled.
+#[instrument(skip_all)]
+pub async fn shutdown_example(
+    ctx: &ExampleContext,
+) -> Result<(ShutdownReport, usize, usize), ShutdownError> {
+    let mut coordinator = ShutdownCoordinator::new();
+    let (jobs, mut queue) = mpsc::channel::<u32>(8);
+    let accepted = Arc::new(Mutex::new(0));
+    let buffered = Arc::new(Mutex::new(Vec::new()));
+    let written = Arc::new(Mutex::new(Vec::new()));
+
+    let mut stop = coordinator.subscribe();
+    let intake_accepted = Arc::clone(&accepted);
+    let (latency, cancel) = (ctx.latency().clone(), ctx.cancel_token().clone());
+    let intake = tokio::spawn(async move {
+        for job in 0.. {
+            tokio::select! {
+                _ = stop.wait_for(|&stop| stop) => break,
+                _ = cancel.cancelled() => break,
+                _ = latency.sleep(Duration::from_millis(5)) => {}
+            }
+            if jobs.send(job).await.is_err() {
+                break;
+            }
+            *intake_accepted.lock().unwrap() += 1;
+        }
+    });
+    let worker_buffer = Arc::clone(&buffered);
+    let latency = ctx.latency().clone();
+    let worker = tokio::spawn(async move {
+        while let Some(job) = queue.recv().await {
+            latency.sleep(Duration::from_millis(2)).await;
+            worker_buffer.lock().unwrap().push(job * 10);
+        }
+    });
+    ctx.latency().sleep(Duration::from_millis(50)).await;
+
+    let flushed_to = Arc::clone(&written);
+    let (close_latency, flush_latency) = (ctx.latency().clone(), ctx.latency().clone());
+    coordinator
+        .phase(
+            "close connections",
+            &["flush writers"],
+            Duration::from_millis(50),
+            move || async move { close_latency.sleep(Duration::from_millis(5)).await },
+        )
+        .phase(
+            "flush writers",
+            &["drain workers"],
+            Duration::from_millis(50),
+            move || async move {
+                let pending = std::mem::take(&mut *buffered.lock().unwrap());
+                flush_latency.sleep(Duration::from_millis(5)).await;
+                flushed_to.lock().unwrap().extend(pending);
+            },
+        )
+        .phase(
+            "stop intake",
+            &[],
+            Duration::from_millis(50),
+            || async move {
+                let _ = intake.await;
+            },
+        )
+        .phase(
+            "drain workers",
+            &["stop intake"],
+            Duration::from_millis(100),
+            || async move {
+                let _ = worker.await;
+            },
+        );
+
+    let report = coordinator.shutdown().await?;
+    let accepted = *accepted.lock().unwrap();
+    let written = written.lock().unwrap().len();
+    info!(order = ?report.order(), accepted, written, "shut down");
+    Ok((report, accepted, written))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::sleep;
+
+    /// A phase action recording its name in `log`, after `duration`.
+    fn record(
+        log: &Arc<Mutex<Vec<&'static str>>>,
+        name: &'static str,
+        duration: Duration,
+    ) -> impl FnOnce() -> BoxFuture<'static, ()> + Send + 'static {
+        let log = Arc::clone(log);
+        move || {
+            async move {
+                sleep(duration).await;
+                log.lock().unwrap().push(name);
+            }
+            .boxed()
+        }
+    }
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    #[tokio::test(start_paused = true)]
+    async fn test_phases_run_in_dependency_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut coordinator = ShutdownCoordinator::new();
+        let step = Duration::from_millis(1);
+        coordinator
+            .phase("close", &["flush"], TIMEOUT, record(&log, "close", step))
+            .phase("flush", &["drain"], TIMEOUT, record(&log, "flush", step))
+            .phase("drain", &["intake"], TIMEOUT, record(&log, "drain", step))
+            .phase("intake", &[], TIMEOUT, record(&log, "intake", step));
+
+        let expected = vec!["intake", "drain", "flush", "close"];
+        assert_eq!(coordinator.order().unwrap(), expected);
+        let report = coordinator.shutdown().await.unwrap();
+        assert_eq!(report.order(), expected);
+        assert_eq!(*log.lock().unwrap(), expected);
+        assert!(report.timed_out().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_independent_phases_keep_registration_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut coordinator = ShutdownCoordinator::new();
+        let step = Duration::ZERO;
+        coordinator
+            .phase("c", &["a", "b"], TIMEOUT, record(&log, "c", step))
+            .phase("b", &[], TIMEOUT, record(&log, "b", step))
+            .phase("a", &[], TIMEOUT, record(&log, "a", step))
+            .phase("d", &[], TIMEOUT, record(&log, "d", step));
+
+        coordinator.shutdown().await.unwrap();
+        assert_eq!(*log.lock().unwrap(), vec!["b", "a", "c", "d"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timed_out_phase_does_not_block_the_next() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut coordinator = ShutdownCoordinator::new();
+        coordinator
+            .phase("stuck", &[], Duration::from_millis(20), || {
+                std::future::pending()
+            })
+            .phase(
+                "after",
+                &["stuck"],
+                TIMEOUT,
+                record(&log, "after", Duration::from_millis(5)),
+            );
+
+        let start = Instant::now();
+        let report = coordinator.shutdown().await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_millis(25));
+        assert_eq!(
+            report.phases,
+            vec![
+                ("stuck", PhaseOutcome::TimedOut(Duration::from_millis(20))),
+                ("after", PhaseOutcome::Completed(Duration::from_millis(5))),
+            ]
+        );
+        assert_eq!(report.timed_out(), vec!["stuck"]);
+        assert_eq!(*log.lock().unwrap(), vec!["after"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unknown_dependency_runs_nothing() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut coordinator = ShutdownCoordinator::new();
+        let signal = coordinator.subscribe();
+        coordinator
+            .phase("a", &[], TIMEOUT, record(&log, "a", Duration::ZERO))
+            .phase("b", &["z"], TIMEOUT, record(&log, "b", Duration::ZERO));
+
+        let error = coordinator.shutdown().await.unwrap_err();
+        assert_eq!(
+            error,
+            ShutdownError::UnknownDependency {
+                phase: "b",
+                dependency: "z"
+            }
+        );
+        assert_eq!(error.to_string(), "phase b runs after unknown phase z");
+        assert!(log.lock().unwrap().is_empty());
+        assert!(!*signal.borrow());
+    }
+
+    #[test]
+    fn test_cycle_is_reported() {
+        let mut coordinator = ShutdownCoordinator::new();
+        coordinator
+            .phase("a", &[], TIMEOUT, || async {})
+            .phase("b", &["a", "d"], TIMEOUT, || async {})
+            .phase("c", &["b"], TIMEOUT, || async {})
+            .phase("d", &["c"], TIMEOUT, || async {});
+
+        let error = coordinator.order().unwrap_err();
+        assert_eq!(error, ShutdownError::Cycle(vec!["b", "c", "d"]));
+        assert_eq!(error.to_string(), "cycle between phases b, c, d");
+    }
+
+    #[test]
+    #[should_panic(expected = "phase a added twice")]
+    fn test_duplicate_phase_panics() {
+        ShutdownCoordinator::new()
+            .phase("a", &[], TIMEOUT, || async {})
+            .phase("a", &[], TIMEOUT, || async {});
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_signal_is_set_before_the_first_phase() {
+        let mut coordinator = ShutdownCoordinator::new();
+        let signal = coordinator.subscribe();
+        let seen = Arc::new(Mutex::new(None));
+        let seen_by_phase = Arc::clone(&seen);
+        coordinator.phase("check", &[], TIMEOUT, move || async move {
+            *seen_by_phase.lock().unwrap() = Some(*signal.borrow());
+        });
+
+        coordinator.shutdown().await.unwrap();
+        assert_eq!(*seen.lock().unwrap(), Some(true));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_when_triggered() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut coordinator = ShutdownCoordinator::new();
+        let signal = coordinator.subscribe();
+        let trigger = coordinator.trigger();
+        coordinator.phase("a", &[], TIMEOUT, record(&log, "a", Duration::ZERO));
+        let shutdown = tokio::spawn(coordinator.shutdown_when_triggered());
+
+        sleep(Duration::from_millis(50)).await;
+        assert!(!shutdown.is_finished());
+        assert!(!*signal.borrow());
+
+        let requester = trigger.clone();
+        requester.request();
+        assert!(trigger.is_requested());
+        let report = shutdown.await.unwrap().unwrap();
+        assert_eq!(report.order(), vec!["a"]);
+        assert!(*signal.borrow());
+        assert_eq!(*log.lock().unwrap(), vec!["a"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_invalid_plan_is_reported_without_trigger() {
+        let mut coordinator = ShutdownCoordinator::new();
+        coordinator.phase("a", &["z"], TIMEOUT, || async {});
+        assert!(matches!(
+            coordinator.shutdown_when_triggered().await,
+            Err(ShutdownError::UnknownDependency { .. })
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_example() {
+        let (report, accepted, written) =
+            shutdown_example(&ExampleContext::captured()).await.unwrap();
+        assert_eq!(
+            report.order(),
+            vec![
+                "stop intake",
+                "drain workers",
+                "flush writers",
+                "close connections"
+            ]
+        );
+        assert!(report.timed_out().is_empty());
+        assert!(accepted > 0);
+        assert_eq!(written, accepted, "every accepted job was written");
+    }
+}
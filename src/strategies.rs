@@ -0,0 +1,268 @@
+//! Concurrency strategies, measurable
+//!
+//! The same N simulated I/O operations, awaited four ways:
+//! - [`Strategy::Sequential`]: one await after the other, N times the latency
+//! - [`Strategy::JoinAll`]: `join_all`, the N-ary `join!`, polling every
+//!   future from the current task
+//! - [`Strategy::Spawned`]: one task per operation, joined with a `JoinSet`;
+//!   runs on several worker threads, at the cost of a spawn per operation
+//! - [`Strategy::Unordered`]: `FuturesUnordered`, polling only the futures
+//!   that were woken
+//!
+//! Plus the building blocks of two other claims: awaiting boxed vs unboxed
+//! futures, and the throughput of bounded vs unbounded `mpsc` channels.
+//! `benches/concurrency.rs` runs them all with criterion (`make bench`).
+
+use std::fmt;
+use std::time::Duration;
+
+use futures::future::join_all;
+use futures::stream::{FuturesUnordered, StreamExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio::time::sleep;
+use tracing::{info, instrument};
+
+use crate::example_context::ExampleContext;
+
+/// A simulated I/O operation returning `id`. Zero latency yields once
+/// instead, leaving only the overhead of the strategy.
+pub async fn simulated_io(id: u64, latency: Duration) -> u64 {
+    if latency.is_zero() {
+        tokio::task::yield_now().await;
+    } else {
+        sleep(latency).await;
+    }
+    id
+}
+
+/// A way to await N operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    Sequential,
+    JoinAll,
+    Spawned,
+    Unordered,
+}
+
+impl Strategy {
+    pub const ALL: [Strategy; 4] = [
+        Strategy::Sequential,
+        Strategy::JoinAll,
+        Strategy::Spawned,
+        Strategy::Unordered,
+    ];
+
+    /// Runs `operations` simulated I/O operations, returning the sum of
+    /// their results.
+    pub async fn run(self, operations: u64, latency: Duration) -> u64 {
+        match self {
+            Strategy::Sequential => {
+                let mut sum = 0;
+                for id in 0..operations {
+                    sum += simulated_io(id, latency).await;
+                }
+                sum
+            }
+            Strategy::JoinAll => join_all((0..operations).map(|id| simulated_io(id, latency)))
+                .await
+                .into_iter()
+                .sum(),
+            Strategy::Spawned => {
+                let mut tasks = JoinSet::new();
+                for id in 0..operations {
+                    tasks.spawn(simulated_io(id, latency));
+                }
+                let mut sum = 0;
+                while let Some(result) = tasks.join_next().await {
+                    sum += result.expect("simulated I/O panicked");
+                }
+                sum
+            }
+            Strategy::Unordered => {
+                (0..operations)
+                    .map(|id| simulated_io(id, latency))
+                    .collect::<FuturesUnordered<_>>()
+                    .fold(0, |sum, id| async move { sum + id })
+                    .await
+            }
+        }
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Strategy::Sequential => "sequential",
+            Strategy::JoinAll => "join_all",
+            Strategy::Spawned => "spawned",
+            Strategy::Unordered => "futures_unordered",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Awaits `count` small futures in place.
+pub async fn await_unboxed(count: u64) -> u64 {
+    let mut sum = 0;
+    for id in 0..count {
+        sum += simulated_io(id, Duration::ZERO).await;
+    }
+    sum
+}
+
+/// Awaits `count` small futures, each boxed first: one allocation each.
+pub async fn await_boxed(count: u64) -> u64 {
+    let mut sum = 0;
+    for id in 0..count {
+        sum += Box::pin(simulated_io(id, Duration::ZERO)).await;
+    }
+    sum
+}
+
+/// Kind of `mpsc` channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Bounded(usize),
+    Unbounded,
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Channel::Bounded(capacity) => write!(f, "bounded({})", capacity),
+            Channel::Unbounded => f.write_str("unbounded"),
+        }
+    }
+}
+
+/// Sends `messages` numbers from a spawned producer to this task, returning
+/// their sum.
+pub async fn channel_throughput(channel: Channel, messages: u64) -> u64 {
+    let mut sum = 0;
+    match channel {
+        Channel::Bounded(capacity) => {
+            let (tx, mut rx) = mpsc::channel(capacity);
+            tokio::spawn(async move {
+                for message in 0..messages {
+                    if tx.send(message).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            while let Some(message) = rx.recv().await {
+                sum += message;
+            }
+        }
+        Channel::Unbounded => {
+            let (tx, mut rx) = mpsc::unbounded_channel();
+            tokio::spawn(async move {
+                for message in 0..messages {
+                    if tx.send(message).is_err() {
+                        break;
+                    }
+                }
+            });
+            while let Some(message) = rx.recv().await {
+                sum += message;
+            }
+        }
+    }
+    sum
+}
+
+/// Example: 50 operations of 10ms each, with every strategy
+///
+/// The operations take the latency of `ctx`, and the strategies are timed
+/// on its clock.
+#[instrument(skip_all)]
+pub async fn strategies_example(ctx: &ExampleContext) -> Vec<(Strategy, Duration)> {
+    let latency = ctx.latency().delay(Duration::from_millis(10));
+    let mut timings = Vec::new();
+    for strategy in Strategy::ALL {
+        let start = ctx.clock().now();
+        strategy.run(50, latency).await;
+        let elapsed = start.elapsed();
+        info!(%strategy, ?elapsed, "50 operations of 10ms");
+        timings.push((strategy, elapsed));
+    }
+    info!("for precise numbers, run `make bench`");
+    timings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Instant;
+
+    const SUM_0_TO_99: u64 = 4950;
+
+    #[tokio::test]
+    async fn test_strategies_agree() {
+        for strategy in Strategy::ALL {
+            assert_eq!(
+                strategy.run(100, Duration::ZERO).await,
+                SUM_0_TO_99,
+                "{}",
+                strategy
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_only_sequential_adds_up_latencies() {
+        for strategy in Strategy::ALL {
+            let start = Instant::now();
+            strategy.run(10, Duration::from_millis(10)).await;
+            let expected = match strategy {
+                Strategy::Sequential => Duration::from_millis(100),
+                _ => Duration::from_millis(10),
+            };
+            assert_eq!(start.elapsed(), expected, "{}", strategy);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_boxed_and_unboxed_agree() {
+        assert_eq!(await_unboxed(100).await, SUM_0_TO_99);
+        assert_eq!(await_boxed(100).await, SUM_0_TO_99);
+    }
+
+    #[tokio::test]
+    async fn test_channels_deliver_everything() {
+        for channel in [
+            Channel::Bounded(1),
+            Channel::Bounded(64),
+            Channel::Unbounded,
+        ] {
+            assert_eq!(
+                channel_throughput(channel, 100).await,
+                SUM_0_TO_99,
+                "{}",
+                channel
+            );
+        }
+    }
+
+    #[test]
+    fn test_names() {
+        let names: Vec<String> = Strategy::ALL.iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            names,
+            vec!["sequential", "join_all", "spawned", "futures_unordered"]
+        );
+        assert_eq!(Channel::Bounded(8).to_string(), "bounded(8)");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_strategies_example() {
+        let timings = strategies_example(&ExampleContext::captured()).await;
+        assert_eq!(
+            timings[0],
+            (Strategy::Sequential, Duration::from_millis(500))
+        );
+        assert!(timings[1..]
+            .iter()
+            .all(|(_, elapsed)| *elapsed == Duration::from_millis(10)));
+    }
+}
@@ -0,0 +1,293 @@
+//! Bridging sync and async code
+//!
+//! Three ways for synchronous code to call the crate's async functions:
+//! - build a runtime and `block_on` the future. Simple, but building a
+//!   runtime per call is costly: fine in a non-async `main` or a one-off
+//!   tool, not in a hot path.
+//! - a bridge to a long-lived runtime: sync callers send requests over an
+//!   `mpsc` channel to a task running on a [`DedicatedRuntime`], and wait for
+//!   the answer on a `oneshot` channel with `blocking_recv`. Many sync
+//!   threads can share one bridge, and their requests run concurrently.
+//! - `futures::executor::block_on`, a minimal executor without reactor or
+//!   timers: it can drive runtime-agnostic futures (the hand-rolled
+//!   `oneshot` channel), but panics on tokio's timers and sockets.
+//!
+//! The other direction fails loudly: `block_on`, `blocking_send` and
+//! `blocking_recv` panic when called from inside a runtime, since blocking
+//! a worker thread would stall every task scheduled on it. Use
+//! `spawn_blocking` there (see `runtimes` for `block_on` and handles).
+//!
+//! From a non-async `main`:
+//!
+//! ```
+//! use rust_async_await_course_example::errors::load_score;
+//!
+//! fn main() -> std::io::Result<()> {
+//!     let runtime = tokio::runtime::Runtime::new()?;
+//!     assert_eq!(runtime.block_on(load_score(4)), Ok(40));
+//!     Ok(())
+//! }
+//! ```
+
+use std::fmt;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use tokio::runtime::Builder;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{info, instrument};
+
+use crate::dedicated_runtime::DedicatedRuntime;
+use crate::errors::{load_score_with, LoadError, MISSING_ID};
+use crate::example_context::{ExampleContext, LatencySimulator};
+
+/// Requests a bridge buffers before sync callers block on sending.
+const BRIDGE_CAPACITY: usize = 32;
+
+/// Loads a score from sync code by building a runtime for the call.
+///
+/// # Panics
+///
+/// When called from inside a runtime.
+pub fn load_score_blocking(id: u32) -> io::Result<Result<u32, LoadError>> {
+    load_score_blocking_with(id, &LatencySimulator::default())
+}
+
+/// Same as [`load_score_blocking`], with the backend latencies simulated by
+/// `latency`.
+pub fn load_score_blocking_with(
+    id: u32,
+    latency: &LatencySimulator,
+) -> io::Result<Result<u32, LoadError>> {
+    let runtime = Builder::new_current_thread().enable_all().build()?;
+    Ok(runtime.block_on(load_score_with(id, latency)))
+}
+
+/// Error of a call through the [`AsyncBridge`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BridgeError {
+    /// The bridge's runtime stopped before answering.
+    Stopped,
+    Load(LoadError),
+}
+
+impl fmt::Display for BridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BridgeError::Stopped => f.write_str("bridge runtime stopped"),
+            BridgeError::Load(error) => write!(f, "load failed: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for BridgeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BridgeError::Stopped => None,
+            BridgeError::Load(error) => Some(error),
+        }
+    }
+}
+
+struct Request {
+    id: u32,
+    reply: oneshot::Sender<Result<u32, LoadError>>,
+}
+
+/// Lets sync threads call [`load_score`](crate::errors::load_score) on a long-lived runtime.
+pub struct AsyncBridge {
+    requests: mpsc::Sender<Request>,
+    // Dropped after `requests`: the serving task sees the channel close
+    // before its runtime stops.
+    _runtime: DedicatedRuntime,
+}
+
+impl AsyncBridge {
+    /// Starts the runtime thread and the task serving requests.
+    pub fn start() -> io::Result<Self> {
+        Self::start_with(LatencySimulator::default())
+    }
+
+    /// Same as [`AsyncBridge::start`], with the backend latencies simulated
+    /// by `latency`.
+    pub fn start_with(latency: LatencySimulator) -> io::Result<Self> {
+        let runtime = DedicatedRuntime::start()?;
+        let (requests, receiver) = mpsc::channel(BRIDGE_CAPACITY);
+        runtime.spawn(serve(receiver, latency));
+        Ok(AsyncBridge {
+            requests,
+            _runtime: runtime,
+        })
+    }
+
+    /// Loads a score, blocking the calling thread until the answer.
+    ///
+    /// # Panics
+    ///
+    /// When called from inside a runtime.
+    pub fn load_score(&self, id: u32) -> Result<u32, BridgeError> {
+        let (reply, answer) = oneshot::channel();
+        self.requests
+            .blocking_send(Request { id, reply })
+            .map_err(|_| BridgeError::Stopped)?;
+        answer
+            .blocking_recv()
+            .map_err(|_| BridgeError::Stopped)?
+            .map_err(BridgeError::Load)
+    }
+}
+
+/// Serves each request in its own task, so slow requests do not hold up
+/// the others.
+async fn serve(mut requests: mpsc::Receiver<Request>, latency: LatencySimulator) {
+    while let Some(Request { id, reply }) = requests.recv().await {
+        let latency = latency.clone();
+        tokio::spawn(async move {
+            // The caller may have given up; nothing to do then.
+            let _ = reply.send(load_score_with(id, &latency).await);
+        });
+    }
+}
+
+/// Receives a value sent from a plain thread on the hand-rolled `oneshot`
+/// channel, with the `futures` executor: no tokio runtime involved. The
+/// thread sends it after 10ms, scaled by `latency`.
+pub fn receive_with_futures_executor(
+    value: u32,
+    latency: &LatencySimulator,
+) -> Result<u32, crate::oneshot::RecvError> {
+    let (tx, rx) = crate::oneshot::channel();
+    let delay = latency.delay(Duration::from_millis(10));
+    thread::spawn(move || {
+        thread::sleep(delay);
+        let _ = tx.send(value);
+    });
+    futures::executor::block_on(rx)
+}
+
+/// Example: sync code calling async code three ways
+///
+/// Synchronous; call it from `spawn_blocking` when inside an async context.
+#[instrument(skip_all)]
+pub fn sync_bridge_example(ctx: &ExampleContext) -> io::Result<Vec<Result<u32, BridgeError>>> {
+    let score = load_score_blocking_with(3, ctx.latency())?;
+    info!(?score, "block_on on a runtime built for the call");
+
+    let received = receive_with_futures_executor(42, ctx.latency());
+    info!(
+        ?received,
+        "futures::executor::block_on on a runtime-agnostic future"
+    );
+
+    let bridge = AsyncBridge::start_with(ctx.latency().clone())?;
+    let scores: Vec<_> = thread::scope(|scope| {
+        let calls: Vec<_> = [1, 2, MISSING_ID, 4]
+            .into_iter()
+            .map(|id| {
+                let bridge = &bridge;
+                scope.spawn(move || bridge.load_score(id))
+            })
+            .collect();
+        calls
+            .into_iter()
+            .map(|call| call.join().expect("caller thread panicked"))
+            .collect()
+    });
+    info!(?scores, "sync threads calling through the bridge");
+    Ok(scores)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_block_on_from_sync_code() {
+        assert_eq!(load_score_blocking(5).unwrap(), Ok(50));
+        assert_eq!(
+            load_score_blocking(MISSING_ID).unwrap(),
+            Err(LoadError::NotFound(MISSING_ID))
+        );
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "Cannot start a runtime from within a runtime")]
+    async fn test_block_on_inside_async_context_panics() {
+        let _ = load_score_blocking(5);
+    }
+
+    #[tokio::test]
+    async fn test_spawn_blocking_is_the_way_from_async() {
+        let score = tokio::task::spawn_blocking(|| load_score_blocking(5))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(score, Ok(50));
+    }
+
+    #[test]
+    fn test_bridge_calls() {
+        let bridge = AsyncBridge::start().unwrap();
+        assert_eq!(bridge.load_score(4), Ok(40));
+        assert_eq!(
+            bridge.load_score(MISSING_ID),
+            Err(BridgeError::Load(LoadError::NotFound(MISSING_ID)))
+        );
+    }
+
+    #[test]
+    fn test_bridge_serves_threads_concurrently() {
+        let bridge = AsyncBridge::start().unwrap();
+        let start = Instant::now();
+        let scores: Vec<_> = thread::scope(|scope| {
+            let calls: Vec<_> = (0..8)
+                .map(|_| scope.spawn(|| bridge.load_score(40)))
+                .collect();
+            calls.into_iter().map(|call| call.join().unwrap()).collect()
+        });
+
+        assert_eq!(scores, vec![Ok(400); 8]);
+        // 8 calls of 40ms each, served concurrently.
+        assert!(start.elapsed() < Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "Cannot block the current thread from within a runtime")]
+    async fn test_bridge_call_inside_async_context_panics() {
+        let bridge = tokio::task::spawn_blocking(AsyncBridge::start)
+            .await
+            .unwrap()
+            .unwrap();
+        let _ = bridge.load_score(1);
+    }
+
+    #[test]
+    fn test_futures_executor_drives_runtime_agnostic_futures() {
+        assert_eq!(
+            receive_with_futures_executor(7, &LatencySimulator::default()),
+            Ok(7)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "there is no reactor running")]
+    fn test_futures_executor_cannot_drive_tokio_timers() {
+        futures::executor::block_on(tokio::time::sleep(Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn test_sync_bridge_example() {
+        let scores = sync_bridge_example(&ExampleContext::captured()).unwrap();
+        assert_eq!(
+            scores,
+            vec![
+                Ok(10),
+                Ok(20),
+                Err(BridgeError::Load(LoadError::NotFound(MISSING_ID))),
+                Ok(40)
+            ]
+        );
+    }
+}
@@ -0,0 +1,282 @@
+//! `Notify`, `Barrier` and `OnceCell`
+//!
+//! Channels move data between tasks; these primitives only coordinate them:
+//! - `Notify` wakes tasks waiting for an event. [`ReadyFlag`] pairs it with
+//!   a flag, the usual pattern: register interest with `enable()` *before*
+//!   checking the flag, or a notification sent in between is lost.
+//!   `notify_one` stores a permit when nobody waits yet, `notify_waiters`
+//!   only wakes the tasks already waiting.
+//! - `Barrier` holds N tasks until all of them reached it: [`start_together`]
+//!   releases workers with staggered setups at the same instant.
+//! - `OnceCell` runs an async initialization once, however many tasks ask
+//!   concurrently: `fetch_data_from_api` gets its reqwest client from a
+//!   `static` cell (`crate::api_client`), built by the first call and shared
+//!   by all the others. A failed initialization leaves the cell empty, and
+//!   the next caller retries.
+//!
+//! A reqwest client's pooled connections belong to the runtime that opened
+//! them, so the `static` client keeps no idle connections: it outlives the
+//! runtime of each test. [`SharedClient`] is the same cell as an ordinary
+//! value, with a simulated setup, for clients used within one runtime.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{Barrier, Notify, OnceCell};
+use tokio::time::{sleep, Instant};
+use tracing::{debug, info, instrument};
+
+use crate::example_context::ExampleContext;
+
+/// A flag tasks can wait on.
+#[derive(Debug, Default)]
+pub struct ReadyFlag {
+    ready: AtomicBool,
+    notify: Notify,
+}
+
+impl ReadyFlag {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the flag and wakes every task waiting on it.
+    pub fn set(&self) {
+        self.ready.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    /// Waits until the flag is set; returns right away if it already is.
+    pub async fn wait(&self) {
+        loop {
+            let mut notified = std::pin::pin!(self.notify.notified());
+            // Registered from here on: a `set` after the check still wakes us.
+            notified.as_mut().enable();
+            if self.is_set() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Runs `workers` tasks whose setup takes `setup * index`, released together
+/// by a barrier. Returns the instant each one started, and how many were
+/// told they are the leader (always one).
+pub async fn start_together(workers: usize, setup: Duration) -> (Vec<Instant>, usize) {
+    let barrier = Arc::new(Barrier::new(workers));
+    let tasks: Vec<_> = (0..workers)
+        .map(|index| {
+            let barrier = Arc::clone(&barrier);
+            tokio::spawn(async move {
+                sleep(setup * index as u32).await;
+                debug!(index, "ready, waiting for the others");
+                let wait = barrier.wait().await;
+                (Instant::now(), wait.is_leader())
+            })
+        })
+        .collect();
+
+    let mut starts = Vec::new();
+    let mut leaders = 0;
+    for task in tasks {
+        let (start, leader) = task.await.expect("worker panicked");
+        starts.push(start);
+        leaders += usize::from(leader);
+    }
+    (starts, leaders)
+}
+
+/// A reqwest client built on first use and then shared, like the one of
+/// `fetch_data_from_api`. Clones share the client.
+#[derive(Debug, Clone, Default)]
+pub struct SharedClient {
+    client: Arc<OnceCell<reqwest::Client>>,
+    initializations: Arc<AtomicUsize>,
+    /// Simulated asynchronous setup, like loading configuration.
+    setup: Duration,
+}
+
+impl SharedClient {
+    pub fn new(setup: Duration) -> Self {
+        SharedClient {
+            setup,
+            ..Self::default()
+        }
+    }
+
+    /// The client, built by the first caller. Concurrent callers wait for
+    /// that single initialization.
+    pub async fn get(&self) -> Result<&reqwest::Client, reqwest::Error> {
+        self.client
+            .get_or_try_init(|| async {
+                sleep(self.setup).await;
+                self.initializations.fetch_add(1, Ordering::SeqCst);
+                debug!("building the shared client");
+                reqwest::Client::builder()
+                    .timeout(Duration::from_secs(10))
+                    .build()
+            })
+            .await
+    }
+
+    /// Initializations run so far: one, however many callers.
+    pub fn initializations(&self) -> usize {
+        self.initializations.load(Ordering::SeqCst)
+    }
+}
+
+/// Example: an event waking waiters, a synchronized start, a shared client,
+/// with the delays simulated by the latencies of `ctx`
+#[instrument(skip_all)]
+pub async fn sync_primitives_example(ctx: &ExampleContext) -> Result<usize, reqwest::Error> {
+    let delay = |ms| ctx.latency().delay(Duration::from_millis(ms));
+    let flag = Arc::new(ReadyFlag::new());
+    let waiters: Vec<_> = (0..3)
+        .map(|_| {
+            let flag = Arc::clone(&flag);
+            tokio::spawn(async move { flag.wait().await })
+        })
+        .collect();
+    ctx.latency().sleep(Duration::from_millis(10)).await;
+    flag.set();
+    for waiter in waiters {
+        waiter.await.expect("waiter panicked");
+    }
+    info!("three waiters woken by one event");
+
+    let (starts, leaders) = start_together(4, delay(10)).await;
+    info!(
+        spread = ?starts.iter().max().unwrap().duration_since(*starts.iter().min().unwrap()),
+        leaders,
+        "four workers started together"
+    );
+
+    let shared = SharedClient::new(delay(20));
+    let clients = futures::future::join_all((0..10).map(|_| shared.get())).await;
+    for client in clients {
+        client?;
+    }
+    info!(
+        initializations = shared.initializations(),
+        "ten callers, one client"
+    );
+    Ok(shared.initializations())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ready_flag_wakes_every_waiter() {
+        let flag = Arc::new(ReadyFlag::new());
+        let waiters: Vec<_> = (0..5)
+            .map(|_| {
+                let flag = Arc::clone(&flag);
+                tokio::spawn(async move { flag.wait().await })
+            })
+            .collect();
+        sleep(Duration::from_millis(10)).await;
+        assert!(waiters.iter().all(|waiter| !waiter.is_finished()));
+
+        flag.set();
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ready_flag_already_set() {
+        let flag = ReadyFlag::new();
+        flag.set();
+        flag.wait().await;
+    }
+
+    #[tokio::test]
+    async fn test_notify_one_stores_a_permit_notify_waiters_does_not() {
+        let notify = Notify::new();
+        notify.notify_one();
+        // The stored permit completes the next `notified()` right away.
+        notify.notified().await;
+
+        notify.notify_waiters();
+        let mut notified = std::pin::pin!(notify.notified());
+        assert!(
+            futures::poll!(notified.as_mut()).is_pending(),
+            "nobody was waiting: the notification is lost"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_barrier_releases_everyone_together() {
+        let begin = Instant::now();
+        let (starts, leaders) = start_together(5, Duration::from_millis(10)).await;
+
+        assert_eq!(starts.len(), 5);
+        // The slowest setup takes 40ms; nobody starts before.
+        assert!(starts
+            .iter()
+            .all(|start| start.duration_since(begin) == Duration::from_millis(40)));
+        assert_eq!(leaders, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shared_client_initialized_once_under_concurrency() {
+        let shared = SharedClient::new(Duration::from_millis(20));
+        let start = Instant::now();
+        let clients: Vec<_> = futures::future::join_all((0..50).map(|_| {
+            let shared = shared.clone();
+            async move { shared.get().await.map(|client| client as *const _ as usize) }
+        }))
+        .await;
+
+        assert_eq!(shared.initializations(), 1);
+        assert_eq!(start.elapsed(), Duration::from_millis(20));
+        let first = clients[0].as_ref().copied().unwrap();
+        assert!(clients
+            .iter()
+            .all(|client| *client.as_ref().unwrap() == first));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_shared_client_initialized_once_across_threads() {
+        let shared = SharedClient::new(Duration::from_millis(5));
+        let tasks: Vec<_> = (0..32)
+            .map(|_| {
+                let shared = shared.clone();
+                tokio::spawn(async move { shared.get().await.is_ok() })
+            })
+            .collect();
+        for task in tasks {
+            assert!(task.await.unwrap());
+        }
+        assert_eq!(shared.initializations(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_initialization_is_retried() {
+        let cell: OnceCell<u32> = OnceCell::new();
+        let failed = cell.get_or_try_init(|| async { Err("not yet") }).await;
+        assert_eq!(failed, Err("not yet"));
+        assert!(!cell.initialized());
+
+        let value = cell.get_or_try_init(|| async { Ok::<_, &str>(7) }).await;
+        assert_eq!(value, Ok(&7));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sync_primitives_example() {
+        assert_eq!(
+            sync_primitives_example(&ExampleContext::captured())
+                .await
+                .unwrap(),
+            1
+        );
+    }
+}
@@ -0,0 +1,472 @@
+//! Named tasks and a registry of the live ones (`cargo run -- tasks`)
+//!
+//! `tokio::spawn` returns a handle and forgets the task: nothing lists the
+//! tasks alive at a given time, nor says what they are doing. [`spawn_named`]
+//! wraps the future before spawning it, so that a [`TaskRegistry`] knows:
+//! - the task's name and when it was spawned
+//! - its state: not polled yet, running (inside `poll`), or idle (suspended
+//!   at an await point, waiting for a wake)
+//! - how many times it was polled
+//!
+//! The entry is removed by a guard owned by the wrapped future. The future
+//! is dropped however the task ends (completion, `abort`, or panic), so the
+//! registry never lists a dead task.
+//!
+//! [`spawn_named`] records into the process-wide [`TaskRegistry::global`].
+//! The crate's long-lived tasks go through it: the pipeline stages, the
+//! periodic scheduler, and the HTTP server with its connections, which the
+//! `tasks` subcommand lists while they run. Tests use their own registry
+//! with [`TaskRegistry::spawn_named`].
+//! tokio-console shows the same information, from the runtime's own
+//! instrumentation; this is the hand-made version.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::{poll_fn, Future};
+use std::pin::pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Instant};
+use tracing::{info, instrument};
+
+use crate::example_context::{ExampleContext, LatencySimulator};
+use crate::pipeline;
+use crate::scheduler::schedule_periodic;
+
+/// What a registered task is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    /// Spawned, not polled yet.
+    Scheduled,
+    /// Inside `poll`.
+    Running,
+    /// Suspended at an await point.
+    Idle,
+}
+
+impl fmt::Display for TaskState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TaskState::Scheduled => "scheduled",
+            TaskState::Running => "running",
+            TaskState::Idle => "idle",
+        })
+    }
+}
+
+/// A live task, as seen by the registry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskInfo {
+    /// Unique within a registry, in spawn order.
+    pub id: u64,
+    pub name: String,
+    pub spawned_at: Instant,
+    pub state: TaskState,
+    pub polls: u64,
+}
+
+impl TaskInfo {
+    /// Time since the task was spawned.
+    pub fn age(&self) -> Duration {
+        self.spawned_at.elapsed()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    next_id: AtomicU64,
+    tasks: Mutex<BTreeMap<u64, TaskInfo>>,
+}
+
+/// Live named tasks. Clones share the registry.
+#[derive(Debug, Clone, Default)]
+pub struct TaskRegistry {
+    inner: Arc<Inner>,
+}
+
+/// Removes its task from the registry when dropped.
+struct Registration {
+    registry: TaskRegistry,
+    id: u64,
+}
+
+impl Registration {
+    fn set_state(&self, state: TaskState) {
+        if let Some(task) = self.registry.inner.tasks.lock().unwrap().get_mut(&self.id) {
+            task.state = state;
+            if state == TaskState::Running {
+                task.polls += 1;
+            }
+        }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.registry.inner.tasks.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl TaskRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The registry of [`spawn_named`].
+    pub fn global() -> &'static TaskRegistry {
+        static GLOBAL: OnceLock<TaskRegistry> = OnceLock::new();
+        GLOBAL.get_or_init(TaskRegistry::new)
+    }
+
+    /// Spawns `future` as a task named `name`, listed in this registry until
+    /// it ends.
+    ///
+    /// # Panics
+    ///
+    /// Outside a tokio runtime, like `tokio::spawn`.
+    pub fn spawn_named<F>(&self, name: impl Into<String>, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        self.inner.tasks.lock().unwrap().insert(
+            id,
+            TaskInfo {
+                id,
+                name: name.into(),
+                spawned_at: Instant::now(),
+                state: TaskState::Scheduled,
+                polls: 0,
+            },
+        );
+        let registration = Registration {
+            registry: self.clone(),
+            id,
+        };
+
+        tokio::spawn(async move {
+            let mut future = pin!(future);
+            let output = poll_fn(|cx| {
+                registration.set_state(TaskState::Running);
+                let poll = future.as_mut().poll(cx);
+                if poll.is_pending() {
+                    registration.set_state(TaskState::Idle);
+                }
+                poll
+            })
+            .await;
+            // Deregistered here on completion; on abort or panic, when the
+            // runtime drops the future.
+            drop(registration);
+            output
+        })
+    }
+
+    /// The live tasks, in spawn order.
+    pub fn snapshot(&self) -> Vec<TaskInfo> {
+        self.inner.tasks.lock().unwrap().values().cloned().collect()
+    }
+
+    /// The live tasks named `name`.
+    pub fn named(&self, name: &str) -> Vec<TaskInfo> {
+        self.snapshot()
+            .into_iter()
+            .filter(|task| task.name == name)
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.tasks.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Spawns `future` as a task named `name`, listed in the global registry
+/// until it ends.
+pub fn spawn_named<F>(name: impl Into<String>, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    TaskRegistry::global().spawn_named(name, future)
+}
+
+/// The tasks as a table, one line per task.
+pub fn render(tasks: &[TaskInfo]) -> String {
+    let mut table = format!(
+        "{:>4}  {:<20} {:<10} {:>6} {:>10}",
+        "ID", "NAME", "STATE", "POLLS", "AGE"
+    );
+    for task in tasks {
+        table.push_str(&format!(
+            "\n{:>4}  {:<20} {:<10} {:>6} {:>10}",
+            task.id,
+            task.name,
+            task.state.to_string(),
+            task.polls,
+            format!("{}ms", task.age().as_millis())
+        ));
+    }
+    table
+}
+
+/// Spawns a ticker, a sleeper and a stuck task in `registry`, and calls
+/// `show` with the live tasks as they end; the stuck one is aborted.
+async fn demo(
+    registry: &TaskRegistry,
+    latency: &LatencySimulator,
+    mut show: impl FnMut(&'static str, Vec<TaskInfo>),
+) {
+    let ticker = registry.spawn_named("ticker", {
+        let latency = latency.clone();
+        async move {
+            for _ in 0..5 {
+                latency.sleep(Duration::from_millis(10)).await;
+            }
+        }
+    });
+    let sleeper = registry.spawn_named("sleeper", sleep(latency.delay(Duration::from_millis(100))));
+    let stuck = registry.spawn_named("stuck", std::future::pending::<()>());
+
+    latency.sleep(Duration::from_millis(20)).await;
+    show("after 20ms", registry.snapshot());
+
+    ticker.await.expect("ticker panicked");
+    show("ticker done", registry.snapshot());
+
+    stuck.abort();
+    let _ = stuck.await;
+    show("stuck aborted", registry.snapshot());
+
+    sleeper.await.expect("sleeper panicked");
+    show("sleeper done", registry.snapshot());
+}
+
+/// Runs the crate's own long-lived tasks, and calls `show` with the live
+/// tasks of the global registry as they end: a pipeline, a periodic task,
+/// and, with the HTTP server built, the server handling slow requests.
+async fn crate_tasks(mut show: impl FnMut(&'static str, Vec<TaskInfo>)) {
+    let registry = TaskRegistry::global();
+    #[cfg(any(test, feature = "http-server"))]
+    let (server, requests) = {
+        let server =
+            crate::http_server::HttpServer::start_local().expect("failed to start the HTTP server");
+        // Building a client blocks for a while: before the other tasks start.
+        let client = reqwest::Client::new();
+        let url = server.url("/delay/150");
+        let requests: Vec<_> = (0..3)
+            .map(|_| spawn_named("http request", client.get(&url).send()))
+            .collect();
+        (server, requests)
+    };
+    let periodic = schedule_periodic(Duration::from_millis(20), || {
+        sleep(Duration::from_millis(5))
+    });
+    let pipeline = spawn_named("pipeline", pipeline::run_pipeline((0..20).collect()));
+
+    sleep(Duration::from_millis(50)).await;
+    show("after 50ms", registry.snapshot());
+
+    let _ = pipeline.await;
+    show("pipeline done", registry.snapshot());
+
+    #[cfg(any(test, feature = "http-server"))]
+    {
+        for request in requests {
+            let _ = request.await;
+        }
+        let _ = server.shutdown().await;
+        show("server shut down", registry.snapshot());
+    }
+
+    periodic.stop().await;
+    show("periodic stopped", registry.snapshot());
+}
+
+/// Runs the `tasks` subcommand: the crate's own tasks, listed from the
+/// global registry at each step.
+pub async fn run_cli() {
+    crate_tasks(|step, tasks| {
+        println!("{}:\n{}\n", step, render(&tasks));
+    })
+    .await;
+}
+
+/// Example: named tasks appearing in and leaving a registry
+///
+/// Returns the number of live tasks at each step.
+#[instrument(skip_all)]
+pub async fn task_registry_example(ctx: &ExampleContext) -> Vec<(&'static str, usize)> {
+    let registry = TaskRegistry::new();
+    let mut counts = Vec::new();
+    demo(&registry, ctx.latency(), |step, tasks| {
+        info!("{}:\n{}", step, render(&tasks));
+        counts.push((step, tasks.len()));
+    })
+    .await;
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::oneshot;
+
+    fn names(tasks: &[TaskInfo]) -> Vec<&str> {
+        tasks.iter().map(|task| task.name.as_str()).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_registered_until_completion() {
+        let registry = TaskRegistry::new();
+        let (tx, rx) = oneshot::channel::<u32>();
+        let handle = registry.spawn_named("waiter", async move { rx.await.unwrap() + 1 });
+
+        let tasks = registry.snapshot();
+        assert_eq!(names(&tasks), ["waiter"]);
+        assert_eq!(tasks[0].state, TaskState::Scheduled);
+        assert_eq!(tasks[0].polls, 0);
+
+        tokio::task::yield_now().await;
+        let task = &registry.snapshot()[0];
+        assert_eq!((task.state, task.polls), (TaskState::Idle, 1));
+
+        tx.send(41).unwrap();
+        assert_eq!(handle.await.unwrap(), 42);
+        assert!(registry.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_deregistered_on_abort() {
+        let registry = TaskRegistry::new();
+        let stuck = registry.spawn_named("stuck", std::future::pending::<()>());
+        let other = registry.spawn_named("other", sleep(Duration::from_secs(1)));
+        tokio::task::yield_now().await;
+        assert_eq!(registry.len(), 2);
+
+        stuck.abort();
+        assert!(stuck.await.unwrap_err().is_cancelled());
+        assert_eq!(names(&registry.snapshot()), ["other"]);
+
+        other.await.unwrap();
+        assert!(registry.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_deregistered_on_panic() {
+        let registry = TaskRegistry::new();
+        let handle = registry.spawn_named("panicking", async {
+            sleep(Duration::from_millis(10)).await;
+            panic!("boom");
+        });
+        assert!(handle.await.unwrap_err().is_panic());
+        assert!(registry.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ids_ages_and_polls() {
+        let registry = TaskRegistry::new();
+        let first = registry.spawn_named("ticker", async {
+            for _ in 0..3 {
+                sleep(Duration::from_millis(10)).await;
+            }
+        });
+        sleep(Duration::from_millis(5)).await;
+        let _second = registry.spawn_named("ticker", std::future::pending::<()>());
+
+        sleep(Duration::from_millis(20)).await;
+        let tasks = registry.named("ticker");
+        assert_eq!(tasks.iter().map(|task| task.id).collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(tasks[0].age(), Duration::from_millis(25));
+        assert_eq!(tasks[1].age(), Duration::from_millis(20));
+        // The first poll, then one per elapsed sleep.
+        assert_eq!(tasks[0].polls, 3);
+        assert_eq!(tasks[1].polls, 1);
+
+        first.await.unwrap();
+        assert_eq!(registry.named("ticker").len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_global_spawn_named() {
+        let name = "global-registry-test";
+        let handle = spawn_named(name, sleep(Duration::from_millis(10)));
+        assert_eq!(TaskRegistry::global().named(name).len(), 1);
+        handle.await.unwrap();
+        assert!(TaskRegistry::global().named(name).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_render() {
+        let registry = TaskRegistry::new();
+        let _handle = registry.spawn_named("worker", std::future::pending::<()>());
+        sleep(Duration::from_millis(30)).await;
+
+        let table = render(&registry.snapshot());
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("NAME") && lines[0].contains("STATE"));
+        assert!(lines[1].contains("worker") && lines[1].contains("idle"));
+        assert!(lines[1].ends_with("30ms"));
+    }
+
+    #[tokio::test]
+    async fn test_crate_tasks_are_listed() {
+        let mut steps = Vec::new();
+        crate_tasks(|step, tasks| {
+            let mut names: Vec<String> = tasks.into_iter().map(|task| task.name).collect();
+            names.sort();
+            names.dedup();
+            steps.push((step, names));
+        })
+        .await;
+
+        // Other tests run concurrently: only check for the tasks started here.
+        let (step, running) = &steps[0];
+        assert_eq!(*step, "after 50ms");
+        for name in [
+            "periodic",
+            "pipeline",
+            "pipeline transformer",
+            "pipeline sink",
+            "http server",
+            "http connection",
+            "http request",
+        ] {
+            assert!(
+                running.iter().any(|task| task == name),
+                "{} not listed",
+                name
+            );
+        }
+        assert_eq!(
+            steps.iter().map(|(step, _)| *step).collect::<Vec<_>>(),
+            [
+                "after 50ms",
+                "pipeline done",
+                "server shut down",
+                "periodic stopped"
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_task_registry_example() {
+        assert_eq!(
+            task_registry_example(&ExampleContext::captured()).await,
+            [
+                ("after 20ms", 3),
+                ("ticker done", 2),
+                ("stuck aborted", 1),
+                ("sleeper done", 0),
+            ]
+        );
+    }
+}
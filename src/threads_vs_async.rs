@@ -0,0 +1,334 @@
+//! Why async: the same workload on threads and on tasks
+//!
+//! The workload: fetch N items, each behind a slow blocking call, and
+//! process each one (a checksum). Two models implement it behind the
+//! [`FetchModel`] trait:
+//! - [`ThreadPerRequest`]: one OS thread per request, blocking for the whole
+//!   fetch. Each thread reserves a full stack ([`THREAD_STACK_SIZE`]) and is
+//!   a kernel object the scheduler has to switch to and from.
+//! - [`AsyncTasks`]: one tokio task per request on a runtime with a couple
+//!   of worker threads. A task waiting on I/O is its future's state machine
+//!   parked in memory, a few hundred bytes, and costs no thread.
+//!
+//! Both finish in about one fetch latency: for waiting, threads and tasks are
+//! equally concurrent. What differs is the cost of each unit of concurrency,
+//! which the report shows: OS threads alive at peak, memory per in-flight
+//! request (stack reservation vs future size), and resident memory growth.
+//! Thread counts and resident memory come from `/proc/self/status`, so they
+//! are only reported on Linux; they are process-wide, hence noisy when
+//! something else runs in the process (the test harness, for instance).
+
+use std::fmt;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tokio::runtime::Builder;
+use tracing::{info, instrument};
+
+use crate::example_context::ExampleContext;
+
+/// Stack size of the threads of [`ThreadPerRequest`]: Rust's default for
+/// spawned threads.
+pub const THREAD_STACK_SIZE: usize = 2 * 1024 * 1024;
+
+/// Size of the payload of a fetched item.
+const PAYLOAD_SIZE: usize = 1024;
+
+/// The payload of item `id`.
+fn payload(id: u64) -> Vec<u8> {
+    (0..PAYLOAD_SIZE).map(|i| (id as usize + i) as u8).collect()
+}
+
+/// The processing step: a checksum of the payload.
+fn checksum(payload: &[u8]) -> u64 {
+    payload.iter().map(|&byte| u64::from(byte)).sum()
+}
+
+/// A field of `/proc/self/status`, in its own unit (`Threads` is a count,
+/// `VmRSS` is in kB).
+fn status_field(status: &str, field: &str) -> Option<u64> {
+    status.lines().find_map(|line| {
+        let value = line.strip_prefix(field)?.strip_prefix(':')?;
+        value.split_whitespace().next()?.parse().ok()
+    })
+}
+
+/// Reads a field of this process's `/proc/self/status`; `None` off Linux.
+fn process_status(field: &str) -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status_field(&status, field)
+}
+
+/// Resident memory and thread count of the process at one instant.
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    threads: Option<u64>,
+    rss_kib: Option<u64>,
+}
+
+impl Sample {
+    fn now() -> Self {
+        Sample {
+            threads: process_status("Threads"),
+            rss_kib: process_status("VmRSS"),
+        }
+    }
+}
+
+/// What a run of a [`FetchModel`] measured.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelRun {
+    pub model: &'static str,
+    pub requests: usize,
+    /// Sum of the checksums, the same for every model.
+    pub checksum: u64,
+    pub elapsed: Duration,
+    /// OS threads the model started.
+    pub threads_started: usize,
+    /// OS threads of the process while every request was in flight.
+    pub peak_threads: Option<u64>,
+    /// Memory set aside for each request in flight: a thread's stack, or a
+    /// task's future.
+    pub bytes_per_request: usize,
+    /// Growth of the resident memory between the start of the run and the
+    /// peak, in KiB.
+    pub rss_growth_kib: Option<u64>,
+}
+
+impl fmt::Display for ModelRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let known = |value: Option<u64>| value.map_or("?".to_string(), |value| value.to_string());
+        write!(
+            f,
+            "{}: {} requests in {:?}, {} thread(s) started, {} alive at peak, \
+             {} bytes per request, +{} KiB resident",
+            self.model,
+            self.requests,
+            self.elapsed,
+            self.threads_started,
+            known(self.peak_threads),
+            self.bytes_per_request,
+            known(self.rss_growth_kib)
+        )
+    }
+}
+
+/// A way to run the fetch/process workload concurrently.
+pub trait FetchModel {
+    fn name(&self) -> &'static str;
+
+    /// Fetches `requests` items, each fetch taking `latency`, processes them,
+    /// and measures the run. Blocks until done.
+    fn run(&self, requests: usize, latency: Duration) -> io::Result<ModelRun>;
+}
+
+/// One OS thread per request, with blocking fetches.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadPerRequest;
+
+/// A blocking fetch: the thread sleeps for the latency.
+fn fetch_blocking(id: u64, latency: Duration) -> Vec<u8> {
+    thread::sleep(latency);
+    payload(id)
+}
+
+impl FetchModel for ThreadPerRequest {
+    fn name(&self) -> &'static str {
+        "threads"
+    }
+
+    fn run(&self, requests: usize, latency: Duration) -> io::Result<ModelRun> {
+        let before = Sample::now();
+        let start = Instant::now();
+
+        let handles = (0..requests as u64)
+            .map(|id| {
+                thread::Builder::new()
+                    .name(format!("fetch-{}", id))
+                    .stack_size(THREAD_STACK_SIZE)
+                    .spawn(move || checksum(&fetch_blocking(id, latency)))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        // Every thread is blocked in its fetch by now.
+        let peak = Sample::now();
+
+        let mut total = 0;
+        for handle in handles {
+            total += handle
+                .join()
+                .map_err(|_| io::Error::other("fetch thread panicked"))?;
+        }
+
+        Ok(ModelRun {
+            model: self.name(),
+            requests,
+            checksum: total,
+            elapsed: start.elapsed(),
+            threads_started: requests,
+            peak_threads: peak.threads,
+            bytes_per_request: THREAD_STACK_SIZE,
+            rss_growth_kib: rss_growth(before, peak),
+        })
+    }
+}
+
+/// One tokio task per request, on a runtime with `worker_threads` workers.
+#[derive(Debug, Clone, Copy)]
+pub struct AsyncTasks {
+    pub worker_threads: usize,
+}
+
+impl Default for AsyncTasks {
+    fn default() -> Self {
+        AsyncTasks { worker_threads: 2 }
+    }
+}
+
+/// A non-blocking fetch: the task is suspended for the latency.
+async fn fetch_async(id: u64, latency: Duration) -> Vec<u8> {
+    tokio::time::sleep(latency).await;
+    payload(id)
+}
+
+impl FetchModel for AsyncTasks {
+    fn name(&self) -> &'static str {
+        "async"
+    }
+
+    fn run(&self, requests: usize, latency: Duration) -> io::Result<ModelRun> {
+        let before = Sample::now();
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(self.worker_threads)
+            .thread_name("async-worker")
+            .enable_time()
+            .build()?;
+        let start = Instant::now();
+
+        let request = |id| async move { checksum(&fetch_async(id, latency).await) };
+        let bytes_per_request = std::mem::size_of_val(&request(0));
+
+        let (total, peak) = runtime.block_on(async {
+            let handles: Vec<_> = (0..requests as u64)
+                .map(|id| tokio::spawn(request(id)))
+                .collect();
+            // The tasks are suspended in their fetches by now.
+            tokio::task::yield_now().await;
+            let peak = Sample::now();
+
+            let mut total = 0;
+            for handle in handles {
+                total += handle
+                    .await
+                    .map_err(|_| io::Error::other("fetch task panicked"))?;
+            }
+            Ok::<_, io::Error>((total, peak))
+        })?;
+
+        Ok(ModelRun {
+            model: self.name(),
+            requests,
+            checksum: total,
+            elapsed: start.elapsed(),
+            threads_started: self.worker_threads,
+            peak_threads: peak.threads,
+            bytes_per_request,
+            rss_growth_kib: rss_growth(before, peak),
+        })
+    }
+}
+
+fn rss_growth(before: Sample, peak: Sample) -> Option<u64> {
+    Some(peak.rss_kib?.saturating_sub(before.rss_kib?))
+}
+
+/// Example: 500 requests of 50ms each, on threads and on tasks
+///
+/// Synchronous: the async model builds its own runtime.
+#[instrument(skip_all)]
+pub fn threads_vs_async_example(ctx: &ExampleContext) -> io::Result<[ModelRun; 2]> {
+    let (requests, latency) = (500, ctx.latency().delay(Duration::from_millis(50)));
+    let models: [&dyn FetchModel; 2] = [&ThreadPerRequest, &AsyncTasks::default()];
+
+    let mut runs = Vec::new();
+    for model in models {
+        let run = model.run(requests, latency)?;
+        info!("{}", run);
+        runs.push(run);
+    }
+    Ok(runs.try_into().expect("two models"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REQUESTS: usize = 64;
+    const LATENCY: Duration = Duration::from_millis(50);
+
+    fn expected_checksum() -> u64 {
+        (0..REQUESTS as u64).map(|id| checksum(&payload(id))).sum()
+    }
+
+    #[test]
+    fn test_both_models_compute_the_same_result_concurrently() {
+        let models: [&dyn FetchModel; 2] = [&ThreadPerRequest, &AsyncTasks::default()];
+        for model in models {
+            let run = model.run(REQUESTS, LATENCY).unwrap();
+            assert_eq!(run.model, model.name());
+            assert_eq!(run.checksum, expected_checksum());
+            // Sequential fetches would take 64 latencies.
+            assert!(
+                run.elapsed >= LATENCY && run.elapsed < LATENCY * 8,
+                "{}",
+                run
+            );
+        }
+    }
+
+    #[test]
+    fn test_cost_per_request() {
+        let threads = ThreadPerRequest.run(REQUESTS, LATENCY).unwrap();
+        let tasks = AsyncTasks { worker_threads: 1 }
+            .run(REQUESTS, LATENCY)
+            .unwrap();
+
+        assert_eq!(threads.threads_started, REQUESTS);
+        assert_eq!(tasks.threads_started, 1);
+        assert_eq!(threads.bytes_per_request, THREAD_STACK_SIZE);
+        assert!(tasks.bytes_per_request * 1000 < threads.bytes_per_request);
+        if cfg!(target_os = "linux") {
+            assert!(threads.peak_threads.unwrap() > REQUESTS as u64);
+            assert!(tasks.peak_threads.is_some());
+        }
+    }
+
+    #[test]
+    fn test_status_field_parsing() {
+        let status = "Name:\tcourse\nThreads:\t12\nVmRSS:\t   10240 kB\n";
+        assert_eq!(status_field(status, "Threads"), Some(12));
+        assert_eq!(status_field(status, "VmRSS"), Some(10240));
+        assert_eq!(status_field(status, "VmSwap"), None);
+        // A prefix of another field is not that field.
+        assert_eq!(status_field("VmRSSx: 1 kB", "VmRSS"), None);
+    }
+
+    #[test]
+    fn test_display() {
+        let run = ModelRun {
+            model: "async",
+            requests: 10,
+            checksum: 0,
+            elapsed: Duration::from_millis(50),
+            threads_started: 2,
+            peak_threads: None,
+            bytes_per_request: 128,
+            rss_growth_kib: Some(4),
+        };
+        assert_eq!(
+            run.to_string(),
+            "async: 10 requests in 50ms, 2 thread(s) started, ? alive at peak, \
+             128 bytes per request, +4 KiB resident"
+        );
+    }
+}
@@ -0,0 +1,427 @@
+//! Transaction scope as a higher-order async function
+//!
+//! `db.with_transaction(|tx| async move { ... })` begins a transaction,
+//! hands a handle to the closure, and awaits the future it returns:
+//! - `Ok` commits the transaction's writes, atomically
+//! - `Err` rolls them back
+//! - if the `with_transaction` future is dropped before the closure's future
+//!   completes (a `timeout`, a lost `select!` branch), a drop guard rolls
+//!   back: cancellation can never commit half a transaction
+//!
+//! The closure receives the [`Transaction`] by value (a cheap handle) rather
+//! than by reference: a closure cannot return a future borrowing its own
+//! argument, so `|tx: &Transaction| async move { ... }` does not compile.
+//! Writes are buffered in the transaction until commit; once it ended, any
+//! clone of the handle left behind gets [`TxError::Closed`].
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::{sleep, timeout};
+use tracing::{debug, info, instrument, warn};
+
+/// Simulated latency of each read and write.
+const IO_LATENCY: Duration = Duration::from_millis(1);
+
+/// Error of an operation on a [`Transaction`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxError {
+    /// The transaction already committed or rolled back.
+    Closed,
+}
+
+impl fmt::Display for TxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxError::Closed => f.write_str("transaction is closed"),
+        }
+    }
+}
+
+impl std::error::Error for TxError {}
+
+/// Transactions committed and rolled back so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TxStats {
+    pub committed: usize,
+    pub rolled_back: usize,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    data: Mutex<BTreeMap<String, i64>>,
+    stats: Mutex<TxStats>,
+    next_id: AtomicU64,
+}
+
+/// An in-memory key-value store with transactions.
+#[derive(Debug, Clone, Default)]
+pub struct Database {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Open,
+    Committed,
+    RolledBack,
+}
+
+#[derive(Debug)]
+struct TxState {
+    /// Pending writes; `None` deletes the key.
+    writes: BTreeMap<String, Option<i64>>,
+    status: Status,
+}
+
+/// Handle to an open transaction. Clones share the same transaction.
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    id: u64,
+    db: Database,
+    state: Arc<Mutex<TxState>>,
+}
+
+impl Transaction {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn open_state(&self) -> Result<std::sync::MutexGuard<'_, TxState>, TxError> {
+        let state = self.state.lock().unwrap();
+        if state.status != Status::Open {
+            return Err(TxError::Closed);
+        }
+        Ok(state)
+    }
+
+    /// Reads `key`, seeing this transaction's own pending writes.
+    pub async fn get(&self, key: &str) -> Result<Option<i64>, TxError> {
+        sleep(IO_LATENCY).await;
+        if let Some(pending) = self.open_state()?.writes.get(key) {
+            return Ok(*pending);
+        }
+        Ok(self.db.get(key))
+    }
+
+    pub async fn set(&self, key: &str, value: i64) -> Result<(), TxError> {
+        sleep(IO_LATENCY).await;
+        self.open_state()?
+            .writes
+            .insert(key.to_string(), Some(value));
+        Ok(())
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<(), TxError> {
+        sleep(IO_LATENCY).await;
+        self.open_state()?.writes.insert(key.to_string(), None);
+        Ok(())
+    }
+
+    /// Ends the transaction, applying its writes if `commit`. Does nothing
+    /// if it already ended.
+    fn finish(&self, commit: bool) {
+        let mut state = self.state.lock().unwrap();
+        if state.status != Status::Open {
+            return;
+        }
+        let writes = std::mem::take(&mut state.writes);
+        let mut stats = self.db.shared.stats.lock().unwrap();
+        if commit {
+            let mut data = self.db.shared.data.lock().unwrap();
+            for (key, value) in writes {
+                match value {
+                    Some(value) => data.insert(key, value),
+                    None => data.remove(&key),
+                };
+            }
+            state.status = Status::Committed;
+            stats.committed += 1;
+        } else {
+            state.status = Status::RolledBack;
+            stats.rolled_back += 1;
+        }
+        debug!(id = self.id, status = ?state.status, "transaction ended");
+    }
+}
+
+/// Rolls the transaction back when dropped before it ended.
+struct RollbackOnDrop(Transaction);
+
+impl Drop for RollbackOnDrop {
+    fn drop(&mut self) {
+        if self.0.state.lock().unwrap().status == Status::Open {
+            warn!(id = self.0.id, "transaction cancelled, rolling back");
+        }
+        self.0.finish(false);
+    }
+}
+
+impl Database {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a database holding `entries`.
+    pub fn with_entries<'a>(entries: impl IntoIterator<Item = (&'a str, i64)>) -> Self {
+        let db = Self::new();
+        db.shared.data.lock().unwrap().extend(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value)),
+        );
+        db
+    }
+
+    /// Committed value of `key`.
+    pub fn get(&self, key: &str) -> Option<i64> {
+        self.shared.data.lock().unwrap().get(key).copied()
+    }
+
+    /// Every committed entry.
+    pub fn snapshot(&self) -> BTreeMap<String, i64> {
+        self.shared.data.lock().unwrap().clone()
+    }
+
+    pub fn stats(&self) -> TxStats {
+        *self.shared.stats.lock().unwrap()
+    }
+
+    /// Runs `body` in a transaction: commits if it returns `Ok`, rolls back
+    /// if it returns `Err` or is cancelled.
+    pub async fn with_transaction<F, Fut, T, E>(&self, body: F) -> Result<T, E>
+    where
+        F: FnOnce(Transaction) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let tx = Transaction {
+            id: self.shared.next_id.fetch_add(1, Ordering::Relaxed),
+            db: self.clone(),
+            state: Arc::new(Mutex::new(TxState {
+                writes: BTreeMap::new(),
+                status: Status::Open,
+            })),
+        };
+        debug!(id = tx.id, "transaction started");
+        let guard = RollbackOnDrop(tx.clone());
+        let result = body(tx).await;
+        guard.0.finish(result.is_ok());
+        result
+    }
+}
+
+/// Error of a [`transfer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferError {
+    InsufficientFunds { account: String, balance: i64 },
+    Tx(TxError),
+}
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransferError::InsufficientFunds { account, balance } => {
+                write!(f, "insufficient funds in {}: balance {}", account, balance)
+            }
+            TransferError::Tx(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for TransferError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TransferError::InsufficientFunds { .. } => None,
+            TransferError::Tx(error) => Some(error),
+        }
+    }
+}
+
+impl From<TxError> for TransferError {
+    fn from(error: TxError) -> Self {
+        TransferError::Tx(error)
+    }
+}
+
+/// Moves `amount` from one account to another. Debits first, then checks
+/// the balance: an overdraft relies on the rollback.
+pub async fn transfer(
+    db: &Database,
+    from: &str,
+    to: &str,
+    amount: i64,
+) -> Result<(), TransferError> {
+    let (from, to) = (from.to_string(), to.to_string());
+    db.with_transaction(|tx| async move {
+        let balance = tx.get(&from).await?.unwrap_or(0) - amount;
+        tx.set(&from, balance).await?;
+        let credited = tx.get(&to).await?.unwrap_or(0) + amount;
+        tx.set(&to, credited).await?;
+        if balance < 0 {
+            return Err(TransferError::InsufficientFunds {
+                account: from,
+                balance: balance + amount,
+            });
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Example: a committed transfer, an overdraft and a cancelled transfer
+#[instrument]
+pub async fn transaction_example() -> (BTreeMap<String, i64>, TxStats) {
+    let db = Database::with_entries([("alice", 100), ("bob", 50)]);
+
+    let committed = transfer(&db, "alice", "bob", 30).await;
+    info!(?committed, balances = ?db.snapshot(), "transfer of 30");
+
+    let overdraft = transfer(&db, "bob", "alice", 500).await;
+    info!(?overdraft, balances = ?db.snapshot(), "transfer of 500 rolled back");
+
+    // Each transfer does 4 operations of 1ms: cancelled half-way.
+    let cancelled = timeout(Duration::from_millis(2), transfer(&db, "alice", "bob", 10)).await;
+    info!(
+        cancelled = cancelled.is_err(),
+        balances = ?db.snapshot(),
+        "transfer cancelled by a timeout"
+    );
+
+    (db.snapshot(), db.stats())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn balances(db: &Database) -> Vec<(String, i64)> {
+        db.snapshot().into_iter().collect()
+    }
+
+    fn accounts(alice: i64, bob: i64) -> Vec<(String, i64)> {
+        vec![("alice".into(), alice), ("bob".into(), bob)]
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_commit_on_ok() {
+        let db = Database::with_entries([("alice", 100), ("bob", 50)]);
+        transfer(&db, "alice", "bob", 30).await.unwrap();
+
+        assert_eq!(balances(&db), accounts(70, 80));
+        assert_eq!(
+            db.stats(),
+            TxStats {
+                committed: 1,
+                rolled_back: 0
+            }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rollback_on_error() {
+        let db = Database::with_entries([("alice", 100), ("bob", 50)]);
+        let error = transfer(&db, "bob", "alice", 500).await.unwrap_err();
+
+        assert_eq!(
+            error,
+            TransferError::InsufficientFunds {
+                account: "bob".into(),
+                balance: 50
+            }
+        );
+        assert_eq!(
+            balances(&db),
+            accounts(100, 50),
+            "the debit was rolled back"
+        );
+        assert_eq!(db.stats().rolled_back, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rollback_on_cancellation() {
+        let db = Database::with_entries([("alice", 100), ("bob", 50)]);
+        for cancel_after_ms in 0..4 {
+            let result = timeout(
+                Duration::from_millis(cancel_after_ms),
+                transfer(&db, "alice", "bob", 10),
+            )
+            .await;
+            assert!(result.is_err(), "cancelled after {}ms", cancel_after_ms);
+            assert_eq!(balances(&db), accounts(100, 50));
+        }
+        assert_eq!(
+            db.stats(),
+            TxStats {
+                committed: 0,
+                rolled_back: 4
+            }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reads_see_own_writes_only() {
+        let db = Database::with_entries([("a", 1)]);
+        let outside = db.clone();
+        db.with_transaction(|tx| async move {
+            tx.set("a", 2).await?;
+            tx.delete("b").await?;
+            assert_eq!(tx.get("a").await?, Some(2));
+            assert_eq!(outside.get("a"), Some(1), "not visible before commit");
+            Ok::<_, TxError>(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(db.get("a"), Some(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_delete_commits() {
+        let db = Database::with_entries([("a", 1), ("b", 2)]);
+        db.with_transaction(|tx| async move { tx.delete("a").await })
+            .await
+            .unwrap();
+        assert_eq!(db.snapshot(), BTreeMap::from([("b".to_string(), 2)]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_leaked_handle_is_closed() {
+        let db = Database::new();
+        let leaked = db
+            .with_transaction(|tx| async move { Ok::<_, TxError>(tx.clone()) })
+            .await
+            .unwrap();
+
+        assert_eq!(leaked.set("late", 1).await, Err(TxError::Closed));
+        assert_eq!(leaked.get("late").await, Err(TxError::Closed));
+        assert_eq!(db.get("late"), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_transaction_ids_increase() {
+        let db = Database::new();
+        let first = db
+            .with_transaction(|tx| async move { Ok::<_, TxError>(tx.id()) })
+            .await;
+        let second = db
+            .with_transaction(|tx| async move { Ok::<_, TxError>(tx.id()) })
+            .await;
+        assert_eq!((first, second), (Ok(0), Ok(1)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_transaction_example() {
+        let (balances, stats) = transaction_example().await;
+        assert_eq!(balances.into_iter().collect::<Vec<_>>(), accounts(70, 80));
+        assert_eq!(
+            stats,
+            TxStats {
+                committed: 1,
+                rolled_back: 2
+            }
+        );
+    }
+}
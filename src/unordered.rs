@@ -0,0 +1,228 @@
+//! Ordered vs unordered completion
+//!
+//! `join_all` polls every future concurrently but hands back all the results
+//! at once, in submission order: the slowest future decides when anything can
+//! be processed. `FuturesUnordered` (in-task) and `JoinSet` (spawned tasks)
+//! yield each result as soon as it is ready, in completion order, which
+//! enables:
+//! - processing results while slower futures are still running
+//! - keeping only the first N of M results and dropping the rest
+//! - exiting early on the first success
+//!
+//! Dropping a `FuturesUnordered` drops (cancels) the futures still in it;
+//! dropping a `JoinSet` aborts the tasks still in it.
+
+use std::time::Duration;
+
+use futures::future::join_all;
+use futures::stream::{FuturesUnordered, StreamExt};
+use tokio::task::JoinSet;
+use tokio::time::sleep;
+use tracing::{debug, info, instrument};
+
+use crate::example_context::ExampleContext;
+
+/// A simulated job: finishes after `delay`, successfully or not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Job {
+    pub id: usize,
+    pub delay: Duration,
+    pub succeeds: bool,
+}
+
+impl Job {
+    /// A successful job taking `delay_ms` milliseconds.
+    pub fn new(id: usize, delay_ms: u64) -> Self {
+        Job {
+            id,
+            delay: Duration::from_millis(delay_ms),
+            succeeds: true,
+        }
+    }
+
+    /// A job failing after `delay_ms` milliseconds.
+    pub fn failing(id: usize, delay_ms: u64) -> Self {
+        Job {
+            succeeds: false,
+            ..Job::new(id, delay_ms)
+        }
+    }
+
+    /// Runs the job, returning its id on success or failure.
+    pub async fn run(self) -> Result<usize, usize> {
+        sleep(self.delay).await;
+        debug!(id = self.id, succeeds = self.succeeds, "job finished");
+        if self.succeeds {
+            Ok(self.id)
+        } else {
+            Err(self.id)
+        }
+    }
+}
+
+/// Jobs with ids `0..` and the given durations, in submission order.
+pub fn jobs(delays_ms: &[u64]) -> Vec<Job> {
+    delays_ms
+        .iter()
+        .enumerate()
+        .map(|(id, &delay_ms)| Job::new(id, delay_ms))
+        .collect()
+}
+
+/// `join_all`: results in submission order, available once all are done.
+pub async fn run_join_all(jobs: Vec<Job>) -> Vec<Result<usize, usize>> {
+    join_all(jobs.into_iter().map(Job::run)).await
+}
+
+/// `FuturesUnordered`: results in completion order, in the current task.
+pub async fn run_unordered(jobs: Vec<Job>) -> Vec<Result<usize, usize>> {
+    let mut pending: FuturesUnordered<_> = jobs.into_iter().map(Job::run).collect();
+    let mut results = Vec::new();
+    while let Some(result) = pending.next().await {
+        results.push(result);
+    }
+    results
+}
+
+/// `JoinSet`: results in completion order, each job in its own task.
+pub async fn run_join_set(jobs: Vec<Job>) -> Vec<Result<usize, usize>> {
+    let mut set = JoinSet::new();
+    for job in jobs {
+        set.spawn(job.run());
+    }
+    let mut results = Vec::new();
+    while let Some(result) = set.join_next().await {
+        results.push(result.expect("job panicked"));
+    }
+    results
+}
+
+/// Keeps the first `n` results to complete and cancels the other jobs.
+pub async fn first_n_of(jobs: Vec<Job>, n: usize) -> Vec<Result<usize, usize>> {
+    let pending: FuturesUnordered<_> = jobs.into_iter().map(Job::run).collect();
+    // `take` stops polling after `n` items; the remaining futures are
+    // dropped with the stream.
+    pending.take(n).collect().await
+}
+
+/// Returns the id of the first job to succeed, without waiting for the
+/// others, or the ids of all the failed jobs (in completion order).
+pub async fn first_success(jobs: Vec<Job>) -> Result<usize, Vec<usize>> {
+    let mut set = JoinSet::new();
+    for job in jobs {
+        set.spawn(job.run());
+    }
+    let mut failures = Vec::new();
+    while let Some(result) = set.join_next().await {
+        match result.expect("job panicked") {
+            // Returning drops the set, which aborts the jobs still running.
+            Ok(id) => return Ok(id),
+            Err(id) => failures.push(id),
+        }
+    }
+    Err(failures)
+}
+
+/// Example: the same jobs collected in order and as they complete
+///
+/// The jobs take the latencies of `ctx`: with jitter, the completion order
+/// changes from run to run, the submission order does not.
+#[instrument(skip_all)]
+pub async fn unordered_example(ctx: &ExampleContext) {
+    let simulated = |jobs: Vec<Job>| -> Vec<Job> {
+        jobs.into_iter()
+            .map(|job| Job {
+                delay: ctx.latency().delay(job.delay),
+                ..job
+            })
+            .collect()
+    };
+    let delays_ms = [30, 10, 20];
+
+    let results = run_join_all(simulated(jobs(&delays_ms))).await;
+    info!(?results, "join_all (submission order)");
+
+    let results = run_unordered(simulated(jobs(&delays_ms))).await;
+    info!(?results, "FuturesUnordered (completion order)");
+
+    let results = run_join_set(simulated(jobs(&delays_ms))).await;
+    info!(?results, "JoinSet (completion order)");
+
+    let results = first_n_of(simulated(jobs(&delays_ms)), 2).await;
+    info!(?results, "first 2 of 3");
+
+    let jobs = vec![Job::failing(0, 5), Job::new(1, 15), Job::new(2, 100)];
+    let result = first_success(simulated(jobs)).await;
+    info!(?result, "first success");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Instant;
+
+    const DELAYS_MS: [u64; 4] = [40, 10, 30, 20];
+
+    fn ids(results: &[Result<usize, usize>]) -> Vec<usize> {
+        results
+            .iter()
+            .map(|result| match result {
+                Ok(id) | Err(id) => *id,
+            })
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_join_all_keeps_submission_order() {
+        let start = Instant::now();
+        let results = run_join_all(jobs(&DELAYS_MS)).await;
+        assert_eq!(ids(&results), vec![0, 1, 2, 3]);
+        // Concurrent, but nothing is returned before the slowest job is done.
+        assert_eq!(start.elapsed(), Duration::from_millis(40));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_futures_unordered_yields_completion_order() {
+        let start = Instant::now();
+        let results = run_unordered(jobs(&DELAYS_MS)).await;
+        assert_eq!(ids(&results), vec![1, 3, 2, 0]);
+        assert_eq!(start.elapsed(), Duration::from_millis(40));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_join_set_yields_completion_order() {
+        let results = run_join_set(jobs(&DELAYS_MS)).await;
+        assert_eq!(ids(&results), vec![1, 3, 2, 0]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_first_n_of_m_does_not_wait_for_the_rest() {
+        let start = Instant::now();
+        let results = first_n_of(jobs(&DELAYS_MS), 2).await;
+        assert_eq!(ids(&results), vec![1, 3]);
+        assert_eq!(start.elapsed(), Duration::from_millis(20));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_first_success_exits_early() {
+        let start = Instant::now();
+        let result =
+            first_success(vec![Job::new(0, 100), Job::failing(1, 5), Job::new(2, 20)]).await;
+        assert_eq!(result, Ok(2));
+        assert_eq!(start.elapsed(), Duration::from_millis(20));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_first_success_reports_all_failures() {
+        let result = first_success(vec![Job::failing(0, 20), Job::failing(1, 10)]).await;
+        assert_eq!(result, Err(vec![1, 0]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unordered_example() {
+        let start = Instant::now();
+        unordered_example(&ExampleContext::captured()).await;
+        // Three full runs of 30ms, first 2 of 3 (20ms), first success (15ms).
+        assert_eq!(start.elapsed(), Duration::from_millis(3 * 30 + 20 + 15));
+    }
+}
@@ -0,0 +1,152 @@
+//! Waker deep-dive: a future that is woken from another thread
+//!
+//! This is the classic `TimerFuture` from the async book, expanded with
+//! logging of every `poll` call. It shows the contract between a future and
+//! the executor driving it:
+//! - `poll` returns `Poll::Pending` and stores the `Waker` it was given
+//! - Some other party (here, an OS thread) later calls `Waker::wake`
+//! - The executor then polls the future again, which now returns `Poll::Ready`
+//!
+//! The future counts how many times it was polled and reports it in its
+//! output, so tests can assert the executor did not poll it more than needed.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, instrument};
+
+use crate::example_context::ExampleContext;
+
+/// State shared between the future and the thread that completes it.
+struct SharedState {
+    /// Set to true by the timer thread once the duration has elapsed.
+    completed: AtomicBool,
+    /// The waker of the most recent poll, if the future is still pending.
+    waker: Mutex<Option<Waker>>,
+    /// Number of times the timer thread called `wake`.
+    wake_count: AtomicU32,
+}
+
+/// Metrics returned by a completed [`TimerFuture`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PollMetrics {
+    /// How many times the executor called `poll` on the future.
+    pub poll_count: u32,
+    /// How many times the future's waker was invoked.
+    pub wake_count: u32,
+    /// Time between the creation of the future and its completion.
+    pub elapsed: Duration,
+}
+
+/// A future that completes once a background thread signals it.
+///
+/// The thread sleeps for the given duration, sets the shared `AtomicBool`,
+/// and wakes the stored `Waker` (if any).
+pub struct TimerFuture {
+    shared: Arc<SharedState>,
+    poll_count: u32,
+    created_at: Instant,
+}
+
+impl TimerFuture {
+    /// Creates a new timer future and starts the thread that completes it.
+    pub fn new(duration: Duration) -> Self {
+        let shared = Arc::new(SharedState {
+            completed: AtomicBool::new(false),
+            waker: Mutex::new(None),
+            wake_count: AtomicU32::new(0),
+        });
+
+        let thread_shared = Arc::clone(&shared);
+        thread::spawn(move || {
+            thread::sleep(duration);
+            thread_shared.completed.store(true, Ordering::Release);
+
+            // Take the waker out of the lock before waking so the executor
+            // can poll (and lock) again without contention.
+            let waker = thread_shared.waker.lock().unwrap().take();
+            if let Some(waker) = waker {
+                info!("timer thread: waking the future");
+                thread_shared.wake_count.fetch_add(1, Ordering::Relaxed);
+                waker.wake();
+            }
+        });
+
+        TimerFuture {
+            shared,
+            poll_count: 0,
+            created_at: Instant::now(),
+        }
+    }
+}
+
+impl Future for TimerFuture {
+    type Output = PollMetrics;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.poll_count += 1;
+        info!(poll = self.poll_count, "checking timer");
+
+        // Store the waker before checking the flag: if we checked first, the
+        // thread could complete between the check and the store, and nobody
+        // would ever wake us (a lost wakeup).
+        *self.shared.waker.lock().unwrap() = Some(cx.waker().clone());
+
+        if self.shared.completed.load(Ordering::Acquire) {
+            info!(poll = self.poll_count, "ready");
+            Poll::Ready(PollMetrics {
+                poll_count: self.poll_count,
+                wake_count: self.shared.wake_count.load(Ordering::Relaxed),
+                elapsed: self.created_at.elapsed(),
+            })
+        } else {
+            info!(poll = self.poll_count, "pending");
+            Poll::Pending
+        }
+    }
+}
+
+/// Example: awaiting a future that is woken from another thread
+///
+/// The future is polled once (Pending), the timer thread wakes it, and it is
+/// polled a second time (Ready).
+#[instrument(skip_all)]
+pub async fn waker_example(ctx: &ExampleContext) -> PollMetrics {
+    info!("Creating a TimerFuture completed by another thread...");
+
+    let metrics = TimerFuture::new(ctx.latency().delay(Duration::from_millis(50))).await;
+
+    info!(
+        "Timer completed after {:?}: polled {} time(s), woken {} time(s)",
+        metrics.elapsed, metrics.poll_count, metrics.wake_count
+    );
+    metrics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_waker_example_polls_twice() {
+        let metrics = waker_example(&ExampleContext::captured()).await;
+        assert_eq!(metrics.poll_count, 2);
+        assert_eq!(metrics.wake_count, 1);
+        assert!(metrics.elapsed >= Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn test_completed_before_first_poll() {
+        let future = TimerFuture::new(Duration::ZERO);
+        // Give the timer thread time to finish before the future is polled.
+        thread::sleep(Duration::from_millis(50));
+
+        let metrics = future.await;
+        assert_eq!(metrics.poll_count, 1);
+        assert_eq!(metrics.wake_count, 0);
+    }
+}
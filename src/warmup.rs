@@ -0,0 +1,276 @@
+//! Warm-up: eager vs lazy initialization
+//!
+//! A client needs resources before serving its first request: a pool of
+//! connections (TCP and TLS handshakes), a cache loaded from storage. Two
+//! strategies:
+//! - eager: build everything at startup, with [`ApiClient::warm_up`]. Startup
+//!   is slower, but the first request costs as much as any other one.
+//! - lazy: build each resource on first use. Startup is instant, but the
+//!   first request pays for the whole initialization: a latency spike, and a
+//!   timeout risk, right when the service starts taking traffic.
+//!
+//! Lazy resources live in a `tokio::sync::OnceCell`: concurrent first
+//! requests share a single initialization instead of racing to build their
+//! own. [`measure`] records startup and first/second request latencies for
+//! both strategies. [`crate::request_id::TracedClient::warm_up`] is the
+//! same idea for a real HTTP client: it opens a pooled connection up front.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::OnceCell;
+use tokio::time::{sleep, Instant};
+use tracing::{debug, info, instrument};
+
+use crate::example_context::ExampleContext;
+
+/// When an [`ApiClient`] builds its resources.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Init {
+    /// At startup.
+    Eager,
+    /// On first use.
+    Lazy,
+}
+
+/// Costs of the simulated resources.
+#[derive(Debug, Clone, Copy)]
+pub struct Costs {
+    /// Handshake of one pooled connection; the pool connects in parallel.
+    pub connect: Duration,
+    pub pool_size: usize,
+    /// Loading the cache.
+    pub cache_load: Duration,
+    /// A request on a ready client.
+    pub request: Duration,
+}
+
+impl Default for Costs {
+    fn default() -> Self {
+        Costs {
+            connect: Duration::from_millis(50),
+            pool_size: 4,
+            cache_load: Duration::from_millis(30),
+            request: Duration::from_millis(5),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Pool {
+    connections: usize,
+}
+
+#[derive(Debug)]
+struct Cache {
+    entries: HashMap<String, String>,
+}
+
+/// A client whose connection pool and cache are built eagerly or lazily.
+#[derive(Debug, Clone)]
+pub struct ApiClient {
+    costs: Costs,
+    pool: Arc<OnceCell<Pool>>,
+    cache: Arc<OnceCell<Cache>>,
+    initializations: Arc<AtomicUsize>,
+}
+
+impl ApiClient {
+    /// Creates a client; with [`Init::Eager`], returns once it is warmed up.
+    pub async fn start(init: Init, costs: Costs) -> Self {
+        let client = ApiClient {
+            costs,
+            pool: Arc::default(),
+            cache: Arc::default(),
+            initializations: Arc::default(),
+        };
+        if init == Init::Eager {
+            client.warm_up().await;
+        }
+        client
+    }
+
+    /// Builds every resource not built yet, concurrently. Idempotent.
+    pub async fn warm_up(&self) {
+        tokio::join!(self.pool(), self.cache());
+    }
+
+    /// Whether every resource is built.
+    pub fn is_warm(&self) -> bool {
+        self.pool.initialized() && self.cache.initialized()
+    }
+
+    /// Resources built so far, counting each one once.
+    pub fn initializations(&self) -> usize {
+        self.initializations.load(Ordering::SeqCst)
+    }
+
+    async fn pool(&self) -> &Pool {
+        self.pool
+            .get_or_init(|| async {
+                debug!(size = self.costs.pool_size, "connecting pool");
+                let handshakes = (0..self.costs.pool_size).map(|_| sleep(self.costs.connect));
+                futures::future::join_all(handshakes).await;
+                self.initializations.fetch_add(1, Ordering::SeqCst);
+                Pool {
+                    connections: self.costs.pool_size,
+                }
+            })
+            .await
+    }
+
+    async fn cache(&self) -> &Cache {
+        self.cache
+            .get_or_init(|| async {
+                debug!("loading cache");
+                sleep(self.costs.cache_load).await;
+                self.initializations.fetch_add(1, Ordering::SeqCst);
+                Cache {
+                    entries: HashMap::from([("greeting".to_string(), "hello".to_string())]),
+                }
+            })
+            .await
+    }
+
+    /// Looks `key` up, building the resources first if needed.
+    pub async fn get(&self, key: &str) -> Option<String> {
+        let (pool, cache) = tokio::join!(self.pool(), self.cache());
+        if let Some(value) = cache.entries.get(key) {
+            return Some(value.clone());
+        }
+        debug!(connections = pool.connections, key, "cache miss");
+        sleep(self.costs.request).await;
+        None
+    }
+}
+
+/// Startup and request latencies of one strategy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InitReport {
+    pub init: Init,
+    /// Time until the client is returned.
+    pub startup: Duration,
+    pub first_request: Duration,
+    pub second_request: Duration,
+}
+
+/// Starts a client with `init` and times startup and its first two requests.
+pub async fn measure(init: Init, costs: Costs) -> InitReport {
+    let start = Instant::now();
+    let client = ApiClient::start(init, costs).await;
+    let startup = start.elapsed();
+
+    let mut latencies = [Duration::ZERO; 2];
+    for latency in &mut latencies {
+        let start = Instant::now();
+        client.get("missing").await;
+        *latency = start.elapsed();
+    }
+    InitReport {
+        init,
+        startup,
+        first_request: latencies[0],
+        second_request: latencies[1],
+    }
+}
+
+/// Example: the latency of the first request, eager vs lazy, with the costs
+/// simulated by the latencies of `ctx`
+#[instrument(skip_all)]
+pub async fn warmup_example(ctx: &ExampleContext) -> [InitReport; 2] {
+    let defaults = Costs::default();
+    let costs = Costs {
+        connect: ctx.latency().delay(defaults.connect),
+        cache_load: ctx.latency().delay(defaults.cache_load),
+        request: ctx.latency().delay(defaults.request),
+        ..defaults
+    };
+    let reports = [
+        measure(Init::Eager, costs).await,
+        measure(Init::Lazy, costs).await,
+    ];
+    for report in &reports {
+        info!(
+            init = ?report.init,
+            startup = ?report.startup,
+            first = ?report.first_request,
+            second = ?report.second_request,
+            "latencies"
+        );
+    }
+    reports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_eager_pays_at_startup() {
+        let report = measure(Init::Eager, Costs::default()).await;
+        // Pool and cache load concurrently: max(50, 30).
+        assert_eq!(report.startup, millis(50));
+        assert_eq!(report.first_request, millis(5));
+        assert_eq!(report.second_request, millis(5));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lazy_pays_on_first_request() {
+        let report = measure(Init::Lazy, Costs::default()).await;
+        assert_eq!(report.startup, Duration::ZERO);
+        assert_eq!(report.first_request, millis(55));
+        assert_eq!(report.second_request, millis(5));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrent_first_requests_share_initialization() {
+        let client = ApiClient::start(Init::Lazy, Costs::default()).await;
+        let start = Instant::now();
+        futures::future::join_all((0..10).map(|_| client.get("greeting"))).await;
+
+        assert_eq!(client.initializations(), 2, "one pool, one cache");
+        assert_eq!(start.elapsed(), millis(50));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_warm_up_is_idempotent() {
+        let client = ApiClient::start(Init::Lazy, Costs::default()).await;
+        assert!(!client.is_warm());
+
+        client.warm_up().await;
+        let start = Instant::now();
+        client.warm_up().await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        assert!(client.is_warm());
+        assert_eq!(client.initializations(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cache_hit_skips_the_request() {
+        let client = ApiClient::start(Init::Eager, Costs::default()).await;
+        let start = Instant::now();
+        assert_eq!(client.get("greeting").await.as_deref(), Some("hello"));
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_clones_share_resources() {
+        let client = ApiClient::start(Init::Lazy, Costs::default()).await;
+        client.clone().warm_up().await;
+        assert!(client.is_warm());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_warmup_example() {
+        let [eager, lazy] = warmup_example(&ExampleContext::captured()).await;
+        assert!(eager.startup > lazy.startup);
+        assert!(eager.first_request < lazy.first_request);
+        assert_eq!(eager.second_request, lazy.second_request);
+    }
+}
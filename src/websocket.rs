@@ -0,0 +1,258 @@
+//! Websocket chat server (feature `websocket`)
+//!
+//! Every message a client sends is broadcast to all connected clients,
+//! including the sender. Each connection is handled by its own task and split
+//! into two halves:
+//! - the stream half, read by the connection task, which publishes incoming
+//!   messages on a `broadcast` channel
+//! - the sink half, owned by a writer task, which forwards every broadcast
+//!   message to the client
+//!
+//! Splitting lets reading and writing progress independently: a client that
+//! is not sending anything still receives the others' messages. `broadcast`
+//! gives each subscriber its own cursor into a bounded buffer; a client too
+//! slow to keep up gets `Lagged` and skips the messages it missed instead of
+//! slowing everyone down.
+
+use std::io;
+use std::net::SocketAddr;
+
+use futures::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+use tokio_tungstenite::{accept_async, connect_async, MaybeTlsStream, WebSocketStream};
+use tracing::{debug, info, info_span, instrument, warn, Instrument};
+
+/// Number of messages a slow client can fall behind before skipping some.
+const BROADCAST_CAPACITY: usize = 64;
+
+/// Binds the chat server to `addr` (use port 0 for an ephemeral port) and
+/// spawns its accept loop.
+///
+/// Returns the bound address and the handle of the accept loop; aborting it
+/// stops accepting new clients.
+pub async fn spawn_chat_server(addr: &str) -> io::Result<(SocketAddr, JoinHandle<()>)> {
+    let listener = TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    let (messages, _) = broadcast::channel(BROADCAST_CAPACITY);
+
+    let accept_loop = tokio::spawn(
+        async move {
+            let mut next_id = 0;
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(error) => {
+                        warn!(%error, "accept failed");
+                        continue;
+                    }
+                };
+                next_id += 1;
+                let name = format!("client-{}", next_id);
+                let span = info_span!("connection", %peer, %name);
+                tokio::spawn(handle_connection(stream, name, messages.clone()).instrument(span));
+            }
+        }
+        .instrument(info_span!("chat_server", %local_addr)),
+    );
+
+    info!(%local_addr, "chat server listening");
+    Ok((local_addr, accept_loop))
+}
+
+/// Serves one client until it disconnects.
+async fn handle_connection(stream: TcpStream, name: String, messages: broadcast::Sender<String>) {
+    // Subscribe before the handshake: once the client sees the connection
+    // open, it must not miss messages sent by others.
+    let mut incoming = messages.subscribe();
+    let socket = match accept_async(stream).await {
+        Ok(socket) => socket,
+        Err(error) => {
+            warn!(%error, "websocket handshake failed");
+            return;
+        }
+    };
+    info!("client connected");
+
+    let (mut sink, mut stream) = socket.split();
+
+    let writer = tokio::spawn(
+        async move {
+            loop {
+                match incoming.recv().await {
+                    Ok(text) => {
+                        if sink.send(Message::Text(text)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!(skipped, "client too slow, messages skipped");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        }
+        .in_current_span(),
+    );
+
+    while let Some(message) = stream.next().await {
+        match message {
+            Ok(Message::Text(text)) => {
+                debug!(%text, "message received");
+                // Fails only when nobody is subscribed, which cannot happen
+                // while this client's writer is running.
+                let _ = messages.send(format!("{}: {}", name, text));
+            }
+            Ok(Message::Close(_)) | Err(_) => break,
+            // Pings are answered by tungstenite; binary messages are ignored.
+            Ok(_) => {}
+        }
+    }
+
+    writer.abort();
+    info!("client disconnected");
+}
+
+/// A chat client.
+pub struct ChatClient {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl ChatClient {
+    /// Connects to the chat server at `addr`.
+    pub async fn connect(addr: SocketAddr) -> Result<Self, WsError> {
+        let (socket, _response) = connect_async(format!("ws://{}", addr)).await?;
+        Ok(ChatClient { socket })
+    }
+
+    /// Sends a message to every connected client.
+    pub async fn send(&mut self, text: &str) -> Result<(), WsError> {
+        self.socket.send(Message::Text(text.to_string())).await
+    }
+
+    /// Waits for the next chat message, or `None` once the server closed the
+    /// connection.
+    pub async fn recv(&mut self) -> Option<String> {
+        while let Some(message) = self.socket.next().await {
+            match message {
+                Ok(Message::Text(text)) => return Some(text),
+                Ok(Message::Close(_)) | Err(_) => return None,
+                Ok(_) => {}
+            }
+        }
+        None
+    }
+
+    /// Closes the connection.
+    pub async fn close(mut self) -> Result<(), WsError> {
+        self.socket.close(None).await
+    }
+}
+
+/// Example: two clients chatting through the server
+#[instrument]
+pub async fn websocket_example() -> Result<Vec<String>, WsError> {
+    let (addr, server) = spawn_chat_server("127.0.0.1:0").await?;
+
+    let mut alice = ChatClient::connect(addr).await?;
+    let mut bob = ChatClient::connect(addr).await?;
+
+    alice.send("hello bob").await?;
+    let mut received = vec![bob.recv().await.unwrap_or_default()];
+    bob.send("hi alice").await?;
+    // Alice first receives her own message, then Bob's answer.
+    received.push(alice.recv().await.unwrap_or_default());
+    received.push(alice.recv().await.unwrap_or_default());
+    info!(?received, "chat transcript");
+
+    alice.close().await?;
+    bob.close().await?;
+    server.abort();
+    Ok(received)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{fixture, Fixture};
+    use crate::send_sync::assert_send;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    /// A chat server on an ephemeral port, aborted on teardown.
+    ///
+    /// The teardown does not await the server's task: it runs on the test's
+    /// runtime, which a teardown run by `Drop` cannot wait on.
+    async fn chat_server() -> Fixture<SocketAddr, JoinHandle<()>> {
+        fixture(
+            async { spawn_chat_server("127.0.0.1:0").await.unwrap() },
+            |server| async move { server.abort() },
+        )
+        .await
+    }
+
+    async fn recv(client: &mut ChatClient) -> String {
+        timeout(Duration::from_secs(5), client.recv())
+            .await
+            .expect("no message within 5s")
+            .expect("connection closed")
+    }
+
+    #[tokio::test]
+    async fn test_messages_are_broadcast_to_all_clients() {
+        chat_server()
+            .await
+            .run(|addr| async move {
+                assert_ne!(addr.port(), 0);
+
+                let mut first = ChatClient::connect(addr).await.unwrap();
+                let mut second = ChatClient::connect(addr).await.unwrap();
+
+                first.send("hello").await.unwrap();
+                assert_eq!(recv(&mut first).await, "client-1: hello");
+                assert_eq!(recv(&mut second).await, "client-1: hello");
+
+                second.send("hi").await.unwrap();
+                assert_eq!(recv(&mut first).await, "client-2: hi");
+                assert_eq!(recv(&mut second).await, "client-2: hi");
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_disconnected_client_does_not_break_the_chat() {
+        chat_server()
+            .await
+            .run(|addr| async move {
+                let mut first = ChatClient::connect(addr).await.unwrap();
+                let second = ChatClient::connect(addr).await.unwrap();
+                second.close().await.unwrap();
+
+                let mut third = ChatClient::connect(addr).await.unwrap();
+                first.send("still here").await.unwrap();
+                assert_eq!(recv(&mut third).await, "client-1: still here");
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_websocket_example() {
+        let received = websocket_example().await.unwrap();
+        assert_eq!(
+            received,
+            vec![
+                "client-1: hello bob",
+                "client-1: hello bob",
+                "client-2: hi alice"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_futures_are_send() {
+        assert_send(&websocket_example());
+        assert_send(&spawn_chat_server("127.0.0.1:0"));
+    }
+}